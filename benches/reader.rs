@@ -0,0 +1,167 @@
+use std::io::{BufRead, BufReader, Cursor, Read, Result, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+use criterion::{criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion, Throughput};
+use pop3_client::{Builder, Client};
+
+/// Serves canned replies and swallows the commands
+struct Canned(Cursor<Vec<u8>>);
+
+impl Read for Canned {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for Canned {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+fn client(replies: &[u8]) -> Client {
+    let mut data = b"+OK POP3 server ready\r\n".to_vec();
+    data.extend_from_slice(replies);
+    Client::from_stream(Canned(Cursor::new(data))).unwrap()
+}
+
+fn list(c: &mut Criterion) {
+    let mut reply = b"+OK 10000 messages\r\n".to_vec();
+    for i in 1..=10_000 {
+        reply.extend_from_slice(format!("{} {}\r\n", i, 1000 + i).as_bytes());
+    }
+    reply.extend_from_slice(b".\r\n");
+
+    let mut group = c.benchmark_group("list");
+    group.throughput(Throughput::Bytes(reply.len() as u64));
+    group.bench_function("10000 messages", |b| {
+        b.iter_batched(
+            || client(&reply),
+            |mut client| client.list(None).unwrap(),
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+fn retr_small(c: &mut Criterion) {
+    const MESSAGES: usize = 1000;
+
+    let message = "From: me@my.host.com\r\nSubject: hello\r\n\r\nA short body.\r\n";
+    let mut replies = vec![];
+    for _ in 0..MESSAGES {
+        replies.extend_from_slice(format!("+OK {} octets\r\n{}.\r\n", message.len(), message).as_bytes());
+    }
+
+    let mut group = c.benchmark_group("retr");
+    group.throughput(Throughput::Elements(MESSAGES as u64));
+    group.bench_function("1000 small messages", |b| {
+        b.iter_batched(
+            || client(&replies),
+            |mut client| {
+                for msg in 1..=MESSAGES as u32 {
+                    client.retr(msg).unwrap();
+                }
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+/// A message of `lines` lines of 76 octets, some of them dot-stuffed
+fn large_message(lines: usize) -> Vec<u8> {
+    let mut message = b"From: me@my.host.com\r\nSubject: attachment\r\n\r\n".to_vec();
+    for i in 0..lines {
+        let lead = if i % 100 == 0 { ".." } else { "AA" };
+        message.extend_from_slice(lead.as_bytes());
+        message.extend_from_slice(&[b'A'; 74]);
+        message.extend_from_slice(b"\r\n");
+    }
+    message.extend_from_slice(b".\r\n");
+    message
+}
+
+fn multiline(c: &mut Criterion) {
+    let message = large_message(100_000);
+    let mut reply = format!("+OK {} octets\r\n", message.len()).into_bytes();
+    reply.extend_from_slice(&message);
+
+    let mut group = c.benchmark_group("multiline");
+    group.throughput(Throughput::Bytes(reply.len() as u64));
+    group.bench_function("100000 lines", |b| {
+        b.iter_batched(
+            || client(&reply),
+            |mut client| client.retr(1).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+/// Accept connections on a local port, answering every `RETR` with the same message
+fn mock_server(message: Vec<u8>) -> u16 {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let port = listener.local_addr().unwrap().port();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let mut stream = stream.unwrap();
+            let message = message.clone();
+            thread::spawn(move || {
+                stream.write_all(b"+OK POP3 server ready\r\n").unwrap();
+                let mut commands = BufReader::new(stream.try_clone().unwrap());
+                let mut line = String::new();
+                while commands.read_line(&mut line).unwrap_or(0) > 0 {
+                    if line.starts_with("RETR") {
+                        stream.write_all(format!("+OK {} octets\r\n", message.len()).as_bytes()).unwrap();
+                        stream.write_all(&message).unwrap();
+                    } else {
+                        stream.write_all(b"+OK\r\n").unwrap();
+                    }
+                    if line.starts_with("QUIT") {
+                        break;
+                    }
+                    line.clear();
+                }
+            });
+        }
+    });
+    port
+}
+
+fn retr_bulk(c: &mut Criterion) {
+    const MESSAGES: u32 = 20;
+
+    let message = large_message(10_000);
+    let port = mock_server(message.clone());
+
+    let mut group = c.benchmark_group("retr bulk");
+    group.throughput(Throughput::Bytes(message.len() as u64 * MESSAGES as u64));
+    group.sample_size(20);
+    for buffer in [8 * 1024, 64 * 1024, 256 * 1024] {
+        group.bench_with_input(BenchmarkId::from_parameter(buffer), &buffer, |b, buffer| {
+            b.iter_batched(
+                || {
+                    let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+                    Builder::default().read_buffer_size(*buffer).from_stream(stream).unwrap()
+                },
+                |mut client| {
+                    for msg in 1..=MESSAGES {
+                        client.retr(msg).unwrap();
+                    }
+                    client.quit().unwrap();
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, list, retr_small, multiline, retr_bulk);
+criterion_main!(benches);
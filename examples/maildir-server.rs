@@ -0,0 +1,26 @@
+//! Serve a maildir over POP3, e.g. to read local mail with a client only speaking POP3
+//!
+//! usage: maildir-server ADDRESS USER PASSWORD MAILDIR
+//!
+//! There's no TLS: listen on localhost, or behind a TLS terminating proxy.
+
+use std::env;
+use std::net::TcpListener;
+use std::process;
+
+use pop3_client::server::{MaildirBackend, Server};
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let [address, user, password, maildir] = &args[..] else {
+        eprintln!("usage: maildir-server ADDRESS USER PASSWORD MAILDIR");
+        process::exit(2);
+    };
+
+    let backend = MaildirBackend::new().user(user, password, maildir);
+    let served = TcpListener::bind(address).and_then(|listener| Server::new(backend).run(listener));
+    if let Err(e) = served {
+        eprintln!("maildir-server: {}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,11 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use pop3_client::parser;
+
+fuzz_target!(|data: &[u8]| {
+    let capabilities = parser::capabilities(data);
+    for (name, _) in capabilities.iter() {
+        assert!(capabilities.contains(name));
+    }
+});
@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use pop3_client::parser;
+
+fuzz_target!(|data: &[u8]| {
+    let (_, text) = parser::status(data);
+    assert!(text.len() <= data.len());
+});
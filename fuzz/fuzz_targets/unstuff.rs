@@ -0,0 +1,12 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+use pop3_client::parser;
+
+fuzz_target!(|data: &[u8]| {
+    for line in parser::unstuff(data) {
+        assert!(!line.contains(&b'\n'));
+    }
+    let top = parser::top(data);
+    assert!(top.headers.len() <= data.len());
+});
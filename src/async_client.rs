@@ -0,0 +1,265 @@
+use std::future::poll_fn;
+use std::io;
+use std::task::{Context, Poll};
+
+#[cfg(any(feature = "async-tokio", feature = "async-futures"))]
+use std::pin::Pin;
+
+use crate::proto::{Command, Event, Session};
+use crate::{parser, secret, AuthStep, Compliance, Error, Response, Result, Stage};
+
+/// Octets asked of the transport per read
+const READ_CHUNK: usize = 8 * 1024;
+
+/// The connection under an [`AsyncClient`], whatever runtime it comes from
+///
+/// The client only needs bytes in and out, so it runs on any runtime: wrap a tokio stream in [`Tokio`] with the
+/// `async-tokio` feature, an async-std or smol one in [`FuturesIo`] with the `async-futures` feature, or implement
+/// the three methods for anything else, e.g. an in-memory stream in tests.
+///
+/// [`AsyncClient`]: struct.AsyncClient.html
+/// [`Tokio`]: struct.Tokio.html
+/// [`FuturesIo`]: struct.FuturesIo.html
+pub trait AsyncTransport: Unpin {
+    /// Read into `buf`, returning how much was read; `0` means the connection was closed
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>>;
+
+    /// Write from `buf`, returning how much was written
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>>;
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>>;
+}
+
+/// A tokio stream as an [`AsyncTransport`], e.g. a `TcpStream` or a `TlsStream` of tokio-rustls
+///
+/// [`AsyncTransport`]: trait.AsyncTransport.html
+#[cfg(feature = "async-tokio")]
+#[derive(Debug)]
+pub struct Tokio<T>(pub T);
+
+#[cfg(feature = "async-tokio")]
+impl<T: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin> AsyncTransport for Tokio<T> {
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        let mut buf = tokio::io::ReadBuf::new(buf);
+        Pin::new(&mut self.0)
+            .poll_read(cx, &mut buf)
+            .map_ok(|()| buf.filled().len())
+    }
+
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+}
+
+/// A `futures-io` stream as an [`AsyncTransport`], as used by async-std and smol
+///
+/// [`AsyncTransport`]: trait.AsyncTransport.html
+#[cfg(feature = "async-futures")]
+#[derive(Debug)]
+pub struct FuturesIo<T>(pub T);
+
+#[cfg(feature = "async-futures")]
+impl<T: futures_io::AsyncRead + futures_io::AsyncWrite + Unpin> AsyncTransport for FuturesIo<T> {
+    fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_read(cx, buf)
+    }
+
+    fn poll_write(&mut self, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+}
+
+/// A POP3 client for async code, running over any [`AsyncTransport`]
+///
+/// The protocol is handled by a [`proto::Session`], so replies are framed and checked as by [`Client`]; the
+/// connection, and TLS on it, is up to the caller, as is the runtime.
+///
+/// # Example
+/// ```no_run
+/// use pop3_client::{AsyncClient, AsyncTransport};
+///
+/// // e.g. `Tokio(tokio::net::TcpStream::connect("my.host.com:110").await?)`
+/// async fn fetch_first<T: AsyncTransport>(transport: T) -> pop3_client::Result<()> {
+///     let mut client = AsyncClient::new(transport).await?;
+///     client.login("sweet_username", "very_secret_password").await?;
+///     let (count, size) = client.stat().await?;
+///     let message = client.retr(1).await?;
+///     client.quit().await
+/// }
+/// ```
+///
+/// [`AsyncTransport`]: trait.AsyncTransport.html
+/// [`proto::Session`]: proto/struct.Session.html
+/// [`Client`]: struct.Client.html
+#[derive(Debug)]
+pub struct AsyncClient<T> {
+    transport: T,
+    session: Session,
+    greeting: String,
+}
+
+impl<T: AsyncTransport> AsyncClient<T> {
+    /// Start a session over an established connection, reading the greeting
+    ///
+    /// # Errors
+    /// Fails with [`Error::Server`] when the greeting is `-ERR`.
+    ///
+    /// [`Error::Server`]: enum.Error.html#variant.Server
+    pub async fn new(transport: T) -> Result<Self> {
+        Self::with_compliance(transport, Compliance::default()).await
+    }
+
+    /// Same as [`new()`], checking the replies as strictly as [`Builder::compliance()`] does
+    ///
+    /// [`new()`]: struct.AsyncClient.html#method.new
+    /// [`Builder::compliance()`]: struct.Builder.html#method.compliance
+    pub async fn with_compliance(transport: T, compliance: Compliance) -> Result<Self> {
+        let mut session = Session::new();
+        session.compliance(compliance);
+        let mut client = Self {
+            transport,
+            session,
+            greeting: String::new(),
+        };
+        match client.next_event().await? {
+            Event::Greeting(greeting) => client.greeting = greeting.info,
+            Event::Reply { .. } => unreachable!("no command was sent"),
+        }
+        Ok(client)
+    }
+
+    /// The text of the greeting, without the status indicator
+    pub fn greeting(&self) -> &str {
+        &self.greeting
+    }
+
+    pub fn stage(&self) -> Stage {
+        self.session.stage()
+    }
+
+    /// The transport, e.g. to shut it down after [`quit()`]
+    ///
+    /// [`quit()`]: struct.AsyncClient.html#method.quit
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Log in with `USER` and `PASS`
+    ///
+    /// Unlike [`Client::login()`], nothing checks the transport is encrypted: that's the caller's to make sure of.
+    ///
+    /// # Errors
+    /// Fails with [`Error::Auth`] when the server refuses either command.
+    ///
+    /// [`Client::login()`]: struct.Client.html#method.login
+    /// [`Error::Auth`]: enum.Error.html#variant.Auth
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<()> {
+        if self.stage() != Stage::Authorization {
+            return Err(Error::WrongStage("login is only allowed in Authorization stage".to_string()));
+        }
+        self.command(&Command::User(username))
+            .await
+            .map_err(|e| e.at_login(AuthStep::User, None))?;
+        self.command(&Command::Pass(password))
+            .await
+            .map_err(|e| e.at_login(AuthStep::Pass, None))
+            .map(|_| ())
+    }
+
+    /// Number of messages in the mailbox and their total size in octets
+    pub async fn stat(&mut self) -> Result<(u32, u64)> {
+        let response = self.command(&Command::Stat).await?;
+        parser::stat(response.info.as_bytes())
+    }
+
+    /// The `LIST` reply, for a single message or the whole mailbox
+    pub async fn list(&mut self, msg: Option<u32>) -> Result<Response> {
+        self.command(&Command::List(msg)).await
+    }
+
+    /// The `UIDL` reply, for a single message or the whole mailbox
+    pub async fn uidl(&mut self, msg: Option<u32>) -> Result<Response> {
+        self.command(&Command::Uidl(msg)).await
+    }
+
+    /// Download a message, whose lines are [`Response::lines`]
+    ///
+    /// [`Response::lines`]: struct.Response.html#structfield.lines
+    pub async fn retr(&mut self, msg: u32) -> Result<Response> {
+        self.command(&Command::Retr(msg)).await
+    }
+
+    /// The headers of a message and its first `n` lines
+    pub async fn top(&mut self, msg: u32, n: u32) -> Result<Response> {
+        self.command(&Command::Top(msg, n)).await
+    }
+
+    pub async fn dele(&mut self, msg: u32) -> Result<Response> {
+        self.command(&Command::Dele(msg)).await
+    }
+
+    pub async fn noop(&mut self) -> Result<()> {
+        self.command(&Command::Noop).await.map(|_| ())
+    }
+
+    pub async fn rset(&mut self) -> Result<Response> {
+        self.command(&Command::Rset).await
+    }
+
+    /// End the session, which commits the deletions
+    pub async fn quit(&mut self) -> Result<()> {
+        self.command(&Command::Quit).await.map(|_| ())
+    }
+
+    /// Send any command and read its reply
+    ///
+    /// # Errors
+    /// Fails with [`Error::Server`] on `-ERR`, and with [`Error::ConnectionAborted`] when the connection closes
+    /// before the reply is in.
+    ///
+    /// [`Error::Server`]: enum.Error.html#variant.Server
+    /// [`Error::ConnectionAborted`]: enum.Error.html#variant.ConnectionAborted
+    pub async fn command(&mut self, command: &Command<'_>) -> Result<Response> {
+        let mut line = self.session.send(command)?;
+        let written = self.write_all(&line).await;
+        secret::wipe(&mut line);
+        written?;
+        match self.next_event().await? {
+            Event::Reply { response, .. } => Ok(response),
+            Event::Greeting(_) => unreachable!("the greeting was read first"),
+        }
+    }
+
+    async fn write_all(&mut self, mut buf: &[u8]) -> Result<()> {
+        while !buf.is_empty() {
+            let written = poll_fn(|cx| self.transport.poll_write(cx, buf)).await.map_err(Error::Io)?;
+            if written == 0 {
+                return Err(Error::Io(io::ErrorKind::WriteZero.into()));
+            }
+            buf = &buf[written..];
+        }
+        poll_fn(|cx| self.transport.poll_flush(cx)).await.map_err(Error::Io)
+    }
+
+    async fn next_event(&mut self) -> Result<Event> {
+        let mut chunk = vec![0; READ_CHUNK];
+        loop {
+            if let Some(event) = self.session.poll() {
+                return event;
+            }
+            let read = poll_fn(|cx| self.transport.poll_read(cx, &mut chunk)).await.map_err(Error::Io)?;
+            if read == 0 {
+                return Err(Error::ConnectionAborted);
+            }
+            self.session.receive(&chunk[..read]);
+        }
+    }
+}
@@ -0,0 +1,237 @@
+use crate::search::Headers;
+use crate::{encoded_word, Client, MessageInfo, Result};
+
+/// Body lines asked with the first `TOP` of [`Client::attachments()`]
+///
+/// [`Client::attachments()`]: struct.Client.html#method.attachments
+const FIRST_WINDOW: u32 = 256;
+/// Body lines past which [`Client::attachments()`] stops asking for more, about 1 MiB of base64
+///
+/// [`Client::attachments()`]: struct.Client.html#method.attachments
+const MAX_WINDOW: u32 = 16384;
+
+/// An attachment of a message, see [`Client::attachments()`]
+///
+/// [`Client::attachments()`]: struct.Client.html#method.attachments
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AttachmentInfo {
+    /// The `filename` of the `Content-Disposition`, or else the `name` of the `Content-Type`; decoded
+    pub filename: Option<String>,
+    /// The media type in lowercase, e.g. `"application/pdf"`
+    pub mime_type: String,
+    /// Size in octets once decoded, estimated from the encoded lines
+    ///
+    /// For an attachment running past the lines fetched, the rest of the message is counted as its own.
+    pub approx_size: u64,
+}
+
+/// A MIME part which isn't a multipart, with what was seen of its body
+struct Part {
+    headers: Headers,
+    /// Encoded octets of the body, without line endings
+    octets: u64,
+    lines: u64,
+}
+
+impl Part {
+    fn new(headers: Headers) -> Self {
+        Self { headers, octets: 0, lines: 0 }
+    }
+
+    fn attachment(&self, rest: u64) -> Option<AttachmentInfo> {
+        let (mime_type, type_params) = parameters(self.headers.get("Content-Type").unwrap_or("text/plain"));
+        let (disposition, disposition_params) = parameters(self.headers.get("Content-Disposition").unwrap_or(""));
+        let filename = parameter(&disposition_params, "filename").or_else(|| parameter(&type_params, "name"));
+        if disposition != "attachment" && filename.is_none() {
+            return None;
+        }
+
+        let encoding = self.headers.get("Content-Transfer-Encoding").unwrap_or("7bit");
+        let base64 = encoding.eq_ignore_ascii_case("base64");
+        let approx_size = if base64 {
+            (self.octets + rest) * 3 / 4
+        } else {
+            self.octets + 2 * self.lines + rest
+        };
+        Some(AttachmentInfo { filename, mime_type, approx_size })
+    }
+}
+
+/// Where the lines of a message stand
+enum State {
+    /// Before the first boundary of a multipart, or after its last
+    Skipping,
+    /// The header lines of a part
+    Headers(Vec<u8>),
+    Body(Part),
+}
+
+/// Walks the lines of a message, collecting the attachments of the parts it goes through
+struct Scan {
+    boundaries: Vec<String>,
+    state: State,
+    found: Vec<AttachmentInfo>,
+    /// Whether the last boundary of the outermost multipart went by
+    complete: bool,
+}
+
+impl Scan {
+    fn new(headers: &[u8]) -> Self {
+        let mut scan = Scan { boundaries: vec![], state: State::Skipping, found: vec![], complete: false };
+        scan.enter(Headers::parse(headers));
+        // A message which isn't a multipart is known from its headers, unless it's an attachment itself
+        scan.complete = match &scan.state {
+            State::Body(part) => part.attachment(0).is_none(),
+            _ => false,
+        };
+        scan
+    }
+
+    /// Start on a part whose headers are known
+    fn enter(&mut self, headers: Headers) {
+        let (mime_type, params) = parameters(headers.get("Content-Type").unwrap_or("text/plain"));
+        self.state = match parameter(&params, "boundary") {
+            Some(boundary) if mime_type.starts_with("multipart/") => {
+                self.boundaries.push(boundary);
+                State::Skipping
+            }
+            _ => State::Body(Part::new(headers)),
+        };
+    }
+
+    /// Done with the current part, if it was a body
+    fn leave(&mut self, rest: u64) {
+        if let State::Body(part) = std::mem::replace(&mut self.state, State::Skipping) {
+            self.found.extend(part.attachment(rest));
+        }
+    }
+
+    fn line(&mut self, line: &[u8]) {
+        let delimiter = line.strip_prefix(b"--").map(|text| String::from_utf8_lossy(text).trim_end().to_string());
+        if let Some(delimiter) = delimiter {
+            let boundary = self.boundaries.iter().rposition(|boundary| {
+                delimiter == *boundary || delimiter.strip_suffix("--") == Some(boundary.as_str())
+            });
+            if let Some(i) = boundary {
+                self.leave(0);
+                if delimiter == self.boundaries[i] {
+                    self.boundaries.truncate(i + 1);
+                    self.state = State::Headers(vec![]);
+                } else {
+                    self.boundaries.truncate(i);
+                    self.complete = self.boundaries.is_empty();
+                }
+                return;
+            }
+        }
+
+        match &mut self.state {
+            State::Skipping => {}
+            State::Headers(headers) if line.is_empty() => {
+                let headers = Headers::parse(headers);
+                self.enter(headers);
+            }
+            State::Headers(headers) => {
+                headers.extend_from_slice(line);
+                headers.extend_from_slice(b"\r\n");
+            }
+            State::Body(part) => {
+                part.octets += line.len() as u64;
+                part.lines += 1;
+            }
+        }
+    }
+}
+
+impl Client {
+    /// The attachments of a message, read from its MIME structure without downloading it whole
+    ///
+    /// The message is read with `TOP`, first with 256 lines of body, then twice as many each time until the
+    /// structure ends, up to 16384 lines. Past that, the attachment the lines stop in is given the rest of the
+    /// message as its size, and the ones after it are missed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// # client.login("sweet_username", "very_secret_password")?;
+    /// for attachment in client.attachments(1)? {
+    ///     println!("{:?} ({}, ~{} octets)", attachment.filename, attachment.mime_type, attachment.approx_size);
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`top()`], and [`list()`] when the size of the message is needed.
+    ///
+    /// [`top()`]: struct.Client.html#method.top
+    /// [`list()`]: struct.Client.html#method.list
+    pub fn attachments(&mut self, msg: u32) -> Result<Vec<AttachmentInfo>> {
+        let mut window = FIRST_WINDOW;
+        loop {
+            let top = self.top(msg, window)?;
+            let mut scan = Scan::new(&top.headers);
+            for line in &top.body_lines {
+                scan.line(line);
+            }
+            let whole = top.body_lines.len() < window as usize;
+            if scan.complete || whole {
+                scan.leave(0);
+                return Ok(scan.found);
+            }
+            if window < MAX_WINDOW {
+                window *= 2;
+                continue;
+            }
+
+            let seen = top.headers.len() + 2 + top.body_lines.iter().map(|line| line.len() + 2).sum::<usize>();
+            let size = match self.list_entries(Some(msg))?.first() {
+                Some(MessageInfo { size, .. }) => *size,
+                None => 0,
+            };
+            scan.leave(size.saturating_sub(seen as u64));
+            return Ok(scan.found);
+        }
+    }
+}
+
+/// A header value such as `text/plain; charset="utf-8"`: the value in lowercase, and its parameters
+fn parameters(value: &str) -> (String, Vec<(String, String)>) {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    for c in value.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+
+    let mut fields = fields.into_iter();
+    let value = fields.next().unwrap_or_default().trim().to_lowercase();
+    let params = fields
+        .filter_map(|field| {
+            let (name, value) = field.split_once('=')?;
+            Some((name.trim().to_lowercase(), value.trim().to_string()))
+        })
+        .collect();
+    (value, params)
+}
+
+/// The parameter called `name`, preferring its extended form `name*`; decoded
+fn parameter(params: &[(String, String)], name: &str) -> Option<String> {
+    let extended = params
+        .iter()
+        .find(|(param, _)| param.strip_suffix('*') == Some(name))
+        .and_then(|(_, value)| encoded_word::decode_extended(value));
+    extended.or_else(|| {
+        params
+            .iter()
+            .find(|(param, _)| param == name)
+            .map(|(_, value)| encoded_word::decode(value))
+    })
+}
@@ -0,0 +1,123 @@
+use std::collections::VecDeque;
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{secret, Builder, Client, Error, Result};
+
+/// A command of the session, as kept by [`Builder::audit_log()`]
+///
+/// [`Builder::audit_log()`]: struct.Builder.html#method.audit_log
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct AuditEvent {
+    /// When the command was sent
+    pub at: SystemTime,
+    /// The command line, with passwords, digests and SASL responses replaced by `***`
+    pub command: String,
+    /// Until the reply had been read
+    pub duration: Duration,
+    pub outcome: AuditOutcome,
+    /// Octets written for the command
+    pub sent: u64,
+    /// Octets read from the connection until the reply was in
+    pub received: u64,
+}
+
+/// How an [`AuditEvent`] ended
+///
+/// [`AuditEvent`]: struct.AuditEvent.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum AuditOutcome {
+    Ok,
+    /// The server answered `-ERR`, or refused a login; holds the error text
+    Refused(String),
+    /// There was no valid reply, e.g. because the connection broke; holds the error text
+    Failed(String),
+}
+
+/// The last events of a session, none unless a capacity was set
+#[derive(Default)]
+pub(crate) struct AuditLog {
+    pub capacity: usize,
+    events: VecDeque<AuditEvent>,
+}
+
+impl AuditLog {
+    pub(crate) fn record<T>(&mut self, command: &str, duration: Duration, sent: u64, received: u64, result: &Result<T>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.capacity {
+            self.events.pop_front();
+        }
+        let outcome = match result {
+            Ok(_) => AuditOutcome::Ok,
+            Err(e @ Error::Server(_))
+            | Err(e @ Error::Auth(_))
+            | Err(e @ Error::MailboxLocked { .. })
+            | Err(e @ Error::LoginDelay { .. }) => AuditOutcome::Refused(e.to_string()),
+            Err(e) => AuditOutcome::Failed(e.to_string()),
+        };
+        self.events.push_back(AuditEvent {
+            at: SystemTime::now() - duration,
+            command: String::from_utf8_lossy(&secret::redact(command.as_bytes(), false)).to_string(),
+            duration,
+            outcome,
+            sent,
+            received,
+        });
+    }
+}
+
+impl Builder {
+    /// Keep an audit trail of the last `capacity` commands of the session, see [`Client::audit()`]
+    ///
+    /// Nothing is kept by default. Secrets are left out as in a transcript: `PASS ***`, `APOP name ***`,
+    /// `AUTH mechanism ***`, while the answers to SASL challenges aren't commands, so aren't kept at all.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// #
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default().audit_log(100).connect("my.host.com", 995)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Client::audit()`]: struct.Client.html#method.audit
+    pub fn audit_log(&mut self, capacity: usize) -> &mut Self {
+        self.audit_log = capacity;
+        self
+    }
+}
+
+impl Client {
+    /// The commands of the session so far, the oldest first, up to the capacity set with
+    /// [`Builder::audit_log()`]
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// #
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default()
+    ///     .audit_log(100)
+    ///     .credentials("sweet_username", "very_secret_password")
+    ///     .open("my.host.com", 995)?;
+    /// client.retr(1)?;
+    /// for event in client.audit() {
+    ///     println!("{:?} {} {:?} ({} octets)", event.at, event.command, event.outcome, event.received);
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Builder::audit_log()`]: struct.Builder.html#method.audit_log
+    pub fn audit(&self) -> Vec<AuditEvent> {
+        self.audit.events.iter().cloned().collect()
+    }
+}
@@ -0,0 +1,616 @@
+use std::str::FromStr;
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use md5::{Digest, Md5};
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::command::MAX_COMMAND_LEN;
+use crate::error::AuthStep;
+use crate::greeting::apop_timestamp;
+use crate::response::SaslReply;
+use crate::secret::{self, Credentials, Password, Sensitive};
+use crate::{Client, Error, Result};
+
+/// How [`Builder::open()`] authenticates with the stored credentials
+///
+/// For other mechanisms, see [`Authenticator`].
+///
+/// [`Builder::open()`]: struct.Builder.html#method.open
+/// [`Authenticator`]: trait.Authenticator.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(rename_all = "kebab-case"))]
+pub enum AuthMechanism {
+    /// Plaintext `USER` and `PASS`
+    #[default]
+    UserPass,
+    /// `APOP` with a digest of the greeting timestamp and the password
+    Apop,
+    /// The strongest mechanism the server offers, see [`Client::auto_auth()`]
+    ///
+    /// [`Client::auto_auth()`]: struct.Client.html#method.auto_auth
+    Auto,
+}
+
+impl FromStr for AuthMechanism {
+    type Err = Error;
+
+    /// `user-pass`, `apop` or `auto`, case insensitive
+    fn from_str(name: &str) -> Result<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "user-pass" => Ok(AuthMechanism::UserPass),
+            "apop"      => Ok(AuthMechanism::Apop),
+            "auto"      => Ok(AuthMechanism::Auto),
+            _ => Err(Error::InvalidArgument(format!("unknown authentication method {:?}", name))),
+        }
+    }
+}
+
+/// Mechanisms sending the secret as it is, which need TLS unless [`Builder::allow_plaintext_auth()`] says otherwise
+///
+/// [`Builder::allow_plaintext_auth()`]: struct.Builder.html#method.allow_plaintext_auth
+const CLEARTEXT_MECHANISMS: [&str; 3] = ["PLAIN", "LOGIN", "XOAUTH2"];
+
+/// What [`Client::auto_auth()`] tries, strongest first, unless [`Builder::mechanisms()`] says otherwise
+///
+/// [`Client::auto_auth()`]: struct.Client.html#method.auto_auth
+/// [`Builder::mechanisms()`]: struct.Builder.html#method.mechanisms
+const MECHANISMS: [&str; 7] = [
+    "SCRAM-SHA-256-PLUS",
+    "SCRAM-SHA-256",
+    "SCRAM-SHA-1-PLUS",
+    "SCRAM-SHA-1",
+    "CRAM-MD5",
+    "PLAIN",
+    "USER",
+];
+
+impl Client {
+    /// The text the server greeted with, after the `+OK`
+    pub fn greeting(&self) -> &str {
+        &self.greeting
+    }
+
+    /// Authorise using the APOP method, computing the digest from the password and the greeting timestamp
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// client.apop_with_password("another_sweet_username", "tanstaaf")?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Fails with [`Error::InvalidReply`] if the greeting carries no timestamp, which means the server doesn't
+    /// support `APOP`. Otherwise, the errors are the ones of [`apop()`].
+    ///
+    /// [`Error::InvalidReply`]: enum.Error.html#variant.InvalidReply
+    /// [`apop()`]: struct.Client.html#method.apop
+    pub fn apop_with_password<P: Password + ?Sized>(
+        &mut self,
+        name: &str,
+        password: &P,
+    ) -> Result<String> {
+        let timestamp = apop_timestamp(&self.greeting)
+            .ok_or_else(|| Error::InvalidReply("greeting carries no APOP timestamp".to_string()))?;
+
+        let secret = Sensitive::new(format!("{}{}", timestamp, password.expose_password()));
+        let digest = hex(&Md5::digest(secret.as_bytes()));
+
+        self.apop(name, &digest)
+    }
+
+    /// Authorise with a SASL mechanism, the `AUTH` command of [RFC 5034]
+    ///
+    /// `initial_response` is sent along with the command when given, and the server may ask for more with
+    /// challenges: `respond` gets each of them decoded, and returns the answer to send. Both go over the wire
+    /// in base64. An error from `respond` cancels the exchange, and is returned once the server has confirmed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// client.auth("PLAIN", None, |_challenge| Ok(b"\0sweet_username\0tanstaaf".to_vec()))?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// A refusal gives [`Error::Auth`] with the [`AuthStep::Auth`] step.
+    ///
+    /// `PLAIN`, `LOGIN` and `XOAUTH2`, which send the secret as it is, fail with [`Error::PlaintextAuth`] without
+    /// TLS, unless allowed with [`Builder::allow_plaintext_auth()`].
+    ///
+    /// [RFC 5034]: https://tools.ietf.org/html/rfc5034
+    /// [`Error::Auth`]: enum.Error.html#variant.Auth
+    /// [`AuthStep::Auth`]: enum.AuthStep.html#variant.Auth
+    /// [`Error::PlaintextAuth`]: enum.Error.html#variant.PlaintextAuth
+    /// [`Builder::allow_plaintext_auth()`]: struct.Builder.html#method.allow_plaintext_auth
+    pub fn auth<F: FnMut(&[u8]) -> Result<Vec<u8>>>(
+        &mut self,
+        mechanism: &str,
+        initial_response: Option<&[u8]>,
+        mut respond: F,
+    ) -> Result<()> {
+        if self.is_authorized() {
+            return Err(Error::WrongStage("login is only allowed in Authorization stage".to_string()));
+        }
+        self.check_capability(&format!("AUTH {}", mechanism))?;
+        if CLEARTEXT_MECHANISMS.iter().any(|m| m.eq_ignore_ascii_case(mechanism)) {
+            self.check_plaintext(&format!("AUTH {}", mechanism))?;
+        }
+        let mut command = Sensitive::new(format!("AUTH {}", mechanism));
+        // An initial response too long for the command line waits for the first, empty, challenge
+        let mut pending = initial_response.map(|response| Sensitive::new(encode(response)));
+        if let Some(response) = pending.as_deref() {
+            if command.len() + response.len() + 3 <= MAX_COMMAND_LEN {
+                command = Sensitive::new(format!("{} {}", &*command, response));
+                pending = None;
+            }
+        }
+
+        let account = self.labels.account.clone();
+        let delay = self.login_delay;
+        let result = self.authenticating(mechanism, &account, |client| {
+            client.observed(&command, |client| {
+                client.write_command(&command)?;
+                let mut cancelled = None;
+                loop {
+                    let reply = SaslReply::read(
+                        &mut client.conn.io,
+                        &mut client.conn.buffer,
+                        client.compliance,
+                        client.server_type.quirks(),
+                        &client.limits,
+                    );
+                    let challenge = match client.check_broken(reply)? {
+                        SaslReply::Done(frame) => {
+                            // The server refuses a cancelled exchange, which says nothing about the credentials
+                            if let Some(e) = cancelled {
+                                return Err(e);
+                            }
+                            return frame.into_result().map(|_| ()).map_err(|e| e.at_login(AuthStep::Auth, delay));
+                        }
+                        SaslReply::Challenge(challenge) => challenge,
+                    };
+                    let answer = match pending.take() {
+                        Some(response) => response,
+                        None => match decode(&challenge).and_then(|challenge| respond(&challenge)) {
+                            Ok(mut answer) => {
+                                let encoded = BASE64.encode(&answer);
+                                secret::wipe(&mut answer);
+                                Sensitive::new(encoded)
+                            }
+                            Err(e) => {
+                                cancelled = Some(e);
+                                Sensitive::new("*".to_string())
+                            }
+                        },
+                    };
+                    client.write_line(&answer)?;
+                }
+            })
+        });
+        self.track_stage("AUTH", result)
+    }
+
+    /// Send an answer to an `AUTH` challenge; not a command, so not bound by its length limit
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        let mut bytes = Vec::with_capacity(line.len() + 2);
+        bytes.extend_from_slice(line.as_bytes());
+        bytes.extend_from_slice(b"\r\n");
+        self.conn.queue_line(bytes)?;
+        self.flush()
+    }
+}
+
+/// Encode an initial response
+fn encode(data: &[u8]) -> String {
+    if data.is_empty() {
+        // An empty initial response, as opposed to none, see RFC 5034, section 4
+        "=".to_string()
+    } else {
+        BASE64.encode(data)
+    }
+}
+
+fn decode(text: &[u8]) -> Result<Vec<u8>> {
+    BASE64
+        .decode(text)
+        .map_err(|e| Error::InvalidReply(format!("challenge is not base64: {}", e)))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub(crate) fn hmac_md5(key: &[u8], parts: &[&[u8]]) -> [u8; 16] {
+    let mut mac = <Hmac<Md5>>::new_from_slice(key).expect("HMAC takes keys of any length");
+    for part in parts {
+        mac.update(part);
+    }
+    mac.finalize().into_bytes().into()
+}
+
+/// `N` octets from the CSPRNG of the OS, for client nonces and challenges
+pub(crate) fn random_bytes<const N: usize>() -> Result<[u8; N]> {
+    let mut bytes = [0; N];
+    getrandom::fill(&mut bytes).map_err(|e| Error::Sasl(format!("no random octets from the OS: {}", e)))?;
+    Ok(bytes)
+}
+
+impl Client {
+    /// Log in with the strongest mechanism both sides support, as announced by `CAPA`
+    ///
+    /// In order: `SCRAM-SHA-256`, `SCRAM-SHA-1` (with the `scram` feature, bound to the TLS channel when
+    /// possible), `CRAM-MD5`, `PLAIN` if the connection is encrypted, and finally `USER`/`PASS`, which servers
+    /// without `CAPA` get. [`Builder::mechanisms()`] replaces the list; `PLAIN` is then tried on plain text too.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::{Client, Credentials};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// client.auto_auth(&Credentials::new("sweet_username", "very_secret_password"))?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Fails with [`Error::Sasl`] if none of the mechanisms of [`Builder::mechanisms()`] is offered, and with
+    /// [`Error::InvalidArgument`] if it names one this build doesn't implement. Otherwise, the errors are the ones
+    /// of the chosen mechanism.
+    ///
+    /// [`Builder::mechanisms()`]: struct.Builder.html#method.mechanisms
+    /// [`Error::Sasl`]: enum.Error.html#variant.Sasl
+    /// [`Error::InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    pub fn auto_auth(&mut self, credentials: &Credentials) -> Result<()> {
+        let offered = match self.capa() {
+            Ok(capabilities) => capabilities.arguments("SASL").map(<[String]>::to_vec).unwrap_or_default(),
+            Err(Error::Server(_)) => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        let candidates = match &self.mechanisms {
+            Some(mechanisms) => match mechanisms.iter().find(|mechanism| !implements(mechanism)) {
+                Some(unknown) => return Err(Error::InvalidArgument(format!("unsupported mechanism {}", unknown))),
+                None => mechanisms.clone(),
+            },
+            None => MECHANISMS
+                .iter()
+                .filter(|mechanism| implements(mechanism) && (**mechanism != "PLAIN" || self.tls))
+                .map(|mechanism| mechanism.to_string())
+                .collect(),
+        };
+        let bound = self.can_bind();
+        let chosen = candidates
+            .into_iter()
+            .find(|mechanism| match mechanism.as_str() {
+                "USER" => true,
+                "APOP" => apop_timestamp(&self.greeting).is_some(),
+                plus if plus.ends_with("-PLUS") && !bound => false,
+                sasl => offered.iter().any(|o| o.eq_ignore_ascii_case(sasl)),
+            })
+            .ok_or_else(|| Error::Sasl(format!("no mechanism in common with the server, which offers {:?}", offered)))?;
+
+        let Credentials { username, password } = credentials;
+        match chosen.as_str() {
+            #[cfg(feature = "scram")]
+            "SCRAM-SHA-256-PLUS" => crate::Scram::sha256(username, password).authenticate(self),
+            #[cfg(feature = "scram")]
+            "SCRAM-SHA-256" => crate::Scram::sha256(username, password).channel_binding(false).authenticate(self),
+            #[cfg(feature = "scram")]
+            "SCRAM-SHA-1-PLUS" => crate::Scram::sha1(username, password).authenticate(self),
+            #[cfg(feature = "scram")]
+            "SCRAM-SHA-1" => crate::Scram::sha1(username, password).channel_binding(false).authenticate(self),
+            "CRAM-MD5" => CramMd5::new(username, password).authenticate(self),
+            "PLAIN"    => SaslPlain::new(username, password).authenticate(self),
+            "APOP"     => self.apop_with_password(username, password).map(|_| ()),
+            _          => self.login(username, password),
+        }
+    }
+
+    /// Whether the `-PLUS` variants of SCRAM can bind to the connection
+    fn can_bind(&mut self) -> bool {
+        #[cfg(all(feature = "scram", feature = "with-rustls"))]
+        return self.tls_server_end_point().is_some();
+        #[cfg(not(all(feature = "scram", feature = "with-rustls")))]
+        false
+    }
+}
+
+/// Whether [`Client::auto_auth()`] knows the mechanism in this build
+///
+/// [`Client::auto_auth()`]: struct.Client.html#method.auto_auth
+fn implements(mechanism: &str) -> bool {
+    match mechanism {
+        "CRAM-MD5" | "PLAIN" | "APOP" | "USER" => true,
+        scram => cfg!(feature = "scram") && MECHANISMS[..4].contains(&scram),
+    }
+}
+
+/// A way to log in, for [`Builder::authenticator()`]
+///
+/// Implement it for mechanisms the crate doesn't know, e.g. GSSAPI through an external crate, on top of
+/// [`Client::auth()`].
+///
+/// [`Builder::authenticator()`]: struct.Builder.html#method.authenticator
+/// [`Client::auth()`]: struct.Client.html#method.auth
+pub trait Authenticator: Send + Sync {
+    /// Log in on a client in the Authorization stage
+    fn authenticate(&self, client: &mut Client) -> Result<()>;
+}
+
+/// Plaintext `USER` and `PASS`, see [`Client::login()`]
+///
+/// [`Client::login()`]: struct.Client.html#method.login
+#[derive(Clone)]
+pub struct UserPass {
+    pub username: String,
+    pub password: String,
+}
+
+impl UserPass {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+}
+
+impl Authenticator for UserPass {
+    fn authenticate(&self, client: &mut Client) -> Result<()> {
+        client.login(&self.username, &self.password)
+    }
+}
+
+/// `APOP` with a digest of the greeting timestamp and the password, see [`Client::apop_with_password()`]
+///
+/// [`Client::apop_with_password()`]: struct.Client.html#method.apop_with_password
+#[derive(Clone)]
+pub struct Apop {
+    pub username: String,
+    pub password: String,
+}
+
+impl Apop {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+}
+
+impl Authenticator for Apop {
+    fn authenticate(&self, client: &mut Client) -> Result<()> {
+        client.apop_with_password(&self.username, &self.password).map(|_| ())
+    }
+}
+
+/// `AUTH PLAIN` of [RFC 4616]: the username and password in a single response
+///
+/// [RFC 4616]: https://tools.ietf.org/html/rfc4616
+#[derive(Clone)]
+pub struct SaslPlain {
+    pub username: String,
+    pub password: String,
+    /// The identity to act as, when not the one of `username`
+    pub authzid: Option<String>,
+}
+
+impl SaslPlain {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+            authzid: None,
+        }
+    }
+}
+
+impl Authenticator for SaslPlain {
+    fn authenticate(&self, client: &mut Client) -> Result<()> {
+        let message = Sensitive::new(format!(
+            "{}\0{}\0{}",
+            self.authzid.as_deref().unwrap_or_default(),
+            self.username,
+            self.password
+        ));
+        client.labels.account = self.username.clone();
+        client.auth("PLAIN", Some(message.as_bytes()), |_| {
+            Err(Error::InvalidReply("unexpected challenge to AUTH PLAIN".to_string()))
+        })
+    }
+}
+
+/// `AUTH XOAUTH2`, with an OAuth 2.0 access token instead of a password, as Gmail and Outlook.com take it
+///
+/// Getting and refreshing the token is up to the application.
+#[derive(Clone)]
+pub struct XOAuth2 {
+    pub username: String,
+    pub token: String,
+}
+
+impl XOAuth2 {
+    pub fn new(username: &str, token: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            token: token.to_string(),
+        }
+    }
+}
+
+impl Authenticator for XOAuth2 {
+    fn authenticate(&self, client: &mut Client) -> Result<()> {
+        let message = Sensitive::new(format!("user={}\x01auth=Bearer {}\x01\x01", self.username, self.token));
+        client.labels.account = self.username.clone();
+        // A challenge carries the details of a failure; the empty answer lets the server end with -ERR
+        client.auth("XOAUTH2", Some(message.as_bytes()), |_| Ok(Vec::new()))
+    }
+}
+
+/// `AUTH CRAM-MD5` of [RFC 2195]: proves the password with an HMAC of a challenge, without sending it
+///
+/// [RFC 2195]: https://tools.ietf.org/html/rfc2195
+#[derive(Clone)]
+pub struct CramMd5 {
+    pub username: String,
+    pub password: String,
+}
+
+impl CramMd5 {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+}
+
+impl Authenticator for CramMd5 {
+    fn authenticate(&self, client: &mut Client) -> Result<()> {
+        client.labels.account = self.username.clone();
+        client.auth("CRAM-MD5", None, |challenge| {
+            let digest = hmac_md5(self.password.as_bytes(), &[challenge]);
+            Ok(format!("{} {}", self.username, hex(&digest)).into_bytes())
+        })
+    }
+}
+
+/// `AUTH DIGEST-MD5` of [RFC 2831], without integrity or confidentiality layers
+///
+/// Unlike CRAM-MD5, the server proves it knows the password too: a wrong proof fails with
+/// [`Error::Sasl`]. RFC 6331 declares the mechanism historic; use it only with servers offering nothing better.
+///
+/// [RFC 2831]: https://tools.ietf.org/html/rfc2831
+/// [`Error::Sasl`]: enum.Error.html#variant.Sasl
+#[derive(Clone)]
+pub struct DigestMd5 {
+    username: String,
+    password: String,
+    host: Option<String>,
+    cnonce: Option<String>,
+}
+
+impl DigestMd5 {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+            host: None,
+            cnonce: None,
+        }
+    }
+
+    /// The host name of the server for the `digest-uri`; by default, the host connected to
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = Some(host.to_string());
+        self
+    }
+
+    /// Use a fixed client nonce instead of a random one, so responses can be compared with known ones
+    #[cfg(feature = "test-util")]
+    pub fn cnonce(mut self, cnonce: &str) -> Self {
+        self.cnonce = Some(cnonce.to_string());
+        self
+    }
+
+    /// `response-value` of RFC 2831, section 2.1.2.1; `method` is `AUTHENTICATE` for the client's proof and
+    /// empty for the server's
+    fn response(&self, realm: &str, nonce: &str, cnonce: &str, uri: &str, method: &str) -> String {
+        let secret = Sensitive::new(format!("{}:{}:{}", self.username, realm, self.password));
+        let mut a1 = Md5::digest(secret.as_bytes()).to_vec();
+        a1.extend_from_slice(format!(":{}:{}", nonce, cnonce).as_bytes());
+        let a2 = format!("{}:{}", method, uri);
+        let kd = format!("{}:{}:00000001:{}:auth:{}", hex(&Md5::digest(&a1)), nonce, cnonce, hex(&Md5::digest(a2)));
+        secret::wipe(&mut a1);
+        hex(&Md5::digest(kd))
+    }
+}
+
+impl Authenticator for DigestMd5 {
+    fn authenticate(&self, client: &mut Client) -> Result<()> {
+        client.labels.account = self.username.clone();
+        let host = self.host.clone().unwrap_or_else(|| client.labels.host.clone());
+        let uri = format!("pop/{}", host);
+        let cnonce = match &self.cnonce {
+            Some(cnonce) => cnonce.clone(),
+            None => hex(&random_bytes::<16>()?),
+        };
+        let mut expected = None;
+        client.auth("DIGEST-MD5", None, |challenge| {
+            let directives = directives(&String::from_utf8_lossy(challenge));
+            let get = |name: &str| directives.iter().find(|(key, _)| key == name).map(|(_, value)| value.as_str());
+
+            if let Some(expected) = expected.take() {
+                return match get("rspauth") {
+                    Some(proof) if proof == expected => Ok(Vec::new()),
+                    _ => Err(Error::Sasl("DIGEST-MD5: the server doesn't know the password".to_string())),
+                };
+            }
+            let nonce = get("nonce")
+                .ok_or_else(|| Error::InvalidReply("DIGEST-MD5 challenge without a nonce".to_string()))?;
+            if !get("qop").unwrap_or("auth").split(',').any(|qop| qop.trim() == "auth") {
+                return Err(Error::Sasl("DIGEST-MD5: the server requires a security layer".to_string()));
+            }
+            let realm = get("realm").unwrap_or_default();
+            expected = Some(self.response(realm, nonce, &cnonce, &uri, ""));
+            Ok(format!(
+                "charset=utf-8,username=\"{}\",realm=\"{}\",nonce=\"{}\",nc=00000001,cnonce=\"{}\",\
+                 digest-uri=\"{}\",response={},qop=auth",
+                quote(&self.username),
+                quote(realm),
+                quote(nonce),
+                cnonce,
+                quote(&uri),
+                self.response(realm, nonce, &cnonce, &uri, "AUTHENTICATE")
+            )
+            .into_bytes())
+        })
+    }
+}
+
+/// The `name=value` pairs of a DIGEST-MD5 challenge, with quoted values unquoted
+fn directives(text: &str) -> Vec<(String, String)> {
+    let mut pairs = Vec::new();
+    let mut rest = text.trim();
+    while let Some(eq) = rest.find('=') {
+        let name = rest[..eq].trim().trim_start_matches(',').trim().to_ascii_lowercase();
+        rest = &rest[eq + 1..];
+        let mut value = String::new();
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let mut chars = quoted.char_indices();
+            let mut end = quoted.len();
+            while let Some((i, c)) = chars.next() {
+                match c {
+                    '\\' => value.extend(chars.next().map(|(_, c)| c)),
+                    '"' => {
+                        end = i + 1;
+                        break;
+                    }
+                    c => value.push(c),
+                }
+            }
+            rest = &quoted[end..];
+        } else {
+            let end = rest.find(',').unwrap_or(rest.len());
+            value = rest[..end].trim().to_string();
+            rest = &rest[end..];
+        }
+        pairs.push((name, value));
+        rest = rest.trim_start().trim_start_matches(',');
+    }
+    pairs
+}
+
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
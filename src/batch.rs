@@ -0,0 +1,455 @@
+use std::fmt;
+
+use bytes::Bytes;
+
+use crate::parser;
+use crate::proto::Command;
+use crate::{quirks, Client, Error, MailboxStat, MessageInfo, Response, Result, TopResult, UidlEntry};
+
+/// The outcome of [`Client::dele_many()`] and [`Client::dele_all_or_nothing()`]
+///
+/// [`Client::dele_many()`]: struct.Client.html#method.dele_many
+/// [`Client::dele_all_or_nothing()`]: struct.Client.html#method.dele_all_or_nothing
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BatchReport {
+    /// Messages the server marked as deleted, in the order asked
+    pub deleted: Vec<u32>,
+    /// Messages the server refused to delete, with the text of its `-ERR` reply
+    pub failed: Vec<(u32, String)>,
+    /// Whether the deletions were undone with `RSET` because some failed
+    pub reset: bool,
+}
+
+impl BatchReport {
+    /// Whether every message of the batch is marked as deleted
+    pub fn is_complete(&self) -> bool {
+        self.failed.is_empty() && !self.reset
+    }
+}
+
+/// A command of a batch that failed, see [`BatchError`]
+///
+/// [`BatchError`]: struct.BatchError.html
+#[derive(Debug)]
+pub struct BatchFailure {
+    /// Position of the command in the batch
+    pub index: usize,
+    /// The command line; `PASS` and `APOP` without their arguments
+    pub command: String,
+    /// An [`Error::Server`] for a `-ERR` reply
+    ///
+    /// [`Error::Server`]: enum.Error.html#variant.Server
+    pub error: Error,
+}
+
+/// The commands of a batch that failed, while the others went through, see [`Client::pipeline()`]
+///
+/// # Example
+///
+/// ```no_run
+/// # use pop3_client::Client;
+/// # use pop3_client::proto::Command;
+/// # fn main() -> pop3_client::Result<()> {
+/// # let mut client = Client::connect("my.host.com", 110)?;
+/// if let Err(errors) = client.pipeline(&[Command::Dele(1), Command::Dele(2)]) {
+///     eprintln!("{}", errors);
+///     for failure in errors {
+///         eprintln!("{}: {}", failure.command, failure.error);
+///     }
+/// }
+/// #    Ok(())
+/// # }
+/// ```
+///
+/// [`Client::pipeline()`]: struct.Client.html#method.pipeline
+#[derive(Debug)]
+pub struct BatchError {
+    total: usize,
+    failures: Vec<BatchFailure>,
+}
+
+impl BatchError {
+    /// The failed commands, in the order of the batch
+    pub fn failures(&self) -> &[BatchFailure] {
+        &self.failures
+    }
+
+    /// Number of commands in the batch, failed or not
+    pub fn total(&self) -> usize {
+        self.total
+    }
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} of {} commands failed", self.failures.len(), self.total)?;
+        for (i, failure) in self.failures.iter().enumerate() {
+            let separator = if i == 0 { ": " } else { "; " };
+            write!(f, "{}{} ({})", separator, failure.command, failure.error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BatchError {}
+
+impl IntoIterator for BatchError {
+    type Item = BatchFailure;
+    type IntoIter = std::vec::IntoIter<BatchFailure>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.failures.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a BatchError {
+    type Item = &'a BatchFailure;
+    type IntoIter = std::slice::Iter<'a, BatchFailure>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.failures.iter()
+    }
+}
+
+/// An operation of a [`Batch`], the way it was queued
+///
+/// [`Batch`]: struct.Batch.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operation {
+    Stat,
+    List(Option<u32>),
+    Uidl(Option<u32>),
+    Retr(u32),
+    Top(u32, u32),
+    Dele(u32),
+    Noop,
+}
+
+impl Operation {
+    fn command(self) -> Command<'static> {
+        match self {
+            Operation::Stat        => Command::Stat,
+            Operation::List(msg)   => Command::List(msg),
+            Operation::Uidl(msg)   => Command::Uidl(msg),
+            Operation::Retr(msg)   => Command::Retr(msg),
+            Operation::Top(msg, n) => Command::Top(msg, n),
+            Operation::Dele(msg)   => Command::Dele(msg),
+            Operation::Noop        => Command::Noop,
+        }
+    }
+}
+
+/// The result of an operation of a [`Batch`], in the shape of the matching method of [`Client`]
+///
+/// [`Batch`]: struct.Batch.html
+/// [`Client`]: struct.Client.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Stat(MailboxStat),
+    /// One entry for a single message, see [`Client::list_entries()`]
+    ///
+    /// [`Client::list_entries()`]: struct.Client.html#method.list_entries
+    List(Vec<MessageInfo>),
+    Uidl(Vec<UidlEntry>),
+    /// The message as [`Client::retr_bytes()`] returns it
+    ///
+    /// [`Client::retr_bytes()`]: struct.Client.html#method.retr_bytes
+    Retr(Bytes),
+    Top(TopResult),
+    Dele,
+    Noop,
+}
+
+/// Commands queued to run together, created by [`Client::batch()`]
+///
+/// Nothing is sent before [`execute()`], which pipelines the commands when the server allows it.
+///
+/// [`Client::batch()`]: struct.Client.html#method.batch
+/// [`execute()`]: #method.execute
+pub struct Batch<'c> {
+    client: &'c mut Client,
+    operations: Vec<Operation>,
+}
+
+impl Batch<'_> {
+    pub fn stat(mut self) -> Self {
+        self.operations.push(Operation::Stat);
+        self
+    }
+
+    pub fn list(mut self, msg: Option<u32>) -> Self {
+        self.operations.push(Operation::List(msg));
+        self
+    }
+
+    pub fn uidl(mut self, msg: Option<u32>) -> Self {
+        self.operations.push(Operation::Uidl(msg));
+        self
+    }
+
+    pub fn retr(mut self, msg: u32) -> Self {
+        self.operations.push(Operation::Retr(msg));
+        self
+    }
+
+    pub fn top(mut self, msg: u32, n: u32) -> Self {
+        self.operations.push(Operation::Top(msg, n));
+        self
+    }
+
+    pub fn dele(mut self, msg: u32) -> Self {
+        self.operations.push(Operation::Dele(msg));
+        self
+    }
+
+    pub fn noop(mut self) -> Self {
+        self.operations.push(Operation::Noop);
+        self
+    }
+
+    /// Send the queued commands, and parse their replies
+    ///
+    /// # Errors
+    /// A [`BatchError`] listing the operations which failed, whether refused by the server or with a reply that
+    /// couldn't be parsed. Like with [`Client::pipeline()`], the other operations still went through.
+    ///
+    /// [`BatchError`]: struct.BatchError.html
+    /// [`Client::pipeline()`]: struct.Client.html#method.pipeline
+    pub fn execute(self) -> std::result::Result<Vec<Outcome>, BatchError> {
+        let Batch { client, operations } = self;
+        let commands: Vec<_> = operations.iter().map(|operation| operation.command()).collect();
+        let results = client.run_batch(&commands);
+
+        let mut outcomes = vec![];
+        let mut failures = vec![];
+        for (index, (operation, result)) in operations.iter().zip(results).enumerate() {
+            match result.and_then(|response| client.outcome(*operation, &response)) {
+                Ok(outcome) => outcomes.push(outcome),
+                Err(error) => failures.push(BatchFailure { index, command: commands[index].redacted(), error }),
+            }
+        }
+        if failures.is_empty() {
+            Ok(outcomes)
+        } else {
+            Err(BatchError { total: operations.len(), failures })
+        }
+    }
+}
+
+impl Client {
+    /// Mark several messages as deleted, and tell which ones the server refused
+    ///
+    /// The `DELE` commands are pipelined when an earlier [`capa()`] found the `PIPELINING` capability, and sent
+    /// one by one otherwise. A refusal doesn't stop the batch.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let report = client.dele_many(&[1, 2, 3])?;
+    /// for (msg, reason) in &report.failed {
+    ///     eprintln!("message {} is kept: {}", msg, reason);
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Anything but an `-ERR` reply to a `DELE` ends the batch, e.g. a broken connection. Some of the messages
+    /// may be marked as deleted by then.
+    ///
+    /// [`capa()`]: struct.Client.html#method.capa
+    pub fn dele_many(&mut self, msgs: &[u32]) -> Result<BatchReport> {
+        if !self.is_authorized() {
+            return Err(Error::WrongStage("DELE is only allowed in Transaction stage".to_string()));
+        }
+        let commands: Vec<_> = msgs.iter().map(|msg| Command::Dele(*msg)).collect();
+        let mut report = BatchReport::default();
+        for (msg, result) in msgs.iter().zip(self.run_batch(&commands)) {
+            match result {
+                Ok(_) => report.deleted.push(*msg),
+                Err(Error::Server(reason)) => report.failed.push((*msg, reason)),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(report)
+    }
+
+    /// Mark several messages as deleted, or none of them: if the server refuses any, `RSET` undoes the batch
+    ///
+    /// Mind that `RSET` also unmarks the messages deleted before the batch.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// if client.dele_all_or_nothing(&[1, 2, 3])?.is_complete() {
+    ///     client.quit()?;
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`dele_many()`], plus the errors of [`rset()`].
+    ///
+    /// [`dele_many()`]: struct.Client.html#method.dele_many
+    /// [`rset()`]: struct.Client.html#method.rset
+    pub fn dele_all_or_nothing(&mut self, msgs: &[u32]) -> Result<BatchReport> {
+        let mut report = self.dele_many(msgs)?;
+        if !report.failed.is_empty() {
+            self.rset()?;
+            report.reset = true;
+        }
+        Ok(report)
+    }
+
+    /// Send a batch of commands, and collect the replies of all of them, failed or not
+    ///
+    /// The commands are pipelined when an earlier [`capa()`] found the `PIPELINING` capability, and sent one by one
+    /// otherwise. A `-ERR` reply doesn't stop the batch; once the connection breaks, the remaining commands fail
+    /// with [`Error::ConnectionAborted`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # use pop3_client::proto::Command;
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let responses = client.pipeline(&[Command::Stat, Command::Uidl(None)])?;
+    /// println!("{}", responses[0].info);
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// A [`BatchError`] listing every command which failed, if any did.
+    ///
+    /// [`capa()`]: struct.Client.html#method.capa
+    /// [`Error::ConnectionAborted`]: enum.Error.html#variant.ConnectionAborted
+    /// [`BatchError`]: struct.BatchError.html
+    pub fn pipeline(&mut self, commands: &[Command]) -> std::result::Result<Vec<Response>, BatchError> {
+        let mut responses = vec![];
+        let mut failures = vec![];
+        for (index, result) in self.run_batch(commands).into_iter().enumerate() {
+            match result {
+                Ok(response) => responses.push(response),
+                Err(error) => failures.push(BatchFailure { index, command: commands[index].redacted(), error }),
+            }
+        }
+        if failures.is_empty() {
+            Ok(responses)
+        } else {
+            Err(BatchError { total: commands.len(), failures })
+        }
+    }
+
+    /// The reply to each command, with `-ERR` replies as [`Error::Server`]
+    ///
+    /// [`Error::Server`]: enum.Error.html#variant.Server
+    fn run_batch(&mut self, commands: &[Command]) -> Vec<Result<Response>> {
+        let reply = |response: Response| {
+            if response.is_ok() { Ok(response) } else { Err(Error::Server(response.info)) }
+        };
+        let mut results = Vec::with_capacity(commands.len());
+        let mut broken = false;
+
+        if let Some(earlier) = self.in_flight.earliest() {
+            let earlier = earlier.to_string();
+            return commands.iter().map(|_| Err(Error::CommandInFlight(earlier.clone()))).collect();
+        }
+        if self.pipelining {
+            let queued: Vec<_> = commands
+                .iter()
+                .map(|command| {
+                    let line = command.line();
+                    self.check_capability(&line).and_then(|_| self.queue_command(&line))
+                })
+                .collect();
+            // A failed flush counts against the first command, and the others never got out
+            if let Err(e) = self.flush() {
+                results.push(Err(e));
+                broken = true;
+            }
+            for (command, queued) in commands.iter().zip(queued).skip(results.len()) {
+                if broken {
+                    results.push(Err(Error::ConnectionAborted));
+                    continue;
+                }
+                if let Err(e) = queued {
+                    results.push(Err(e));
+                    continue;
+                }
+                let result = self.next_response(command.is_multiline());
+                broken = result.is_err();
+                results.push(result.and_then(reply));
+            }
+            if broken {
+                self.in_flight.lost();
+            }
+        } else {
+            for command in commands {
+                if broken {
+                    results.push(Err(Error::ConnectionAborted));
+                    continue;
+                }
+                let line = command.line();
+                let result = self.check_capability(&line).and_then(|_| self.command(&line, command.is_multiline()));
+                broken = matches!(result, Err(ref e) if !matches!(e, Error::InvalidArgument(_) | Error::NotSupportedByServer(_)));
+                results.push(result.and_then(reply));
+            }
+        }
+
+        if results.iter().any(Result::is_ok) {
+            self.invalidate_cache();
+        }
+        results
+    }
+
+    /// Queue commands to send together, see [`Batch`]
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::{Client, Outcome};
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let outcomes = client.batch().dele(1).dele(2).retr(5).execute()?;
+    /// if let Outcome::Retr(message) = &outcomes[2] {
+    ///     std::fs::write("message.eml", message)?;
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Batch`]: struct.Batch.html
+    pub fn batch(&mut self) -> Batch<'_> {
+        Batch { client: self, operations: vec![] }
+    }
+
+    /// Parse the successful reply to `operation`
+    fn outcome(&self, operation: Operation, response: &Response) -> Result<Outcome> {
+        let scan_listing = if self.quirks().listing_junk { quirks::scan_listing_with_junk } else { parser::scan_listing };
+        let payload = response.payload();
+
+        Ok(match operation {
+            Operation::Stat => Outcome::Stat(MailboxStat::from(parser::stat(response.info.as_bytes())?)),
+            Operation::List(msg)  => Outcome::List(listing(response, msg, scan_listing)?),
+            Operation::Uidl(msg)  => Outcome::Uidl(listing(response, msg, parser::uidl_listing)?),
+            Operation::Retr(_)    => Outcome::Retr(self.line_ending.message(payload)),
+            Operation::Top(..)    => Outcome::Top(parser::top(payload)),
+            Operation::Dele(_)    => Outcome::Dele,
+            Operation::Noop       => Outcome::Noop,
+        })
+    }
+}
+
+/// The entries of a `LIST` or `UIDL` reply, on its status line for a single message
+fn listing<T>(response: &Response, msg: Option<u32>, parse: fn(&[u8]) -> Result<T>) -> Result<Vec<T>> {
+    match msg {
+        Some(_) => parse(response.info.as_bytes()).map(|entry| vec![entry]),
+        None    => response.lines.iter().map(|line| parse(line.as_bytes())).collect(),
+    }
+}
@@ -0,0 +1,168 @@
+//! Command line access to a POP3 mailbox
+//!
+//! Connection settings come from flags, falling back to the `POP3_HOST`, `POP3_PORT`, `POP3_USER`,
+//! `POP3_PASSWORD` and `POP3_TLS` environment variables.
+
+use std::env;
+use std::io::{self, Write};
+use std::process;
+
+use pop3_client::fetcher::{AccountConfig, Credentials};
+use pop3_client::store::maildir::ExportOptions;
+use pop3_client::{Error, Result};
+
+const USAGE: &str = "\
+usage: pop3-cli [options] <command>
+
+options:
+    --host HOST          server name              [$POP3_HOST]
+    --port PORT          server port, 110         [$POP3_PORT]
+    --user USER          user name                [$POP3_USER]
+    --password PASSWORD  password                 [$POP3_PASSWORD]
+    --tls, --no-tls      upgrade with STLS or not [$POP3_TLS]
+    --plaintext          allow the password without TLS
+
+commands:
+    stat
+    list [MSG]
+    uidl [MSG]
+    retr MSG
+    top MSG LINES
+    dele MSG
+    fetch --maildir DIR";
+
+enum Command {
+    Stat,
+    List(Option<u32>),
+    Uidl(Option<u32>),
+    Retr(u32),
+    Top(u32, u32),
+    Dele(u32),
+    Fetch(String),
+}
+
+struct Options {
+    account: AccountConfig,
+    command: Command,
+}
+
+fn main() {
+    let options = match parse(env::args().skip(1).collect()) {
+        Ok(options) => options,
+        Err(message) => {
+            eprintln!("pop3-cli: {}\n\n{}", message, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(options) {
+        eprintln!("pop3-cli: {}", e);
+        process::exit(1);
+    }
+}
+
+fn parse(args: Vec<String>) -> std::result::Result<Options, String> {
+    let var = |name: &str| env::var(name).ok();
+
+    let mut host = var("POP3_HOST");
+    let mut port = var("POP3_PORT");
+    let mut user = var("POP3_USER");
+    let mut password = var("POP3_PASSWORD");
+    let mut tls = var("POP3_TLS").is_some_and(|v| v == "1" || v.eq_ignore_ascii_case("true"));
+    let mut plaintext = false;
+    let mut maildir = None;
+    let mut positional = vec![];
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--host"     => host = Some(value()?),
+            "--port"     => port = Some(value()?),
+            "--user"     => user = Some(value()?),
+            "--password" => password = Some(value()?),
+            "--maildir"  => maildir = Some(value()?),
+            "--tls"      => tls = true,
+            "--no-tls"   => tls = false,
+            "--plaintext" => plaintext = true,
+            "-h" | "--help" => return Err("help requested".to_string()),
+            flag if flag.starts_with("--") => return Err(format!("unknown option {}", flag)),
+            _ => positional.push(arg),
+        }
+    }
+
+    let number = |arg: Option<&String>, name: &str| -> std::result::Result<u32, String> {
+        arg.ok_or_else(|| format!("missing {}", name))?
+            .parse()
+            .map_err(|_| format!("{} must be a number", name))
+    };
+    let optional = |arg: Option<&String>| arg.map(|_| number(arg, "MSG")).transpose();
+
+    let command = match positional.first().map(String::as_str) {
+        Some("stat") => Command::Stat,
+        Some("list") => Command::List(optional(positional.get(1))?),
+        Some("uidl") => Command::Uidl(optional(positional.get(1))?),
+        Some("retr") => Command::Retr(number(positional.get(1), "MSG")?),
+        Some("top")  => Command::Top(number(positional.get(1), "MSG")?, number(positional.get(2), "LINES")?),
+        Some("dele") => Command::Dele(number(positional.get(1), "MSG")?),
+        Some("fetch") => Command::Fetch(maildir.ok_or("fetch needs --maildir DIR")?),
+        Some(other)  => return Err(format!("unknown command {}", other)),
+        None         => return Err("missing command".to_string()),
+    };
+
+    let port = match port {
+        Some(port) => port.parse().map_err(|_| "PORT must be a number".to_string())?,
+        None       => 110,
+    };
+
+    Ok(Options {
+        account: AccountConfig {
+            host: host.ok_or("missing --host")?,
+            port,
+            tls,
+            allow_plaintext_auth: plaintext,
+            credentials: Credentials::new(&user.ok_or("missing --user")?, &password.ok_or("missing --password")?),
+            ..AccountConfig::default()
+        },
+        command,
+    })
+}
+
+fn run(options: Options) -> Result<()> {
+    let mut client = options.account.open()?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    match options.command {
+        Command::Stat => {
+            let stat = client.mailbox_stat()?;
+            writeln!(out, "{} {}", stat.count, stat.size)?;
+        }
+        Command::List(msg) => {
+            for info in client.list_entries(msg)? {
+                writeln!(out, "{} {}", info.id, info.size)?;
+            }
+        }
+        Command::Uidl(msg) => {
+            for entry in client.uidl_entries(msg)? {
+                writeln!(out, "{} {}", entry.id, entry.uid)?;
+            }
+        }
+        Command::Retr(msg) => {
+            client.retr_to_writer(msg, &mut out)?;
+        }
+        Command::Top(msg, lines) => out.write_all(client.top_text(msg, lines)?.as_bytes())?,
+        Command::Dele(msg) => {
+            client.dele(msg)?;
+        }
+        Command::Fetch(dir) => {
+            for (uid, path) in client.export_to_maildir(&dir, &ExportOptions::default())? {
+                writeln!(out, "{} {}", uid, path.display())?;
+            }
+        }
+    }
+
+    // QUIT makes the deletions permanent
+    out.flush().map_err(Error::Io)?;
+    client.quit()
+}
@@ -0,0 +1,54 @@
+use std::collections::HashMap;
+
+use crate::UidlEntry;
+
+/// Last known mailbox metadata, kept until the mailbox state changes
+#[derive(Default)]
+pub(crate) struct Cache {
+    pub stat: Option<(u32, u64)>,
+    pub list: HashMap<Option<u32>, String>,
+    pub uidl: HashMap<Option<u32>, String>,
+    /// The highest message number of the session, from a `STAT` seen before anything was deleted
+    pub highest: Option<u32>,
+    changed: bool,
+}
+
+impl Cache {
+    pub fn clear(&mut self) {
+        self.stat = None;
+        self.list.clear();
+        self.uidl.clear();
+        self.changed = true;
+    }
+
+    /// Take note of a `STAT` count; once messages have been deleted, it no longer tells the highest number
+    pub fn saw_count(&mut self, count: u32) {
+        if !self.changed {
+            self.highest = Some(count);
+        }
+    }
+}
+
+/// Message numbers and unique IDs of the session, both ways
+///
+/// Numbers don't change during a session, deletions included, so the map is good until it ends.
+pub(crate) struct UidMap {
+    numbers: HashMap<String, u32>,
+    uids: HashMap<u32, String>,
+}
+
+impl UidMap {
+    pub fn new(entries: Vec<UidlEntry>) -> Self {
+        let numbers = entries.iter().map(|entry| (entry.uid.clone(), entry.id)).collect();
+        let uids = entries.into_iter().map(|entry| (entry.id, entry.uid)).collect();
+        Self { numbers, uids }
+    }
+
+    pub fn number(&self, uid: &str) -> Option<u32> {
+        self.numbers.get(uid).copied()
+    }
+
+    pub fn uid(&self, msg: u32) -> Option<&str> {
+        self.uids.get(&msg).map(String::as_str)
+    }
+}
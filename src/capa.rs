@@ -0,0 +1,208 @@
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::{parser, stage, Builder, Client, Error, Result};
+
+/// The capabilities announced by `CAPA`, see [RFC 2449]
+///
+/// Names are kept in upper case, each with the arguments that followed it on its line.
+///
+/// [RFC 2449]: https://tools.ietf.org/html/rfc2449#section-5
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize), serde(transparent))]
+pub struct Capabilities {
+    entries: BTreeMap<String, Vec<String>>,
+}
+
+/// How long the server keeps messages that were downloaded, from the `EXPIRE` capability of [RFC 2449]
+///
+/// [RFC 2449]: https://tools.ietf.org/html/rfc2449#section-6.7
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RetentionPolicy {
+    /// Days a message is kept after it was first downloaded; `Some(0)` means leaving messages on the server
+    /// doesn't work at all
+    pub days: Option<u32>,
+    /// The policy may differ between users, so it's only final once logged in
+    pub user_specific: bool,
+    /// Messages are never deleted by the server
+    pub never: bool,
+}
+
+impl Capabilities {
+    /// Whether the server announced the capability, e.g. `"UIDL"`; case insensitive
+    pub fn contains(&self, name: &str) -> bool {
+        self.entries.contains_key(&name.to_ascii_uppercase())
+    }
+
+    /// Arguments of the capability, e.g. the mechanisms of `"SASL"`
+    pub fn arguments(&self, name: &str) -> Option<&[String]> {
+        self.entries.get(&name.to_ascii_uppercase()).map(Vec::as_slice)
+    }
+
+    /// The least time the server wants between two logins, from `LOGIN-DELAY`
+    pub fn login_delay(&self) -> Option<Duration> {
+        let seconds = self.arguments("LOGIN-DELAY")?.first()?.parse().ok()?;
+        Some(Duration::from_secs(seconds))
+    }
+
+    /// The retention policy from `EXPIRE`, if the server announced one it could make sense of
+    pub fn retention(&self) -> Option<RetentionPolicy> {
+        let arguments = self.arguments("EXPIRE")?;
+        let schedule = arguments.first()?;
+        let never = schedule.eq_ignore_ascii_case("NEVER");
+        let days = if never { None } else { Some(schedule.parse().ok()?) };
+        Some(RetentionPolicy {
+            days,
+            user_specific: arguments.get(1).is_some_and(|arg| arg.eq_ignore_ascii_case("USER")),
+            never,
+        })
+    }
+
+    /// All the capabilities with their arguments, ordered by name
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[String])> {
+        self.entries.iter().map(|(name, args)| (name.as_str(), args.as_slice()))
+    }
+}
+
+impl From<BTreeMap<String, Vec<String>>> for Capabilities {
+    fn from(entries: BTreeMap<String, Vec<String>>) -> Self {
+        Self { entries }
+    }
+}
+
+impl Client {
+    /// Ask the server which optional features it supports (the `CAPA` command)
+    ///
+    /// The client remembers a `PIPELINING` capability, which [`dele_many()`] makes use of, and the retention
+    /// policy, see [`retention_policy()`]. The whole reply is kept until the session logs in, see
+    /// [`capabilities()`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let capabilities = client.capa()?;
+    /// if capabilities.contains("TOP") {
+    ///     client.top(1, 0)?;
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// The server will return an error response if it predates [RFC 2449] and does not know `CAPA`.
+    ///
+    /// [RFC 2449]: https://tools.ietf.org/html/rfc2449
+    /// [`dele_many()`]: struct.Client.html#method.dele_many
+    /// [`retention_policy()`]: struct.Client.html#method.retention_policy
+    /// [`capabilities()`]: struct.Client.html#method.capabilities
+    pub fn capa(&mut self) -> Result<Capabilities> {
+        let reply = self.query_frame("CAPA", true)?;
+        let capabilities = parser::capabilities(&reply.payload());
+        self.fingerprint_capabilities(&capabilities);
+        self.login_delay = capabilities.login_delay().or(self.login_delay);
+        self.pipelining = capabilities.contains("PIPELINING");
+        self.retention = capabilities.retention().or(self.retention);
+        self.capabilities = Some(capabilities.clone());
+        Ok(capabilities)
+    }
+
+    /// The reply to the last [`capa()`] of the current stage, if any
+    ///
+    /// Servers may announce more once logged in, so a login forgets the capabilities announced before.
+    ///
+    /// [`capa()`]: struct.Client.html#method.capa
+    pub fn capabilities(&self) -> Option<&Capabilities> {
+        self.capabilities.as_ref()
+    }
+
+    /// How long the server keeps downloaded messages, as found by the last [`capa()`] announcing `EXPIRE`
+    ///
+    /// Worth a warning to users who leave their messages on the server: they may disappear anyway.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// client.capa()?;
+    /// if let Some(days) = client.retention_policy().and_then(|policy| policy.days) {
+    ///     eprintln!("the server deletes messages {} days after they are downloaded", days);
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`capa()`]: struct.Client.html#method.capa
+    pub fn retention_policy(&self) -> Option<RetentionPolicy> {
+        self.retention
+    }
+
+    /// Fail with `Error::NotSupportedByServer` when the checks are on and the capabilities of the stage are known
+    /// but lack the one `command` needs
+    pub(crate) fn check_capability(&self, command: &str) -> Result<()> {
+        let capabilities = match &self.capabilities {
+            Some(capabilities) if self.capability_checks => capabilities,
+            _ => return Ok(()),
+        };
+        let keyword = stage::keyword(command);
+        let (announced, capability) = match keyword.as_str() {
+            "TOP" | "UIDL" | "USER" | "STLS" | "UTF8" | "LANG" => (capabilities.contains(&keyword), keyword),
+            "AUTH" => match command.split(' ').nth(1) {
+                Some(mechanism) => (
+                    capabilities
+                        .arguments("SASL")
+                        .is_some_and(|mechanisms| mechanisms.iter().any(|m| m.eq_ignore_ascii_case(mechanism))),
+                    format!("SASL {}", mechanism.to_ascii_uppercase()),
+                ),
+                None => (capabilities.contains("SASL"), "SASL".to_string()),
+            },
+            _ => return Ok(()),
+        };
+        if !announced {
+            return Err(Error::NotSupportedByServer(capability));
+        }
+        Ok(())
+    }
+}
+
+impl Builder {
+    /// Refuse commands for optional features the server didn't announce, rather than sending them
+    ///
+    /// Once [`Client::capa()`] has been called in the current stage, `TOP`, `UIDL`, `USER`, `STLS`, `UTF8`,
+    /// `LANG` and `AUTH` with an unannounced SASL mechanism fail right away with
+    /// [`Error::NotSupportedByServer`], saving a round trip. Without a `CAPA` reply to go by, everything is sent
+    /// as usual; so are the commands of [`Client::command()`]. Off by default.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::{Builder, Error};
+    /// #
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default()
+    ///     .capability_checks(true)
+    ///     .credentials("sweet_username", "very_secret_password")
+    ///     .open("my.host.com", 995)?;
+    /// client.capa()?;
+    /// match client.top(1, 0) {
+    ///     Err(Error::NotSupportedByServer(_)) => println!("no TOP, downloading the whole message"),
+    ///     result => println!("{}", result?.to_text()),
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Client::capa()`]: struct.Client.html#method.capa
+    /// [`Error::NotSupportedByServer`]: enum.Error.html#variant.NotSupportedByServer
+    /// [`Client::command()`]: struct.Client.html#method.command
+    pub fn capability_checks(&mut self, enabled: bool) -> &mut Self {
+        self.capability_checks = enabled;
+        self
+    }
+}
@@ -0,0 +1,31 @@
+use crate::{Error, Result};
+
+/// Longest command line allowed, including the terminating CRLF ([RFC 2449], section 4)
+///
+/// [RFC 2449]: https://tools.ietf.org/html/rfc2449#section-4
+pub const MAX_COMMAND_LEN: usize = 255;
+
+/// Check the command line and terminate it with CRLF
+///
+/// Every command goes through here, so that arguments coming from the user can't smuggle in line
+/// breaks and with them additional commands.
+pub(crate) fn encode(command: &str) -> Result<Vec<u8>> {
+    if let Some(c) = command.chars().find(|c| matches!(c, '\r' | '\n' | '\0')) {
+        return Err(Error::InvalidArgument(format!(
+            "command contains a forbidden character {:?}",
+            c
+        )));
+    }
+    if command.len() + 2 > MAX_COMMAND_LEN {
+        return Err(Error::InvalidArgument(format!(
+            "command is {} octets long, the limit is {}",
+            command.len() + 2,
+            MAX_COMMAND_LEN
+        )));
+    }
+
+    let mut line = Vec::with_capacity(command.len() + 2);
+    line.extend_from_slice(command.as_bytes());
+    line.extend_from_slice(b"\r\n");
+    Ok(line)
+}
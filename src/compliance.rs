@@ -0,0 +1,81 @@
+use crate::{Error, Result};
+
+/// How forgiving the client is towards replies that bend the protocol
+///
+/// Many servers in the wild don't follow [RFC 1939] to the letter, so by default the client tolerates whatever it can make sense of.
+/// The strict mode is meant for testing server implementations: it turns every deviation into an [`Error::InvalidReply`].
+///
+/// [RFC 1939]: https://tools.ietf.org/html/rfc1939
+/// [`Error::InvalidReply`]: enum.Error.html#variant.InvalidReply
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Compliance {
+    /// Reject malformed status lines, lower case `+ok` and `-err` included, lines not terminated by CRLF and
+    /// scan listings with extra fields, and fail with [`Error::LineTooLong`] on status lines longer than the
+    /// 512 octets of [RFC 2449]
+    ///
+    /// Non-ASCII usernames and passwords are refused too, with [`Error::Encoding`], unless the session was switched
+    /// to UTF-8 first as [RFC 6856] requires.
+    ///
+    /// [`Error::LineTooLong`]: enum.Error.html#variant.LineTooLong
+    /// [RFC 2449]: https://tools.ietf.org/html/rfc2449#section-4
+    /// [`Error::Encoding`]: enum.Error.html#variant.Encoding
+    /// [RFC 6856]: https://tools.ietf.org/html/rfc6856#section-2
+    Strict,
+    /// Accept anything that can be understood
+    #[default]
+    Lenient,
+}
+
+impl Compliance {
+    /// Every line has to end with CRLF
+    pub(crate) fn check_line(self, line: &[u8]) -> Result<()> {
+        if self == Compliance::Strict && !line.ends_with(b"\r\n") {
+            return Err(Error::InvalidReply(format!(
+                "line not terminated by CRLF: {:?}",
+                String::from_utf8_lossy(line)
+            )));
+        }
+        Ok(())
+    }
+
+    /// The status line is `+OK` or `-ERR`, followed either by a space and some text, or by the line end
+    pub(crate) fn check_status(self, line: &[u8]) -> Result<()> {
+        if self == Compliance::Lenient {
+            return Ok(());
+        }
+        let rest = line
+            .strip_prefix(b"+OK")
+            .or_else(|| line.strip_prefix(b"-ERR"));
+        match rest {
+            Some(rest) if rest.starts_with(b" ") || rest == b"\r\n" => Ok(()),
+            _ => Err(Error::InvalidReply(format!(
+                "malformed status line: {:?}",
+                String::from_utf8_lossy(line)
+            ))),
+        }
+    }
+
+    /// A scan listing is exactly a message number and a size, separated by a single space
+    pub(crate) fn check_scan_listing(self, line: &str) -> Result<()> {
+        if self == Compliance::Lenient {
+            return Ok(());
+        }
+        let mut fields = line.trim_end_matches("\r\n").split(' ');
+        let numeric = |field: Option<&str>| {
+            field.is_some_and(|f| !f.is_empty() && f.bytes().all(|c| c.is_ascii_digit()))
+        };
+        if numeric(fields.next()) && numeric(fields.next()) && fields.next().is_none() {
+            Ok(())
+        } else {
+            Err(Error::InvalidReply(format!("out-of-spec scan listing: {:?}", line)))
+        }
+    }
+
+    /// Credentials are ASCII outside of UTF-8 mode
+    pub(crate) fn check_credentials(self, utf8: bool, username: &str, password: &str) -> Result<()> {
+        if self == Compliance::Strict && !utf8 && (!username.is_ascii() || !password.is_ascii()) {
+            return Err(Error::Encoding("non-ASCII credentials require UTF8 mode".to_string()));
+        }
+        Ok(())
+    }
+}
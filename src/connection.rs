@@ -0,0 +1,157 @@
+use std::io::{BufReader, Read, Write};
+
+use bytes::BytesMut;
+
+use crate::progress::Tracker;
+use crate::response::{Frame, Limits};
+use crate::stream::{SocketOptions, Stream, Throttled, Watched, WriteBuffered};
+use crate::{command, secret, telemetry, Builder, Client, Compliance, Error, Quirks, Response, Result};
+
+/// The connection under a [`Client`]: commands out, replies in, and nothing else
+///
+/// No stage is tracked, no reply is checked beyond its framing and `-ERR` isn't an error, so a proxy or a
+/// protocol test can say anything to the server and see exactly what it answers. Not even the greeting is read:
+/// that's the first [`read_status()`]. Hand the connection to [`Client::from_connection()`] or
+/// [`Builder::from_connection()`] for the checks.
+///
+/// # Example
+/// ```no_run
+/// use pop3_client::Connection;
+/// # fn main() -> pop3_client::Result<()> {
+/// let mut connection = Connection::connect("my.host.com", 110)?;
+/// println!("greeting: {}", connection.read_status()?.info);
+/// connection.send_command("CAPA")?;
+/// for line in connection.read_multiline()?.lines {
+///     println!("{}", line);
+/// }
+/// #    Ok(())
+/// # }
+/// ```
+///
+/// [`Client`]: struct.Client.html
+/// [`read_status()`]: struct.Connection.html#method.read_status
+/// [`Client::from_connection()`]: struct.Client.html#method.from_connection
+/// [`Builder::from_connection()`]: struct.Builder.html#method.from_connection
+pub struct Connection {
+    pub(crate) io: BufReader<WriteBuffered<Throttled<Watched>>>,
+    /// Holds the reply being read
+    pub(crate) buffer: BytesMut,
+}
+
+impl Connection {
+    pub(crate) fn new(stream: Stream) -> Self {
+        Self {
+            io: BufReader::new(WriteBuffered::new(Throttled::new(Watched::new(stream)))),
+            buffer: BytesMut::new(),
+        }
+    }
+
+    /// Open a plain TCP connection
+    pub fn connect(host: &str, port: u16) -> Result<Self> {
+        SocketOptions::default()
+            .connect(host, port, None)
+            .map(|socket| Self::new(Stream::Plain(socket)))
+            .map_err(Error::Io)
+    }
+
+    /// Run over an already established connection, e.g. a tunnel or an in-memory stream in tests
+    pub fn from_stream<S: Read + Write + Send + 'static>(stream: S) -> Self {
+        Self::new(Stream::Other(Box::new(stream)))
+    }
+
+    /// Send a command line, adding the CRLF
+    ///
+    /// # Errors
+    /// Fails with [`Error::InvalidArgument`] when the command holds a line break or is longer than
+    /// [`MAX_COMMAND_LEN`], as it would be taken for several commands.
+    ///
+    /// [`Error::InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    /// [`MAX_COMMAND_LEN`]: constant.MAX_COMMAND_LEN.html
+    pub fn send_command(&mut self, command: &str) -> Result<()> {
+        self.queue_line(command::encode(command)?)?;
+        self.io.get_mut().flush().map_err(Error::Io)
+    }
+
+    /// Read a single line reply, the greeting included
+    pub fn read_status(&mut self) -> Result<Response> {
+        self.read_frame(false, Compliance::default(), Quirks::default(), &Limits::default(), None)
+            .map(Response::from)
+    }
+
+    /// Read a reply with a payload, which is only there when the reply is `+OK`
+    pub fn read_multiline(&mut self) -> Result<Response> {
+        self.read_frame(true, Compliance::default(), Quirks::default(), &Limits::default(), None)
+            .map(Response::from)
+    }
+
+    /// Write a line without sending it yet, then wipe it as it may hold a secret
+    pub(crate) fn queue_line(&mut self, mut line: Vec<u8>) -> Result<()> {
+        let result = self.io.get_mut().write_all(&line).map_err(Error::Io);
+        secret::wipe(&mut line);
+        result
+    }
+
+    pub(crate) fn read_frame(
+        &mut self,
+        multiline: bool,
+        compliance: Compliance,
+        quirks: Quirks,
+        limits: &Limits,
+        tracker: Option<&mut Tracker>,
+    ) -> Result<Frame> {
+        Frame::read(&mut self.io, &mut self.buffer, multiline, compliance, quirks, limits, tracker)
+    }
+
+    pub(crate) fn watched(&mut self) -> &mut Watched {
+        self.io.get_mut().get_mut().get_mut()
+    }
+}
+
+impl Client {
+    /// Start a session over a [`Connection`], reading the greeting unless it was read already
+    ///
+    /// # Example
+    /// ```no_run
+    /// use pop3_client::{Client, Connection};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut connection = Connection::connect("my.host.com", 110)?;
+    /// let greeting = connection.read_status()?;
+    /// let mut client = Client::from_connection(connection, Some(greeting))?;
+    /// client.login("sweet_username", "very_secret_password")?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Fails with [`Error::Server`] when the greeting is `-ERR`.
+    ///
+    /// [`Connection`]: struct.Connection.html
+    /// [`Error::Server`]: enum.Error.html#variant.Server
+    pub fn from_connection(connection: Connection, greeting: Option<Response>) -> Result<Self> {
+        let mut client = Self::new(connection);
+        match greeting {
+            Some(greeting) if greeting.is_ok() => client.greeted(greeting.info),
+            Some(greeting) => return Err(Error::Server(greeting.info)),
+            None => client.read_greeting()?,
+        }
+        Ok(client)
+    }
+}
+
+impl Builder {
+    /// Start a session over a [`Connection`], with the settings of this builder
+    ///
+    /// Same as [`Client::from_connection()`]; like [`from_stream()`], no TLS is negotiated.
+    ///
+    /// [`Connection`]: struct.Connection.html
+    /// [`Client::from_connection()`]: struct.Client.html#method.from_connection
+    /// [`from_stream()`]: struct.Builder.html#method.from_stream
+    pub fn from_connection(&mut self, connection: Connection, greeting: Option<Response>) -> Result<Client> {
+        let session = telemetry::Span::session("");
+        session
+            .in_scope(|| Client::from_connection(connection, greeting))
+            .and_then(|mut client| {
+                client.labels.session = session.clone();
+                session.in_scope(|| self.configure(client))
+            })
+    }
+}
@@ -0,0 +1,61 @@
+use std::collections::HashSet;
+
+use sha2::{Digest, Sha256};
+
+use crate::search::Headers;
+use crate::{Client, Result};
+
+/// What makes two messages the same, for skipping duplicates in [`Client::migrate()`] and
+/// [`Client::export_to_maildir()`]
+///
+/// Some servers, Gmail among them, show a message under a new unique ID after it was moved between folders,
+/// so the UIDs alone let it through twice.
+///
+/// [`Client::migrate()`]: struct.Client.html#method.migrate
+/// [`Client::export_to_maildir()`]: struct.Client.html#method.export_to_maildir
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dedup {
+    /// The same `Message-ID` header, read with `TOP n 0` so duplicates aren't downloaded; messages without one
+    /// are never duplicates
+    MessageId,
+    /// The same content, by SHA-256; every message is downloaded to tell
+    ContentHash,
+    /// Either of the above
+    Any,
+}
+
+impl Dedup {
+    /// Whether the `Message-ID` of `msg` is among the `seen` keys; asks the server only when matching on it
+    pub(crate) fn known_id(self, client: &mut Client, msg: u32, seen: &HashSet<String>) -> Result<bool> {
+        if self == Dedup::ContentHash {
+            return Ok(false);
+        }
+        let headers = client.top_headers(msg)?;
+        Ok(message_id_key(&headers).is_some_and(|key| seen.contains(&key)))
+    }
+
+    /// The keys telling the message apart, `message-id:<...>` and `sha256:<hex>`
+    ///
+    /// They are kept in the same set as the unique IDs of a [`UidStore`]; the prefixes keep them apart.
+    ///
+    /// [`UidStore`]: state/trait.UidStore.html
+    pub(crate) fn keys(self, message: &[u8]) -> Vec<String> {
+        let mut keys = vec![];
+        if self != Dedup::ContentHash {
+            keys.extend(message_id_key(&Headers::parse(message)));
+        }
+        if self != Dedup::MessageId {
+            let digest: String = Sha256::digest(message).iter().map(|b| format!("{:02x}", b)).collect();
+            keys.push(format!("sha256:{}", digest));
+        }
+        keys
+    }
+}
+
+fn message_id_key(headers: &Headers) -> Option<String> {
+    headers
+        .get("Message-ID")
+        .map(str::trim)
+        .filter(|id| !id.is_empty())
+        .map(|id| format!("message-id:{}", id))
+}
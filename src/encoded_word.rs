@@ -0,0 +1,104 @@
+//! Encoded words of header fields, after [RFC 2047], and extended parameter values after [RFC 2231]
+//!
+//! [RFC 2047]: https://tools.ietf.org/html/rfc2047
+//! [RFC 2231]: https://tools.ietf.org/html/rfc2231
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+
+/// `value` with its encoded words such as `=?UTF-8?B?...?=` decoded
+///
+/// Whitespace between two encoded words is dropped, so that a text split across words comes out whole. A word
+/// which doesn't decode, e.g. in a charset unknown without the `with-encoding` feature, is left as it is.
+pub(crate) fn decode(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut after_word = false;
+    while let Some(start) = rest.find("=?") {
+        let (before, candidate) = rest.split_at(start);
+        match word(candidate) {
+            Some((text, len)) => {
+                if !(after_word && before.trim().is_empty()) {
+                    decoded.push_str(before);
+                }
+                decoded.push_str(&text);
+                rest = &candidate[len..];
+                after_word = true;
+            }
+            None => {
+                decoded.push_str(before);
+                decoded.push_str("=?");
+                rest = &candidate[2..];
+                after_word = false;
+            }
+        }
+    }
+    decoded.push_str(rest);
+    decoded
+}
+
+/// The text of the encoded word `s` starts with, and the length of that word
+fn word(s: &str) -> Option<(String, usize)> {
+    let (charset, rest) = s[2..].split_once('?')?;
+    let (encoding, rest) = rest.split_once('?')?;
+    let text = &rest[..rest.find("?=")?];
+    if charset.is_empty() || [charset, encoding, text].iter().any(|part| part.contains(char::is_whitespace)) {
+        return None;
+    }
+
+    let bytes = match encoding {
+        "B" | "b" => BASE64.decode(text).ok()?,
+        // Quoted-printable, with `_` for a space
+        "Q" | "q" => unescape(text, b'=', Some(b'_'))?,
+        _ => return None,
+    };
+    let len = 2 + charset.len() + 1 + encoding.len() + 1 + text.len() + 2;
+    Some((decode_charset(charset, &bytes)?, len))
+}
+
+/// `text` with the bytes escaped as `escape` and two hex digits put back, and `space` taken for a space
+fn unescape(text: &str, escape: u8, space: Option<u8>) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.bytes();
+    while let Some(c) = chars.next() {
+        bytes.push(match c {
+            c if c == escape => {
+                let hex = [chars.next()?, chars.next()?];
+                u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?
+            }
+            c if Some(c) == space => b' ',
+            c => c,
+        });
+    }
+    Some(bytes)
+}
+
+/// An extended parameter value such as `UTF-8'en'caf%C3%A9.pdf`, or `None` if it doesn't decode
+pub(crate) fn decode_extended(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let (charset, _language, text) = (parts.next()?, parts.next()?, parts.next()?);
+    let bytes = unescape(text, b'%', None)?;
+    decode_charset(if charset.is_empty() { "us-ascii" } else { charset }, &bytes)
+}
+
+/// `bytes` in `charset`, which may carry a language as in `UTF-8*en` ([RFC 2231])
+///
+/// [RFC 2231]: https://tools.ietf.org/html/rfc2231#section-5
+fn decode_charset(charset: &str, bytes: &[u8]) -> Option<String> {
+    let charset = charset.split('*').next()?;
+    if charset.eq_ignore_ascii_case("utf-8") || charset.eq_ignore_ascii_case("us-ascii") {
+        return Some(String::from_utf8_lossy(bytes).into_owned());
+    }
+    if charset.eq_ignore_ascii_case("iso-8859-1") || charset.eq_ignore_ascii_case("latin1") {
+        return Some(bytes.iter().map(|c| char::from(*c)).collect());
+    }
+
+    #[cfg(feature = "with-encoding")]
+    {
+        let encoding = encoding_rs::Encoding::for_label(charset.as_bytes())?;
+        Some(encoding.decode_without_bom_handling(bytes).0.into_owned())
+    }
+
+    #[cfg(not(feature = "with-encoding"))]
+    None
+}
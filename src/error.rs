@@ -0,0 +1,304 @@
+use std::fmt;
+use std::io;
+use std::time::Duration;
+
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+/// Everything that can go wrong while talking to the server
+///
+/// The `Display` output is meant to be read by humans; match on the variants to react to a
+/// particular kind of failure.
+#[derive(Debug)]
+pub enum Error {
+    /// The underlying connection failed
+    Io(io::Error),
+    /// The server closed the connection
+    ConnectionAborted,
+    /// The server replied with `-ERR`; holds the text following the status indicator
+    Server(String),
+    /// The server refused a login; the session is still in the Authorization stage, so it can be tried again
+    Auth(AuthError),
+    /// The server refused a login with `[IN-USE]`: another session holds the maildrop
+    ///
+    /// `retry_after` is the `LOGIN-DELAY` announced by [`Client::capa()`], if it was called; the lock may be gone
+    /// sooner, but logging in again takes that long anyway.
+    ///
+    /// [`Client::capa()`]: struct.Client.html#method.capa
+    MailboxLocked {
+        step: AuthStep,
+        reason: String,
+        retry_after: Option<Duration>,
+    },
+    /// The server refused a login with `[LOGIN-DELAY]`: the last one was too recent
+    ///
+    /// `retry_after` is the `LOGIN-DELAY` announced by [`Client::capa()`], if it was called.
+    ///
+    /// [`Client::capa()`]: struct.Client.html#method.capa
+    LoginDelay {
+        step: AuthStep,
+        reason: String,
+        retry_after: Option<Duration>,
+    },
+    /// The reply does not follow the protocol
+    InvalidReply(String),
+    /// The command is not allowed in the current stage of the session
+    WrongStage(String),
+    /// An argument can't be sent to the server, e.g. because it contains a line break
+    InvalidArgument(String),
+    /// Message numbers start at 1, and can't exceed the count of a `STAT` cached before any deletion; nothing was
+    /// sent. See [`Builder::cache()`]
+    ///
+    /// [`Builder::cache()`]: struct.Builder.html#method.cache
+    InvalidMessageNumber(u32),
+    /// The message could not be decoded
+    Encoding(String),
+    /// The TLS connection could not be set up
+    Tls(String),
+    /// The local store failed to load or save the state
+    Store(String),
+    /// A reply exceeded the configured maximum size in octets; the connection has been closed
+    ResponseTooLarge(usize),
+    /// A reply line exceeded the configured maximum length in octets; the connection has been closed
+    LineTooLong(usize),
+    /// The command was aborted through a [`CancellationToken`]; the connection has been closed
+    ///
+    /// [`CancellationToken`]: struct.CancellationToken.html
+    Cancelled,
+    /// The message received is much smaller or larger than `LIST` announced; holds the announced size, then
+    /// the received one, in octets. See [`Builder::verify_sizes()`]
+    ///
+    /// [`Builder::verify_sizes()`]: struct.Builder.html#method.verify_sizes
+    SizeMismatch(u64, u64),
+    /// The greeting failed the check set with [`Builder::expect_banner()`]; holds the text after the `+OK`
+    ///
+    /// [`Builder::expect_banner()`]: struct.Builder.html#method.expect_banner
+    UnexpectedGreeting(String),
+    /// Our side of a SASL mechanism failed, e.g. because the Kerberos ticket has expired
+    Sasl(String),
+    /// The command would have sent a password in the clear, without TLS; holds the command. See
+    /// [`Builder::allow_plaintext_auth()`]
+    ///
+    /// [`Builder::allow_plaintext_auth()`]: struct.Builder.html#method.allow_plaintext_auth
+    PlaintextAuth(String),
+    /// An account configuration is incomplete or malformed, see [`AccountConfig`]
+    ///
+    /// [`AccountConfig`]: fetcher/struct.AccountConfig.html
+    Config(String),
+    /// A number in a reply could not be parsed; holds what it stands for, e.g. `"size"`, and the offending token
+    Parse { field: &'static str, raw: String },
+    /// The server didn't announce the capability the command needs, so it wasn't sent; holds the capability,
+    /// e.g. `"TOP"` or `"SASL CRAM-MD5"`. See [`Builder::capability_checks()`]
+    ///
+    /// [`Builder::capability_checks()`]: struct.Builder.html#method.capability_checks
+    NotSupportedByServer(String),
+    /// The reply to an earlier command hasn't been read in full, so nothing was sent; holds the keyword of that
+    /// command
+    ///
+    /// Replies to commands queued with [`Client::queue_command()`] are read with [`Client::next_response()`].
+    /// A command cut short by a panic, e.g. in a progress callback, leaves the rest of its reply on the wire
+    /// for good: only [`Client::reconnect()`] helps then.
+    ///
+    /// [`Client::queue_command()`]: struct.Client.html#method.queue_command
+    /// [`Client::next_response()`]: struct.Client.html#method.next_response
+    /// [`Client::reconnect()`]: struct.Client.html#method.reconnect
+    CommandInFlight(String),
+}
+
+/// The response code in brackets at the start of an `-ERR` text, see [RFC 2449] and [RFC 3206]
+///
+/// [RFC 2449]: https://tools.ietf.org/html/rfc2449#section-8
+/// [RFC 3206]: https://tools.ietf.org/html/rfc3206
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub enum ErrorCode {
+    /// `[IN-USE]`: the maildrop is locked by another session
+    InUse,
+    /// `[LOGIN-DELAY]`: logging in again so soon is not allowed
+    LoginDelay,
+    /// `[SYS/TEMP]`: a temporary problem on the server
+    SysTemp,
+    /// `[SYS/PERM]`: a problem on the server that won't go away by itself
+    SysPerm,
+    /// `[AUTH]`: the credentials were wrong
+    Auth,
+    /// Any other code, in upper case and without the brackets
+    Other(String),
+}
+
+/// The login command the server refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthStep {
+    User,
+    Pass,
+    Apop,
+    /// `AUTH`, or the answer to one of its challenges
+    Auth,
+}
+
+/// A login refused by the server, see [`Error::Auth`]
+///
+/// [`Error::Auth`]: enum.Error.html#variant.Auth
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthError {
+    pub step: AuthStep,
+    /// The text the server sent after `-ERR`
+    pub reason: String,
+}
+
+impl AuthError {
+    /// The response code the server put in front of the reason, e.g. [`ErrorCode::Auth`] for wrong credentials
+    ///
+    /// [`ErrorCode::Auth`]: enum.ErrorCode.html#variant.Auth
+    pub fn code(&self) -> Option<ErrorCode> {
+        ErrorCode::parse(&self.reason)
+    }
+}
+
+impl ErrorCode {
+    fn parse(text: &str) -> Option<Self> {
+        let text = text.trim_start().strip_prefix('[')?;
+        let code = text[..text.find(']')?].to_ascii_uppercase();
+        Some(match code.as_str() {
+            "IN-USE"      => ErrorCode::InUse,
+            "LOGIN-DELAY" => ErrorCode::LoginDelay,
+            "SYS/TEMP"    => ErrorCode::SysTemp,
+            "SYS/PERM"    => ErrorCode::SysPerm,
+            "AUTH"        => ErrorCode::Auth,
+            _             => ErrorCode::Other(code),
+        })
+    }
+}
+
+impl Error {
+    /// The response code of a [`Server`] or [`Auth`] error, if the server sent one
+    ///
+    /// [`Server`]: #variant.Server
+    /// [`Auth`]: #variant.Auth
+    pub fn code(&self) -> Option<ErrorCode> {
+        match self {
+            Error::Server(text) => ErrorCode::parse(text),
+            Error::Auth(e)      => e.code(),
+            Error::MailboxLocked { .. } => Some(ErrorCode::InUse),
+            Error::LoginDelay { .. }    => Some(ErrorCode::LoginDelay),
+            _ => None,
+        }
+    }
+
+    /// How long to wait before logging in again, for [`MailboxLocked`] and [`LoginDelay`], if known
+    ///
+    /// [`MailboxLocked`]: #variant.MailboxLocked
+    /// [`LoginDelay`]: #variant.LoginDelay
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            Error::MailboxLocked { retry_after, .. } | Error::LoginDelay { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+
+    /// Tell which login step a `-ERR` reply refused, with the `LOGIN-DELAY` of `CAPA` for locks and delays
+    pub(crate) fn at_login(self, step: AuthStep, retry_after: Option<Duration>) -> Self {
+        let reason = match self {
+            Error::Server(reason) => reason,
+            e => return e,
+        };
+        match ErrorCode::parse(&reason) {
+            Some(ErrorCode::InUse)      => Error::MailboxLocked { step, reason, retry_after },
+            Some(ErrorCode::LoginDelay) => Error::LoginDelay { step, reason, retry_after },
+            _ => Error::Auth(AuthError { step, reason }),
+        }
+    }
+
+    /// Whether trying again later may succeed
+    ///
+    /// This covers dropped, reset and timed out connections, and the `[IN-USE]` and `[SYS/TEMP]`
+    /// response codes of [RFC 2449].
+    ///
+    /// [RFC 2449]: https://tools.ietf.org/html/rfc2449#section-8
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Io(e) => matches!(
+                e.kind(),
+                io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::WouldBlock
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::UnexpectedEof
+            ),
+            Error::ConnectionAborted => true,
+            Error::Server(_) | Error::Auth(_) => {
+                matches!(self.code(), Some(ErrorCode::InUse) | Some(ErrorCode::SysTemp))
+            }
+            Error::MailboxLocked { .. } => true,
+            _ => false,
+        }
+    }
+
+    /// Whether this is a complete `-ERR` reply that [`is_transient()`], so the session is still usable
+    ///
+    /// [`is_transient()`]: #method.is_transient
+    pub(crate) fn is_transient_reply(&self) -> bool {
+        matches!(self, Error::Server(_) | Error::Auth(_) | Error::MailboxLocked { .. }) && self.is_transient()
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e)           => write!(f, "{}", e),
+            Error::ConnectionAborted => write!(f, "Connection aborted"),
+            Error::Server(text)    => write!(f, "{}", text.trim_end()),
+            Error::Auth(e)         => write!(f, "Login refused at {:?}: {}", e.step, e.reason.trim_end()),
+            Error::MailboxLocked { step, reason, .. } => {
+                write!(f, "Mailbox locked, login refused at {:?}: {}", step, reason.trim_end())
+            }
+            Error::LoginDelay { step, reason, .. } => {
+                write!(f, "Logging in again too soon, refused at {:?}: {}", step, reason.trim_end())
+            }
+            Error::InvalidReply(e) => write!(f, "Invalid reply: {}", e),
+            Error::WrongStage(e)   => write!(f, "{}", e),
+            Error::InvalidArgument(e) => write!(f, "Invalid argument: {}", e),
+            Error::InvalidMessageNumber(msg) => write!(f, "No message number {} in the mailbox", msg),
+            Error::Encoding(e)     => write!(f, "Invalid encoding: {}", e),
+            Error::Tls(e)          => write!(f, "TLS: {}", e),
+            Error::Store(e)        => write!(f, "Store: {}", e),
+            Error::ResponseTooLarge(max) => write!(f, "Response exceeds {} octets", max),
+            Error::LineTooLong(max)      => write!(f, "Response line exceeds {} octets", max),
+            Error::Cancelled             => write!(f, "Cancelled"),
+            Error::SizeMismatch(listed, received) => {
+                write!(f, "Received {} octets, {} announced", received, listed)
+            }
+            Error::UnexpectedGreeting(text) => write!(f, "Unexpected greeting: {}", text.trim_end()),
+            Error::Sasl(e)                  => write!(f, "SASL: {}", e),
+            Error::PlaintextAuth(command)   => {
+                write!(f, "Refusing to send {} without TLS; see Builder::allow_plaintext_auth()", command)
+            }
+            Error::Config(e)                => write!(f, "Account configuration: {}", e),
+            Error::Parse { field, raw }     => write!(f, "Invalid {} in reply: {:?}", field, raw),
+            Error::NotSupportedByServer(capability) => {
+                write!(f, "The server doesn't announce {}; the command wasn't sent", capability)
+            }
+            Error::CommandInFlight(command) => {
+                write!(f, "The reply to {} hasn't been read yet; the command wasn't sent", command)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
@@ -0,0 +1,82 @@
+use crate::{Builder, Client, Result};
+
+/// Opens independent sessions to one server with the settings of a [`Builder`], see
+/// [`Builder::build_factory()`]
+///
+/// Every session gets a fresh copy of the settings, while the TLS config, with its root store and the TLS
+/// sessions to resume, is shared rather than built again. The factory can be shared between threads, so
+/// `|| factory.new_client()` is what [`Client::parallel_fetch()`] and [`ClientPool::from_factory()`] take.
+///
+/// # Example
+/// ```no_run
+/// # use pop3_client::Builder;
+/// # fn main() -> pop3_client::Result<()> {
+/// let factory = Builder::default()
+///     .credentials("sweet_username", "very_secret_password")
+///     .build_factory("my.host.com", 995);
+/// let mut client = factory.new_client()?;
+/// let ids: Vec<u32> = client.list_entries(None)?.iter().map(|entry| entry.id).collect();
+/// let messages = client.parallel_fetch(&ids, 3, || factory.new_client());
+/// #    Ok(())
+/// # }
+/// ```
+///
+/// [`Builder`]: struct.Builder.html
+/// [`Builder::build_factory()`]: struct.Builder.html#method.build_factory
+/// [`Client::parallel_fetch()`]: struct.Client.html#method.parallel_fetch
+/// [`ClientPool::from_factory()`]: pool/struct.ClientPool.html#method.from_factory
+#[derive(Clone, Debug)]
+pub struct ClientFactory {
+    builder: Builder,
+    host: String,
+    port: u16,
+}
+
+impl ClientFactory {
+    /// Open a new session: logged in when the builder has credentials or an authenticator, as with
+    /// [`Builder::open()`], and otherwise only connected, as with [`Builder::connect()`]
+    ///
+    /// [`Builder::open()`]: struct.Builder.html#method.open
+    /// [`Builder::connect()`]: struct.Builder.html#method.connect
+    pub fn new_client(&self) -> Result<Client> {
+        let mut builder = self.builder.clone();
+        if builder.has_credentials() {
+            builder.open(&self.host, self.port)
+        } else {
+            builder.connect(&self.host, self.port)
+        }
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    /// The user name of the fixed credentials, empty with none
+    pub(crate) fn username(&self) -> &str {
+        self.builder.credentials.as_ref().map_or("", |credentials| &credentials.username)
+    }
+}
+
+impl Builder {
+    /// A [`ClientFactory`] opening sessions to `host` and `port` with these settings
+    ///
+    /// Later changes to the builder don't reach the factory.
+    ///
+    /// [`ClientFactory`]: struct.ClientFactory.html
+    pub fn build_factory(&self, host: &str, port: u16) -> ClientFactory {
+        ClientFactory {
+            builder: self.clone(),
+            host: host.to_string(),
+            port,
+        }
+    }
+
+    /// Whether `open()` can log in, so sessions can be opened again without the caller
+    pub(crate) fn has_credentials(&self) -> bool {
+        self.credentials.is_some() || self.credentials_provider.is_some() || self.authenticator.is_some()
+    }
+}
@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, PoisonError};
+use std::thread::{self, JoinHandle};
+
+#[cfg(feature = "stream")]
+use std::pin::Pin;
+#[cfg(feature = "stream")]
+use std::task::{Context, Poll, Waker};
+
+use crate::{Client, Error, Result};
+
+/// A downloaded message, or why it couldn't be
+type Item = Result<(u32, Vec<u8>)>;
+
+struct State {
+    items: VecDeque<Item>,
+    /// The worker is through the messages, or stopped at an error
+    done: bool,
+    /// The consumer is gone, or wants the client back
+    closed: bool,
+    #[cfg(feature = "stream")]
+    waker: Option<Waker>,
+}
+
+/// The bounded queue between the worker and the consumer
+struct Queue {
+    state: Mutex<State>,
+    changed: Condvar,
+    capacity: usize,
+}
+
+impl Queue {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn wait<'a>(&self, state: MutexGuard<'a, State>) -> MutexGuard<'a, State> {
+        self.changed.wait(state).unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Wake up the other side, whether it blocks or polls
+    fn notify(&self, _state: &mut State) {
+        self.changed.notify_all();
+        #[cfg(feature = "stream")]
+        if let Some(waker) = _state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    /// Wait for room in the queue; `false` if the consumer is gone meanwhile
+    fn reserve(&self) -> bool {
+        let mut state = self.lock();
+        while state.items.len() >= self.capacity && !state.closed {
+            state = self.wait(state);
+        }
+        !state.closed
+    }
+
+    fn push(&self, item: Item) {
+        let mut state = self.lock();
+        state.items.push_back(item);
+        self.notify(&mut state);
+    }
+
+    fn finish(&self) {
+        let mut state = self.lock();
+        state.done = true;
+        self.notify(&mut state);
+    }
+
+    fn close(&self) {
+        let mut state = self.lock();
+        state.closed = true;
+        self.notify(&mut state);
+    }
+}
+
+/// Marks the queue done when the worker ends, a panic included, so the consumer doesn't wait for it forever
+struct Finish(Arc<Queue>);
+
+impl Drop for Finish {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.push(Err(Error::ConnectionAborted));
+        }
+        self.0.finish();
+    }
+}
+
+/// Messages downloaded ahead of their consumer by a worker thread, see [`Client::fetch_stream()`]
+///
+/// Dropping the stream stops the worker after the message it is downloading, and drops the client.
+///
+/// [`Client::fetch_stream()`]: struct.Client.html#method.fetch_stream
+pub struct FetchStream {
+    queue: Arc<Queue>,
+    worker: Option<JoinHandle<Client>>,
+}
+
+impl FetchStream {
+    /// Stop downloading, and get the client back once the message being downloaded is in
+    ///
+    /// The messages waiting in the queue are dropped.
+    ///
+    /// # Errors
+    /// [`Error::ConnectionAborted`] if the worker panicked, e.g. in a progress callback.
+    ///
+    /// [`Error::ConnectionAborted`]: enum.Error.html#variant.ConnectionAborted
+    pub fn finish(mut self) -> Result<Client> {
+        self.queue.close();
+        match self.worker.take() {
+            Some(worker) => worker.join().map_err(|_| Error::ConnectionAborted),
+            None         => Err(Error::ConnectionAborted),
+        }
+    }
+}
+
+impl Iterator for FetchStream {
+    type Item = Item;
+
+    /// Block until the next message is in
+    fn next(&mut self) -> Option<Item> {
+        let mut state = self.queue.lock();
+        loop {
+            if let Some(item) = state.items.pop_front() {
+                self.queue.notify(&mut state);
+                return Some(item);
+            }
+            if state.done {
+                return None;
+            }
+            state = self.queue.wait(state);
+        }
+    }
+}
+
+#[cfg(feature = "stream")]
+impl futures_core::Stream for FetchStream {
+    type Item = Item;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Item>> {
+        let mut state = self.queue.lock();
+        if let Some(item) = state.items.pop_front() {
+            self.queue.notify(&mut state);
+            return Poll::Ready(Some(item));
+        }
+        if state.done {
+            return Poll::Ready(None);
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for FetchStream {
+    fn drop(&mut self) {
+        self.queue.close();
+    }
+}
+
+impl Client {
+    /// Download messages on a worker thread, at most `capacity` of them ahead of the consumer
+    ///
+    /// The worker waits for room in the queue before each `RETR`, so a consumer writing to slow storage slows the
+    /// downloads down instead of piling messages up in memory. It stops at the first error but an `-ERR` reply,
+    /// after passing it on. With the `stream` feature, [`FetchStream`] is a `futures_core::Stream` as well, whose
+    /// polls never block.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// # client.login("sweet_username", "very_secret_password")?;
+    /// let ids: Vec<u32> = client.list_entries(None)?.iter().map(|entry| entry.id).collect();
+    /// let mut messages = client.fetch_stream(ids, 4);
+    /// for message in &mut messages {
+    ///     let (id, content) = message?;
+    ///     std::fs::write(format!("{}.eml", id), content).map_err(pop3_client::Error::Io)?;
+    /// }
+    /// messages.finish()?.quit()?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`FetchStream`]: struct.FetchStream.html
+    pub fn fetch_stream<I>(mut self, ids: I, capacity: usize) -> FetchStream
+    where
+        I: IntoIterator<Item = u32>,
+        I::IntoIter: Send + 'static,
+    {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(State {
+                items: VecDeque::new(),
+                done: false,
+                closed: false,
+                #[cfg(feature = "stream")]
+                waker: None,
+            }),
+            changed: Condvar::new(),
+            capacity: capacity.max(1),
+        });
+        let ids = ids.into_iter();
+        let shared = queue.clone();
+        let worker = thread::spawn(move || {
+            let _finish = Finish(shared.clone());
+            for msg in ids {
+                if !shared.reserve() {
+                    break;
+                }
+                let result = self.retr_bytes(msg);
+                let broken = matches!(result, Err(ref e) if !matches!(e, Error::Server(_)));
+                shared.push(result.map(|content| (msg, Vec::from(content))));
+                if broken {
+                    break;
+                }
+            }
+            self
+        });
+        FetchStream {
+            queue,
+            worker: Some(worker),
+        }
+    }
+}
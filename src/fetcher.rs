@@ -0,0 +1,384 @@
+//! Concurrent download of new messages from several accounts
+//!
+//! Every account gets its own worker thread, which connects, authorizes, lists the unique IDs and
+//! retrieves each message it hasn't seen before. Messages are delivered through a channel as soon
+//! as they arrive, together with the index of the account they came from.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use pop3_client::fetcher::{AccountConfig, Credentials, Fetcher};
+//!
+//! let mut fetcher = Fetcher::default();
+//! fetcher.account(AccountConfig {
+//!     host:                 "my.host.com".to_string(),
+//!     port:                 110,
+//!     tls:                  false,
+//!     allow_plaintext_auth: true,
+//!     credentials:          Credentials::new("sweet_username", "very_secret_password"),
+//!     ..AccountConfig::default()
+//! });
+//!
+//! for (account_id, result) in fetcher.spawn() {
+//!     match result {
+//!         Ok((uid, bytes)) => println!("{}: {} ({} octets)", account_id, uid, bytes.len()),
+//!         Err(e)           => eprintln!("{}: {}", account_id, e),
+//!     }
+//! }
+//! ```
+
+use std::collections::HashSet;
+use std::str::FromStr;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use bytes::Bytes;
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Deserializer};
+
+pub use crate::secret::Credentials;
+use crate::state::{SyncOptions, UidStore, SAVE_BATCH};
+use crate::{AuthMechanism, Builder, Client, Error, Result, UidlEntry};
+
+type SharedStore = Arc<Mutex<dyn UidStore + Send>>;
+
+/// What a worker sends back: the account index, and either a `(uid, message)` pair or an error
+pub type Delivery = (usize, Result<(String, Bytes)>);
+
+/// Where and how to fetch the mail of a single account
+///
+/// Besides spelling it out, a configuration can be read from the environment with [`from_env()`], or, with the
+/// `config` feature, from TOML with [`from_toml()`]. [`builder()`] turns it into a [`Builder`] for everything but
+/// the host and port.
+///
+/// [`from_env()`]: #method.from_env
+/// [`from_toml()`]: #method.from_toml
+/// [`builder()`]: #method.builder
+/// [`Builder`]: ../struct.Builder.html
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(Deserialize), serde(default))]
+pub struct AccountConfig {
+    pub host: String,
+    pub port: u16,
+    /// Upgrade the connection with `STLS`; requires the `with-rustls` feature
+    pub tls: bool,
+    /// Send the password even without TLS, see [`Builder::allow_plaintext_auth()`]
+    ///
+    /// [`Builder::allow_plaintext_auth()`]: ../struct.Builder.html#method.allow_plaintext_auth
+    pub allow_plaintext_auth: bool,
+    /// How to log in, see [`Builder::auth_mechanism()`]
+    ///
+    /// [`Builder::auth_mechanism()`]: ../struct.Builder.html#method.auth_mechanism
+    pub auth: AuthMechanism,
+    pub credentials: Credentials,
+    /// Give up on a command after this long, see [`Builder::command_timeout()`]; whole seconds in TOML
+    ///
+    /// [`Builder::command_timeout()`]: ../struct.Builder.html#method.command_timeout
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "seconds"))]
+    pub timeout: Option<Duration>,
+    /// Days to leave downloaded messages on the server, `None` for good
+    ///
+    /// The client doesn't act on it; compare it with [`Client::retention_policy()`] to warn users whose server
+    /// expires messages sooner.
+    ///
+    /// [`Client::retention_policy()`]: ../struct.Client.html#method.retention_policy
+    pub retention_days: Option<u32>,
+}
+
+impl Default for AccountConfig {
+    fn default() -> Self {
+        Self {
+            host:                 String::new(),
+            port:                 110,
+            tls:                  cfg!(feature = "with-rustls"),
+            allow_plaintext_auth: false,
+            auth:                 AuthMechanism::default(),
+            credentials:          Credentials::default(),
+            timeout:              None,
+            retention_days:       None,
+        }
+    }
+}
+
+/// `<prefix>_<name>` from the environment, parsed
+fn env_var<T: FromStr>(prefix: &str, name: &str) -> Result<Option<T>> {
+    let name = format!("{}_{}", prefix, name);
+    std::env::var(&name)
+        .ok()
+        .map(|value| value.parse().map_err(|_| Error::Config(format!("invalid {}: {:?}", name, value))))
+        .transpose()
+}
+
+#[cfg(feature = "serde")]
+fn seconds<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<Option<Duration>, D::Error> {
+    Option::<u64>::deserialize(deserializer).map(|seconds| seconds.map(Duration::from_secs))
+}
+
+impl AccountConfig {
+    /// Read a configuration from `<prefix>_HOST`, `<prefix>_USERNAME`, `<prefix>_PASSWORD` and, if set,
+    /// `<prefix>_PORT`, `<prefix>_TLS`, `<prefix>_ALLOW_PLAINTEXT_AUTH`, `<prefix>_AUTH`, `<prefix>_TIMEOUT`
+    /// (in seconds) and `<prefix>_RETENTION_DAYS`
+    ///
+    /// Flags take `true` or `false`, and `<prefix>_AUTH` one of `user-pass`, `apop` and `auto`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::fetcher::AccountConfig;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// // MAIL_HOST=pop.example.com MAIL_USERNAME=me MAIL_PASSWORD=... MAIL_TIMEOUT=30
+    /// let mut client = AccountConfig::from_env("MAIL")?.open()?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Fails with [`Error::Config`] when a required variable is missing or a value can't be parsed.
+    ///
+    /// [`Error::Config`]: ../enum.Error.html#variant.Config
+    pub fn from_env(prefix: &str) -> Result<Self> {
+        let required = |name: &str| {
+            env_var(prefix, name)?.ok_or_else(|| Error::Config(format!("{}_{} is not set", prefix, name)))
+        };
+
+        let defaults = Self::default();
+        Ok(Self {
+            host:                 required("HOST")?,
+            port:                 env_var(prefix, "PORT")?.unwrap_or(defaults.port),
+            tls:                  env_var(prefix, "TLS")?.unwrap_or(defaults.tls),
+            allow_plaintext_auth: env_var(prefix, "ALLOW_PLAINTEXT_AUTH")?.unwrap_or(defaults.allow_plaintext_auth),
+            auth:                 env_var(prefix, "AUTH")?.unwrap_or(defaults.auth),
+            credentials:          Credentials::new(&required("USERNAME")?, &required("PASSWORD")?),
+            timeout:              env_var(prefix, "TIMEOUT")?.map(Duration::from_secs),
+            retention_days:       env_var(prefix, "RETENTION_DAYS")?,
+        })
+    }
+
+    /// Parse a configuration from TOML, with the field names of this struct
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use pop3_client::fetcher::AccountConfig;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let config = AccountConfig::from_toml(r#"
+    ///     host = "pop.example.com"
+    ///     port = 995
+    ///     auth = "auto"
+    ///     timeout = 30
+    ///
+    ///     [credentials]
+    ///     username = "me"
+    ///     password = "secret"
+    /// "#)?;
+    /// assert_eq!(config.timeout, Some(std::time::Duration::from_secs(30)));
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Fails with [`Error::Config`] on malformed TOML, unknown values and a missing host or username.
+    ///
+    /// [`Error::Config`]: ../enum.Error.html#variant.Config
+    #[cfg(feature = "config")]
+    pub fn from_toml(text: &str) -> Result<Self> {
+        let config: Self = toml::from_str(text).map_err(|e| Error::Config(e.message().to_string()))?;
+        if config.host.is_empty() {
+            return Err(Error::Config("no host given".to_string()));
+        }
+        if config.credentials.username.is_empty() {
+            return Err(Error::Config("no username given".to_string()));
+        }
+        Ok(config)
+    }
+
+    /// The key of this account in a [`UidStore`], `username@host:port`
+    ///
+    /// [`UidStore`]: ../state/trait.UidStore.html
+    pub fn id(&self) -> String {
+        format!("{}@{}:{}", self.credentials.username, self.host, self.port)
+    }
+
+    /// Connect and log in with this configuration
+    pub fn open(&self) -> Result<Client> {
+        self.builder()?.open(&self.host, self.port)
+    }
+
+    /// A [`Builder`] set up with everything of this configuration but the host and port
+    ///
+    /// # Errors
+    /// Fails with [`Error::Tls`] when TLS is asked for without the `with-rustls` feature.
+    ///
+    /// [`Builder`]: ../struct.Builder.html
+    /// [`Error::Tls`]: ../enum.Error.html#variant.Tls
+    pub fn builder(&self) -> Result<Builder> {
+        let mut builder = self.tls_builder()?;
+        builder
+            .allow_plaintext_auth(self.allow_plaintext_auth)
+            .auth_mechanism(self.auth)
+            .credentials(&self.credentials.username, &self.credentials.password);
+        if let Some(timeout) = self.timeout {
+            builder.command_timeout(timeout);
+        }
+        Ok(builder)
+    }
+
+    #[cfg(feature = "with-rustls")]
+    fn tls_builder(&self) -> Result<Builder> {
+        let mut builder = Builder::default();
+        builder.tls(self.tls);
+        Ok(builder)
+    }
+
+    #[cfg(not(feature = "with-rustls"))]
+    fn tls_builder(&self) -> Result<Builder> {
+        if self.tls {
+            return Err(Error::Tls("built without the with-rustls feature".to_string()));
+        }
+        Ok(Builder::default())
+    }
+}
+
+struct Account {
+    config: AccountConfig,
+    seen: HashSet<String>,
+}
+
+/// Downloads new messages from a set of accounts, one thread per account
+#[derive(Default)]
+pub struct Fetcher {
+    accounts: Vec<Account>,
+    store: Option<SharedStore>,
+    sync: SyncOptions,
+}
+
+impl Fetcher {
+    /// Add an account; its index in the order of addition is the `account_id` of its deliveries
+    pub fn account(&mut self, config: AccountConfig) -> &mut Self {
+        self.accounts.push(Account {
+            config,
+            seen: HashSet::new(),
+        });
+        self
+    }
+
+    /// Mark unique IDs of the given account as already downloaded, so they are skipped
+    pub fn seen<I>(&mut self, account_id: usize, uids: I) -> &mut Self
+    where
+        I: IntoIterator<Item = String>,
+    {
+        if let Some(account) = self.accounts.get_mut(account_id) {
+            account.seen.extend(uids);
+        }
+        self
+    }
+
+    /// Remember the downloaded unique IDs in `store`, and skip them on the next runs
+    ///
+    /// Each worker loads the account's unique IDs when it starts, and saves them every hundred
+    /// delivered messages and when it stops. IDs of messages which are no longer on the server are forgotten.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # #[cfg(feature = "serde")] {
+    /// use pop3_client::fetcher::Fetcher;
+    /// use pop3_client::state::JsonFileStore;
+    ///
+    /// let mut fetcher = Fetcher::default();
+    /// fetcher.uid_store(JsonFileStore::new("/var/lib/fetcher/seen.json"));
+    /// # }
+    /// ```
+    pub fn uid_store<S: UidStore + Send + 'static>(&mut self, store: S) -> &mut Self {
+        self.store = Some(Arc::new(Mutex::new(store)));
+        self
+    }
+
+    /// How messages get their unique IDs on servers refusing `UIDL`; by hashing their headers by default
+    pub fn sync_options(&mut self, options: SyncOptions) -> &mut Self {
+        self.sync = options;
+        self
+    }
+
+    /// Start a worker for every account
+    ///
+    /// The channel is closed once all the workers are done. A worker stops at the first error,
+    /// after reporting it; dropping the receiver stops the workers after their current message.
+    pub fn spawn(self) -> Receiver<Delivery> {
+        let (tx, rx) = channel();
+
+        for (account_id, account) in self.accounts.into_iter().enumerate() {
+            let tx = tx.clone();
+            let store = self.store.clone();
+            let sync = self.sync;
+            thread::spawn(move || {
+                if let Err(e) = fetch_account(account_id, &account, store.as_ref(), &sync, &tx) {
+                    tx.send((account_id, Err(e))).ok();
+                }
+            });
+        }
+
+        rx
+    }
+}
+
+fn fetch_account(
+    account_id: usize,
+    account: &Account,
+    store: Option<&SharedStore>,
+    sync: &SyncOptions,
+    tx: &Sender<Delivery>,
+) -> Result<()> {
+    let key = account.config.id();
+    let stored = match store {
+        Some(store) => lock(store)?.load(&key)?,
+        None        => HashSet::new(),
+    };
+
+    let mut client = account.config.open()?;
+    let entries = client.sync_entries(sync)?;
+
+    let mut seen: HashSet<String> = entries
+        .iter()
+        .map(|entry| &entry.uid)
+        .filter(|uid| stored.contains(*uid))
+        .cloned()
+        .collect();
+
+    let save = |seen: &HashSet<String>| match store {
+        Some(store) => lock(store)?.save(&key, seen),
+        None        => Ok(()),
+    };
+    let mut fetched = Ok(());
+    let mut unsaved = 0;
+    for UidlEntry { id: msg, uid } in entries {
+        if seen.contains(&uid) || account.seen.contains(&uid) {
+            continue;
+        }
+        let bytes = match client.retr_bytes(msg) {
+            Ok(bytes) => bytes,
+            Err(e) => {
+                fetched = Err(e);
+                break;
+            }
+        };
+        if tx.send((account_id, Ok((uid.clone(), bytes)))).is_err() {
+            break;
+        }
+        seen.insert(uid);
+        unsaved += 1;
+        if unsaved == SAVE_BATCH {
+            save(&seen)?;
+            unsaved = 0;
+        }
+    }
+
+    let saved = save(&seen);
+    fetched?;
+    saved?;
+    client.quit()
+}
+
+fn lock(store: &SharedStore) -> Result<std::sync::MutexGuard<'_, dyn UidStore + Send + 'static>> {
+    store.lock().map_err(|_| Error::Store("poisoned".to_string()))
+}
@@ -0,0 +1,113 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::stream::SocketOptions;
+use crate::{Client, Error, Result};
+
+/// The greeting of the server, taken apart
+///
+/// Returned by [`Client::banner()`] and [`Client::grab_banner()`], and checked by the predicate of [`Builder::expect_banner()`].
+///
+/// [`Client::banner()`]: struct.Client.html#method.banner
+/// [`Client::grab_banner()`]: struct.Client.html#method.grab_banner
+/// [`Builder::expect_banner()`]: struct.Builder.html#method.expect_banner
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Greeting {
+    /// The whole text after the `+OK`
+    pub text: String,
+    /// The name the server gives for itself: the first word of the text if it looks like a domain name,
+    /// otherwise the domain of the timestamp
+    pub host: Option<String>,
+    /// The `<...>` timestamp used by `APOP`, brackets included
+    pub timestamp: Option<String>,
+}
+
+pub(crate) type Check = Arc<dyn Fn(&Greeting) -> bool + Send + Sync>;
+
+impl Greeting {
+    pub(crate) fn parse(text: &str) -> Self {
+        let timestamp = apop_timestamp(text);
+        let named = text
+            .split_whitespace()
+            .next()
+            .filter(|word| word.contains('.') && word.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'.' || c == b'-'))
+            .map(|word| word.trim_end_matches('.'));
+        let stamped = timestamp
+            .and_then(|stamp| stamp.rfind('@').map(|at| &stamp[at + 1..stamp.len() - 1]))
+            .filter(|domain| !domain.is_empty());
+        Self {
+            text: text.to_string(),
+            host: named.or(stamped).map(str::to_string),
+            timestamp: timestamp.map(str::to_string),
+        }
+    }
+}
+
+/// The `<...>` timestamp of the greeting, see [RFC 1939], section 7
+///
+/// [RFC 1939]: https://tools.ietf.org/html/rfc1939#page-15
+pub(crate) fn apop_timestamp(greeting: &str) -> Option<&str> {
+    let start = greeting.find('<')?;
+    let end = start + greeting[start..].find('>')?;
+    Some(&greeting[start..=end])
+}
+
+impl Client {
+    /// The greeting of the server, with the host name and `APOP` timestamp picked out
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let client = Client::connect("my.host.com", 110)?;
+    /// if let Some(host) = client.banner().host {
+    ///     println!("connected to {}", host);
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    pub fn banner(&self) -> Greeting {
+        Greeting::parse(&self.greeting)
+    }
+
+    /// Connect, read the greeting and leave, without TLS or logging in, e.g. to check a server is up
+    ///
+    /// Connecting, the greeting and the `QUIT` are each given up on after `timeout`. A failing `QUIT` is
+    /// ignored, the greeting being all that was asked.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # use std::time::Duration;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let greeting = Client::grab_banner("my.host.com", 110, Duration::from_secs(5))?;
+    /// println!("up: {}", greeting.text);
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// [`Error::Io`] when the server can't be reached or doesn't greet in time, and [`Error::Server`] when it
+    /// greets with `-ERR`.
+    ///
+    /// [`Error::Io`]: enum.Error.html#variant.Io
+    /// [`Error::Server`]: enum.Error.html#variant.Server
+    pub fn grab_banner(host: &str, port: u16, timeout: Duration) -> Result<Greeting> {
+        let options = SocketOptions { connect_timeout: Some(timeout), ..SocketOptions::default() };
+        let mut client = Client::connect_notls(host, port, None, &options)?;
+        let greeting = client.banner();
+        client.set_command_timeout(timeout);
+        client.quit().ok();
+        Ok(greeting)
+    }
+
+    /// Refuse the session if the greeting fails the check set with `Builder::expect_banner`
+    pub(crate) fn check_banner(&self, check: &Check) -> Result<()> {
+        if check(&self.banner()) {
+            Ok(())
+        } else {
+            Err(Error::UnexpectedGreeting(self.greeting.clone()))
+        }
+    }
+}
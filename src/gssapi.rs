@@ -0,0 +1,124 @@
+//! `AUTH GSSAPI` of [RFC 4752], for Kerberos-only mail servers
+//!
+//! The crate doesn't link a GSS-API library itself: [`Gssapi`] runs the SASL side of the exchange, including
+//! the negotiation of the security layer once the context is established, on top of a [`SecurityContext`]
+//! from a crate such as `libgssapi` or `cross-krb5`. The context is initiated for the `pop@<host>` service,
+//! without confidentiality, since the session is protected by TLS if at all.
+//!
+//! ```ignore
+//! use libgssapi::{context::{ClientCtx, CtxFlags, SecurityContext as _}, credential::{Cred, CredUsage},
+//!                 name::Name, oid::{OidSet, GSS_MECH_KRB5, GSS_NT_HOSTBASED_SERVICE}};
+//! use pop3_client::gssapi::{GssResult, Gssapi, SecurityContext};
+//!
+//! struct Krb5(ClientCtx);
+//!
+//! impl SecurityContext for Krb5 {
+//!     fn step(&mut self, token: Option<&[u8]>) -> GssResult<Option<Vec<u8>>> {
+//!         Ok(self.0.step(token, None)?.map(|token| token.to_vec()))
+//!     }
+//!     fn is_complete(&self) -> bool {
+//!         self.0.is_complete()
+//!     }
+//!     fn wrap(&mut self, message: &[u8]) -> GssResult<Vec<u8>> {
+//!         Ok(self.0.wrap(false, message)?.to_vec())
+//!     }
+//!     fn unwrap(&mut self, token: &[u8]) -> GssResult<Vec<u8>> {
+//!         Ok(self.0.unwrap(token)?.to_vec())
+//!     }
+//! }
+//!
+//! let authenticator = Gssapi::new(|| {
+//!     let service = Name::new(b"pop@mail.corp.example", Some(&GSS_NT_HOSTBASED_SERVICE))?;
+//!     let mut mechs = OidSet::new()?;
+//!     mechs.add(&GSS_MECH_KRB5)?;
+//!     let cred = Cred::acquire(None, None, CredUsage::Initiate, Some(&mechs))?;
+//!     Ok(Krb5(ClientCtx::new(Some(cred), service, CtxFlags::GSS_C_MUTUAL_FLAG, Some(&GSS_MECH_KRB5))))
+//! });
+//! ```
+//!
+//! [RFC 4752]: https://tools.ietf.org/html/rfc4752
+
+use crate::{Authenticator, Client, Error, Result};
+
+/// What a GSS-API call may fail with; becomes [`Error::Sasl`](../enum.Error.html#variant.Sasl)
+pub type GssResult<T> = std::result::Result<T, Box<dyn std::error::Error + Send + Sync>>;
+
+/// Bit of the security layer mask meaning no security layer
+const NO_SECURITY_LAYER: u8 = 1;
+
+/// A GSS-API security context initiated by the client
+pub trait SecurityContext {
+    /// Process the token from the server, `None` to start, and return the token to send back, if any
+    fn step(&mut self, token: Option<&[u8]>) -> GssResult<Option<Vec<u8>>>;
+
+    /// Whether the context is established
+    fn is_complete(&self) -> bool;
+
+    /// Protect a message for integrity only, `gss_wrap` without confidentiality
+    fn wrap(&mut self, message: &[u8]) -> GssResult<Vec<u8>>;
+
+    /// Check and extract a message wrapped by the server, `gss_unwrap`
+    fn unwrap(&mut self, token: &[u8]) -> GssResult<Vec<u8>>;
+}
+
+/// `AUTH GSSAPI`, with a fresh security context for every login
+pub struct Gssapi<F> {
+    context: F,
+    authzid: Option<String>,
+}
+
+impl<C, F> Gssapi<F>
+where
+    C: SecurityContext,
+    F: Fn() -> GssResult<C> + Send + Sync,
+{
+    /// Log in with the contexts `context` creates, as the principal they are for
+    pub fn new(context: F) -> Self {
+        Self { context, authzid: None }
+    }
+
+    /// Act as `authzid` instead of the principal, when the server allows it
+    pub fn authzid(mut self, authzid: &str) -> Self {
+        self.authzid = Some(authzid.to_string());
+        self
+    }
+}
+
+impl<C, F> Authenticator for Gssapi<F>
+where
+    C: SecurityContext,
+    F: Fn() -> GssResult<C> + Send + Sync,
+{
+    fn authenticate(&self, client: &mut Client) -> Result<()> {
+        let mut context = (self.context)().map_err(local)?;
+        let initial = context.step(None).map_err(local)?.unwrap_or_default();
+        client.auth("GSSAPI", Some(&initial), |challenge| {
+            if !context.is_complete() {
+                return Ok(context.step(Some(challenge)).map_err(local)?.unwrap_or_default());
+            }
+            if challenge.is_empty() {
+                return Ok(Vec::new());
+            }
+            negotiate(&mut context, challenge, self.authzid.as_deref())
+        })
+    }
+}
+
+/// Answer the wrapped security layer offer of the server, choosing no security layer (RFC 4752, section 3.1)
+fn negotiate<C: SecurityContext>(context: &mut C, challenge: &[u8], authzid: Option<&str>) -> Result<Vec<u8>> {
+    let offer = context.unwrap(challenge).map_err(local)?;
+    if offer.len() != 4 {
+        return Err(Error::InvalidReply(format!("security layer offer is {} octets, not 4", offer.len())));
+    }
+    if offer[0] & NO_SECURITY_LAYER == 0 {
+        return Err(Error::Sasl("the server requires a security layer".to_string()));
+    }
+    // No security layer, so no maximum message size
+    let mut answer = vec![NO_SECURITY_LAYER, 0, 0, 0];
+    answer.extend_from_slice(authzid.unwrap_or_default().as_bytes());
+    context.wrap(&answer).map_err(local)
+}
+
+fn local(e: Box<dyn std::error::Error + Send + Sync>) -> Error {
+    Error::Sasl(format!("GSSAPI: {}", e))
+}
@@ -0,0 +1,110 @@
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use crate::{Client, Error};
+
+/// How many round trips [`LatencyStats`] are taken over
+///
+/// [`LatencyStats`]: struct.LatencyStats.html
+const WINDOW: usize = 32;
+
+/// Round-trip times of the last commands of a session, see [`Client::latency()`]
+///
+/// Downloads with `RETR` and `TOP` are left out, as they measure the bandwidth more than the server.
+///
+/// [`Client::latency()`]: struct.Client.html#method.latency
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct LatencyStats {
+    /// Number of round trips measured, at most the last 32
+    pub samples: usize,
+    pub last: Duration,
+    pub min: Duration,
+    pub max: Duration,
+    pub mean: Duration,
+}
+
+/// The outcome of [`Client::health_check()`]
+///
+/// [`Client::health_check()`]: struct.Client.html#method.health_check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Health {
+    /// Whether the server answered; an `-ERR` reply counts, a broken connection doesn't
+    pub alive: bool,
+    /// How long the check took
+    pub round_trip: Duration,
+    /// The statistics of the session, this check included if the server answered
+    pub latency: LatencyStats,
+}
+
+/// The round trips behind [`LatencyStats`]
+///
+/// [`LatencyStats`]: struct.LatencyStats.html
+#[derive(Default)]
+pub(crate) struct Latencies {
+    samples: VecDeque<Duration>,
+}
+
+impl Latencies {
+    pub(crate) fn record(&mut self, command: &str, elapsed: Duration) {
+        if matches!(crate::stage::keyword(command).as_str(), "RETR" | "TOP") {
+            return;
+        }
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(elapsed);
+    }
+
+    fn stats(&self) -> LatencyStats {
+        let samples = self.samples.len();
+        if samples == 0 {
+            return LatencyStats::default();
+        }
+        LatencyStats {
+            samples,
+            last: self.samples[samples - 1],
+            min: self.samples.iter().min().copied().unwrap_or_default(),
+            max: self.samples.iter().max().copied().unwrap_or_default(),
+            mean: self.samples.iter().sum::<Duration>() / samples as u32,
+        }
+    }
+}
+
+impl Client {
+    /// Check that the connection still works, with `NOOP` once logged in and `CAPA` before
+    ///
+    /// Unlike [`noop()`], this doesn't fail: a dead connection is reported as such. The check takes at most the
+    /// timeout given to [`set_command_timeout()`], if any.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # use std::time::Duration;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let health = client.health_check();
+    /// if !health.alive || health.latency.mean > Duration::from_secs(2) {
+    ///     client = Client::connect("my.host.com", 110)?;
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`noop()`]: struct.Client.html#method.noop
+    /// [`set_command_timeout()`]: struct.Client.html#method.set_command_timeout
+    pub fn health_check(&mut self) -> Health {
+        let start = Instant::now();
+        let result = if self.is_authorized() { self.noop() } else { self.capa().map(|_| ()) };
+        Health {
+            alive: matches!(result, Ok(()) | Err(Error::Server(_))),
+            round_trip: start.elapsed(),
+            latency: self.latency(),
+        }
+    }
+
+    /// Round-trip times of the last 32 commands answered by the server
+    pub fn latency(&self) -> LatencyStats {
+        self.latencies.stats()
+    }
+}
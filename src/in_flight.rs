@@ -0,0 +1,58 @@
+use std::collections::VecDeque;
+
+use crate::{stage, Error, Result};
+
+/// The commands whose replies haven't been read in full, so that no other command gets to read them
+///
+/// A command stays current when reading its reply is cut short by a panic, e.g. in a progress callback or the
+/// writer of `Client::retr_to_writer()`: the rest of the reply is still on the wire, so whatever follows fails
+/// with `Error::CommandInFlight` instead of taking it for its own reply.
+#[derive(Default)]
+pub(crate) struct InFlight {
+    /// The command sent and read by a method of the client
+    current: Option<String>,
+    /// Keywords of the commands queued with `Client::queue_command()`, in order
+    pipelined: VecDeque<String>,
+}
+
+impl InFlight {
+    /// The oldest command whose reply hasn't been read
+    pub fn earliest(&self) -> Option<&str> {
+        self.current.as_deref().or_else(|| self.pipelined.front().map(String::as_str))
+    }
+
+    /// Before a command and its reply, which must come after all the others
+    pub fn start(&mut self, command: &str) -> Result<()> {
+        if let Some(earlier) = self.earliest() {
+            return Err(Error::CommandInFlight(earlier.to_string()));
+        }
+        self.current = Some(stage::keyword(command));
+        Ok(())
+    }
+
+    /// The reply to the current command has been read in full, or the connection is lost
+    pub fn finish(&mut self) {
+        self.current = None;
+    }
+
+    /// Before queueing a command or reading the reply to one, which must not come in the middle of another reply
+    pub fn check_current(&self) -> Result<()> {
+        match &self.current {
+            Some(earlier) => Err(Error::CommandInFlight(earlier.clone())),
+            None => Ok(()),
+        }
+    }
+
+    pub fn queued(&mut self, command: &str) {
+        self.pipelined.push_back(stage::keyword(command));
+    }
+
+    pub fn answered(&mut self) {
+        self.pipelined.pop_front();
+    }
+
+    /// The connection broke, so no more replies are coming
+    pub fn lost(&mut self) {
+        self.pipelined.clear();
+    }
+}
@@ -0,0 +1,144 @@
+use std::io::{self, Write};
+
+use bytes::Bytes;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+
+use crate::{Client, MessageInfo, Result};
+
+/// A hash function for [`Client::retr_with_digest()`]
+///
+/// [`Client::retr_with_digest()`]: struct.Client.html#method.retr_with_digest
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Algo {
+    Md5,
+    Sha256,
+}
+
+/// What was checked about a downloaded message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Integrity {
+    /// Octets received, after dot-unstuffing
+    pub octets: u64,
+    /// The size announced by `LIST`
+    pub listed_size: u64,
+    /// Fewer octets arrived than `LIST` announced
+    ///
+    /// More octets are not flagged, as some servers announce sizes with bare LF line endings.
+    pub truncated: bool,
+    pub digest: Vec<u8>,
+}
+
+impl Integrity {
+    /// The digest in lowercase hex
+    pub fn hex(&self) -> String {
+        self.digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+enum Hasher {
+    Md5(Md5),
+    Sha256(Sha256),
+}
+
+/// Hashes and counts whatever goes through it
+struct Hashing<'w, W: Write + ?Sized> {
+    inner: &'w mut W,
+    hasher: Hasher,
+}
+
+impl<W: Write + ?Sized> Write for Hashing<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        match &mut self.hasher {
+            Hasher::Md5(hasher)    => hasher.update(&buf[..len]),
+            Hasher::Sha256(hasher) => hasher.update(&buf[..len]),
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl Client {
+    /// Same as [`retr_with_digest()`], streaming into `writer`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::{Algo, Client};
+    /// # use std::fs::File;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let mut file = File::create("1.eml").map_err(pop3_client::Error::Io)?;
+    /// let integrity = client.retr_to_writer_with_digest(1, &mut file, Algo::Sha256)?;
+    /// if integrity.truncated {
+    ///     eprintln!("only {} of {} octets", integrity.octets, integrity.listed_size);
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`list()`] and [`retr_to_writer()`].
+    ///
+    /// [`retr_with_digest()`]: struct.Client.html#method.retr_with_digest
+    /// [`list()`]: struct.Client.html#method.list
+    /// [`retr_to_writer()`]: struct.Client.html#method.retr_to_writer
+    pub fn retr_to_writer_with_digest<W: Write + ?Sized>(
+        &mut self,
+        msg: u32,
+        writer: &mut W,
+        algo: Algo,
+    ) -> Result<Integrity> {
+        let listed_size = self.list_entries(Some(msg))?.first().map_or(0, |MessageInfo { size, .. }| *size);
+
+        let mut hashing = Hashing {
+            inner: writer,
+            hasher: match algo {
+                Algo::Md5    => Hasher::Md5(Md5::new()),
+                Algo::Sha256 => Hasher::Sha256(Sha256::new()),
+            },
+        };
+        let octets = self.retr_to_writer(msg, &mut hashing)?;
+        let digest = match hashing.hasher {
+            Hasher::Md5(hasher)    => hasher.finalize().to_vec(),
+            Hasher::Sha256(hasher) => hasher.finalize().to_vec(),
+        };
+
+        Ok(Integrity {
+            octets,
+            listed_size,
+            truncated: octets < listed_size,
+            digest,
+        })
+    }
+
+    /// Download the message, hashing it on the way, and check its size against `LIST`
+    ///
+    /// The message is dot-unstuffed, as written by [`retr_to_writer()`], and so is what the digest covers.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::{Algo, Client};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let (message, integrity) = client.retr_with_digest(1, Algo::Sha256)?;
+    /// println!("{} octets, sha256 {}", message.len(), integrity.hex());
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`list()`] and [`retr()`].
+    ///
+    /// [`retr_to_writer()`]: struct.Client.html#method.retr_to_writer
+    /// [`list()`]: struct.Client.html#method.list
+    /// [`retr()`]: struct.Client.html#method.retr
+    pub fn retr_with_digest(&mut self, msg: u32, algo: Algo) -> Result<(Bytes, Integrity)> {
+        let mut message = vec![];
+        let integrity = self.retr_to_writer_with_digest(msg, &mut message, algo)?;
+        Ok((Bytes::from(message), integrity))
+    }
+}
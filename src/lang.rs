@@ -0,0 +1,80 @@
+use crate::{Client, Error, Result};
+
+/// A response language offered by the server, see [RFC 6856]
+///
+/// [RFC 6856]: https://tools.ietf.org/html/rfc6856#section-3
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Language {
+    /// Language tag, e.g. `en` or `pt-BR`
+    pub tag: String,
+    /// Human-readable description, possibly in the language itself
+    pub description: String,
+}
+
+impl Language {
+    fn parse(line: &str) -> Result<Self> {
+        let line = line.trim();
+        let mut parts = line.splitn(2, ' ');
+        let tag = parts
+            .next()
+            .filter(|tag| !tag.is_empty())
+            .ok_or_else(|| Error::InvalidReply(line.to_string()))?;
+
+        Ok(Self {
+            tag: tag.to_string(),
+            description: parts.next().unwrap_or("").trim().to_string(),
+        })
+    }
+}
+
+impl Client {
+    /// List the response languages the server offers, or select one of them (the `LANG` command)
+    ///
+    /// With `None`, returns all the available languages. With a tag, asks the server to switch its
+    /// response texts to that language and returns the single language it confirmed; `"*"` lets the
+    /// server pick the default. The selected tag is then reported by [`language()`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let available = client.lang(None)?;
+    /// if available.iter().any(|l| l.tag == "es") {
+    ///     client.lang(Some("es"))?;
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// The server may return an error response if:
+    /// - It does not support the `LANG` capability
+    /// - The requested language is not available
+    ///
+    /// [`language()`]: struct.Client.html#method.language
+    pub fn lang(&mut self, tag: Option<&str>) -> Result<Vec<Language>> {
+        match tag {
+            None => self
+                .query_string("LANG", true)?
+                .lines()
+                .skip(1)
+                .filter(|line| !line.trim().is_empty())
+                .map(Language::parse)
+                .collect(),
+            Some(tag) => {
+                let reply = self.query_string(&format!("LANG {}", tag), false)?;
+                let language = Language::parse(&reply)?;
+                self.language = Some(language.tag.clone());
+                Ok(vec![language])
+            }
+        }
+    }
+
+    /// Tag of the response language selected with [`lang()`], if any
+    ///
+    /// [`lang()`]: struct.Client.html#method.lang
+    pub fn language(&self) -> Option<&str> {
+        self.language.as_deref()
+    }
+}
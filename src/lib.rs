@@ -1,8 +1,76 @@
-use std::io::BufRead;
-use std::io::{BufReader, Write};
-use std::net::TcpStream;
+use std::fmt;
+use std::io::{BufReader, Read, Write};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
 
-use bytes::{Bytes, BytesMut, BufMut};
+use bytes::Bytes;
+#[cfg(feature = "with-encoding")]
+use bytes::{BufMut, BytesMut};
+
+mod attachment;
+#[cfg(feature = "async")]
+mod async_client;
+mod audit;
+mod auth;
+mod batch;
+mod cache;
+mod capa;
+mod command;
+mod compliance;
+mod connection;
+mod dedup;
+mod encoded_word;
+mod error;
+mod factory;
+mod fetch_stream;
+mod greeting;
+mod health;
+mod in_flight;
+#[cfg(feature = "gssapi")]
+pub mod gssapi;
+mod integrity;
+mod lang;
+mod mailbox;
+mod message;
+mod normalize;
+#[cfg(feature = "ntlm")]
+mod ntlm;
+mod parallel;
+mod progress;
+mod proxy;
+mod quirks;
+#[cfg(feature = "record")]
+pub mod record;
+mod response;
+mod retry;
+#[cfg(feature = "scram")]
+mod scram;
+mod stage;
+#[cfg(feature = "with-rustls")]
+mod tls;
+pub mod secret;
+#[cfg(feature = "server")]
+pub mod server;
+pub mod fetcher;
+pub mod migrate;
+pub mod monitor;
+pub mod parser;
+pub mod pool;
+pub mod proto;
+pub mod search;
+pub mod state;
+pub mod store;
+mod stream;
+pub mod telemetry;
+#[cfg(feature = "test-util")]
+pub mod test_util;
+mod undo;
+
+use cache::{Cache, UidMap};
+pub use secret::{AccountRef, Credentials};
+use secret::{Password, Sensitive, CREDENTIAL_ATTEMPTS};
+use stream::{SocketOptions, Stream, Throttled, Watched, WriteBuffered};
+use undo::UndoDeletes;
 
 #[cfg(feature = "with-rustls")]
 use {
@@ -12,7 +80,48 @@ use {
     webpki::DNSNameRef,
 };
 
-pub type Result<T> = std::result::Result<T, String>;
+pub use attachment::AttachmentInfo;
+#[cfg(feature = "async")]
+pub use async_client::{AsyncClient, AsyncTransport};
+#[cfg(feature = "async-futures")]
+pub use async_client::FuturesIo;
+#[cfg(feature = "async-tokio")]
+pub use async_client::Tokio;
+pub use audit::{AuditEvent, AuditOutcome};
+pub use auth::{Apop, AuthMechanism, Authenticator, CramMd5, DigestMd5, SaslPlain, UserPass, XOAuth2};
+pub use batch::{Batch, BatchError, BatchFailure, BatchReport, Outcome};
+pub use capa::{Capabilities, RetentionPolicy};
+pub use command::MAX_COMMAND_LEN;
+pub use compliance::Compliance;
+pub use connection::Connection;
+pub use dedup::Dedup;
+pub use error::{AuthError, AuthStep, Error, ErrorCode};
+pub use factory::ClientFactory;
+pub use fetch_stream::FetchStream;
+pub use greeting::Greeting;
+pub use health::{Health, LatencyStats};
+pub use integrity::{Algo, Integrity};
+pub use lang::Language;
+pub use mailbox::{Listing, MailboxStat, MessageInfo, TopResult, UidlEntry};
+pub use message::Message;
+pub use normalize::Normalize;
+#[cfg(feature = "ntlm")]
+pub use ntlm::Ntlm;
+pub use parallel::PartialFetch;
+pub use progress::Progress;
+pub use proxy::ProxyHeader;
+pub use quirks::{Quirks, ServerType};
+pub use response::{Response, Status};
+use response::{copy_payload, Frame, Limits};
+pub use retry::RetryPolicy;
+#[cfg(feature = "scram")]
+pub use scram::Scram;
+pub use stage::Stage;
+pub use stream::CancellationToken;
+#[cfg(feature = "with-rustls")]
+pub use tls::{TlsPolicy, TlsVersion};
+
+pub type Result<T> = std::result::Result<T, Error>;
 
 
 /// A builder to create a [`Client`] with a connection.
@@ -21,27 +130,153 @@ pub type Result<T> = std::result::Result<T, String>;
 ///
 /// [`Client`]: struct.Client
 /// [`ClientConfig`]: https://docs.rs/rustls/0.15.2/rustls/struct.ClientConfig.html
+#[derive(Clone)]
 pub struct Builder {
     #[cfg(feature = "with-rustls")]
     config: Arc<ClientConfig>,
+    #[cfg(feature = "with-rustls")]
+    tls: TlsPolicy,
+    #[cfg(feature = "with-rustls")]
+    tls_resumption: bool,
+    /// The oldest and newest TLS versions allowed
+    #[cfg(feature = "with-rustls")]
+    tls_versions: (TlsVersion, TlsVersion),
+    cache: bool,
+    retry: RetryPolicy,
+    utf8: bool,
+    compliance: Compliance,
+    limits: Limits,
+    /// Shared by the clones, so that sessions and factories don't each keep a copy of the password
+    credentials: Option<std::sync::Arc<Credentials>>,
+    credentials_provider: Option<secret::Provider>,
+    auth_mechanism: AuthMechanism,
+    mechanisms: Option<Vec<String>>,
+    plaintext_auth: bool,
+    line_ending: Normalize,
+    command_timeout: Option<Duration>,
+    authenticator: Option<std::sync::Arc<dyn Authenticator>>,
+    progress: Option<progress::Callback>,
+    throttle: Option<u64>,
+    inter_command_delay: Option<Duration>,
+    undo_deletes_on_error: bool,
+    read_buffer_size: Option<usize>,
+    socket: SocketOptions,
+    size_tolerance: Option<u32>,
+    banner: Option<greeting::Check>,
+    /// Commands kept for `Client::audit()`
+    audit_log: usize,
+    capability_checks: bool,
+    auto_retry_idempotent: bool,
+    #[cfg(feature = "record")]
+    record: Option<std::path::PathBuf>,
 }
 
 impl Default for Builder {
     #[cfg(not(feature = "with-rustls"))]
     fn default() -> Self {
-        Self {}
+        Self {
+            cache: false,
+            retry: RetryPolicy::none(),
+            utf8: false,
+            compliance: Compliance::default(),
+            limits: Limits::default(),
+            credentials: None,
+            credentials_provider: None,
+            auth_mechanism: AuthMechanism::default(),
+            mechanisms: None,
+            plaintext_auth: false,
+            line_ending: Normalize::default(),
+            command_timeout: None,
+            authenticator: None,
+            progress: None,
+            throttle: None,
+            inter_command_delay: None,
+            undo_deletes_on_error: false,
+            read_buffer_size: None,
+            socket: SocketOptions::default(),
+            size_tolerance: None,
+            banner: None,
+            audit_log: 0,
+            capability_checks: false,
+            auto_retry_idempotent: false,
+            #[cfg(feature = "record")]
+            record: None,
+        }
     }
 
     #[cfg(feature = "with-rustls")]
     fn default() -> Self {
-        let mut config = ClientConfig::new();
-        config
-            .root_store
-            .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
-
-        let config = Arc::new(config);
+        Self {
+            config: tls::default_config(),
+            tls: TlsPolicy::Required,
+            tls_resumption: true,
+            tls_versions: (TlsVersion::Tls1_2, TlsVersion::Tls1_3),
+            cache: false,
+            retry: RetryPolicy::none(),
+            utf8: false,
+            compliance: Compliance::default(),
+            limits: Limits::default(),
+            credentials: None,
+            credentials_provider: None,
+            auth_mechanism: AuthMechanism::default(),
+            mechanisms: None,
+            plaintext_auth: false,
+            line_ending: Normalize::default(),
+            command_timeout: None,
+            authenticator: None,
+            progress: None,
+            throttle: None,
+            inter_command_delay: None,
+            undo_deletes_on_error: false,
+            read_buffer_size: None,
+            socket: SocketOptions::default(),
+            size_tolerance: None,
+            banner: None,
+            audit_log: 0,
+            capability_checks: false,
+            auto_retry_idempotent: false,
+            #[cfg(feature = "record")]
+            record: None,
+        }
+    }
+}
 
-        Self { config }
+/// The settings, with the credentials only told apart from their absence
+impl fmt::Debug for Builder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut debug = f.debug_struct("Builder");
+        #[cfg(feature = "with-rustls")]
+        debug
+            .field("tls", &self.tls)
+            .field("tls_resumption", &self.tls_resumption)
+            .field("tls_versions", &self.tls_versions)
+            .field("trusted_roots", &self.config.root_store.len());
+        debug
+            .field("cache", &self.cache)
+            .field("retry", &self.retry)
+            .field("utf8", &self.utf8)
+            .field("compliance", &self.compliance)
+            .field("limits", &self.limits)
+            .field("credentials", &self.credentials.as_ref().map(|_| "<redacted>"))
+            .field("credentials_provider", &self.credentials_provider.is_some())
+            .field("auth_mechanism", &self.auth_mechanism)
+            .field("mechanisms", &self.mechanisms)
+            .field("plaintext_auth", &self.plaintext_auth)
+            .field("line_ending", &self.line_ending)
+            .field("command_timeout", &self.command_timeout)
+            .field("authenticator", &self.authenticator.is_some())
+            .field("throttle", &self.throttle)
+            .field("inter_command_delay", &self.inter_command_delay)
+            .field("undo_deletes_on_error", &self.undo_deletes_on_error)
+            .field("read_buffer_size", &self.read_buffer_size)
+            .field("socket", &self.socket)
+            .field("size_tolerance", &self.size_tolerance)
+            .field("audit_log", &self.audit_log)
+            .field("capability_checks", &self.capability_checks)
+            .field("auto_retry_idempotent", &self.auto_retry_idempotent);
+        #[cfg(feature = "record")]
+        debug.field("record", &self.record);
+        debug.finish_non_exhaustive()
     }
 }
 
@@ -52,10 +287,9 @@ impl Builder {
     /// # Example
     ///
     /// ```no_run
-    /// # use std::result::Result;
     /// # use pop3_client::Builder;
     /// #
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     ///      let client = Builder::default().connect("my.host.com", 110)?;
     ///
     /// #    Ok(())
@@ -67,7 +301,29 @@ impl Builder {
     /// [`Client::connect()`]: struct.Client.html#method.connect
     #[cfg(not(feature = "with-rustls"))]
     pub fn connect(&mut self, host: &str, port: u16) -> Result<Client> {
-        Client::connect_notls(host, port)
+        self.connect_to(host, port, None)
+    }
+
+    #[cfg(not(feature = "with-rustls"))]
+    fn connect_to(&mut self, host: &str, port: u16, address: Option<SocketAddr>) -> Result<Client> {
+        let session = telemetry::Span::session(host);
+        let mut attempts = 0;
+        session
+            .connect(port)
+            .in_scope(|| {
+                self.retry.run(Error::is_transient, || {
+                    attempts += 1;
+                    if attempts > 1 {
+                        telemetry::reconnected(host, self.account());
+                    }
+                    Client::connect_notls(host, port, address, &self.socket)
+                })
+            })
+            .and_then(|mut client| {
+                client.labels.session = session.clone();
+                client.origin = Some(Box::new(Origin { builder: self.clone(), host: host.to_string(), port, address }));
+                session.in_scope(|| self.configure(client))
+            })
     }
 
     /// Connect to the designated host and port using TLS
@@ -79,18 +335,172 @@ impl Builder {
     /// [`Client::connect()`]: struct.Client.html#method.connect
     #[cfg(feature = "with-rustls")]
     pub fn connect(&mut self, host: &str, port: u16) -> Result<Client> {
-        Client::connect_rustls(host, port, self.config.clone())
+        self.connect_to(host, port, None)
+    }
+
+    #[cfg(feature = "with-rustls")]
+    fn connect_to(&mut self, host: &str, port: u16, address: Option<SocketAddr>) -> Result<Client> {
+        let session = telemetry::Span::session(host);
+        let mut attempts = 0;
+        session
+            .connect(port)
+            .in_scope(|| {
+                self.retry.run(Error::is_transient, || {
+                    attempts += 1;
+                    if attempts > 1 {
+                        telemetry::reconnected(host, self.account());
+                    }
+                    let config = if self.tls_resumption {
+                        self.config.clone()
+                    } else {
+                        tls::without_resumption(&self.config)
+                    };
+                    Client::connect_rustls(host, port, address, &self.socket, config, self.tls)
+                })
+            })
+            .and_then(|mut client| {
+                client.labels.session = session.clone();
+                client.origin = Some(Box::new(Origin { builder: self.clone(), host: host.to_string(), port, address }));
+                session.in_scope(|| self.configure(client))
+            })
+    }
+
+    /// Connect to `address` without looking up any name, still checking the TLS certificate against `tls_name`
+    ///
+    /// For containers, tests and mail hosts on a fixed address. `tls_name` also stands for the host in
+    /// telemetry; without the `with-rustls` feature that's all it is used for.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let address = "192.0.2.10:110".parse().unwrap();
+    /// let mut client = Builder::default().connect_addr(address, "mail.example.com")?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// The same as [`connect()`].
+    ///
+    /// [`connect()`]: struct.Builder.html#method.connect
+    pub fn connect_addr(&mut self, address: SocketAddr, tls_name: &str) -> Result<Client> {
+        self.connect_to(tls_name, address.port(), Some(address))
+    }
+
+    /// Start a session over an already established connection, with the settings of this builder
+    ///
+    /// Same as [`Client::from_stream()`]: no TLS is negotiated, whatever [`tls()`] says.
+    ///
+    /// [`Client::from_stream()`]: struct.Client.html#method.from_stream
+    /// [`tls()`]: struct.Builder.html#method.tls
+    pub fn from_stream<S: Read + Write + Send + 'static>(&mut self, stream: S) -> Result<Client> {
+        let session = telemetry::Span::session("");
+        session
+            .in_scope(|| Client::from_stream(stream))
+            .and_then(|mut client| {
+                client.labels.session = session.clone();
+                session.in_scope(|| self.configure(client))
+            })
+    }
+
+    /// Upgrade the connection with `STLS` (the default), or stay on plain text
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// #
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let client = Builder::default().tls(false).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    #[cfg(feature = "with-rustls")]
+    pub fn tls(&mut self, enabled: bool) -> &mut Self {
+        self.tls = if enabled { TlsPolicy::Required } else { TlsPolicy::Never };
+        self
+    }
+
+    /// Choose whether to upgrade with `STLS`, and what to do when the server refuses; [`tls()`] in more detail
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::{Builder, TlsPolicy};
+    /// #
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let client = Builder::default().tls_policy(TlsPolicy::Opportunistic).connect("my.host.com", 110)?;
+    /// if !client.is_tls() {
+    ///     eprintln!("warning: the password goes in plain text");
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`tls()`]: struct.Builder.html#method.tls
+    #[cfg(feature = "with-rustls")]
+    pub fn tls_policy(&mut self, policy: TlsPolicy) -> &mut Self {
+        self.tls = policy;
+        self
+    }
+
+    /// Let [`Client::login()`] and `AUTH PLAIN` send the password on a connection without TLS, which they refuse by
+    /// default
+    ///
+    /// Streams given to [`from_stream()`] count as plain text; allow it when they are encrypted underneath.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// #
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let client = Builder::default()
+    ///     .allow_plaintext_auth(true)
+    ///     .credentials("sweet_username", "very_secret_password")
+    ///     .open("localhost", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Client::login()`]: struct.Client.html#method.login
+    /// [`from_stream()`]: struct.Builder.html#method.from_stream
+    pub fn allow_plaintext_auth(&mut self, allowed: bool) -> &mut Self {
+        self.plaintext_auth = allowed;
+        self
+    }
+
+    /// Resume earlier TLS sessions with the same server, on by default
+    ///
+    /// Resuming skips most of the handshake, which adds up for clients polling often. Sessions are remembered
+    /// in the [`ClientConfig`], which the default one shares between all builders, so reconnects, retries and the
+    /// connections of a [`ClientPool`] all benefit. Turn it off to get a full handshake every time, e.g. to
+    /// check the certificate on each connection.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let client = Builder::default().tls_resumption(false).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`ClientConfig`]: https://docs.rs/rustls/0.19/rustls/struct.ClientConfig.html
+    /// [`ClientPool`]: pool/struct.ClientPool.html
+    #[cfg(feature = "with-rustls")]
+    pub fn tls_resumption(&mut self, enabled: bool) -> &mut Self {
+        self.tls_resumption = enabled;
+        self
     }
 
     /// Define a custom config for the TLS connection
     ///
+    /// Sessions are only resumed between connections sharing the same config, see [`tls_resumption()`].
+    ///
     /// # Example
     /// ```no_run
-    /// # use std::result::Result;
     /// # use pop3_client::Builder;
     ///   use rustls::ClientConfig;
     /// #
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     ///
     /// let mut config = ClientConfig::new();
     /// config
@@ -101,11 +511,636 @@ impl Builder {
     /// #    Ok(())
     /// # }
     /// ```
+    ///
+    /// [`tls_resumption()`]: struct.Builder.html#method.tls_resumption
     #[cfg(feature = "with-rustls")]
     pub fn rustls_config(&mut self, config: ClientConfig) -> &mut Self {
         self.config = Arc::new(config);
         self
     }
+
+    /// Keep the last `STAT`, `LIST` and `UIDL` results on the client
+    ///
+    /// The cached values are served by [`Client::cached_stat()`], [`Client::cached_list()`] and [`Client::cached_uidl()`], and are dropped whenever `DELE` or `RSET` changes the mailbox state.
+    ///
+    /// The message count of a `STAT` taken before anything was deleted also bounds the message numbers: higher ones
+    /// are refused without a round trip, with [`Error::InvalidMessageNumber`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// #
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default().cache(true).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Client::cached_stat()`]: struct.Client.html#method.cached_stat
+    /// [`Client::cached_list()`]: struct.Client.html#method.cached_list
+    /// [`Client::cached_uidl()`]: struct.Client.html#method.cached_uidl
+    /// [`Error::InvalidMessageNumber`]: enum.Error.html#variant.InvalidMessageNumber
+    pub fn cache(&mut self, enabled: bool) -> &mut Self {
+        self.cache = enabled;
+        self
+    }
+
+    /// Retry failed connection attempts, and commands the server reports to have failed temporarily
+    ///
+    /// Connecting is retried on any transient failure (see [`Error::is_transient()`]).
+    /// Commands are only retried on `-ERR [IN-USE]` and `-ERR [SYS/TEMP]` replies: after a dropped or timed out
+    /// connection the session state is lost, so those are reported to the caller.
+    ///
+    /// [`Error::is_transient()`]: enum.Error.html#method.is_transient
+    pub fn retries(&mut self, policy: RetryPolicy) -> &mut Self {
+        self.retry = policy;
+        self
+    }
+
+    /// Switch the session to UTF-8 with the `UTF8` command right after connecting
+    ///
+    /// See [`Client::utf8()`] for what this changes.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default().enable_utf8(true).connect("my.host.com", 110)?;
+    /// client.login("пользователь", "пароль")?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Client::utf8()`]: struct.Client.html#method.utf8
+    pub fn enable_utf8(&mut self, enabled: bool) -> &mut Self {
+        self.utf8 = enabled;
+        self
+    }
+
+    /// Choose how strictly replies have to follow the protocol, see [`Compliance`]
+    ///
+    /// The greeting is always read leniently; the mode applies from the first command on.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::{Builder, Compliance};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default().compliance(Compliance::Strict).connect("localhost", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Compliance`]: enum.Compliance.html
+    pub fn compliance(&mut self, compliance: Compliance) -> &mut Self {
+        self.compliance = compliance;
+        self
+    }
+
+    /// Cap the size of a whole reply, in octets
+    ///
+    /// A reply going over the limit fails with [`Error::ResponseTooLarge`], and the connection is closed,
+    /// as the rest of the reply can't be told apart from the next one. Mind that `RETR` replies contain the whole message.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default()
+    ///     .max_response_size(64 * 1024 * 1024)
+    ///     .max_line_length(64 * 1024)
+    ///     .connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Error::ResponseTooLarge`]: enum.Error.html#variant.ResponseTooLarge
+    pub fn max_response_size(&mut self, octets: usize) -> &mut Self {
+        self.limits.max_response_size = Some(octets);
+        self
+    }
+
+    /// Cap the length of a single reply line, in octets including the line ending; 1 MiB by default
+    ///
+    /// A line going over the limit fails with [`Error::LineTooLong`], and the connection is closed.
+    /// With [`Compliance::Strict`], status lines are further held to the 512 octets of [RFC 2449].
+    ///
+    /// [`Error::LineTooLong`]: enum.Error.html#variant.LineTooLong
+    /// [`Compliance::Strict`]: enum.Compliance.html#variant.Strict
+    /// [RFC 2449]: https://tools.ietf.org/html/rfc2449#section-4
+    pub fn max_line_length(&mut self, octets: usize) -> &mut Self {
+        self.limits.max_line_length = Some(octets);
+        self
+    }
+
+    /// Remember the credentials for [`open()`]
+    ///
+    /// [`open()`]: struct.Builder.html#method.open
+    pub fn credentials(&mut self, username: &str, password: &str) -> &mut Self {
+        self.credentials = Some(std::sync::Arc::new(Credentials::new(username, password)));
+        self
+    }
+
+    /// Ask `provider` for the credentials in [`open()`], only once connected, instead of storing them
+    ///
+    /// This is the place for a keyring lookup or a password prompt. When the server refuses the login,
+    /// the provider is asked again with the next [`AccountRef::attempt`], up to 3 attempts; returning an error
+    /// gives up right away. Takes precedence over [`credentials()`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::{Builder, Credentials, Error};
+    /// # fn prompt(question: &str) -> String { unimplemented!() }
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default()
+    ///     .credentials_provider(|account| {
+    ///         if account.attempt > 1 {
+    ///             eprintln!("wrong password, try again");
+    ///         }
+    ///         let password = prompt(&format!("Password for {}: ", account.host));
+    ///         Ok(Credentials::new("sweet_username", &password))
+    ///     })
+    ///     .open("my.host.com", 995)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`open()`]: struct.Builder.html#method.open
+    /// [`credentials()`]: struct.Builder.html#method.credentials
+    /// [`AccountRef::attempt`]: struct.AccountRef.html#structfield.attempt
+    pub fn credentials_provider<F>(&mut self, provider: F) -> &mut Self
+    where
+        F: Fn(&AccountRef) -> Result<Credentials> + Send + Sync + 'static,
+    {
+        self.credentials_provider = Some(std::sync::Arc::new(provider));
+        self
+    }
+
+    /// Choose how [`open()`] authenticates, `USER`/`PASS` by default
+    ///
+    /// [`open()`]: struct.Builder.html#method.open
+    pub fn auth_mechanism(&mut self, mechanism: AuthMechanism) -> &mut Self {
+        self.auth_mechanism = mechanism;
+        self
+    }
+
+    /// Mechanisms for [`Client::auto_auth()`] to pick from, most preferred first, e.g. `["CRAM-MD5", "USER"]`
+    ///
+    /// Names are those of `CAPA`, plus `USER` for `USER`/`PASS` and `APOP`; the `-PLUS` variants of SCRAM need
+    /// TLS. Mechanisms the server doesn't offer are skipped.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::{AuthMechanism, Builder};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let client = Builder::default()
+    ///     .credentials("sweet_username", "very_secret_password")
+    ///     .auth_mechanism(AuthMechanism::Auto)
+    ///     .mechanisms(&["SCRAM-SHA-256", "PLAIN"])
+    ///     .open("my.host.com", 995)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Client::auto_auth()`]: struct.Client.html#method.auto_auth
+    pub fn mechanisms(&mut self, mechanisms: &[&str]) -> &mut Self {
+        self.mechanisms = Some(mechanisms.iter().map(|m| m.to_ascii_uppercase()).collect());
+        self
+    }
+
+    /// Authenticate with `authenticator` in [`open()`], instead of the [`credentials()`]
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::{Builder, XOAuth2};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default()
+    ///     .authenticator(XOAuth2::new("sweet_username@gmail.com", "ya29.access-token"))
+    ///     .open("pop.gmail.com", 995)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`open()`]: struct.Builder.html#method.open
+    /// [`credentials()`]: struct.Builder.html#method.credentials
+    pub fn authenticator<A: Authenticator + 'static>(&mut self, authenticator: A) -> &mut Self {
+        self.authenticator = Some(std::sync::Arc::new(authenticator));
+        self
+    }
+
+    /// Connect, upgrade to TLS if enabled, and authenticate with the [`authenticator()`] or the stored
+    /// [`credentials()`] in one go
+    ///
+    /// The returned client is in the Transaction stage, ready to work with the mailbox.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default()
+    ///     .credentials("sweet_username", "very_secret_password")
+    ///     .open("my.host.com", 110)?;
+    /// let (messages, octets) = client.stat()?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Fails with [`Error::InvalidArgument`] when no credentials were given. Otherwise, the errors are the ones of
+    /// [`connect()`], of the [`credentials_provider()`] and of the chosen authentication method.
+    ///
+    /// [`authenticator()`]: struct.Builder.html#method.authenticator
+    /// [`credentials()`]: struct.Builder.html#method.credentials
+    /// [`connect()`]: struct.Builder.html#method.connect
+    /// [`credentials_provider()`]: struct.Builder.html#method.credentials_provider
+    /// [`Error::InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    pub fn open(&mut self, host: &str, port: u16) -> Result<Client> {
+        self.open_to(host, port, None)
+    }
+
+    /// Same as `open()`, to `address` when given
+    fn open_to(&mut self, host: &str, port: u16, address: Option<SocketAddr>) -> Result<Client> {
+        if let Some(authenticator) = self.authenticator.clone() {
+            let mut client = self.connect_to(host, port, address)?;
+            authenticator.authenticate(&mut client)?;
+            return Ok(client);
+        }
+        if let Some(provider) = self.credentials_provider.clone() {
+            let mut client = self.connect_to(host, port, address)?;
+            let mut attempt = 1;
+            loop {
+                let credentials = provider(&AccountRef { host, port, attempt })?;
+                match self.log_in(&mut client, &credentials) {
+                    Err(Error::Auth(_)) if attempt < CREDENTIAL_ATTEMPTS => attempt += 1,
+                    result => return result.map(|_| client),
+                }
+            }
+        }
+        let credentials = self.credentials
+            .clone()
+            .ok_or_else(|| Error::InvalidArgument("no credentials given".to_string()))?;
+
+        let mut client = self.connect_to(host, port, address)?;
+        self.log_in(&mut client, &credentials)?;
+        Ok(client)
+    }
+
+    fn log_in(&self, client: &mut Client, credentials: &Credentials) -> Result<()> {
+        match self.auth_mechanism {
+            AuthMechanism::UserPass => client.login(&credentials.username, &credentials.password),
+            AuthMechanism::Apop     => client.apop_with_password(&credentials.username, &credentials.password).map(|_| ()),
+            AuthMechanism::Auto     => client.auto_auth(credentials),
+        }
+    }
+
+    /// Call `callback` while messages are being downloaded, with the octets received so far and the size `LIST` expects
+    ///
+    /// The callback runs at least once per message, and then once every 64 KiB. Finding the size takes an extra
+    /// `LIST` before each download.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default()
+    ///     .progress(|p| match p.expected {
+    ///         Some(size) => eprint!("\r{}/{} octets", p.received, size),
+    ///         None       => eprint!("\r{} octets", p.received),
+    ///     })
+    ///     .connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    pub fn progress<F: Fn(Progress) + Send + Sync + 'static>(&mut self, callback: F) -> &mut Self {
+        self.progress = Some(std::sync::Arc::new(callback));
+        self
+    }
+
+    /// Read replies at most `bytes_per_sec` octets per second, so bulk downloads leave room on slow links
+    ///
+    /// Short bursts of up to one second worth of octets go through at full speed. Commands are not throttled.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let client = Builder::default().throttle(256 * 1024).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    pub fn throttle(&mut self, bytes_per_sec: u64) -> &mut Self {
+        self.throttle = Some(bytes_per_sec);
+        self
+    }
+
+    /// Leave at least `delay` between sending two commands, for providers throttling or blocking clients which
+    /// send them in quick succession
+    ///
+    /// The delay counts from when the previous command went out, so it's mostly spent waiting for its reply
+    /// anyway. Pipelined commands leave together, and wait once. The command timeout only starts once the
+    /// delay is over.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # use std::time::Duration;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let client = Builder::default().inter_command_delay(Duration::from_millis(200)).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    pub fn inter_command_delay(&mut self, delay: Duration) -> &mut Self {
+        self.inter_command_delay = Some(delay);
+        self
+    }
+
+    /// Unmark the deleted messages with `RSET` when a session fails midway, so a half done job isn't committed
+    ///
+    /// Once messages are marked as deleted, any failure but an `-ERR` reply, e.g. a timeout or a writer failing
+    /// in [`Client::retr_to_writer()`], makes [`Client::quit()`] send `RSET` before `QUIT`. Dropping the client
+    /// without quitting sends `RSET` too, for servers which commit deletions when the connection closes. An `RSET`
+    /// of the caller's own starts over.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default()
+    ///     .undo_deletes_on_error(true)
+    ///     .credentials("sweet_username", "very_secret_password")
+    ///     .open("my.host.com", 110)?;
+    /// for id in 1..=client.stat()?.0 {
+    ///     client.retr_to_writer(id, &mut std::io::sink())?;
+    ///     client.dele(id)?;
+    /// }
+    /// client.quit()?; // only commits if every message made it
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Client::retr_to_writer()`]: struct.Client.html#method.retr_to_writer
+    /// [`Client::quit()`]: struct.Client.html#method.quit
+    pub fn undo_deletes_on_error(&mut self, enabled: bool) -> &mut Self {
+        self.undo_deletes_on_error = enabled;
+        self
+    }
+
+    /// Write a transcript of the session to `path`, with the credentials left out
+    ///
+    /// Replay it with [`Client::replay_from()`]. The format is described in the [`record`] module.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default().record_to("session.log").connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Client::replay_from()`]: struct.Client.html#method.replay_from
+    /// [`record`]: record/index.html
+    #[cfg(feature = "record")]
+    pub fn record_to<P: AsRef<std::path::Path>>(&mut self, path: P) -> &mut Self {
+        self.record = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Set the capacity of the buffer replies are read through, in octets; 8 KiB by default
+    ///
+    /// A larger buffer means fewer reads from the socket, which pays off when downloading many or large
+    /// messages, e.g. when migrating a mailbox.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default().read_buffer_size(256 * 1024).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    pub fn read_buffer_size(&mut self, octets: usize) -> &mut Self {
+        self.read_buffer_size = Some(octets);
+        self
+    }
+
+    /// Send commands right away instead of waiting for the previous segment to be acknowledged (Nagle's algorithm)
+    ///
+    /// Each command is a single short write, so on some networks this takes a noticeable delay off every round trip.
+    /// The system default is kept unless this is called.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default().tcp_nodelay(true).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    pub fn tcp_nodelay(&mut self, enabled: bool) -> &mut Self {
+        self.socket.nodelay = Some(enabled);
+        self
+    }
+
+    /// Probe the connection with TCP keepalives once it has been idle for `idle`
+    ///
+    /// This keeps NAT and firewall entries alive, and lets a long-lived session notice a dead peer, e.g. in a
+    /// [`ClientPool`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # use std::time::Duration;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default().tcp_keepalive(Duration::from_secs(60)).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`ClientPool`]: pool/struct.ClientPool.html
+    pub fn tcp_keepalive(&mut self, idle: Duration) -> &mut Self {
+        self.socket.keepalive = Some(idle);
+        self
+    }
+
+    /// Set the size of the socket send buffer, `SO_SNDBUF`, in octets
+    ///
+    /// The system may round it, or double it as Linux does.
+    pub fn send_buffer_size(&mut self, octets: usize) -> &mut Self {
+        self.socket.send_buffer = Some(octets);
+        self
+    }
+
+    /// Set the size of the socket receive buffer, `SO_RCVBUF`, in octets
+    ///
+    /// Unlike [`read_buffer_size()`], this is the kernel's buffer; raising it helps bulk downloads over links with
+    /// a long round trip. The system may round it, or double it as Linux does.
+    ///
+    /// [`read_buffer_size()`]: struct.Builder.html#method.read_buffer_size
+    pub fn recv_buffer_size(&mut self, octets: usize) -> &mut Self {
+        self.socket.recv_buffer = Some(octets);
+        self
+    }
+
+    /// Give up on connecting after `timeout`, and then on the greeting after as long again
+    ///
+    /// Without it, connecting takes as long as the system allows, often minutes for an unreachable host, and a
+    /// server which never greets blocks forever. Both fail with an [`Error::Io`] of kind `TimedOut`. Each address
+    /// of the host is tried in turn within the same `timeout`.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # use std::time::Duration;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default().connect_timeout(Duration::from_secs(10)).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Error::Io`]: enum.Error.html#variant.Io
+    pub fn connect_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.socket.connect_timeout = Some(timeout);
+        self
+    }
+
+    /// Open every connection with a PROXY protocol `header`, before the greeting, for load balancers requiring it
+    ///
+    /// Only connections opened by [`connect()`] get it, not the streams given to [`from_stream()`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::{Builder, ProxyHeader};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let header = ProxyHeader::V2 {
+    ///     source: "192.0.2.1:56324".parse().unwrap(),
+    ///     destination: "198.51.100.1:110".parse().unwrap(),
+    /// };
+    /// let mut client = Builder::default().send_proxy_protocol(header).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`connect()`]: struct.Builder.html#method.connect
+    /// [`from_stream()`]: struct.Builder.html#method.from_stream
+    pub fn send_proxy_protocol(&mut self, header: ProxyHeader) -> &mut Self {
+        self.socket.proxy = Some(header);
+        self
+    }
+
+    /// Refuse servers whose greeting fails `check`, e.g. to make sure the port is the mail server expected
+    ///
+    /// The session ends with [`Error::UnexpectedGreeting`] before logging in, so no credentials are sent to
+    /// the wrong service.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default()
+    ///     .expect_banner(|greeting| greeting.host.as_deref() == Some("mail.example.com"))
+    ///     .connect("mail.example.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Error::UnexpectedGreeting`]: enum.Error.html#variant.UnexpectedGreeting
+    pub fn expect_banner<F: Fn(&Greeting) -> bool + Send + Sync + 'static>(&mut self, check: F) -> &mut Self {
+        self.banner = Some(std::sync::Arc::new(check));
+        self
+    }
+
+    /// Choose the line endings of downloaded messages, as the server sent them by default
+    ///
+    /// This covers everything `RETR` returns: [`Client::retr()`], [`Client::retr_bytes()`],
+    /// [`Client::retr_to_writer()`] and what is built on them, such as the maildir export. `TOP` is left alone.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::{Builder, Normalize};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default().line_ending(Normalize::Lf).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Client::retr()`]: struct.Client.html#method.retr
+    /// [`Client::retr_bytes()`]: struct.Client.html#method.retr_bytes
+    /// [`Client::retr_to_writer()`]: struct.Client.html#method.retr_to_writer
+    pub fn line_ending(&mut self, normalize: Normalize) -> &mut Self {
+        self.line_ending = normalize;
+        self
+    }
+
+    /// Check the size of every downloaded message against `LIST`, allowing a deviation of `percent`
+    ///
+    /// Messages off by more fail with [`Error::SizeMismatch`], once received in full so the session goes on.
+    /// This catches truncation by middleboxes, at the cost of a `LIST` before every `RETR`. Sizes are counted
+    /// as on the wire; leave some slack for servers announcing sizes with bare LF line endings.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default().verify_sizes(10).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Error::SizeMismatch`]: enum.Error.html#variant.SizeMismatch
+    pub fn verify_sizes(&mut self, percent: u32) -> &mut Self {
+        self.size_tolerance = Some(percent);
+        self
+    }
+
+    /// Give up on a command when its reply hasn't arrived within `timeout`, see [`Client::set_command_timeout()`]
+    ///
+    /// [`Client::set_command_timeout()`]: struct.Client.html#method.set_command_timeout
+    pub fn command_timeout(&mut self, timeout: Duration) -> &mut Self {
+        self.command_timeout = Some(timeout);
+        self
+    }
+
+    /// The user name to label metrics with before logging in
+    fn account(&self) -> &str {
+        self.credentials.as_ref().map_or("", |credentials| credentials.username.as_str())
+    }
+
+    fn configure(&self, mut client: Client) -> Result<Client> {
+        if let Some(check) = &self.banner {
+            client.check_banner(check)?;
+        }
+        client.retry = self.retry.clone();
+        client.compliance = self.compliance;
+        client.limits = self.limits;
+        client.progress = self.progress.clone();
+        client.size_tolerance = self.size_tolerance;
+        client.mechanisms = self.mechanisms.clone();
+        client.plaintext_auth = self.plaintext_auth;
+        client.line_ending = self.line_ending;
+        client.command_timeout = self.command_timeout;
+        client.undo_deletes.enabled = self.undo_deletes_on_error;
+        client.audit.capacity = self.audit_log;
+        client.capability_checks = self.capability_checks;
+        client.auto_retry_idempotent = self.auto_retry_idempotent;
+        client.conn.io.get_mut().get_mut().set_rate(self.throttle);
+        client.conn.io.get_mut().set_pause(self.inter_command_delay);
+        if let Some(octets) = self.read_buffer_size {
+            client.set_read_buffer_size(octets);
+        }
+        #[cfg(feature = "record")]
+        if let Some(path) = &self.record {
+            let transcript = std::fs::File::create(path).map_err(Error::Io)?;
+            let stream = std::mem::replace(client.stream(), Stream::Closed);
+            let recorder = record::Recorder::new(stream, transcript, &client.greeting);
+            *client.stream() = Stream::Recorded(Box::new(recorder));
+        }
+        if self.cache {
+            client.cache = Some(Cache::default());
+        }
+        if self.utf8 {
+            client.utf8()?;
+        }
+        Ok(client)
+    }
 }
 
 /// The key structure for the crate, delineating capabilities of the POP3 client as per the protocol [RFC]
@@ -120,15 +1155,79 @@ impl Builder {
 /// - The command was sent on the wrong stage. In other words, you tried to do something before you authorized.
 /// - The server returned an error response. We'll look at those within each separate method
 ///
-/// To find out more, read the output of the error you've got -- it's always a string!
+/// To find out more, read the output of the error you've got, or match on the [`Error`] variants.
+///
+/// [`Error`]: enum.Error.html
 ///
 /// [RFC]: https://tools.ietf.org/html/rfc1081
 pub struct Client {
-    #[cfg(feature = "with-rustls")]
-    client: BufReader<StreamOwned<ClientSession, TcpStream>>,
-    #[cfg(not(feature = "with-rustls"))]
-    client: BufReader<TcpStream>,
-    authorized: bool,
+    conn: Connection,
+    stage: Stage,
+    cache: Option<Cache>,
+    /// Filled from `UIDL` on first use, see `Client::msg_for_uid()`
+    uid_map: Option<UidMap>,
+    retry: RetryPolicy,
+    language: Option<String>,
+    utf8: bool,
+    compliance: Compliance,
+    limits: Limits,
+    greeting: String,
+    server_type: ServerType,
+    tls: bool,
+    progress: Option<progress::Callback>,
+    tracker: Option<progress::Tracker>,
+    size_tolerance: Option<u32>,
+    command_timeout: Option<Duration>,
+    mechanisms: Option<Vec<String>>,
+    plaintext_auth: bool,
+    login_delay: Option<Duration>,
+    pipelining: bool,
+    retention: Option<RetentionPolicy>,
+    /// From the last `CAPA` of the current stage
+    capabilities: Option<Capabilities>,
+    capability_checks: bool,
+    auto_retry_idempotent: bool,
+    latencies: health::Latencies,
+    audit: audit::AuditLog,
+    in_flight: in_flight::InFlight,
+    line_ending: Normalize,
+    labels: telemetry::Labels,
+    undo_deletes: UndoDeletes,
+    peer_addr: Option<SocketAddr>,
+    local_addr: Option<SocketAddr>,
+    connected_at: Instant,
+    origin: Option<Box<Origin>>,
+}
+
+/// Where a client connected by [`Builder::connect()`] came from, for [`Client::reconnect()`]
+///
+/// [`Builder::connect()`]: struct.Builder.html#method.connect
+/// [`Client::reconnect()`]: struct.Client.html#method.reconnect
+#[derive(Clone)]
+struct Origin {
+    builder: Builder,
+    host: String,
+    port: u16,
+    /// Given to `Builder::connect_addr()`
+    address: Option<SocketAddr>,
+}
+
+/// Where the client is connected and how far the session got; no credentials nor mail, as the connection and
+/// its buffers are left out
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("host", &self.origin.as_ref().map(|origin| &origin.host))
+            .field("port", &self.origin.as_ref().map(|origin| origin.port))
+            .field("peer_addr", &self.peer_addr)
+            .field("tls", &self.tls)
+            .field("stage", &self.stage)
+            .field("server_type", &self.server_type)
+            .field("bytes_sent", &self.bytes_sent())
+            .field("bytes_received", &self.bytes_received())
+            .field("connected_for", &self.connected_at.elapsed())
+            .finish_non_exhaustive()
+    }
 }
 
 impl Client {
@@ -139,10 +1238,9 @@ impl Client {
     /// # Example
     ///
     /// ```no_run
-    /// # use std::result::Result;
     /// # use pop3_client::Client;
     /// #
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     ///let client = Client::connect("my.host.com", 110)?;
     ///
     /// #    Ok(())
@@ -154,15 +1252,35 @@ impl Client {
         Builder::default().connect(host, port)
     }
 
+    /// Start a session over an already established connection, e.g. a tunnel or an in-memory stream in tests
+    ///
+    /// The greeting is read right away. No TLS is negotiated: wrap the stream yourself if needed.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # use std::net::TcpStream;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let stream = TcpStream::connect("127.0.0.1:1110").map_err(pop3_client::Error::Io)?;
+    /// let client = Client::from_stream(stream)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    pub fn from_stream<S: Read + Write + Send + 'static>(stream: S) -> Result<Self> {
+        let mut client = Self::new(Connection::from_stream(stream));
+        client.read_greeting()?;
+        Ok(client)
+    }
+
     /// Authorization through plaintext login and password
     ///
     /// # Example
     ///
     /// ```no_run
-    /// # use std::result::Result;
     /// # use pop3_client::Client;
     /// #
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     /// # let mut client = Client::connect("my.host.com", 110)?;
     /// client.login("sweet_username", "very_secret_password")?;
     /// #    Ok(())
@@ -173,23 +1291,50 @@ impl Client {
     /// - the username was not found
     /// - the password does not match the username
     /// - the connection to this mailbox has been locked by another device -- so you won't be able to connect until the lock is released.
-    pub fn login(&mut self, username: &str, password: &str) -> Result<()> {
-        if self.authorized {
-            return Err("login is only allowed in Authorization stage".to_string());
-        }
-        let username_query = format!("USER {}\r\n", username);
-        let password_query = format!("PASS {}\r\n", password);
-
-        self.query_string(&username_query, false)
-            .and_then(|s1| {
-                self.query_string(&password_query, false)
-                    .map(|s2| format!("{}{}", s1, s2))
-                    .map(|s| {
-                        self.authorized = true;
-                        s
-                    })
+    ///
+    /// Those come as [`Error::Auth`], telling whether `USER` or `PASS` was refused and why, or as
+    /// [`Error::MailboxLocked`] and [`Error::LoginDelay`] for the `[IN-USE]` and `[LOGIN-DELAY]` response codes. The
+    /// session stays in the Authorization stage, so `login()` can be called again.
+    ///
+    /// Non-ASCII usernames and passwords are sent as they are, as many servers take them, unless [`Compliance::Strict`]
+    /// asks for the session to be switched to UTF-8 with [`utf8()`] first. Ones containing line breaks are always
+    /// refused, as they would inject extra commands.
+    ///
+    /// Without TLS, nothing is sent and the login fails with [`Error::PlaintextAuth`], unless allowed with
+    /// [`Builder::allow_plaintext_auth()`].
+    ///
+    /// The password may be given as anything implementing [`Password`]: `str`, `String`, or a `SecretString` with the
+    /// `secrecy` feature enabled.
+    ///
+    /// [`Error::Auth`]: enum.Error.html#variant.Auth
+    /// [`Error::MailboxLocked`]: enum.Error.html#variant.MailboxLocked
+    /// [`Error::LoginDelay`]: enum.Error.html#variant.LoginDelay
+    /// [`Compliance::Strict`]: enum.Compliance.html#variant.Strict
+    /// [`utf8()`]: struct.Client.html#method.utf8
+    /// [`Error::PlaintextAuth`]: enum.Error.html#variant.PlaintextAuth
+    /// [`Builder::allow_plaintext_auth()`]: struct.Builder.html#method.allow_plaintext_auth
+    /// [`Password`]: secret/trait.Password.html
+    pub fn login<P: Password + ?Sized>(&mut self, username: &str, password: &P) -> Result<()> {
+        let password = password.expose_password();
+        if self.is_authorized() {
+            return Err(Error::WrongStage("login is only allowed in Authorization stage".to_string()));
+        }
+        self.compliance.check_credentials(self.utf8, username, password)?;
+        self.check_plaintext("USER")?;
+        let username_query = format!("USER {}", username);
+        let password_query = Sensitive::new(format!("PASS {}", password));
+
+        let retry = self.retry.clone();
+        let delay = self.login_delay;
+        self.authenticating("USER", username, |client| {
+            retry.run(Error::is_transient_reply, || {
+                client.send(&username_query, false)
+                    .map_err(|e| e.at_login(AuthStep::User, delay))?;
+                client.send(&password_query, false)
+                    .map_err(|e| e.at_login(AuthStep::Pass, delay))
             })
-            .map(|_| ())
+        })
+        .map(|_| ())
     }
 
     /// End the session, consuming the client
@@ -197,10 +1342,8 @@ impl Client {
     /// # Example
     ///
     /// ```compile_fail
-    /// # use std::result::Result;
-    /// #
     /// # use pop3_client::Client;
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     /// # let mut client = Client::connect("my.host.com", 110)?;
     ///client.quit()?;
     ///client.noop()?; // Shouldn't compile, as the client has been consumed upon quitting
@@ -208,20 +1351,68 @@ impl Client {
     /// # }
     /// ```
     pub fn quit(mut self) -> Result<()> {
-        self.query_string("QUIT\r\n", false).map(|_| ())
+        self.logout()
+    }
+
+    /// End the session and open a new one to the same server, with the settings of the [`Builder`]
+    ///
+    /// The client is back in the Authorization stage, as after [`Builder::connect()`]: log in again, possibly
+    /// as another user. Deletions are committed by the `QUIT`, unless undone by
+    /// [`Builder::undo_deletes_on_error()`]. A failing `QUIT` doesn't stop the reconnection, the old
+    /// connection may well be gone already.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// client.login("alice", "alice_password")?;
+    /// client.reconnect()?;
+    /// client.login("bob", "bob_password")?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Fails with [`Error::InvalidArgument`] for clients made by [`from_stream()`], which have nothing to
+    /// reconnect to. Otherwise, the errors are the ones of [`Builder::connect()`]; the client is then left
+    /// disconnected, and may try again.
+    ///
+    /// [`Builder`]: struct.Builder.html
+    /// [`Builder::connect()`]: struct.Builder.html#method.connect
+    /// [`Builder::undo_deletes_on_error()`]: struct.Builder.html#method.undo_deletes_on_error
+    /// [`Error::InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    /// [`from_stream()`]: struct.Client.html#method.from_stream
+    pub fn reconnect(&mut self) -> Result<()> {
+        let Origin { mut builder, host, port, address } = match &self.origin {
+            Some(origin) => Origin::clone(origin),
+            None => return Err(Error::InvalidArgument("the client wasn't connected by host and port".to_string())),
+        };
+        if !self.undo_deletes.quitting {
+            self.logout().ok();
+        }
+        *self = builder.connect_to(&host, port, address)?;
+        Ok(())
+    }
+
+    fn logout(&mut self) -> Result<()> {
+        if self.undo_deletes.due() {
+            self.rset()?;
+        }
+        self.undo_deletes.quitting = true;
+        self.query_string("QUIT", false).map(|_| ())
     }
 
     /// Display the statistics for the mailbox (that's what the `STAT` command does).
     ///
-    /// In the resulting u32 tuple, the first number is the number of messages, and the second one is number of octets in those messages.
+    /// In the resulting tuple, the first number is the number of messages, and the second one is number of octets in those messages,
+    /// 64 bits wide as large mailboxes exceed 4 GiB.
     ///
     /// # Example
     ///
     /// ```no_run
-    /// # use std::result::Result;
-    /// #
     /// # use pop3_client::Client;
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     /// # let mut client = Client::connect("my.host.com", 110)?;
     /// let (messages, octets) = client.stat()?;
     /// assert_eq!(messages, 2);
@@ -229,20 +1420,16 @@ impl Client {
     /// #    Ok(())
     /// # }
     /// ```
-    pub fn stat(&mut self) -> Result<(u32, u32)> {
-        match self.query_string("STAT\r\n", false) {
-            Err(e) => Err(e),
-            Ok(ref s) => {
-                let mut s = s
-                    .trim()
-                    .split(' ')
-                    .map(|i| i.parse::<u32>().map_err(|e| e.to_string()));
-                Ok((
-                    s.next().ok_or("INVALID_REPLY")??,
-                    s.next().ok_or("INVALID_REPLY")??,
-                ))
-            }
+    /// # Errors
+    /// Fails with [`Error::Parse`] when a number in the reply isn't one, or doesn't fit.
+    ///
+    /// [`Error::Parse`]: enum.Error.html#variant.Parse
+    pub fn stat(&mut self) -> Result<(u32, u64)> {
+        let stat = parser::stat(self.query_string("STAT", false)?.as_bytes())?;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.saw_count(stat.0);
         }
+        Ok(stat)
     }
 
     /// Show the statistical information on a chosen letter, or all letters. The information in question always required to start with the letter size, but use of additional stats is not regimented in any way.
@@ -251,10 +1438,8 @@ impl Client {
     /// # Example
     ///
     /// ```no_run
-    /// # use std::result::Result;
-    /// #
     /// # use pop3_client::Client;
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     /// # let mut client = Client::connect("my.host.com", 110)?;
     /// let single_stats = client.list(Some(1))?; // show info on the letter number 1
     /// let all_stats = client.list(None)?; // show info on all letters
@@ -268,11 +1453,25 @@ impl Client {
     /// - The letter under the given index has been marked deleted
     pub fn list(&mut self, msg: Option<u32>) -> Result<String> {
         let query = if let Some(num) = msg {
-            format!("LIST {}\r\n", num)
+            self.check_msg(num)?;
+            format!("LIST {}", num)
         } else {
-            "LIST\r\n".to_string()
+            "LIST".to_string()
         };
-        self.query_string(&query, msg.is_none())
+        let reply = self.query_string(&query, msg.is_none())?;
+
+        // The junk is known, so only the parsing in `list_entries()` has a say
+        if self.quirks().listing_junk {
+            return Ok(reply);
+        }
+        if msg.is_none() {
+            for line in reply.split_inclusive('\n').skip(1) {
+                self.compliance.check_scan_listing(line)?;
+            }
+        } else {
+            self.compliance.check_scan_listing(&reply)?;
+        }
+        Ok(reply)
     }
 
     /// Show the full content of the chosen message
@@ -281,10 +1480,8 @@ impl Client {
     /// # Example
     ///
     /// ```no_run
-    /// # use std::result::Result;
-    /// #
     /// # use pop3_client::Client;
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     /// # let mut client = Client::connect("my.host.com", 110)?;
     /// let letter_content = client.retr(5)?;
     ///
@@ -295,36 +1492,52 @@ impl Client {
     /// The server may return an error response if:
     /// - The letter under the given index does not exist in the mailbox
     /// - The letter under the given index has been marked deleted
+    ///
+    /// Message number 0 fails with [`Error::InvalidMessageNumber`] without asking the server, as do numbers past
+    /// the count of a `STAT` seen with the [`Builder::cache()`] enabled. The same goes for all the commands taking
+    /// a message number.
+    ///
+    /// [`Error::InvalidMessageNumber`]: enum.Error.html#variant.InvalidMessageNumber
+    /// [`Builder::cache()`]: struct.Builder.html#method.cache
     pub fn retr(&mut self, msg: u32) -> Result<String> {
-        let query = format!("RETR {}\r\n", msg);
+        let line_ending = self.line_ending;
+        self.retr_text(msg).map(|text| line_ending.text(text))
+    }
+
+    fn retr_text(&mut self, msg: u32) -> Result<String> {
+        self.check_msg(msg)?;
+        let query = format!("RETR {}", msg);
 
         #[cfg(feature = "with-encoding")]
         {
-            let reply = self.query(&query, true)?;
+            let payload = self.tracked(msg, |client| client.query_frame(&query, true))?.payload();
 
             let mut head = true;
-            let mut data = BytesMut::with_capacity(reply.len());
+            let mut head_len = 0;
+            let mut data = BytesMut::with_capacity(payload.len());
 
             let mut charset: Option<String> = None;
 
-            for line in reply.split(|c| *c == b'\n').skip(1) {
+            for line in payload.split_inclusive(|c| *c == b'\n') {
+                let line = line.strip_suffix(b"\n").unwrap_or(line);
 
-                data.put(&line[..]);
+                data.put(line);
                 data.put_u8(b'\n');
 
-                if line == b"\r" {
+                if head && line == b"\r" {
                     head = false;
+                    head_len = data.len();
                 }
 
                 if head && line.starts_with(b"Content-Type:") {
                     let mut tmp = line.split(|c| *c == b'=');
-                    let prefix  = tmp.next().ok_or("Invalid charset")?;
+                    let prefix  = tmp.next().ok_or_else(|| Error::Encoding("Invalid charset".to_string()))?;
 
                     if prefix.ends_with(b"charset") {
-                        let cset = tmp.next().ok_or("Invalid charset")?;
+                        let cset = tmp.next().ok_or_else(|| Error::Encoding("Invalid charset".to_string()))?;
                         charset = Some(
                             std::str::from_utf8(&cset[..cset.len()-1])
-                                .map_err(|_| String::from("INVALID_ENCODING"))?
+                                .map_err(|e| Error::Encoding(e.to_string()))?
                                 .to_lowercase()
                         );
                     }
@@ -333,36 +1546,146 @@ impl Client {
 
             Ok(if let Some(charset) = charset {
                 let encoding = encoding_rs::Encoding::for_label(charset.as_bytes())
-                    .ok_or_else(|| String::from("ENCODING_NOT_FOUND"))?;
+                    .ok_or_else(|| Error::Encoding(format!("{} not found", charset)))?;
 
-                let (data, _, _) = encoding.decode(&data);
+                if self.utf8 {
+                    // In UTF8 mode the headers are UTF-8 regardless of the body charset (RFC 6532)
+                    let (head, body) = data.split_at(head_len);
+                    let mut text = std::str::from_utf8(head)
+                        .map_err(|e| Error::Encoding(e.to_string()))?
+                        .to_string();
+                    text.push_str(&encoding.decode(body).0);
+                    text
+                } else {
+                    let (data, _, _) = encoding.decode(&data);
 
-                data.to_string()
+                    data.to_string()
+                }
             } else {
                 std::str::from_utf8(&data[..])
-                    .map_err(|_| String::from("INVALID_ENCODING"))?
+                    .map_err(|e| Error::Encoding(e.to_string()))?
                     .to_string()
             })
         }
 
         #[cfg(not(feature = "with-encoding"))]
         {
-            self.query_string(&query, true)
-                .map(|s| s.split('\n').skip(1).collect::<Vec<&str>>().join("\n"))
+            self.tracked(msg, |client| client.query_frame(&query, true))
+                .and_then(|frame| utf8(frame.payload()))
         }
     }
 
 
+    /// Download the chosen message straight into `writer`, and return the number of octets written
+    ///
+    /// The message is dot-unstuffed, with the line endings chosen by [`Builder::line_ending()`]. Unlike [`retr()`],
+    /// it is never held in memory as a whole, which suits archiving large mailboxes to disk.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let mut file = std::fs::File::create("message.eml")?;
+    /// let octets = client.retr_to_writer(5, &mut file)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`retr()`]. A failing writer gives [`Error::Io`], after the rest of the message has been read,
+    /// so the session can go on. The command is not retried, as part of the message may already be written.
+    ///
+    /// [`retr()`]: struct.Client.html#method.retr
+    /// [`Builder::line_ending()`]: struct.Builder.html#method.line_ending
+    /// [`Error::Io`]: enum.Error.html#variant.Io
+    pub fn retr_to_writer<W: Write + ?Sized>(&mut self, msg: u32, writer: &mut W) -> Result<u64> {
+        self.check_msg(msg)?;
+        let line_ending = self.line_ending;
+        let result = self.tracked(msg, |client| {
+            client.observed("RETR", |client| {
+                client.write_command(&format!("RETR {}", msg))?;
+                client.read_reply(false)?.into_result()?;
+                copy_payload(
+                    &mut client.conn.io,
+                    &mut client.conn.buffer,
+                    client.compliance,
+                    client.server_type.quirks(),
+                    &client.limits,
+                    client.tracker.as_mut(),
+                    |line| {
+                        let line = line_ending.line(line);
+                        writer.write_all(&line).map(|_| line.len())
+                    },
+                )
+            })
+        });
+        self.check_broken(result)
+    }
+
+    /// Download the chosen message as it is, without decoding it to text
+    ///
+    /// The message is dot-unstuffed, with the line endings chosen by [`Builder::line_ending()`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let message = client.retr_bytes(5)?;
+    /// std::fs::write("message.eml", &message)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`retr()`].
+    ///
+    /// [`retr()`]: struct.Client.html#method.retr
+    /// [`Builder::line_ending()`]: struct.Builder.html#method.line_ending
+    pub fn retr_bytes(&mut self, msg: u32) -> Result<Bytes> {
+        self.check_msg(msg)?;
+        let reply = self.tracked(msg, |client| client.query_frame(&format!("RETR {}", msg), true))?;
+        Ok(self.line_ending.message(&reply.payload()))
+    }
+
+    /// Download the chosen message along with the status line the server sent before it
+    ///
+    /// The text of the `+OK` line, often the size, is in [`Response::info`], and the message as sent, still
+    /// dot-stuffed, in [`Response::payload()`]. Where the status line ends is known from reading it, so the first
+    /// line of the message is never mistaken for it.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let response = client.retr_response(5)?;
+    /// println!("{}: {} lines", response.info, response.lines.len());
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`retr()`].
+    ///
+    /// [`retr()`]: struct.Client.html#method.retr
+    /// [`Response::info`]: struct.Response.html#structfield.info
+    /// [`Response::payload()`]: struct.Response.html#method.payload
+    pub fn retr_response(&mut self, msg: u32) -> Result<Response> {
+        self.check_msg(msg)?;
+        self.tracked(msg, |client| client.query_frame(&format!("RETR {}", msg), true))
+            .map(Response::from)
+    }
+
     /// Mark the chosen message as deleted
     ///
     ///
     /// # Example
     ///
     /// ```no_run
-    /// # use std::result::Result;
-    /// #
     /// # use pop3_client::Client;
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     /// # let mut client = Client::connect("my.host.com", 110)?;
     /// client.dele(3)?; // now, the THIRD message is marked as deleted, and no new manipulations on it are possible
     ///
@@ -374,8 +1697,10 @@ impl Client {
     /// - The letter under the given index does not exist in the mailbox
     /// - The letter under the given index has been marked deleted
     pub fn dele(&mut self, msg: u32) -> Result<String> {
-        let query = format!("DELE {}\r\n", msg);
+        self.check_msg(msg)?;
+        let query = format!("DELE {}", msg);
         self.query_string(&query, false)
+            .inspect(|_| self.invalidate_cache())
     }
 
 
@@ -384,10 +1709,8 @@ impl Client {
     /// # Example
     ///
     /// ```no_run
-    /// # use std::result::Result;
-    /// #
     /// # use pop3_client::Client;
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     /// # let mut client = Client::connect("my.host.com", 110)?;
     /// assert!(client.noop().is_ok());
     ///
@@ -395,7 +1718,7 @@ impl Client {
     /// # }
     /// ```
     pub fn noop(&mut self) -> Result<()> {
-        self.query("NOOP\r\n", false).map(|_| ())
+        self.query("NOOP", false).map(|_| ())
     }
 
     /// Reset the session state, unmarking the items marked as deleted
@@ -404,10 +1727,8 @@ impl Client {
     /// # Example
     ///
     /// ```no_run
-    /// # use std::result::Result;
-    /// #
     /// # use pop3_client::Client;
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     /// # let mut client = Client::connect("my.host.com", 110)?;
     /// client.dele(3)?;
     /// client.dele(4)?;
@@ -416,22 +1737,22 @@ impl Client {
     /// # }
     /// ```
     pub fn rset(&mut self) -> Result<String> {
-        self.query_string("RSET\r\n", false)
+        self.query_string("RSET", false)
+            .inspect(|_| self.invalidate_cache())
     }
 
-    /// Show top n lines of a chosen message
+    /// Show the headers and the top n lines of the body of a chosen message
     ///
+    /// The lines are dot-unstuffed and kept as bytes, so nothing is lost when the message isn't valid UTF-8.
     ///
     /// # Example
     ///
     /// ```no_run
-    /// # use std::result::Result;
-    /// #
     /// # use pop3_client::Client;
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     /// # let mut client = Client::connect("my.host.com", 110)?;
     /// let top = client.top(1, 2)?; // Get TWO first lines of the FIRST message
-    ///
+    /// assert!(top.body_lines.len() <= 2);
     /// #    Ok(())
     /// # }
     /// ```
@@ -440,9 +1761,18 @@ impl Client {
     /// The server may return an error response if:
     /// - The letter under the given index does not exist in the mailbox
     /// - The letter under the given index has been marked deleted
-    pub fn top(&mut self, msg: u32, n: u32) -> Result<Bytes> {
-        let query = format!("TOP {} {}\r\n", msg, n);
-        self.query(&query, true)
+    pub fn top(&mut self, msg: u32, n: u32) -> Result<TopResult> {
+        self.check_msg(msg)?;
+        let query = format!("TOP {} {}", msg, n);
+        let reply = self.query_frame(&query, true)?;
+        Ok(parser::top(&reply.payload()))
+    }
+
+    /// Same as [`top()`], lossily converted to text for display
+    ///
+    /// [`top()`]: struct.Client.html#method.top
+    pub fn top_text(&mut self, msg: u32, n: u32) -> Result<String> {
+        self.top(msg, n).map(|top| top.to_text())
     }
 
     /// Show the unique ID listing for the chosen message or for all the messages. Unlike message numbering, this ID does not change between sessions.
@@ -451,10 +1781,8 @@ impl Client {
     /// # Example
     ///
     /// ```no_run
-    /// # use std::result::Result;
-    /// #
     /// # use pop3_client::Client;
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     /// # let mut client = Client::connect("my.host.com", 110)?;
     /// let uidl_all = client.uidl(None)?;
     /// let uidl_one = client.uidl(Some(1));
@@ -469,9 +1797,10 @@ impl Client {
     /// - The letter under the given index has been marked deleted
     pub fn uidl(&mut self, msg: Option<u32>) -> Result<String> {
         let query = if let Some(num) = msg {
-            format!("UIDL {}\r\n", num)
+            self.check_msg(num)?;
+            format!("UIDL {}", num)
         } else {
-            "UIDL\r\n".to_string()
+            "UIDL".to_string()
         };
         self.query_string(&query, msg.is_none())
     }
@@ -483,10 +1812,8 @@ impl Client {
     /// # Example
     ///
     /// ```no_run
-    /// # use std::result::Result;
-    /// #
     /// # use pop3_client::Client;
-    /// # fn main() -> Result<(), String> {
+    /// # fn main() -> pop3_client::Result<()> {
     /// # let mut client = Client::connect("my.host.com", 110)?;
     /// client.apop("another_sweet_username", "c4c9334bac560ecc979e58001b3e22fb")?;
     ///
@@ -494,155 +1821,553 @@ impl Client {
     /// # }
     /// ```
     /// # Errors
-    /// The server will return error if permission was denied.
+    /// The server will return error if permission was denied, as an [`Error::Auth`].
     ///
     /// [RFC]: https://tools.ietf.org/html/rfc1081
+    /// [`Error::Auth`]: enum.Error.html#variant.Auth
     pub fn apop(&mut self, name: &str, digest: &str) -> Result<String> {
-        if self.authorized {
-            return Err("login is only allowed in Authorization stage".to_string());
+        if self.is_authorized() {
+            return Err(Error::WrongStage("login is only allowed in Authorization stage".to_string()));
         }
-        let query = format!("APOP {} {}\r\n", name, digest);
-        self.query_string(&query, false).map(|s| {
-            self.authorized = true;
-            s
+        let query = format!("APOP {} {}", name, digest);
+        let retry = self.retry.clone();
+        let delay = self.login_delay;
+        self.authenticating("APOP", name, |client| {
+            retry.run(Error::is_transient_reply, || {
+                client.send(&query, false)
+                    .map_err(|e| e.at_login(AuthStep::Apop, delay))
+            })
         })
+        .map(Frame::into_bytes)
+        .and_then(utf8)
     }
 
-    #[cfg(not(feature = "with-rustls"))]
-    fn connect_notls(host: &str, port: u16) -> Result<Self> {
-        TcpStream::connect((host, port))
-            .map(|client| Self {
-                client: BufReader::new(client),
-                authorized: false,
-            })
-            .map_err(|e| format!("{:?}", e))
-            .and_then(|mut client| client.read_response(false).map(|_| client))
+    /// Send any command and get the reply exactly as the server sent it
+    ///
+    /// This bypasses all the checks the typed methods do, and a `-ERR` reply is not an error here:
+    /// look at [`Response::status`]. The command is sent with a CRLF appended, and the rest of a multiline reply is only read
+    /// if `multiline` is set and the status is `+OK`.
+    ///
+    /// # Errors
+    /// Commands containing CR, LF or NUL, or longer than [`MAX_COMMAND_LEN`], are refused with [`Error::InvalidArgument`]
+    /// before anything is sent.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let response = client.command("CAPA", true)?;
+    /// println!("{}", String::from_utf8_lossy(&response.raw));
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Response::status`]: struct.Response.html#structfield.status
+    /// [`MAX_COMMAND_LEN`]: constant.MAX_COMMAND_LEN.html
+    /// [`Error::InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    pub fn command(&mut self, command: &str, multiline: bool) -> Result<Response> {
+        let response = self.observed(command, |client| {
+            client.write_command(command)?;
+            client.read_reply(multiline).map(Response::from)
+        })?;
+        if response.is_ok() {
+            self.track_stage(command, Ok(()))?;
+        }
+        Ok(response)
     }
 
-    #[cfg(feature = "with-rustls")]
-    fn connect_rustls(host: &str, port: u16, config: Arc<ClientConfig>) -> Result<Self> {
-        let hostname = DNSNameRef::try_from_ascii_str(host).map_err(|_| "DNS_NAMEREF_FAILED")?;
+    /// Put a command in the write buffer without sending it yet
+    ///
+    /// Together with [`flush()`] and [`next_response()`], this allows pipelining commands on servers announcing
+    /// the `PIPELINING` capability: queue a batch, and then read the replies in order. The same checks as in
+    /// [`command()`] apply. Until every reply has been read, the other methods sending a command fail with
+    /// [`Error::CommandInFlight`], as they would read the wrong reply.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// for msg in 1..=3 {
+    ///     client.queue_command(&format!("DELE {}", msg))?;
+    /// }
+    /// client.flush()?;
+    /// for _ in 1..=3 {
+    ///     assert!(client.next_response(false)?.is_ok());
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`flush()`]: struct.Client.html#method.flush
+    /// [`next_response()`]: struct.Client.html#method.next_response
+    /// [`command()`]: struct.Client.html#method.command
+    /// [`Error::CommandInFlight`]: enum.Error.html#variant.CommandInFlight
+    pub fn queue_command(&mut self, command: &str) -> Result<()> {
+        self.in_flight.check_current()?;
+        self.queue(command)?;
+        self.in_flight.queued(command);
+        Ok(())
+    }
+
+    /// Put a command in the write buffer, leaving the bookkeeping of its reply to the caller
+    pub(crate) fn queue(&mut self, command: &str) -> Result<()> {
+        let line = command::encode(command)?;
+        let result = self.conn.queue_line(line);
+        self.count_command(command);
+        self.undo_deletes.sent(&stage::keyword(command));
+        result
+    }
+
+    /// Send out all the queued commands in one go
+    ///
+    /// Reading a reply flushes the queue as well.
+    pub fn flush(&mut self) -> Result<()> {
+        self.conn.io.get_ref().pace();
+        let deadline = self.command_timeout.map(|timeout| Instant::now() + timeout);
+        self.watched().deadline = deadline;
+        let result = self.conn.io.get_mut().flush().map_err(Error::Io);
+        self.undo_deletes.saw(&result);
+        result
+    }
+
+    /// Give up on a command when its reply hasn't fully arrived `timeout` after sending it
+    ///
+    /// A command running late fails with an [`Error::Io`] of kind `TimedOut`, and the connection is closed, as
+    /// the rest of the reply may still arrive. For pipelined commands, the time counts from [`flush()`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # use std::time::Duration;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// client.set_command_timeout(Duration::from_secs(30));
+    /// let message = client.retr(1)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Error::Io`]: enum.Error.html#variant.Io
+    /// [`flush()`]: struct.Client.html#method.flush
+    pub fn set_command_timeout(&mut self, timeout: Duration) {
+        self.command_timeout = Some(timeout);
+    }
+
+    /// A handle to abort the running command from another thread
+    ///
+    /// All the tokens of a client share the same state. Blocked reads notice the cancellation within a tenth of
+    /// a second; streams given to [`from_stream()`] only between reads.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::{Client, Error};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let token = client.cancellation_token();
+    /// std::thread::spawn(move || {
+    ///     std::thread::sleep(std::time::Duration::from_secs(60));
+    ///     token.cancel();
+    /// });
+    /// match client.retr(1) {
+    ///     Err(Error::Cancelled) => eprintln!("gave up, reconnect to go on"),
+    ///     result => println!("{}", result?),
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`from_stream()`]: struct.Client.html#method.from_stream
+    pub fn cancellation_token(&mut self) -> CancellationToken {
+        self.watched().token()
+    }
+
+    /// Whether the connection was upgraded with `STLS`
+    ///
+    /// With [`TlsPolicy::Opportunistic`], this is how to tell that the server refused and the session went on in
+    /// plain text. Streams given to [`from_stream()`] count as plain text, even if they are encrypted underneath.
+    ///
+    /// [`TlsPolicy::Opportunistic`]: enum.TlsPolicy.html#variant.Opportunistic
+    /// [`from_stream()`]: struct.Client.html#method.from_stream
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+
+    /// The address of the server, `None` for streams given to [`from_stream()`]
+    ///
+    /// It is kept once the connection is closed, to tell which server a broken session was talking to.
+    ///
+    /// [`from_stream()`]: struct.Client.html#method.from_stream
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.peer_addr
+    }
+
+    /// The address of this end of the connection, `None` for streams given to [`from_stream()`]
+    ///
+    /// [`from_stream()`]: struct.Client.html#method.from_stream
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// When the connection was established, before the greeting was read
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # use std::time::Duration;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// if client.connected_at().elapsed() > Duration::from_secs(600) || client.bytes_received() > 1 << 30 {
+    ///     client.quit()?;
+    ///     client = Client::connect("my.host.com", 110)?;
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    pub fn connected_at(&self) -> Instant {
+        self.connected_at
+    }
+
+    /// Octets sent to the server so far, commands and credentials included
+    ///
+    /// Over TLS, these are counted before encryption, so the records on the wire take a little more.
+    pub fn bytes_sent(&self) -> u64 {
+        self.conn.io.get_ref().get_ref().get_ref().sent
+    }
+
+    /// Octets received from the server so far, including those read ahead and not handed out yet
+    ///
+    /// Over TLS, these are counted after decryption.
+    pub fn bytes_received(&self) -> u64 {
+        self.conn.io.get_ref().get_ref().get_ref().received
+    }
+
+    /// Refuse message number 0, and numbers past the highest one known from `STAT`, before they go on the wire
+    pub(crate) fn check_msg(&self, msg: u32) -> Result<()> {
+        let highest = self.cache.as_ref().and_then(|cache| cache.highest);
+        if msg == 0 || highest.is_some_and(|highest| msg > highest) {
+            return Err(Error::InvalidMessageNumber(msg));
+        }
+        Ok(())
+    }
+
+    /// Refuse to send a password in the clear with `command`, unless TLS protects it or the user allowed it
+    pub(crate) fn check_plaintext(&self, command: &str) -> Result<()> {
+        if self.tls || self.plaintext_auth {
+            return Ok(());
+        }
+        Err(Error::PlaintextAuth(command.to_string()))
+    }
+
+    /// Read the reply to the oldest command not answered yet, see [`queue_command()`]
+    ///
+    /// Just like with [`command()`], a `-ERR` reply is not an error here.
+    ///
+    /// [`queue_command()`]: struct.Client.html#method.queue_command
+    /// [`command()`]: struct.Client.html#method.command
+    pub fn next_response(&mut self, multiline: bool) -> Result<Response> {
+        self.in_flight.check_current()?;
+        let result = self.read_reply(multiline).map(Response::from);
+        self.in_flight.answered();
+        result
+    }
+
+    /// Switch the session to UTF-8 (the `UTF8` command of [RFC 6856])
+    ///
+    /// Only allowed in the Authorization stage. Afterwards, usernames and passwords may contain non-ASCII characters,
+    /// and the headers of retrieved messages are treated as UTF-8 regardless of the body charset.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// client.utf8()?;
+    /// assert!(client.is_utf8());
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// The server will return an error response if it does not support the `UTF8` capability.
+    ///
+    /// [RFC 6856]: https://tools.ietf.org/html/rfc6856#section-2
+    pub fn utf8(&mut self) -> Result<()> {
+        if self.is_authorized() {
+            return Err(Error::WrongStage("UTF8 is only allowed in Authorization stage".to_string()));
+        }
+        self.query("UTF8", false)
+            .map(|_| self.utf8 = true)
+    }
+
+    /// Whether the session has been switched to UTF-8 with [`utf8()`]
+    ///
+    /// [`utf8()`]: struct.Client.html#method.utf8
+    pub fn is_utf8(&self) -> bool {
+        self.utf8
+    }
+
+    /// Same as [`stat()`], but served from the cache when it has been enabled with [`Builder::cache()`]
+    ///
+    /// Without the cache enabled, this simply calls [`stat()`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Builder::default().cache(true).connect("my.host.com", 110)?;
+    /// let (messages, octets) = client.cached_stat()?; // queries the server
+    /// let (messages, octets) = client.cached_stat()?; // no round trip this time
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`stat()`]: struct.Client.html#method.stat
+    /// [`Builder::cache()`]: struct.Builder.html#method.cache
+    pub fn cached_stat(&mut self) -> Result<(u32, u64)> {
+        if let Some(stat) = self.cache.as_ref().and_then(|c| c.stat) {
+            return Ok(stat);
+        }
+        let stat = self.stat()?;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.stat = Some(stat);
+        }
+        Ok(stat)
+    }
+
+    /// Same as [`list()`], but served from the cache when it has been enabled with [`Builder::cache()`]
+    ///
+    /// [`list()`]: struct.Client.html#method.list
+    /// [`Builder::cache()`]: struct.Builder.html#method.cache
+    pub fn cached_list(&mut self, msg: Option<u32>) -> Result<String> {
+        if let Some(list) = self.cache.as_ref().and_then(|c| c.list.get(&msg)) {
+            return Ok(list.clone());
+        }
+        let list = self.list(msg)?;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.list.insert(msg, list.clone());
+        }
+        Ok(list)
+    }
+
+    /// Same as [`uidl()`], but served from the cache when it has been enabled with [`Builder::cache()`]
+    ///
+    /// [`uidl()`]: struct.Client.html#method.uidl
+    /// [`Builder::cache()`]: struct.Builder.html#method.cache
+    pub fn cached_uidl(&mut self, msg: Option<u32>) -> Result<String> {
+        if let Some(uidl) = self.cache.as_ref().and_then(|c| c.uidl.get(&msg)) {
+            return Ok(uidl.clone());
+        }
+        let uidl = self.uidl(msg)?;
+        if let Some(cache) = self.cache.as_mut() {
+            cache.uidl.insert(msg, uidl.clone());
+        }
+        Ok(uidl)
+    }
+
+    /// Drop all the cached `STAT`, `LIST` and `UIDL` results
+    ///
+    /// `DELE` and `RSET` do this automatically; call it by hand if you know the mailbox has changed otherwise.
+    pub fn invalidate_cache(&mut self) {
+        if let Some(cache) = self.cache.as_mut() {
+            cache.clear();
+        }
+    }
+
+    fn new(mut conn: Connection) -> Self {
+        let (peer_addr, local_addr) = conn.watched().get_mut().addresses();
+        Self {
+            conn,
+            stage: Stage::Authorization,
+            cache: None,
+            uid_map: None,
+            retry: RetryPolicy::none(),
+            language: None,
+            utf8: false,
+            compliance: Compliance::default(),
+            limits: Limits::default(),
+            greeting: String::new(),
+            server_type: ServerType::Unknown,
+            tls: false,
+            progress: None,
+            tracker: None,
+            size_tolerance: None,
+            command_timeout: None,
+            mechanisms: None,
+            plaintext_auth: false,
+            login_delay: None,
+            pipelining: false,
+            retention: None,
+            capabilities: None,
+            capability_checks: false,
+            auto_retry_idempotent: false,
+            latencies: health::Latencies::default(),
+            audit: audit::AuditLog::default(),
+            in_flight: in_flight::InFlight::default(),
+            line_ending: Normalize::default(),
+            labels: telemetry::Labels::default(),
+            undo_deletes: UndoDeletes::default(),
+            peer_addr,
+            local_addr,
+            connected_at: Instant::now(),
+            origin: None,
+        }
+    }
+
+    /// Swap the read buffer for one of `octets` capacity; only while it holds nothing, so no reply gets lost
+    fn set_read_buffer_size(&mut self, octets: usize) {
+        if !self.conn.io.buffer().is_empty() {
+            return;
+        }
+        let empty = BufReader::with_capacity(0, WriteBuffered::new(Throttled::new(Watched::new(Stream::Closed))));
+        let inner = std::mem::replace(&mut self.conn.io, empty).into_inner();
+        // An empty buffer would read nothing, which looks like the end of the stream
+        self.conn.io = BufReader::with_capacity(octets.max(1), inner);
+    }
 
-        let session = ClientSession::new(&config, hostname);
-        let socket = TcpStream::connect((host, port))
-            .map(BufReader::new)
-            .map_err(|e| format!("{:?}", e))
+    fn connect_notls(host: &str, port: u16, address: Option<SocketAddr>, options: &SocketOptions) -> Result<Self> {
+        options
+            .connect(host, port, address)
+            .map(|client| Self::new(Connection::new(Stream::Plain(client))))
+            .map_err(Error::Io)
             .and_then(|mut client| {
-                let mut buf = String::new();
-                client
-                    .read_line(&mut buf)
-                    .map_err(|e| e.to_string())
-                    .and_then(|_| {
-                        if buf.starts_with("+OK") {
-                            Ok(buf[4..].to_owned())
-                        } else {
-                            Err(buf[5..].to_owned())
-                        }
-                    })
-                    .map(|_| client)
+                client.labels.host = host.to_string();
+                client.watched().deadline = options.connect_timeout.map(|timeout| Instant::now() + timeout);
+                let result = client.read_greeting();
+                client.watched().deadline = None;
+                result.map(|_| client)
             })
-            .and_then(|mut client| {
-                client
-                    .get_mut()
-                    .write_all("STLS\r\n".as_bytes())
-                    .map_err(|e| e.to_string())
-                    .and_then(|_| {
-                        let mut buf = String::new();
-                        client
-                            .read_line(&mut buf)
-                            .map_err(|e| e.to_string())
-                            .and_then(|_| {
-                                println!("STLS: {}", &buf);
-                                if buf.starts_with("+OK") {
-                                    Ok(buf[4..].to_owned())
-                                } else {
-                                    Err(buf[5..].to_owned())
-                                }
-                            })
-                    })
-                    .map(|_| client.into_inner())
-            })?;
-
-        let tls_stream = StreamOwned::new(session, socket);
-
-        Ok(Self {
-            client: BufReader::new(tls_stream),
-            authorized: false,
-        })
     }
 
-    fn read_response(&mut self, multiline: bool) -> Result<Bytes> {
-        let mut response = BytesMut::new();
-        let mut buffer   = vec![];
-        self.client
-            .read_until(b'\n', &mut buffer)
-            .map_err(|e| e.to_string())
-            .and_then(|x| {
-                if x == 0 {
-                    Err("Connection aborted".to_string())
-                } else {
-                    Ok(x)
-                }
-            })
-            .and_then(|_| {
-                if buffer.starts_with(b"+OK") {
-                    Ok(Bytes::copy_from_slice(&buffer[4..]))
-                } else {
-                    Err(std::str::from_utf8(
-                        if buffer.len() < 6 {
-                            &buffer
-                        } else {
-                            &buffer[5..]
-                        })
-                        .unwrap_or_else(|_| "Error is not valid utf-8")
-                        .to_string()
-                    )
-                }
-            })
-            .and_then(|s| {
-                if multiline {
-                    response.put(&s[..]);
-                    while !buffer.ends_with(b".\r\n") {
-                        buffer.clear();
-                        let read = self
-                            .client
-                            .read_until(b'\n', &mut buffer)
-                            .map_err(|e| e.to_string())
-                            .and_then(|x| {
-                                if x == 0 {
-                                    Err("Connection aborted".to_string())
-                                } else {
-                                    Ok(x)
-                                }
-                            })
-                            .map(|_| Bytes::new());
-                        if read.is_err() {
-                            return read;
-                        }
-                        response.put(
-                            &buffer[..buffer.len() - if buffer.ends_with(b".\r\n") { 3 } else { 0 }],
-                        );
-                    }
-                    Ok(response.freeze())
-                } else {
-                    Ok(s)
-                }
-            })
+    #[cfg(feature = "with-rustls")]
+    fn connect_rustls(
+        host: &str,
+        port: u16,
+        address: Option<SocketAddr>,
+        options: &SocketOptions,
+        config: Arc<ClientConfig>,
+        policy: TlsPolicy,
+    ) -> Result<Self> {
+        if config.versions.is_empty() && policy != TlsPolicy::Never {
+            return Err(Error::Tls("the minimum TLS version is above the maximum".to_string()));
+        }
+        let mut client = Self::connect_notls(host, port, address, options)?;
+        if policy == TlsPolicy::Never {
+            return Ok(client);
+        }
+        match client.query("STLS", false) {
+            Ok(_) => (),
+            Err(Error::Server(_)) if policy == TlsPolicy::Opportunistic => return Ok(client),
+            Err(e) => return Err(e),
+        }
+        // Anything sent before the handshake could be injected by a man in the middle
+        if !client.conn.io.buffer().is_empty() {
+            return Err(Error::Tls("data received after STLS, before the handshake".to_string()));
+        }
+
+        let hostname = DNSNameRef::try_from_ascii_str(host).map_err(|e| Error::Tls(e.to_string()))?;
+        if let Stream::Plain(socket) = std::mem::replace(client.stream(), Stream::Closed) {
+            let session = ClientSession::new(&config, hostname);
+            *client.stream() = Stream::Tls(Box::new(StreamOwned::new(session, socket)));
+            client.tls = true;
+        }
+        Ok(client)
+    }
+
+    fn watched(&mut self) -> &mut Watched {
+        self.conn.watched()
+    }
+
+    fn stream(&mut self) -> &mut Stream {
+        self.watched().get_mut()
+    }
+
+    /// Close the connection after errors that leave the session out of step with the server
+    fn check_broken<T>(&mut self, result: Result<T>) -> Result<T> {
+        let result = match result {
+            Err(Error::Io(_)) if self.watched().is_cancelled() => Err(Error::Cancelled),
+            result => result,
+        };
+        let broken = match &result {
+            // The rest of the reply is still on the wire, so there is no way to resynchronise
+            Err(Error::ResponseTooLarge(_)) | Err(Error::LineTooLong(_)) | Err(Error::Cancelled) => true,
+            Err(Error::Io(e)) => e.kind() == std::io::ErrorKind::TimedOut,
+            _ => false,
+        };
+        if broken {
+            self.stream().shutdown();
+        }
+        self.undo_deletes.saw(&result);
+        result
+    }
+
+    fn read_greeting(&mut self) -> Result<()> {
+        let greeting = self.read_reply(false)?.into_result()?.info();
+        self.greeted(greeting);
+        Ok(())
     }
 
+    fn greeted(&mut self, greeting: String) {
+        self.server_type = ServerType::fingerprint(&greeting);
+        self.greeting = greeting;
+    }
+
+    fn read_response(&mut self, multiline: bool) -> Result<Frame> {
+        self.read_reply(multiline)?.into_result()
+    }
+
+    fn read_reply(&mut self, multiline: bool) -> Result<Frame> {
+        let result = self.conn.read_frame(
+            multiline,
+            self.compliance,
+            self.server_type.quirks(),
+            &self.limits,
+            self.tracker.as_mut(),
+        );
+        self.check_broken(result)
+    }
+
+    fn write_command(&mut self, command: &str) -> Result<()> {
+        self.queue(command)?;
+        self.flush()
+    }
+
+    fn send(&mut self, query: &str, multiline: bool) -> Result<Frame> {
+        self.check_capability(query)?;
+        let result = self.observed(query, |client| {
+            client.write_command(query)
+                .and_then(|_| client.read_response(multiline))
+        });
+        self.track_stage(query, result)
+    }
+
+    /// Send the command, repeating it as long as the retry policy allows when the server reports a
+    /// temporary failure. Broken connections are not retried, as the session is lost with them.
     fn query(&mut self, query: &str, multiline: bool) -> Result<Bytes> {
-        self.client
-            .get_mut()
-            .write_all(query.as_bytes())
-            .map_err(|e| e.to_string())
-            .and_then(|_| self.read_response(multiline))
+        self.query_frame(query, multiline).map(Frame::into_bytes)
     }
 
-    fn query_string(&mut self, query: &str, multiline: bool) -> Result<String> {
-        let reply = self.query(query, multiline)?;
+    /// Same as `query()`, with the status line and the payload kept apart
+    fn query_frame(&mut self, query: &str, multiline: bool) -> Result<Frame> {
+        let retry = self.retry.clone();
+        match retry.run(Error::is_transient_reply, || self.send(query, multiline)) {
+            Err(e) if self.reopen_for(query, &e) => retry.run(Error::is_transient_reply, || self.send(query, multiline)),
+            result => result,
+        }
+    }
 
-        std::str::from_utf8(&reply[..])
-            .map(|s| s.to_string())
-            .map_err(|_| String::from("Error is not valid utf-8"))
+    fn query_string(&mut self, query: &str, multiline: bool) -> Result<String> {
+        self.query(query, multiline).and_then(utf8)
     }
 }
+
+fn utf8(reply: Bytes) -> Result<String> {
+    std::str::from_utf8(&reply[..])
+        .map(|s| s.to_string())
+        .map_err(|e| Error::Encoding(e.to_string()))
+}
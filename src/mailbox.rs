@@ -0,0 +1,457 @@
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
+use crate::cache::UidMap;
+use crate::response::{unstuffed, PayloadLines};
+use crate::{parser, quirks, Client, Error, Result};
+
+/// Size of the maildrop, as reported by `STAT`
+///
+/// Counts and message numbers are `u32` throughout the crate, as `LIST` and `UIDL` give them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MailboxStat {
+    /// Number of messages, not counting the ones marked as deleted
+    pub count: u32,
+    /// Total size of those messages in octets
+    pub size: u64,
+}
+
+impl MailboxStat {
+    /// The message numbers, from 1 to `count`, as long as no message has been deleted in the session
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// for id in client.mailbox_stat()?.ids() {
+    ///     let message = client.retr_bytes(id)?;
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    pub fn ids(&self) -> std::ops::RangeInclusive<u32> {
+        1..=self.count
+    }
+}
+
+impl From<(u32, u64)> for MailboxStat {
+    fn from((count, size): (u32, u64)) -> Self {
+        Self { count, size }
+    }
+}
+
+/// A scan listing line of `LIST`, see [RFC 1939]
+///
+/// [RFC 1939]: https://tools.ietf.org/html/rfc1939#page-6
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct MessageInfo {
+    /// Message number within the session
+    pub id: u32,
+    /// Size in octets
+    pub size: u64,
+}
+
+/// A unique-id listing line of `UIDL`, see [RFC 1939]
+///
+/// [RFC 1939]: https://tools.ietf.org/html/rfc1939#page-12
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct UidlEntry {
+    /// Message number within the session
+    pub id: u32,
+    /// Unique id, stable across sessions
+    pub uid: String,
+}
+
+/// The reply to `TOP`: the header section and the first lines of the body, see [RFC 1939]
+///
+/// [RFC 1939]: https://tools.ietf.org/html/rfc1939#page-11
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TopResult {
+    /// Header lines, dot-unstuffed, each ending with CRLF; without the empty line that ends them
+    pub headers: Vec<u8>,
+    /// Body lines, dot-unstuffed and without line endings
+    pub body_lines: Vec<Vec<u8>>,
+}
+
+impl TopResult {
+    /// The headers, an empty line and the body lines, with CRLF line endings and invalid UTF-8 replaced
+    pub fn to_text(&self) -> String {
+        let mut text = String::from_utf8_lossy(&self.headers).to_string();
+        text.push_str("\r\n");
+        for line in &self.body_lines {
+            text.push_str(&String::from_utf8_lossy(line));
+            text.push_str("\r\n");
+        }
+        text
+    }
+}
+
+fn listing<T>(reply: &str, multiline: bool, parse: fn(&[u8]) -> Result<T>) -> Result<Vec<T>> {
+    if multiline {
+        reply
+            .lines()
+            .skip(1)
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| parse(line.as_bytes()))
+            .collect()
+    } else {
+        Ok(vec![parse(reply.as_bytes())?])
+    }
+}
+
+/// The entry of a single-message listing, or `None` for an `-ERR` without a response code: the server's way to
+/// say there is no such message
+fn found<T>(result: Result<Vec<T>>) -> Result<Option<T>> {
+    missing(result).map(|entries| entries.and_then(|entries| entries.into_iter().next()))
+}
+
+/// `None` for the `-ERR` reply to a missing message
+fn missing<T>(result: Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(ref e @ Error::Server(_)) if e.code().is_none() => Ok(None),
+        Err(Error::InvalidMessageNumber(_)) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl Client {
+    /// Same as [`stat()`], as a [`MailboxStat`]
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let stat = client.mailbox_stat()?;
+    /// println!("{} messages, {} octets", stat.count, stat.size);
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`stat()`]: struct.Client.html#method.stat
+    /// [`MailboxStat`]: struct.MailboxStat.html
+    pub fn mailbox_stat(&mut self) -> Result<MailboxStat> {
+        self.stat().map(MailboxStat::from)
+    }
+
+    /// Same as [`list()`], parsed into one [`MessageInfo`] per message
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let total: u64 = client.list_entries(None)?.iter().map(|m| m.size).sum();
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`list()`], plus [`Error::InvalidReply`] for a line without a numeric size.
+    ///
+    /// [`list()`]: struct.Client.html#method.list
+    /// [`MessageInfo`]: struct.MessageInfo.html
+    /// [`Error::InvalidReply`]: enum.Error.html#variant.InvalidReply
+    pub fn list_entries(&mut self, msg: Option<u32>) -> Result<Vec<MessageInfo>> {
+        let reply = self.list(msg)?;
+        let parse = if self.quirks().listing_junk { quirks::scan_listing_with_junk } else { parser::scan_listing };
+        listing(&reply, msg.is_none(), parse)
+    }
+
+    /// Same as [`uidl()`], parsed into one [`UidlEntry`] per message
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// for entry in client.uidl_entries(None)? {
+    ///     println!("{}: {}", entry.id, entry.uid);
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`uidl()`], plus [`Error::InvalidReply`] for a malformed line.
+    ///
+    /// [`uidl()`]: struct.Client.html#method.uidl
+    /// [`UidlEntry`]: struct.UidlEntry.html
+    /// [`Error::InvalidReply`]: enum.Error.html#variant.InvalidReply
+    pub fn uidl_entries(&mut self, msg: Option<u32>) -> Result<Vec<UidlEntry>> {
+        if self.quirks().no_uidl {
+            return self.uidl_from_headers(msg);
+        }
+        let reply = self.uidl(msg)?;
+        listing(&reply, msg.is_none(), parser::uidl_listing)
+    }
+
+    /// The scan listing of a single message, or `None` if the mailbox has no such message
+    ///
+    /// A message marked as deleted counts as missing. Unlike with [`list_entries()`], there's no need to look
+    /// into the error text to tell a missing message from a broken session.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// match client.list_one(3)? {
+    ///     Some(info) => println!("{} octets", info.size),
+    ///     None => println!("no message 3"),
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`list_entries()`], except for the `-ERR` reply to a missing message. An `-ERR` carrying a
+    /// response code, e.g. `[SYS/TEMP]`, is still an error.
+    ///
+    /// [`list_entries()`]: struct.Client.html#method.list_entries
+    pub fn list_one(&mut self, msg: u32) -> Result<Option<MessageInfo>> {
+        found(self.list_entries(Some(msg)))
+    }
+
+    /// The unique-id listing of a single message, or `None` if the mailbox has no such message
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// if let Some(entry) = client.uidl_one(3)? {
+    ///     println!("{}", entry.uid);
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`list_one()`].
+    ///
+    /// [`list_one()`]: struct.Client.html#method.list_one
+    pub fn uidl_one(&mut self, msg: u32) -> Result<Option<UidlEntry>> {
+        found(self.uidl_entries(Some(msg)))
+    }
+
+    /// The message number of the unique ID `uid` in this session, or `None` if the mailbox has no such message
+    ///
+    /// The first call asks the server for the whole unique-id listing; the numbers don't change during a session,
+    /// so later ones are answered from memory, both ways. Unique IDs are what tells a message across sessions.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// if let Some(msg) = client.msg_for_uid("1f3c5a")? {
+    ///     println!("message {}", msg);
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`uidl_entries()`].
+    ///
+    /// [`uidl_entries()`]: struct.Client.html#method.uidl_entries
+    pub fn msg_for_uid(&mut self, uid: &str) -> Result<Option<u32>> {
+        Ok(self.uid_map()?.number(uid))
+    }
+
+    /// The unique ID of message number `msg`, from the same listing as [`msg_for_uid()`]
+    ///
+    /// [`msg_for_uid()`]: struct.Client.html#method.msg_for_uid
+    pub fn uid_for_msg(&mut self, msg: u32) -> Result<Option<String>> {
+        Ok(self.uid_map()?.uid(msg).map(str::to_string))
+    }
+
+    /// Same as [`retr()`], for the message with the unique ID `uid`; `None` if the mailbox has no such message,
+    /// or it has been marked as deleted
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// match client.retr_by_uid("1f3c5a")? {
+    ///     Some(message) => println!("{}", message),
+    ///     None => println!("gone"),
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`msg_for_uid()`] and [`retr()`], except for the `-ERR` reply to a missing message.
+    ///
+    /// [`retr()`]: struct.Client.html#method.retr
+    /// [`msg_for_uid()`]: struct.Client.html#method.msg_for_uid
+    pub fn retr_by_uid(&mut self, uid: &str) -> Result<Option<String>> {
+        match self.msg_for_uid(uid)? {
+            Some(msg) => missing(self.retr(msg)),
+            None => Ok(None),
+        }
+    }
+
+    /// Same as [`dele()`], for the message with the unique ID `uid`; `None` if the mailbox has no such message,
+    /// or it has been marked as deleted already
+    ///
+    /// # Errors
+    /// Same as [`msg_for_uid()`] and [`dele()`], except for the `-ERR` reply to a missing message.
+    ///
+    /// [`dele()`]: struct.Client.html#method.dele
+    /// [`msg_for_uid()`]: struct.Client.html#method.msg_for_uid
+    pub fn dele_by_uid(&mut self, uid: &str) -> Result<Option<String>> {
+        match self.msg_for_uid(uid)? {
+            Some(msg) => missing(self.dele(msg)),
+            None => Ok(None),
+        }
+    }
+
+    fn uid_map(&mut self) -> Result<&UidMap> {
+        let map = match self.uid_map.take() {
+            Some(map) => map,
+            None => UidMap::new(self.uidl_entries(None)?),
+        };
+        Ok(self.uid_map.insert(map))
+    }
+}
+
+/// The lines of a `LIST` or `UIDL` reply, parsed as they are read off the connection
+///
+/// Created by [`Client::list_iter()`] and [`Client::uidl_iter()`]. Only one line is held in memory at a time,
+/// however large the mailbox. Dropping the iterator early reads the rest of the reply, so the session can go on.
+///
+/// [`Client::list_iter()`]: struct.Client.html#method.list_iter
+/// [`Client::uidl_iter()`]: struct.Client.html#method.uidl_iter
+pub struct Listing<'c, T> {
+    client: &'c mut Client,
+    lines: PayloadLines,
+    parse: fn(&[u8]) -> Result<T>,
+    /// Check scan listings in strict mode, as `list()` does
+    scan: bool,
+    done: bool,
+}
+
+impl<T> Listing<'_, T> {
+    fn next_line(&mut self) -> Result<bool> {
+        let client = &mut *self.client;
+        let result = self.lines.next(
+            &mut client.conn.io,
+            &mut client.conn.buffer,
+            client.compliance,
+            client.server_type.quirks(),
+            &client.limits,
+        );
+        client.check_broken(result)
+    }
+}
+
+impl<T> Iterator for Listing<'_, T> {
+    type Item = Result<T>;
+
+    fn next(&mut self) -> Option<Result<T>> {
+        while !self.done {
+            match self.next_line() {
+                Ok(false) => self.done = true,
+                // A bad line has been read in full, so the following ones can still be read
+                Err(e @ Error::InvalidReply(_)) => return Some(Err(e)),
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+                Ok(true) => {
+                    let line = unstuffed(&self.client.conn.buffer);
+                    if parser::trim_line_end(line).iter().all(u8::is_ascii_whitespace) {
+                        continue;
+                    }
+                    if self.scan && !self.client.quirks().listing_junk {
+                        let checked = self.client.compliance.check_scan_listing(&String::from_utf8_lossy(line));
+                        if let Err(e) = checked {
+                            return Some(Err(e));
+                        }
+                    }
+                    return Some((self.parse)(line));
+                }
+            }
+        }
+        None
+    }
+}
+
+impl<T> Drop for Listing<'_, T> {
+    fn drop(&mut self) {
+        while !self.done {
+            match self.next_line() {
+                Ok(true) | Err(Error::InvalidReply(_)) => (),
+                Ok(false) | Err(_) => self.done = true,
+            }
+        }
+        self.client.in_flight.finish();
+    }
+}
+
+impl Client {
+    /// Same as [`list_entries(None)`], read lazily for mailboxes too large to hold the whole listing
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let mut total: u64 = 0;
+    /// for info in client.list_iter()? {
+    ///     total += info?.size;
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// The `-ERR` reply to `LIST` fails right away; errors while reading the listing come from the iterator.
+    ///
+    /// [`list_entries(None)`]: struct.Client.html#method.list_entries
+    pub fn list_iter(&mut self) -> Result<Listing<'_, MessageInfo>> {
+        let parse = if self.quirks().listing_junk { quirks::scan_listing_with_junk } else { parser::scan_listing };
+        self.listing_iter("LIST", parse, true)
+    }
+
+    /// Same as [`uidl_entries(None)`], read lazily for mailboxes too large to hold the whole listing
+    ///
+    /// Unlike `uidl_entries`, this has no fallback for servers that lack `UIDL`.
+    ///
+    /// # Errors
+    /// The `-ERR` reply to `UIDL` fails right away; errors while reading the listing come from the iterator.
+    ///
+    /// [`uidl_entries(None)`]: struct.Client.html#method.uidl_entries
+    pub fn uidl_iter(&mut self) -> Result<Listing<'_, UidlEntry>> {
+        self.listing_iter("UIDL", parser::uidl_listing, false)
+    }
+
+    fn listing_iter<T>(&mut self, command: &str, parse: fn(&[u8]) -> Result<T>, scan: bool) -> Result<Listing<'_, T>> {
+        self.check_capability(command)?;
+        let status = self.observed(command, |client| {
+            client.write_command(command)?;
+            client.read_reply(false)?.into_result()
+        });
+        self.track_stage(command, status)?;
+        // The lines are read as the listing is iterated, and what's left of them when it's dropped
+        self.in_flight.start(command)?;
+        Ok(Listing {
+            client: self,
+            lines: PayloadLines::default(),
+            parse,
+            scan,
+            done: false,
+        })
+    }
+}
@@ -0,0 +1,128 @@
+use bytes::Bytes;
+
+use crate::{Client, Error, MessageInfo, Result, UidlEntry};
+
+/// A message of the mailbox, fetching what is asked of it on first use and keeping it for later
+///
+/// Created with [`Client::message()`]. It borrows the client, so commands can't interleave with its own.
+///
+/// # Example
+///
+/// ```no_run
+/// # use pop3_client::Client;
+/// # fn main() -> pop3_client::Result<()> {
+/// # let mut client = Client::connect("my.host.com", 110)?;
+/// let mut message = client.message(3)?;
+/// if message.size()? < 1024 * 1024 {
+///     println!("{}", String::from_utf8_lossy(message.headers()?));
+///     message.delete()?;
+/// }
+/// #    Ok(())
+/// # }
+/// ```
+///
+/// [`Client::message()`]: struct.Client.html#method.message
+pub struct Message<'c> {
+    client: &'c mut Client,
+    id: u32,
+    size: Option<u64>,
+    uid: Option<String>,
+    headers: Option<Bytes>,
+    content: Option<Bytes>,
+}
+
+impl Message<'_> {
+    /// The message number within the session
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Size in octets, from `LIST`
+    pub fn size(&mut self) -> Result<u64> {
+        if let Some(size) = self.size {
+            return Ok(size);
+        }
+        let size = match self.client.list_entries(Some(self.id))?.first() {
+            Some(MessageInfo { size, .. }) => *size,
+            None => return Err(Error::InvalidReply("empty scan listing".to_string())),
+        };
+        self.size = Some(size);
+        Ok(size)
+    }
+
+    /// The unique ID, from `UIDL`
+    pub fn uid(&mut self) -> Result<&str> {
+        if self.uid.is_none() {
+            let entry = self.client.uidl_entries(Some(self.id))?.pop();
+            let UidlEntry { uid, .. } =
+                entry.ok_or_else(|| Error::InvalidReply("empty unique-id listing".to_string()))?;
+            self.uid = Some(uid);
+        }
+        Ok(self.uid.as_deref().unwrap_or_default())
+    }
+
+    /// The header lines with their line endings, without the empty line after them
+    ///
+    /// Taken from the content if it was fetched already, from `TOP n 0` otherwise.
+    pub fn headers(&mut self) -> Result<&[u8]> {
+        if self.headers.is_none() {
+            let headers = match &self.content {
+                Some(content) => content.slice(..header_len(content)),
+                None => Bytes::from(self.client.top(self.id, 0)?.headers),
+            };
+            self.headers = Some(headers);
+        }
+        Ok(self.headers.as_deref().unwrap_or_default())
+    }
+
+    /// The whole message, dot-unstuffed, from `RETR`
+    pub fn content(&mut self) -> Result<&[u8]> {
+        if self.content.is_none() {
+            let mut content = vec![];
+            self.client.retr_to_writer(self.id, &mut content)?;
+            self.content = Some(Bytes::from(content));
+        }
+        Ok(self.content.as_deref().unwrap_or_default())
+    }
+
+    /// Mark the message as deleted with `DELE`
+    pub fn delete(self) -> Result<()> {
+        self.client.dele(self.id).map(|_| ())
+    }
+}
+
+/// Length of the header lines, up to the empty line ending them
+fn header_len(content: &[u8]) -> usize {
+    let mut len = 0;
+    for line in content.split_inclusive(|c| *c == b'\n') {
+        if line == b"\r\n" || line == b"\n" {
+            break;
+        }
+        len += line.len();
+    }
+    len
+}
+
+impl Client {
+    /// A handle on the message numbered `id`, fetching its parts lazily
+    ///
+    /// Nothing is sent to the server until a part is asked for.
+    ///
+    /// # Errors
+    /// [`Error::InvalidArgument`] for the message number 0, as numbering starts at 1.
+    ///
+    /// [`Error::InvalidArgument`]: enum.Error.html#variant.InvalidArgument
+    pub fn message(&mut self, id: u32) -> Result<Message<'_>> {
+        if id == 0 {
+            return Err(Error::InvalidArgument("message numbers start at 1".to_string()));
+        }
+        Ok(Message {
+            client: self,
+            id,
+            size: None,
+            uid: None,
+            headers: None,
+            content: None,
+        })
+    }
+}
@@ -0,0 +1,231 @@
+//! Copy a whole mailbox into a local store
+//!
+//! [`Client::migrate()`] downloads every message and hands it to a [`MessageSink`]: a
+//! [`Maildir`], an mbox [`Writer`], or anything implementing the trait. With a [`UidStore`], the unique
+//! IDs of the copied messages are saved every hundred messages and when the migration stops, so an
+//! interrupted migration picks up where it stopped. The [`Report`] lists an MD5 checksum per message, to check the copies against. With
+//! [`MigrateOptions::skip_duplicates()`], messages already in the UID store or in the sink under another
+//! unique ID are left out.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use pop3_client::Client;
+//! # fn main() -> pop3_client::Result<()> {
+//! # let mut client = Client::connect("my.host.com", 110)?;
+//! # client.login("sweet_username", "very_secret_password")?;
+//! # #[cfg(feature = "serde")] {
+//! use pop3_client::migrate::MigrateOptions;
+//! use pop3_client::state::JsonFileStore;
+//! use pop3_client::store::maildir::Maildir;
+//!
+//! let mut maildir = Maildir::create("/home/me/Maildir").map_err(pop3_client::Error::Io)?;
+//! let mut store = JsonFileStore::new("/home/me/.migrated.json");
+//! let mut options = MigrateOptions::default();
+//! options.resume(&mut store, "me@my.host.com");
+//!
+//! let report = client.migrate(&mut maildir, &mut options)?;
+//! println!("{} copied, {} skipped, {} octets", report.migrated.len(), report.skipped, report.octets);
+//! # }
+//! #    Ok(())
+//! # }
+//! ```
+//!
+//! [`Client::migrate()`]: ../struct.Client.html#method.migrate
+//! [`MessageSink`]: trait.MessageSink.html
+//! [`Maildir`]: ../store/maildir/struct.Maildir.html
+//! [`Writer`]: ../store/mbox/struct.Writer.html
+//! [`UidStore`]: ../state/trait.UidStore.html
+//! [`Report`]: struct.Report.html
+//! [`MigrateOptions::skip_duplicates()`]: struct.MigrateOptions.html#method.skip_duplicates
+
+use std::collections::HashSet;
+use std::io::Write;
+
+use md5::{Digest, Md5};
+
+use crate::state::{SyncOptions, UidStore, SAVE_BATCH};
+use crate::store::{maildir::Maildir, mbox};
+use crate::{Client, Dedup, Error, Result, UidlEntry};
+
+/// Where migrated messages go
+pub trait MessageSink {
+    /// Keep the message, dot-unstuffed and with its original line endings
+    fn store(&mut self, uid: &str, message: &[u8]) -> Result<()>;
+
+    /// Call `f` with every message the sink already holds, for [`MigrateOptions::skip_duplicates()`]; none by
+    /// default
+    ///
+    /// [`MigrateOptions::skip_duplicates()`]: struct.MigrateOptions.html#method.skip_duplicates
+    fn for_each_stored(&self, _f: &mut dyn FnMut(&[u8])) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl MessageSink for Maildir {
+    fn store(&mut self, _uid: &str, message: &[u8]) -> Result<()> {
+        self.deliver(message).map(|_| ()).map_err(Error::Io)
+    }
+
+    fn for_each_stored(&self, f: &mut dyn FnMut(&[u8])) -> Result<()> {
+        self.for_each_message(f).map_err(Error::Io)
+    }
+}
+
+impl<W: Write> MessageSink for mbox::Writer<W> {
+    fn store(&mut self, _uid: &str, message: &[u8]) -> Result<()> {
+        self.write_message(message).map_err(Error::Io)
+    }
+}
+
+/// How [`Client::migrate()`] runs
+///
+/// [`Client::migrate()`]: ../struct.Client.html#method.migrate
+#[derive(Default)]
+pub struct MigrateOptions<'a> {
+    resume: Option<(&'a mut dyn UidStore, String)>,
+    dedup: Option<Dedup>,
+    sync: SyncOptions,
+}
+
+impl<'a> MigrateOptions<'a> {
+    /// Skip the messages saved in `store` under `account` by earlier runs, and save the new ones there
+    pub fn resume(&mut self, store: &'a mut dyn UidStore, account: &str) -> &mut Self {
+        self.resume = Some((store, account.to_string()));
+        self
+    }
+
+    /// Leave out messages seen before under another unique ID, in the UID store or in the sink, as told by `dedup`
+    ///
+    /// The keys of the copied messages are saved in the UID store along with their unique IDs.
+    pub fn skip_duplicates(&mut self, dedup: Dedup) -> &mut Self {
+        self.dedup = Some(dedup);
+        self
+    }
+
+    /// How messages get their unique IDs on servers refusing `UIDL`; by hashing their headers by default
+    pub fn sync_options(&mut self, options: SyncOptions) -> &mut Self {
+        self.sync = options;
+        self
+    }
+}
+
+/// A message copied by [`Client::migrate()`]
+///
+/// [`Client::migrate()`]: ../struct.Client.html#method.migrate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Migrated {
+    pub id: u32,
+    pub uid: String,
+    /// Octets handed to the sink
+    pub size: u64,
+    /// MD5 of those octets, in lowercase hex
+    pub md5: String,
+}
+
+/// The outcome of [`Client::migrate()`]
+///
+/// [`Client::migrate()`]: ../struct.Client.html#method.migrate
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub migrated: Vec<Migrated>,
+    /// Messages left out as copied by an earlier run
+    pub skipped: usize,
+    /// Messages left out as duplicates, see [`MigrateOptions::skip_duplicates()`]
+    ///
+    /// [`MigrateOptions::skip_duplicates()`]: struct.MigrateOptions.html#method.skip_duplicates
+    pub duplicates: usize,
+    /// Total size of the migrated messages
+    pub octets: u64,
+}
+
+impl Client {
+    /// Copy every message of the mailbox into `sink`, oldest first
+    ///
+    /// See the [`migrate`] module. The messages are left on the server.
+    ///
+    /// # Errors
+    /// The migration stops at the first error, whether from the server, the sink or the UID store. The
+    /// messages copied until then are saved in the UID store, if there is one.
+    ///
+    /// [`migrate`]: migrate/index.html
+    pub fn migrate<S: MessageSink + ?Sized>(
+        &mut self,
+        sink: &mut S,
+        options: &mut MigrateOptions,
+    ) -> Result<Report> {
+        let mut done = match &mut options.resume {
+            Some((store, account)) => store.load(account)?,
+            None                   => HashSet::new(),
+        };
+        let mut report = Report::default();
+        if let Some(dedup) = options.dedup {
+            sink.for_each_stored(&mut |message| done.extend(dedup.keys(message)))?;
+        }
+
+        let copied = self.copy_entries(sink, options, &mut done, &mut report);
+        let saved = match &mut options.resume {
+            Some((store, account)) => store.save(account, &done),
+            None                   => Ok(()),
+        };
+        copied?;
+        saved?;
+        Ok(report)
+    }
+
+    fn copy_entries<S: MessageSink + ?Sized>(
+        &mut self,
+        sink: &mut S,
+        options: &mut MigrateOptions,
+        done: &mut HashSet<String>,
+        report: &mut Report,
+    ) -> Result<()> {
+        let mut unsaved = 0;
+        for UidlEntry { id, uid } in self.sync_entries(&options.sync)? {
+            if done.contains(&uid) {
+                report.skipped += 1;
+                continue;
+            }
+
+            let mut duplicate = match options.dedup {
+                Some(dedup) => dedup.known_id(self, id, done)?,
+                None        => false,
+            };
+            let mut message = vec![];
+            if !duplicate {
+                self.retr_to_writer(id, &mut message)?;
+                if let Some(dedup) = options.dedup {
+                    let keys = dedup.keys(&message);
+                    duplicate = keys.iter().any(|key| done.contains(key));
+                    done.extend(keys);
+                }
+            }
+            if !duplicate {
+                sink.store(&uid, &message)?;
+            }
+
+            done.insert(uid.clone());
+            unsaved += 1;
+            if unsaved == SAVE_BATCH {
+                if let Some((store, account)) = &mut options.resume {
+                    store.save(account, done)?;
+                }
+                unsaved = 0;
+            }
+            if duplicate {
+                report.duplicates += 1;
+                continue;
+            }
+
+            report.octets += message.len() as u64;
+            report.migrated.push(Migrated {
+                id,
+                uid,
+                size: message.len() as u64,
+                md5: Md5::digest(&message).iter().map(|b| format!("{:02x}", b)).collect(),
+            });
+        }
+
+        Ok(())
+    }
+}
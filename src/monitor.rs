@@ -0,0 +1,174 @@
+//! Scripted probes of a POP3 server, for availability checks such as Nagios plugins or Prometheus exporters
+//!
+//! A probe connects, asks for the capabilities, logs in if credentials are given, takes the `STAT` of the
+//! mailbox and leaves, timing every step. Instead of stopping at the first error, it reports how far it got.
+//! The capabilities are compared with a baseline, so a server silently dropping `UIDL` or `STLS` after an
+//! upgrade shows up.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use pop3_client::fetcher::{AccountConfig, Credentials};
+//! use pop3_client::monitor::{self, ProbeConfig};
+//!
+//! let report = monitor::probe(&ProbeConfig {
+//!     account: AccountConfig {
+//!         host:        "my.host.com".to_string(),
+//!         credentials: Credentials::new("monitoring", "very_secret_password"),
+//!         ..AccountConfig::default()
+//!     },
+//!     expected_capabilities: vec!["TOP".to_string(), "UIDL".to_string()],
+//!     ..ProbeConfig::default()
+//! });
+//! if report.is_ok() {
+//!     println!("OK - {:?} to log in", report.auth.unwrap_or_default());
+//! } else {
+//!     println!("CRITICAL - {:?}, missing {:?}", report.error, report.missing);
+//! }
+//! ```
+
+use std::time::{Duration, Instant};
+
+use crate::fetcher::AccountConfig;
+use crate::{Capabilities, Error, Greeting, Result};
+
+/// What to probe
+#[derive(Clone)]
+pub struct ProbeConfig {
+    /// Where to connect and how; the login is skipped when the user name is empty
+    pub account: AccountConfig,
+    /// Capabilities the server should announce, by name, e.g. `"UIDL"`
+    pub expected_capabilities: Vec<String>,
+    /// Give up on connecting and on each command after this long
+    pub timeout: Duration,
+}
+
+impl Default for ProbeConfig {
+    fn default() -> Self {
+        Self {
+            account: AccountConfig::default(),
+            expected_capabilities: vec![],
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+/// The steps of a probe, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    /// Connecting, the greeting and `STLS`
+    Connect,
+    Capa,
+    Auth,
+    Stat,
+    Quit,
+}
+
+/// What [`probe()`] found; the timings of the steps it didn't reach are `None`
+///
+/// [`probe()`]: fn.probe.html
+#[derive(Debug)]
+pub struct ProbeReport {
+    pub greeting: Option<Greeting>,
+    pub connect: Option<Duration>,
+    pub capa: Option<Duration>,
+    pub auth: Option<Duration>,
+    pub stat: Option<Duration>,
+    pub quit: Option<Duration>,
+    /// The whole probe
+    pub total: Duration,
+    /// Empty when the server doesn't know `CAPA`
+    pub capabilities: Capabilities,
+    /// Expected capabilities the server didn't announce
+    pub missing: Vec<String>,
+    /// Capabilities announced but not expected; left empty when nothing is expected
+    pub unexpected: Vec<String>,
+    /// The message count and octets from `STAT`
+    pub mailbox: Option<(u32, u64)>,
+    /// The step which failed and why; the probe stops there
+    pub error: Option<(Step, Error)>,
+}
+
+impl ProbeReport {
+    /// Whether every step succeeded and no expected capability is missing
+    pub fn is_ok(&self) -> bool {
+        self.error.is_none() && self.missing.is_empty()
+    }
+}
+
+/// Run the probe, see the [module] documentation
+///
+/// [module]: index.html
+pub fn probe(config: &ProbeConfig) -> ProbeReport {
+    let start = Instant::now();
+    let mut report = ProbeReport {
+        greeting: None,
+        connect: None,
+        capa: None,
+        auth: None,
+        stat: None,
+        quit: None,
+        total: Duration::ZERO,
+        capabilities: Capabilities::default(),
+        missing: vec![],
+        unexpected: vec![],
+        mailbox: None,
+        error: None,
+    };
+    if let Err(failure) = run(config, &mut report) {
+        report.error = Some(failure);
+    }
+    report.total = start.elapsed();
+    report
+}
+
+fn run(config: &ProbeConfig, report: &mut ProbeReport) -> std::result::Result<(), (Step, Error)> {
+    let mut builder = config.account.builder().map_err(|e| (Step::Connect, e))?;
+    builder.connect_timeout(config.timeout).command_timeout(config.timeout);
+
+    let mut client = timed(Step::Connect, &mut report.connect, || {
+        builder.connect(&config.account.host, config.account.port)
+    })?;
+    report.greeting = Some(client.banner());
+
+    // Servers predating CAPA answer -ERR, which leaves every expected capability missing
+    let capabilities = timed(Step::Capa, &mut report.capa, || match client.capa() {
+        Err(Error::Server(_)) => Ok(Capabilities::default()),
+        result => result,
+    })?;
+    compare(config, &capabilities, report);
+    report.capabilities = capabilities;
+
+    let credentials = &config.account.credentials;
+    if !credentials.username.is_empty() {
+        timed(Step::Auth, &mut report.auth, || builder.log_in(&mut client, credentials))?;
+        report.mailbox = Some(timed(Step::Stat, &mut report.stat, || client.stat())?);
+    }
+    timed(Step::Quit, &mut report.quit, || client.quit())
+}
+
+/// Run a step, recording how long it took when it succeeds
+fn timed<T>(step: Step, took: &mut Option<Duration>, f: impl FnOnce() -> Result<T>) -> std::result::Result<T, (Step, Error)> {
+    let start = Instant::now();
+    let result = f().map_err(|e| (step, e))?;
+    *took = Some(start.elapsed());
+    Ok(result)
+}
+
+fn compare(config: &ProbeConfig, capabilities: &Capabilities, report: &mut ProbeReport) {
+    if config.expected_capabilities.is_empty() {
+        return;
+    }
+    report.missing = config
+        .expected_capabilities
+        .iter()
+        .filter(|name| !capabilities.contains(name))
+        .cloned()
+        .collect();
+    report.unexpected = capabilities
+        .iter()
+        .map(|(name, _)| name)
+        .filter(|name| !config.expected_capabilities.iter().any(|expected| expected.eq_ignore_ascii_case(name)))
+        .map(str::to_string)
+        .collect();
+}
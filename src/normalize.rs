@@ -0,0 +1,65 @@
+use std::borrow::Cow;
+
+use bytes::Bytes;
+
+use crate::response::unstuffed;
+
+/// The line endings of downloaded messages, see [`Builder::line_ending()`]
+///
+/// [`Builder::line_ending()`]: struct.Builder.html#method.line_ending
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Normalize {
+    /// `\r\n` everywhere, also on lines a lenient server ended with a bare `\n`
+    CrLf,
+    /// `\n` everywhere, as maildir and mbox files on Unix have them
+    Lf,
+    /// The octets as the server sent them, e.g. to check a DKIM or S/MIME signature
+    #[default]
+    Preserve,
+}
+
+impl Normalize {
+    fn ending(self) -> Option<&'static str> {
+        match self {
+            Normalize::CrLf => Some("\r\n"),
+            Normalize::Lf => Some("\n"),
+            Normalize::Preserve => None,
+        }
+    }
+
+    /// `line` with its ending replaced; a last line without any is left alone
+    pub(crate) fn line(self, line: &[u8]) -> Cow<'_, [u8]> {
+        let (ending, text) = match (self.ending(), line.strip_suffix(b"\n")) {
+            (Some(ending), Some(text)) => (ending.as_bytes(), text.strip_suffix(b"\r").unwrap_or(text)),
+            _ => return Cow::Borrowed(line),
+        };
+        if line.len() == text.len() + ending.len() {
+            return Cow::Borrowed(line);
+        }
+        Cow::Owned([text, ending].concat())
+    }
+
+    /// The payload of a `RETR` reply as the message it holds: dot-unstuffed, with the line endings replaced
+    pub(crate) fn message(self, payload: &[u8]) -> Bytes {
+        let mut message = Vec::with_capacity(payload.len());
+        for line in payload.split_inclusive(|c| *c == b'\n') {
+            message.extend_from_slice(&self.line(unstuffed(line)));
+        }
+        Bytes::from(message)
+    }
+
+    /// A whole message decoded to text, line by line
+    pub(crate) fn text(self, message: String) -> String {
+        let ending = match self.ending() {
+            Some(ending) => ending,
+            None => return message,
+        };
+        message
+            .split_inclusive('\n')
+            .map(|line| match line.strip_suffix('\n') {
+                Some(text) => text.strip_suffix('\r').unwrap_or(text).to_string() + ending,
+                None => line.to_string(),
+            })
+            .collect()
+    }
+}
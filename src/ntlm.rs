@@ -0,0 +1,233 @@
+//! `AUTH NTLM`, which on-premises Exchange servers often require for POP3
+//!
+//! Only NTLMv2 responses of [MS-NLMP] are sent; servers still insisting on NTLMv1 are refused by them. Message
+//! integrity (the MIC) and signing are not negotiated, as POP3 doesn't use them.
+//!
+//! [MS-NLMP]: https://learn.microsoft.com/en-us/openspecifications/windows_protocols/ms-nlmp
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use md4::{Digest, Md4};
+
+use crate::auth::{hmac_md5, random_bytes};
+use crate::{Authenticator, Client, Error, Result};
+
+const SIGNATURE: &[u8; 8] = b"NTLMSSP\0";
+
+const NEGOTIATE_UNICODE: u32 = 0x0000_0001;
+const NEGOTIATE_OEM: u32 = 0x0000_0002;
+const REQUEST_TARGET: u32 = 0x0000_0004;
+const NEGOTIATE_NTLM: u32 = 0x0000_0200;
+const NEGOTIATE_ALWAYS_SIGN: u32 = 0x0000_8000;
+const NEGOTIATE_EXTENDED_SESSIONSECURITY: u32 = 0x0008_0000;
+
+const NEGOTIATE_FLAGS: u32 = NEGOTIATE_UNICODE
+    | NEGOTIATE_OEM
+    | REQUEST_TARGET
+    | NEGOTIATE_NTLM
+    | NEGOTIATE_ALWAYS_SIGN
+    | NEGOTIATE_EXTENDED_SESSIONSECURITY;
+
+/// `MsvAvTimestamp` in the target information of the challenge
+const AV_TIMESTAMP: u16 = 7;
+
+/// 100 ns intervals between 1601-01-01, the Windows epoch, and 1970-01-01
+const FILETIME_UNIX_EPOCH: u64 = 116_444_736_000_000_000;
+
+/// `AUTH NTLM` with NTLMv2 responses
+///
+/// The domain may be given in the username as `DOMAIN\user`, or with [`domain()`]; without one, the domain
+/// the server names in its challenge is used.
+///
+/// [`domain()`]: struct.Ntlm.html#method.domain
+#[derive(Clone)]
+pub struct Ntlm {
+    username: String,
+    password: String,
+    domain: Option<String>,
+    workstation: String,
+    client_challenge: Option<[u8; 8]>,
+}
+
+impl Ntlm {
+    pub fn new(username: &str, password: &str) -> Self {
+        let (domain, username) = match username.split_once('\\') {
+            Some((domain, user)) => (Some(domain.to_string()), user),
+            None => (None, username),
+        };
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+            domain,
+            workstation: String::new(),
+            client_challenge: None,
+        }
+    }
+
+    /// The domain of the account
+    pub fn domain(mut self, domain: &str) -> Self {
+        self.domain = Some(domain.to_string());
+        self
+    }
+
+    /// The name of this machine, empty by default
+    pub fn workstation(mut self, workstation: &str) -> Self {
+        self.workstation = workstation.to_string();
+        self
+    }
+
+    /// Use a fixed client challenge instead of a random one, so responses can be compared with known ones
+    #[cfg(feature = "test-util")]
+    pub fn client_challenge(mut self, challenge: [u8; 8]) -> Self {
+        self.client_challenge = Some(challenge);
+        self
+    }
+
+    /// The `AUTHENTICATE_MESSAGE` answering the `CHALLENGE_MESSAGE` of the server
+    fn authenticate_message(&self, challenge: &[u8]) -> Result<Vec<u8>> {
+        let challenge = Challenge::parse(challenge)?;
+        let domain = self.domain.clone().unwrap_or_else(|| challenge.target_name.clone());
+        let client_challenge = match self.client_challenge {
+            Some(challenge) => challenge,
+            None => random_bytes()?,
+        };
+
+        let nt_hash = Md4::digest(utf16(&self.password));
+        let key = hmac_md5(&nt_hash, &[&utf16(&self.username.to_uppercase()), &utf16(&domain)]);
+
+        let timestamp = challenge.timestamp();
+        let mut blob = vec![1, 1, 0, 0, 0, 0, 0, 0];
+        blob.extend_from_slice(&timestamp.unwrap_or_else(now).to_le_bytes());
+        blob.extend_from_slice(&client_challenge);
+        blob.extend_from_slice(&[0; 4]);
+        blob.extend_from_slice(&challenge.target_info);
+        blob.extend_from_slice(&[0; 4]);
+
+        let proof = hmac_md5(&key, &[&challenge.server_challenge, &blob]);
+        let nt_response = [&proof[..], &blob].concat();
+        // With a timestamp from the server, the LMv2 response is left out (MS-NLMP, section 3.1.5.1.2)
+        let lm_response = match timestamp {
+            Some(_) => vec![0; 24],
+            None => {
+                let lm_proof = hmac_md5(&key, &[&challenge.server_challenge, &client_challenge]);
+                [&lm_proof[..], &client_challenge].concat()
+            }
+        };
+
+        let fields: [&[u8]; 6] = [
+            &lm_response,
+            &nt_response,
+            &utf16(&domain),
+            &utf16(&self.username),
+            &utf16(&self.workstation),
+            &[],
+        ];
+        let mut header = Vec::with_capacity(64);
+        header.extend_from_slice(SIGNATURE);
+        header.extend_from_slice(&3u32.to_le_bytes());
+        let mut payload = Vec::new();
+        let mut offset = 64;
+        for (i, field) in fields.iter().enumerate() {
+            header.extend_from_slice(&(field.len() as u16).to_le_bytes());
+            header.extend_from_slice(&(field.len() as u16).to_le_bytes());
+            header.extend_from_slice(&(offset as u32).to_le_bytes());
+            payload.extend_from_slice(field);
+            offset += field.len();
+            if i == 5 {
+                header.extend_from_slice(&(challenge.flags & NEGOTIATE_FLAGS | NEGOTIATE_UNICODE).to_le_bytes());
+            }
+        }
+        header.extend_from_slice(&payload);
+        Ok(header)
+    }
+}
+
+impl Authenticator for Ntlm {
+    fn authenticate(&self, client: &mut Client) -> Result<()> {
+        let mut negotiated = false;
+        client.auth("NTLM", None, |challenge| {
+            if !negotiated {
+                negotiated = true;
+                return Ok(negotiate_message());
+            }
+            self.authenticate_message(challenge)
+        })
+    }
+}
+
+/// The `NEGOTIATE_MESSAGE`, without domain or workstation
+fn negotiate_message() -> Vec<u8> {
+    let mut message = Vec::with_capacity(32);
+    message.extend_from_slice(SIGNATURE);
+    message.extend_from_slice(&1u32.to_le_bytes());
+    message.extend_from_slice(&NEGOTIATE_FLAGS.to_le_bytes());
+    message.extend_from_slice(&[0; 16]);
+    message
+}
+
+/// The parts of a `CHALLENGE_MESSAGE` the response is computed from
+struct Challenge {
+    target_name: String,
+    flags: u32,
+    server_challenge: [u8; 8],
+    target_info: Vec<u8>,
+}
+
+impl Challenge {
+    fn parse(message: &[u8]) -> Result<Self> {
+        let invalid = || Error::InvalidReply("malformed NTLM challenge".to_string());
+        if message.len() < 32 || &message[..8] != SIGNATURE || u32_at(message, 8) != 2 {
+            return Err(invalid());
+        }
+        let field = |at: usize| -> Result<Vec<u8>> {
+            if message.len() < at + 8 {
+                return Ok(Vec::new());
+            }
+            let len = u16::from_le_bytes([message[at], message[at + 1]]) as usize;
+            let offset = u32_at(message, at + 4) as usize;
+            message.get(offset..offset + len).map(<[u8]>::to_vec).ok_or_else(invalid)
+        };
+        let name = field(12)?;
+        let units: Vec<u16> = name.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+
+        let mut server_challenge = [0; 8];
+        server_challenge.copy_from_slice(&message[24..32]);
+        Ok(Self {
+            target_name: String::from_utf16_lossy(&units),
+            flags: u32_at(message, 20),
+            server_challenge,
+            target_info: field(40)?,
+        })
+    }
+
+    /// The `MsvAvTimestamp` of the target information, as a Windows FILETIME
+    fn timestamp(&self) -> Option<u64> {
+        let mut pairs = &self.target_info[..];
+        while pairs.len() >= 4 {
+            let id = u16::from_le_bytes([pairs[0], pairs[1]]);
+            let len = u16::from_le_bytes([pairs[2], pairs[3]]) as usize;
+            let value = pairs.get(4..4 + len)?;
+            if id == AV_TIMESTAMP && len == 8 {
+                let mut bytes = [0; 8];
+                bytes.copy_from_slice(value);
+                return Some(u64::from_le_bytes(bytes));
+            }
+            pairs = &pairs[4 + len..];
+        }
+        None
+    }
+}
+
+fn u32_at(message: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([message[at], message[at + 1], message[at + 2], message[at + 3]])
+}
+
+fn utf16(text: &str) -> Vec<u8> {
+    text.encode_utf16().flat_map(u16::to_le_bytes).collect()
+}
+
+/// Now, as a Windows FILETIME
+fn now() -> u64 {
+    let since = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    FILETIME_UNIX_EPOCH + since.as_secs() * 10_000_000 + u64::from(since.subsec_nanos() / 100)
+}
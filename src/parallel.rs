@@ -0,0 +1,216 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use bytes::Bytes;
+
+use crate::{CancellationToken, Client, Error, Result};
+
+/// What [`Client::retr_many()`] and [`Client::parallel_fetch_cancellable()`] got, cancelled or not
+///
+/// [`Client::retr_many()`]: struct.Client.html#method.retr_many
+/// [`Client::parallel_fetch_cancellable()`]: struct.Client.html#method.parallel_fetch_cancellable
+#[derive(Debug)]
+pub struct PartialFetch {
+    /// One result per message, in the order asked; those not downloaded fail with [`Error::Cancelled`], or
+    /// [`Error::ConnectionAborted`] when the sessions stopped on errors
+    ///
+    /// [`Error::Cancelled`]: enum.Error.html#variant.Cancelled
+    /// [`Error::ConnectionAborted`]: enum.Error.html#variant.ConnectionAborted
+    pub messages: Vec<Result<Bytes>>,
+    /// How many messages, from the first, have a result from the server; with parallel sessions, some later
+    /// messages may have one too
+    pub completed_up_to: usize,
+}
+
+impl PartialFetch {
+    /// Whether every message has a result from the server, `-ERR` included
+    pub fn is_complete(&self) -> bool {
+        self.completed_up_to == self.messages.len()
+    }
+
+    fn new(results: Vec<Option<Result<Bytes>>>, cancel: Option<&CancellationToken>) -> Self {
+        let completed_up_to = results.iter().take_while(|result| result.is_some()).count();
+        let cancelled = cancel.is_some_and(CancellationToken::is_cancelled);
+        let messages = results
+            .into_iter()
+            .map(|result| match result {
+                Some(result) => result,
+                None if cancelled => Err(Error::Cancelled),
+                None => Err(Error::ConnectionAborted),
+            })
+            .collect();
+        Self { messages, completed_up_to }
+    }
+}
+
+impl Client {
+    /// Download messages one after the other, until done or `cancel` is cancelled
+    ///
+    /// The token is looked at between messages: the message being downloaded is finished, so the session stays
+    /// usable and what was downloaded is kept. To abort the download itself, at the cost of the session, see
+    /// [`cancellation_token()`].
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::{CancellationToken, Client};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let cancel = CancellationToken::default();
+    /// // Hand a clone to the "Stop" button
+    /// let stop = cancel.clone();
+    /// let fetched = client.retr_many(&[1, 2, 3], &cancel);
+    /// for message in &fetched.messages[..fetched.completed_up_to] {
+    ///     println!("{} octets", message.as_ref().map_or(0, |message| message.len()));
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    /// Each message fails as [`retr_bytes()`] does. The download stops after any error but an `-ERR` reply.
+    ///
+    /// [`cancellation_token()`]: struct.Client.html#method.cancellation_token
+    /// [`retr_bytes()`]: struct.Client.html#method.retr_bytes
+    pub fn retr_many(&mut self, msgs: &[u32], cancel: &CancellationToken) -> PartialFetch {
+        let mut results: Vec<Option<Result<Bytes>>> = msgs.iter().map(|_| None).collect();
+        for (msg, slot) in msgs.iter().zip(&mut results) {
+            if cancel.is_cancelled() {
+                break;
+            }
+            let result = self.retr_bytes(*msg);
+            let broken = matches!(result, Err(ref e) if !matches!(e, Error::Server(_)));
+            *slot = Some(result);
+            if broken {
+                break;
+            }
+        }
+        PartialFetch::new(results, Some(cancel))
+    }
+
+    /// Download messages over this session and up to `n_connections` more at once, for large mailboxes
+    ///
+    /// The additional sessions are opened with `open`, each on its own thread, and take the next message not
+    /// yet taken until none is left. A session which can't be opened, e.g. because the server locks the maildrop
+    /// (`[IN-USE]`), leaves the work to the others; at worst, this session does it all. The additional sessions
+    /// end with `QUIT` once done.
+    ///
+    /// Messages are numbered the same in every session as long as none is deleted meanwhile, so don't use this
+    /// while another client expunges the mailbox.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use pop3_client::fetcher::{AccountConfig, Credentials};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let config = AccountConfig {
+    ///     host:        "my.host.com".to_string(),
+    ///     credentials: Credentials::new("sweet_username", "very_secret_password"),
+    ///     ..AccountConfig::default()
+    /// };
+    /// let mut client = config.open()?;
+    /// let ids: Vec<u32> = client.list_entries(None)?.iter().map(|entry| entry.id).collect();
+    /// for (id, message) in ids.iter().zip(client.parallel_fetch(&ids, 3, || config.open())) {
+    ///     std::fs::write(format!("{}.eml", id), message?).map_err(pop3_client::Error::Io)?;
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// # Errors
+    /// One result per message, in the order of `ids`, each failing as [`retr_bytes()`] does. A session stops after
+    /// any error but an `-ERR` reply; messages left over once all sessions stopped fail with
+    /// [`Error::ConnectionAborted`].
+    ///
+    /// [`retr_bytes()`]: struct.Client.html#method.retr_bytes
+    /// [`Error::ConnectionAborted`]: enum.Error.html#variant.ConnectionAborted
+    pub fn parallel_fetch<F>(&mut self, ids: &[u32], n_connections: usize, open: F) -> Vec<Result<Bytes>>
+    where
+        F: Fn() -> Result<Client> + Sync,
+    {
+        PartialFetch::new(self.fetch_in_parallel(ids, n_connections, open, None), None).messages
+    }
+
+    /// [`parallel_fetch()`] until done or `cancel` is cancelled
+    ///
+    /// Every session looks at the token between messages, as [`retr_many()`] does, and the additional ones
+    /// end with `QUIT` once it's cancelled.
+    ///
+    /// [`parallel_fetch()`]: struct.Client.html#method.parallel_fetch
+    /// [`retr_many()`]: struct.Client.html#method.retr_many
+    pub fn parallel_fetch_cancellable<F>(
+        &mut self,
+        ids: &[u32],
+        n_connections: usize,
+        open: F,
+        cancel: &CancellationToken,
+    ) -> PartialFetch
+    where
+        F: Fn() -> Result<Client> + Sync,
+    {
+        PartialFetch::new(self.fetch_in_parallel(ids, n_connections, open, Some(cancel)), Some(cancel))
+    }
+
+    fn fetch_in_parallel<F>(
+        &mut self,
+        ids: &[u32],
+        n_connections: usize,
+        open: F,
+        cancel: Option<&CancellationToken>,
+    ) -> Vec<Option<Result<Bytes>>>
+    where
+        F: Fn() -> Result<Client> + Sync,
+    {
+        let next = AtomicUsize::new(0);
+        let work = |client: &mut Client| {
+            let mut fetched = vec![];
+            loop {
+                if cancel.is_some_and(CancellationToken::is_cancelled) {
+                    break;
+                }
+                let index = next.fetch_add(1, Ordering::Relaxed);
+                let msg = match ids.get(index) {
+                    Some(msg) => *msg,
+                    None      => break,
+                };
+                let result = client.retr_bytes(msg);
+                let broken = matches!(result, Err(ref e) if !matches!(e, Error::Server(_)));
+                fetched.push((index, result));
+                if broken {
+                    break;
+                }
+            }
+            fetched
+        };
+
+        let mut results: Vec<Option<Result<Bytes>>> = ids.iter().map(|_| None).collect();
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..n_connections.min(ids.len().saturating_sub(1)))
+                .map(|_| {
+                    scope.spawn(|| {
+                        // No point in opening a session only to leave
+                        if cancel.is_some_and(CancellationToken::is_cancelled) {
+                            return vec![];
+                        }
+                        match open() {
+                            Ok(mut client) => {
+                                let fetched = work(&mut client);
+                                client.quit().ok();
+                                fetched
+                            }
+                            Err(_) => vec![],
+                        }
+                    })
+                })
+                .collect();
+            let mut fetched = work(self);
+            for worker in workers {
+                fetched.extend(worker.join().unwrap_or_default());
+            }
+            for (index, result) in fetched {
+                results[index] = Some(result);
+            }
+        });
+        results
+    }
+}
@@ -0,0 +1,152 @@
+//! Reply parsing on plain byte slices, separated from any I/O
+//!
+//! Everything here is a pure function of its input, so it can be tested and fuzzed without a
+//! connection. None of the functions panic, whatever the bytes.
+
+use std::collections::BTreeMap;
+
+use crate::{Capabilities, Error, MessageInfo, Result, Status, TopResult, UidlEntry};
+
+/// Split a status line into its indicator and the text after it, without the line ending
+///
+/// The indicators are matched case insensitively, as some servers send `+ok` or `+Ok`, and may follow
+/// whitespace. Anything that isn't `+OK` is taken as `-ERR`; when the `-ERR` itself is missing too,
+/// the text is the whole line. [`Compliance::Strict`] rejects all of these before they get here.
+///
+/// [`Compliance::Strict`]: ../enum.Compliance.html#variant.Strict
+pub fn status(line: &[u8]) -> (Status, &[u8]) {
+    let line = trim_line_end(&line[..status_line_len(line)]);
+    let (status, end) = indicator(line);
+    (status, trim_start(&line[end..]))
+}
+
+/// The status indicator a line starts with, and where it ends; 0 when there's none
+pub(crate) fn indicator(line: &[u8]) -> (Status, usize) {
+    let start = line.len() - trim_start(line).len();
+    let starts_with = |prefix: &[u8]| {
+        line.get(start..start + prefix.len()).is_some_and(|text| text.eq_ignore_ascii_case(prefix))
+    };
+    if starts_with(b"+OK") {
+        (Status::Ok, start + 3)
+    } else if starts_with(b"-ERR") {
+        (Status::Err, start + 4)
+    } else {
+        (Status::Err, 0)
+    }
+}
+
+/// Parse the text of a `STAT` reply: the message count and the size of the mailbox in octets, ignoring
+/// anything after them
+///
+/// The fields may be separated by several spaces and padded with leading zeros. The size is 64 bits wide, as
+/// large mailboxes exceed 4 GiB.
+pub fn stat(info: &[u8]) -> Result<(u32, u64)> {
+    let mut fields = fields(info);
+    let mut next = || fields.next().ok_or_else(|| invalid(info));
+    Ok((number("message count", next()?)?, number("mailbox size", next()?)?))
+}
+
+/// Parse a scan listing of `LIST`: a message number and a size, ignoring anything after them
+pub fn scan_listing(line: &[u8]) -> Result<MessageInfo> {
+    let (id, size) = two_fields(line)?;
+    Ok(MessageInfo {
+        id,
+        size: number("size", size)?,
+    })
+}
+
+/// Parse a unique-id listing of `UIDL`: a message number and the unique id
+pub fn uidl_listing(line: &[u8]) -> Result<UidlEntry> {
+    let (id, uid) = two_fields(line)?;
+    Ok(UidlEntry {
+        id,
+        uid: String::from_utf8_lossy(uid).to_string(),
+    })
+}
+
+/// Parse the payload of a `CAPA` reply, one capability with its arguments per line
+pub fn capabilities(payload: &[u8]) -> Capabilities {
+    let entries: BTreeMap<String, Vec<String>> = unstuff(payload)
+        .into_iter()
+        .filter_map(|line| {
+            let line = String::from_utf8_lossy(line);
+            let mut parts = line.split_whitespace();
+            let name = parts.next()?.to_ascii_uppercase();
+            Some((name, parts.map(str::to_string).collect()))
+        })
+        .collect();
+    Capabilities::from(entries)
+}
+
+/// Parse the payload of a `TOP` reply into the header section and the body lines
+pub fn top(payload: &[u8]) -> TopResult {
+    let mut lines = unstuff(payload).into_iter();
+    let mut headers = vec![];
+    for line in lines.by_ref().take_while(|line| !line.is_empty()) {
+        headers.extend_from_slice(line);
+        headers.extend_from_slice(b"\r\n");
+    }
+    TopResult {
+        headers,
+        body_lines: lines.map(<[u8]>::to_vec).collect(),
+    }
+}
+
+/// Split the payload of a multiline reply into lines, without line endings and with the byte-stuffed
+/// leading `.` removed, stopping at the terminating `.` line if there is one
+pub fn unstuff(payload: &[u8]) -> Vec<&[u8]> {
+    payload
+        .split_inclusive(|c| *c == b'\n')
+        .map(trim_line_end)
+        .take_while(|line| *line != b".")
+        .map(|line| if line.starts_with(b"..") { &line[1..] } else { line })
+        .collect()
+}
+
+/// Length of the first line, including its line ending
+pub(crate) fn status_line_len(raw: &[u8]) -> usize {
+    raw.iter()
+        .position(|c| *c == b'\n')
+        .map_or(raw.len(), |i| i + 1)
+}
+
+pub(crate) fn trim_line_end(line: &[u8]) -> &[u8] {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    line.strip_suffix(b"\r").unwrap_or(line)
+}
+
+fn trim_start(text: &[u8]) -> &[u8] {
+    let start = text.iter().position(|c| !c.is_ascii_whitespace()).unwrap_or(text.len());
+    &text[start..]
+}
+
+fn fields(line: &[u8]) -> impl Iterator<Item = &[u8]> {
+    line.split(|c| c.is_ascii_whitespace()).filter(|field| !field.is_empty())
+}
+
+fn two_fields(line: &[u8]) -> Result<(u32, &[u8])> {
+    let mut fields = fields(line);
+    let id = fields.next().ok_or_else(|| invalid(line))?;
+    let id = number("message number", id)?;
+    Ok((id, fields.next().ok_or_else(|| invalid(line))?))
+}
+
+/// A decimal number made of ASCII digits only, leading zeros allowed; anything else, a sign included, or a
+/// value too large for `T` is an [`Error::Parse`] naming the field
+///
+/// [`Error::Parse`]: ../enum.Error.html#variant.Parse
+pub(crate) fn number<T: std::str::FromStr>(field: &'static str, raw: &[u8]) -> Result<T> {
+    let parse_error = || Error::Parse {
+        field,
+        raw: String::from_utf8_lossy(raw).to_string(),
+    };
+    if raw.is_empty() || !raw.iter().all(u8::is_ascii_digit) {
+        return Err(parse_error());
+    }
+    // Digits only, so this is valid UTF-8
+    std::str::from_utf8(raw).ok().and_then(|digits| digits.parse().ok()).ok_or_else(parse_error)
+}
+
+fn invalid(line: &[u8]) -> Error {
+    Error::InvalidReply(String::from_utf8_lossy(trim_line_end(line)).to_string())
+}
@@ -0,0 +1,172 @@
+//! A pool of authorized connections to a single account
+//!
+//! Handy for applications which poll the mailbox on request rather than in a long-lived loop:
+//! instead of connecting and authorizing every time, they borrow a ready connection from the pool.
+//!
+//! Keep in mind that most servers present a snapshot of the maildrop taken at login time, and lock
+//! the maildrop for the duration of the session, so more than one idle connection per account is
+//! only useful on servers allowing concurrent sessions.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use pop3_client::fetcher::{AccountConfig, Credentials};
+//! use pop3_client::pool::ClientPool;
+//! # fn main() -> pop3_client::Result<()> {
+//!
+//! let pool = ClientPool::new(AccountConfig {
+//!     host:                 "my.host.com".to_string(),
+//!     port:                 110,
+//!     tls:                  false,
+//!     allow_plaintext_auth: true,
+//!     credentials:          Credentials::new("sweet_username", "very_secret_password"),
+//!     ..AccountConfig::default()
+//! }, 2);
+//!
+//! let (messages, _) = pool.get()?.stat()?;
+//! #    Ok(())
+//! # }
+//! ```
+
+use std::ops::{Deref, DerefMut};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::fetcher::AccountConfig;
+use crate::{telemetry, Client, ClientFactory, Result};
+
+type Open = Box<dyn Fn() -> Result<Client> + Send + Sync>;
+
+/// Keeps up to `size` idle, authorized connections to the account
+pub struct ClientPool {
+    open: Open,
+    /// Host and user name, for the metrics
+    account: (String, String),
+    size: usize,
+    max_latency: Option<Duration>,
+    idle: Mutex<Vec<Client>>,
+}
+
+impl ClientPool {
+    /// Create an empty pool; connections are opened lazily by [`get()`]
+    ///
+    /// [`get()`]: struct.ClientPool.html#method.get
+    pub fn new(config: AccountConfig, size: usize) -> Self {
+        let account = (config.host.clone(), config.credentials.username.clone());
+        Self::with_opener(Box::new(move || config.open()), account, size)
+    }
+
+    /// Create an empty pool opening its connections with `factory`, e.g. to set what [`AccountConfig`] can't
+    ///
+    /// [`AccountConfig`]: ../fetcher/struct.AccountConfig.html
+    pub fn from_factory(factory: ClientFactory, size: usize) -> Self {
+        let account = (factory.host().to_string(), factory.username().to_string());
+        Self::with_opener(Box::new(move || factory.new_client()), account, size)
+    }
+
+    fn with_opener(open: Open, account: (String, String), size: usize) -> Self {
+        Self {
+            open,
+            account,
+            size,
+            max_latency: None,
+            idle: Mutex::new(Vec::with_capacity(size)),
+        }
+    }
+
+    /// Drop idle connections whose mean round trip, as of [`Client::latency()`], exceeds `limit`
+    ///
+    /// [`Client::latency()`]: ../struct.Client.html#method.latency
+    pub fn max_latency(mut self, limit: Duration) -> Self {
+        self.max_latency = Some(limit);
+        self
+    }
+
+    /// Borrow a connection
+    ///
+    /// Idle connections are checked with [`Client::health_check()`] first, and the ones which fail the check, or
+    /// are slower than [`max_latency()`], are dropped. When no live connection is left, a new one is opened and
+    /// authorized.
+    /// The connection returns to the pool when the [`PooledClient`] is dropped.
+    ///
+    /// [`Client::health_check()`]: ../struct.Client.html#method.health_check
+    /// [`max_latency()`]: struct.ClientPool.html#method.max_latency
+    /// [`PooledClient`]: struct.PooledClient.html
+    pub fn get(&self) -> Result<PooledClient<'_>> {
+        let mut dead = false;
+        while let Some(mut client) = self.take_idle() {
+            let health = client.health_check();
+            if health.alive && self.max_latency.is_none_or(|limit| health.latency.mean <= limit) {
+                return Ok(PooledClient::new(self, client));
+            }
+            dead = true;
+        }
+        if dead {
+            telemetry::reconnected(&self.account.0, &self.account.1);
+        }
+
+        (self.open)().map(|client| PooledClient::new(self, client))
+    }
+
+    /// Number of idle connections currently held
+    pub fn idle(&self) -> usize {
+        self.idle.lock().map(|idle| idle.len()).unwrap_or(0)
+    }
+
+    fn take_idle(&self) -> Option<Client> {
+        self.idle.lock().ok().and_then(|mut idle| idle.pop())
+    }
+
+    fn release(&self, client: Client) {
+        if let Ok(mut idle) = self.idle.lock() {
+            if idle.len() < self.size {
+                idle.push(client);
+            }
+        }
+    }
+}
+
+/// A connection borrowed from a [`ClientPool`], dereferencing to [`Client`]
+///
+/// [`ClientPool`]: struct.ClientPool.html
+/// [`Client`]: ../struct.Client.html
+pub struct PooledClient<'a> {
+    pool: &'a ClientPool,
+    client: Option<Client>,
+}
+
+impl<'a> PooledClient<'a> {
+    fn new(pool: &'a ClientPool, client: Client) -> Self {
+        Self {
+            pool,
+            client: Some(client),
+        }
+    }
+
+    /// Take the connection out of the pool for good, e.g. to `quit()` and commit the deletions
+    pub fn detach(mut self) -> Client {
+        self.client.take().expect("client is only taken on detach or drop")
+    }
+}
+
+impl Deref for PooledClient<'_> {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        self.client.as_ref().expect("client is only taken on detach or drop")
+    }
+}
+
+impl DerefMut for PooledClient<'_> {
+    fn deref_mut(&mut self) -> &mut Client {
+        self.client.as_mut().expect("client is only taken on detach or drop")
+    }
+}
+
+impl Drop for PooledClient<'_> {
+    fn drop(&mut self) {
+        if let Some(client) = self.client.take() {
+            self.pool.release(client);
+        }
+    }
+}
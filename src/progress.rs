@@ -0,0 +1,85 @@
+use std::sync::Arc;
+
+use crate::{Client, Error, Result};
+
+/// Report at most once per this many octets, so long downloads don't call back for every line
+const REPORT_INTERVAL: u64 = 64 * 1024;
+
+/// How far the download of a message has come, as passed to the callback set with [`Builder::progress()`]
+///
+/// [`Builder::progress()`]: struct.Builder.html#method.progress
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    /// Number of the message being downloaded
+    pub msg: u32,
+    /// Octets received so far
+    pub received: u64,
+    /// Size of the message according to `LIST`, when the server told it
+    pub expected: Option<u64>,
+}
+
+pub(crate) type Callback = Arc<dyn Fn(Progress) + Send + Sync>;
+
+/// Follows a single download, to report its progress and check its size
+pub(crate) struct Tracker {
+    callback: Option<Callback>,
+    progress: Progress,
+}
+
+impl Tracker {
+    /// Called after every line with the octets received so far
+    pub fn update(&mut self, received: usize) {
+        if received as u64 >= self.progress.received + REPORT_INTERVAL {
+            self.report(received);
+        }
+    }
+
+    /// Called once the whole message has been received
+    pub fn finish(&mut self, received: usize) {
+        if received as u64 != self.progress.received || received == 0 {
+            self.report(received);
+        }
+    }
+
+    fn report(&mut self, received: usize) {
+        self.progress.received = received as u64;
+        if let Some(callback) = &self.callback {
+            callback(self.progress);
+        }
+    }
+
+    /// Fail if the received size is off the listed one by more than `percent`
+    fn verify(&self, percent: u32) -> Result<()> {
+        let Progress { received, expected, .. } = self.progress;
+        match expected {
+            Some(listed) if received.abs_diff(listed) * 100 > listed * u64::from(percent) => {
+                Err(Error::SizeMismatch(listed, received))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Client {
+    /// Run a download of `msg`, reporting its progress if a callback is set, and checking its size if asked to
+    pub(crate) fn tracked<T, F: FnOnce(&mut Self) -> Result<T>>(&mut self, msg: u32, download: F) -> Result<T> {
+        if self.progress.is_some() || self.size_tolerance.is_some() {
+            // Without a size the progress is still worth reporting, so a failing LIST is no reason to give up
+            let expected = self
+                .list_entries(Some(msg))
+                .ok()
+                .and_then(|entries| entries.first().map(|info| info.size));
+            self.tracker = Some(Tracker {
+                callback: self.progress.clone(),
+                progress: Progress { msg, received: 0, expected },
+            });
+        }
+        let result = download(self);
+        let tracker = self.tracker.take();
+        let result = result?;
+        match (tracker, self.size_tolerance) {
+            (Some(tracker), Some(percent)) => tracker.verify(percent).map(|_| result),
+            _ => Ok(result),
+        }
+    }
+}
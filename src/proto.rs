@@ -0,0 +1,316 @@
+//! The protocol without the I/O: commands go in and come out as bytes, bytes go in and come out as replies
+//!
+//! [`Session`] never touches a socket, so it can be driven over any transport: an async socket, a serial line
+//! of an embedded device, or a buffer in a test. It takes care of what the transport shouldn't have to: the
+//! encoding and checking of commands, the framing of single and multiline replies as they trickle in, and
+//! the stage of the session. [`Client`] is the blocking transport this crate ships with, and [`AsyncClient`] the
+//! async one, under the `async` feature.
+//!
+//! # Example
+//!
+//! ```
+//! use pop3_client::proto::{Command, Event, Session};
+//! # fn main() -> pop3_client::Result<()> {
+//!
+//! let mut session = Session::new();
+//! session.receive(b"+OK ready\r\n");
+//! assert!(matches!(session.poll(), Some(Ok(Event::Greeting(_)))));
+//!
+//! // Whatever `send()` returns goes out through the transport...
+//! assert_eq!(session.send(&Command::List(None))?, b"LIST\r\n");
+//!
+//! // ...and whatever comes back is fed in, in pieces of any size
+//! session.receive(b"+OK 1 message\r\n1 1");
+//! assert!(session.poll().is_none());
+//! session.receive(b"20\r\n.\r\n");
+//! match session.poll() {
+//!     Some(Ok(Event::Reply { response, .. })) => assert_eq!(response.lines, vec!["1 120"]),
+//!     other => panic!("{:?}", other),
+//! }
+//! #    Ok(())
+//! # }
+//! ```
+//!
+//! [`Session`]: struct.Session.html
+//! [`Client`]: ../struct.Client.html
+//! [`AsyncClient`]: ../struct.AsyncClient.html
+
+use std::collections::VecDeque;
+use std::ops::Range;
+
+use bytes::Bytes;
+
+use crate::parser::{self, trim_line_end};
+use crate::response::Frame;
+use crate::secret::Sensitive;
+use crate::{command, stage, Compliance, Error, Response, Result, Stage, Status};
+
+pub use crate::command::MAX_COMMAND_LEN;
+
+/// A command of [RFC 1939] or one of its extensions
+///
+/// [RFC 1939]: https://tools.ietf.org/html/rfc1939
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command<'a> {
+    User(&'a str),
+    Pass(&'a str),
+    Apop { name: &'a str, digest: &'a str },
+    Stat,
+    List(Option<u32>),
+    Retr(u32),
+    Dele(u32),
+    Noop,
+    Rset,
+    Top(u32, u32),
+    Uidl(Option<u32>),
+    Capa,
+    Stls,
+    Utf8,
+    Quit,
+    /// Any other command line, without the CRLF, and whether its reply is multiline when successful
+    Raw { line: &'a str, multiline: bool },
+}
+
+impl Command<'_> {
+    /// Whether a successful reply has more lines, up to a `.` line
+    pub fn is_multiline(&self) -> bool {
+        match self {
+            Command::List(msg) | Command::Uidl(msg) => msg.is_none(),
+            Command::Retr(_) | Command::Top(..) | Command::Capa => true,
+            Command::Raw { multiline, .. } => *multiline,
+            _ => false,
+        }
+    }
+
+    /// The command line terminated by CRLF
+    ///
+    /// # Errors
+    /// [`Error::InvalidArgument`] if an argument contains a line break, or the line is longer than
+    /// [`MAX_COMMAND_LEN`].
+    ///
+    /// [`Error::InvalidArgument`]: ../enum.Error.html#variant.InvalidArgument
+    /// [`MAX_COMMAND_LEN`]: constant.MAX_COMMAND_LEN.html
+    pub fn encode(&self) -> Result<Vec<u8>> {
+        command::encode(&self.line())
+    }
+
+    /// Take a command line apart, as a server receives it, without the CRLF
+    ///
+    /// Keywords are case insensitive. Lines with an unknown keyword come back as [`Command::Raw`].
+    ///
+    /// # Example
+    /// ```
+    /// use pop3_client::proto::Command;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// assert_eq!(Command::parse("top 3 10")?, Command::Top(3, 10));
+    /// assert_eq!(Command::parse("PASS secret with spaces")?, Command::Pass("secret with spaces"));
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// [`Error::InvalidArgument`] when the arguments of a known command are missing or aren't numbers where
+    /// numbers are due.
+    ///
+    /// [`Command::Raw`]: enum.Command.html#variant.Raw
+    /// [`Error::InvalidArgument`]: ../enum.Error.html#variant.InvalidArgument
+    pub fn parse(line: &str) -> Result<Command<'_>> {
+        let (keyword, rest) = line.split_once(' ').unwrap_or((line, ""));
+        let mut args = rest.split(' ').filter(|arg| !arg.is_empty());
+        let invalid = || Error::InvalidArgument(format!("invalid arguments to {}", keyword.to_ascii_uppercase()));
+        let mut number = || args.next().ok_or_else(invalid)?.parse::<u32>().map_err(|_| invalid());
+        let optional = |rest: &str| match rest.trim() {
+            "" => Ok(None),
+            arg => arg.parse::<u32>().map(Some).map_err(|_| invalid()),
+        };
+
+        Ok(match keyword.to_ascii_uppercase().as_str() {
+            "USER" if !rest.is_empty() => Command::User(rest),
+            "PASS" if !rest.is_empty() => Command::Pass(rest),
+            "APOP" => match rest.split_once(' ') {
+                Some((name, digest)) => Command::Apop { name, digest },
+                None => return Err(invalid()),
+            },
+            "USER" | "PASS" => return Err(invalid()),
+            "STAT" => Command::Stat,
+            "LIST" => Command::List(optional(rest)?),
+            "RETR" => Command::Retr(number()?),
+            "DELE" => Command::Dele(number()?),
+            "NOOP" => Command::Noop,
+            "RSET" => Command::Rset,
+            "TOP"  => Command::Top(number()?, number()?),
+            "UIDL" => Command::Uidl(optional(rest)?),
+            "CAPA" => Command::Capa,
+            "STLS" => Command::Stls,
+            "UTF8" => Command::Utf8,
+            "QUIT" => Command::Quit,
+            _      => Command::Raw { line, multiline: false },
+        })
+    }
+
+    /// The command line, fit for error messages and logs: `PASS` and `APOP` without their arguments
+    pub(crate) fn redacted(&self) -> String {
+        match self {
+            Command::Pass(_)           => "PASS".to_string(),
+            Command::Apop { name, .. } => format!("APOP {}", name),
+            command                    => command.line().to_string(),
+        }
+    }
+
+    pub(crate) fn line(&self) -> Sensitive {
+        let with_msg = |name: &str, msg: &Option<u32>| match msg {
+            Some(msg) => format!("{} {}", name, msg),
+            None      => name.to_string(),
+        };
+        Sensitive::new(match self {
+            Command::User(name)            => format!("USER {}", name),
+            Command::Pass(password)        => format!("PASS {}", password),
+            Command::Apop { name, digest } => format!("APOP {} {}", name, digest),
+            Command::Stat                  => "STAT".to_string(),
+            Command::List(msg)             => with_msg("LIST", msg),
+            Command::Retr(msg)             => format!("RETR {}", msg),
+            Command::Dele(msg)             => format!("DELE {}", msg),
+            Command::Noop                  => "NOOP".to_string(),
+            Command::Rset                  => "RSET".to_string(),
+            Command::Top(msg, n)           => format!("TOP {} {}", msg, n),
+            Command::Uidl(msg)             => with_msg("UIDL", msg),
+            Command::Capa                  => "CAPA".to_string(),
+            Command::Stls                  => "STLS".to_string(),
+            Command::Utf8                  => "UTF8".to_string(),
+            Command::Quit                  => "QUIT".to_string(),
+            Command::Raw { line, .. }      => line.to_string(),
+        })
+    }
+}
+
+/// What [`Session::poll()`] found in the received bytes
+///
+/// [`Session::poll()`]: struct.Session.html#method.poll
+#[derive(Debug, Clone)]
+pub enum Event {
+    /// The server greeted; nothing can be sent before
+    Greeting(Response),
+    /// A positive reply to the oldest command not answered yet
+    Reply {
+        /// The command name, e.g. `"RETR"`; arguments are left out, as they may be secret
+        command: String,
+        response: Response,
+    },
+}
+
+/// A POP3 session as a state machine, see the [module documentation](index.html)
+#[derive(Debug, Default)]
+pub struct Session {
+    buffer: Vec<u8>,
+    /// Name and multiline-ness of the commands sent and not answered yet
+    pending: VecDeque<(String, bool)>,
+    greeted: bool,
+    stage: Stage,
+    compliance: Compliance,
+}
+
+impl Session {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Check the replies as strictly as [`Builder::compliance()`] does
+    ///
+    /// [`Builder::compliance()`]: ../struct.Builder.html#method.compliance
+    pub fn compliance(&mut self, compliance: Compliance) -> &mut Self {
+        self.compliance = compliance;
+        self
+    }
+
+    /// The stage the session is in, following the replies polled so far
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    /// Number of commands sent which haven't been answered yet
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Encode `command`, and expect its reply; the bytes are for the transport to write
+    ///
+    /// Commands may be sent before the previous ones are answered, for servers announcing `PIPELINING`.
+    ///
+    /// # Errors
+    /// Same as [`Command::encode()`]; nothing is expected then.
+    ///
+    /// [`Command::encode()`]: enum.Command.html#method.encode
+    pub fn send(&mut self, command: &Command) -> Result<Vec<u8>> {
+        let bytes = command.encode()?;
+        let name = command.line().split(' ').next().unwrap_or_default().to_ascii_uppercase();
+        self.pending.push_back((name, command.is_multiline()));
+        Ok(bytes)
+    }
+
+    /// Hand over bytes read from the transport
+    pub fn receive(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    /// The next complete reply, if it has been received in full
+    ///
+    /// `-ERR` replies come as errors, like from [`Client`]. Bytes arriving while no reply is expected are left
+    /// alone until a command is sent.
+    ///
+    /// [`Client`]: ../struct.Client.html
+    pub fn poll(&mut self) -> Option<Result<Event>> {
+        let multiline = match (self.greeted, self.pending.front()) {
+            (false, _)                   => false,
+            (true, Some((_, multiline))) => *multiline,
+            (true, None)                 => return None,
+        };
+        let (len, payload) = self.frame_len(multiline)?;
+        let raw: Vec<u8> = self.buffer.drain(..len).collect();
+
+        // The greeting is always read leniently, as by the client
+        if self.greeted {
+            if let Err(e) = self.check(&raw) {
+                // The reply is consumed all the same, so the session stays in step
+                self.pending.pop_front();
+                return Some(Err(e));
+            }
+        }
+        let frame = Frame::new(Bytes::from(raw), payload);
+
+        if !self.greeted {
+            self.greeted = true;
+            return Some(frame.into_result().map(|frame| Event::Greeting(frame.into())));
+        }
+        let (command, _) = self.pending.pop_front()?;
+        let (stage, result) = stage::advance(self.stage, &command, frame.into_result());
+        self.stage = stage;
+        Some(result.map(|frame| Event::Reply {
+            command,
+            response: frame.into(),
+        }))
+    }
+
+    /// Length of the reply at the start of the buffer, once it's all there, and where its payload lies
+    fn frame_len(&self, multiline: bool) -> Option<(usize, Option<Range<usize>>)> {
+        let status_len = self.buffer.iter().position(|c| *c == b'\n')? + 1;
+        if !multiline || parser::status(&self.buffer[..status_len]).0 == Status::Err {
+            return Some((status_len, None));
+        }
+        let mut len = status_len;
+        for line in self.buffer[status_len..].split_inclusive(|c| *c == b'\n') {
+            if !line.ends_with(b"\n") {
+                return None;
+            }
+            if trim_line_end(line) == b"." {
+                return Some((len + line.len(), Some(status_len..len)));
+            }
+            len += line.len();
+        }
+        None
+    }
+
+    fn check(&self, raw: &[u8]) -> Result<()> {
+        self.compliance.check_status(&raw[..parser::status_line_len(raw)])?;
+        raw.split_inclusive(|c| *c == b'\n')
+            .try_for_each(|line| self.compliance.check_line(line))
+    }
+}
@@ -0,0 +1,93 @@
+use std::net::{IpAddr, SocketAddr};
+
+/// The 12 octets opening every version 2 header
+const V2_SIGNATURE: &[u8; 12] = b"\r\n\r\n\0\r\nQUIT\n";
+
+/// A HAProxy PROXY protocol header, sent before the POP3 exchange, see [`Builder::send_proxy_protocol()`]
+///
+/// Load balancers expecting it learn the address of the original client from it instead of the connection.
+/// When the two addresses are of different families, the IPv4 one is sent mapped to IPv6.
+///
+/// # Example
+/// ```
+/// use pop3_client::ProxyHeader;
+///
+/// let header = ProxyHeader::V1 {
+///     source: "192.0.2.1:56324".parse().unwrap(),
+///     destination: "198.51.100.1:110".parse().unwrap(),
+/// };
+/// assert_eq!(header.encode(), b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 110\r\n");
+/// ```
+///
+/// [`Builder::send_proxy_protocol()`]: struct.Builder.html#method.send_proxy_protocol
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProxyHeader {
+    /// Version 1, a line of text
+    V1 { source: SocketAddr, destination: SocketAddr },
+    /// Version 1 `PROXY UNKNOWN`: the receiver goes by the addresses of the connection
+    V1Unknown,
+    /// Version 2, binary
+    V2 { source: SocketAddr, destination: SocketAddr },
+    /// Version 2 `LOCAL` command, for connections made on the proxy's own behalf, e.g. health checks
+    V2Local,
+}
+
+impl ProxyHeader {
+    /// The header as sent on the wire
+    pub fn encode(&self) -> Vec<u8> {
+        match *self {
+            ProxyHeader::V1 { source, destination } => {
+                let (source, destination) = same_family(source, destination);
+                let family = if source.is_ipv4() { "TCP4" } else { "TCP6" };
+                format!(
+                    "PROXY {} {} {} {} {}\r\n",
+                    family,
+                    source.ip(),
+                    destination.ip(),
+                    source.port(),
+                    destination.port()
+                )
+                .into_bytes()
+            }
+            ProxyHeader::V1Unknown => b"PROXY UNKNOWN\r\n".to_vec(),
+            ProxyHeader::V2 { source, destination } => {
+                let (source, destination) = same_family(source, destination);
+                let mut addresses = vec![];
+                for address in [source, destination] {
+                    match address.ip() {
+                        IpAddr::V4(ip) => addresses.extend_from_slice(&ip.octets()),
+                        IpAddr::V6(ip) => addresses.extend_from_slice(&ip.octets()),
+                    }
+                }
+                addresses.extend_from_slice(&source.port().to_be_bytes());
+                addresses.extend_from_slice(&destination.port().to_be_bytes());
+                // TCP over IPv4 or IPv6
+                let family = if source.is_ipv4() { 0x11 } else { 0x21 };
+                v2(0x21, family, &addresses)
+            }
+            ProxyHeader::V2Local => v2(0x20, 0x00, &[]),
+        }
+    }
+}
+
+/// Version and command, address family and protocol, then the addresses after their length
+fn v2(version_command: u8, family: u8, addresses: &[u8]) -> Vec<u8> {
+    let mut header = V2_SIGNATURE.to_vec();
+    header.push(version_command);
+    header.push(family);
+    header.extend_from_slice(&(addresses.len() as u16).to_be_bytes());
+    header.extend_from_slice(addresses);
+    header
+}
+
+/// Both addresses as IPv6 when only one of them is
+fn same_family(source: SocketAddr, destination: SocketAddr) -> (SocketAddr, SocketAddr) {
+    if source.is_ipv4() == destination.is_ipv4() {
+        return (source, destination);
+    }
+    let v6 = |address: SocketAddr| match address.ip() {
+        IpAddr::V4(ip) => SocketAddr::new(IpAddr::V6(ip.to_ipv6_mapped()), address.port()),
+        IpAddr::V6(_) => address,
+    };
+    (v6(source), v6(destination))
+}
@@ -0,0 +1,142 @@
+//! Known deviations of widespread servers, and how the client works around them
+
+use md5::{Digest, Md5};
+
+use crate::{parser, Capabilities, Client, MessageInfo, Result, UidlEntry};
+
+/// The server implementation, as far as the greeting or `CAPA` `IMPLEMENTATION` tell
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ServerType {
+    Dovecot,
+    Qpopper,
+    Exchange,
+    Courier,
+    Zimbra,
+    /// Nothing recognizable was announced
+    #[default]
+    Unknown,
+}
+
+/// Work-arounds the client applies for a [`ServerType`]
+///
+/// [`ServerType`]: enum.ServerType.html
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Quirks {
+    /// Lines may end with a bare LF, which is accepted even in [`Compliance::Strict`] mode
+    ///
+    /// [`Compliance::Strict`]: enum.Compliance.html#variant.Strict
+    pub bare_line_endings: bool,
+    /// Sizes in scan listings may be followed by junk, such as `1 1204bytes`; only the leading digits count
+    pub listing_junk: bool,
+    /// `UIDL` is rejected, so [`Client::uidl_entries()`] derives unique IDs from the message headers instead
+    ///
+    /// [`Client::uidl_entries()`]: struct.Client.html#method.uidl_entries
+    pub no_uidl: bool,
+}
+
+impl ServerType {
+    /// Recognize the implementation from the greeting or the `IMPLEMENTATION` capability
+    pub(crate) fn fingerprint(text: &str) -> Self {
+        let text = text.to_ascii_lowercase();
+        let known = [
+            ("dovecot", ServerType::Dovecot),
+            ("qpopper", ServerType::Qpopper),
+            ("qpop", ServerType::Qpopper),
+            ("exchange", ServerType::Exchange),
+            ("courier", ServerType::Courier),
+            ("zimbra", ServerType::Zimbra),
+        ];
+        known
+            .iter()
+            .find(|(name, _)| text.contains(name))
+            .map_or(ServerType::Unknown, |(_, server)| *server)
+    }
+
+    /// What the client works around for this server
+    pub fn quirks(self) -> Quirks {
+        match self {
+            // Older releases serve mbox files with their LF line endings, and are often built without UIDL
+            ServerType::Qpopper  => Quirks {
+                bare_line_endings: true,
+                no_uidl:           true,
+                ..Quirks::default()
+            },
+            // Some versions append the unit to the sizes of LIST
+            ServerType::Exchange => Quirks {
+                listing_junk: true,
+                ..Quirks::default()
+            },
+            _                    => Quirks::default(),
+        }
+    }
+}
+
+/// A scan listing whose size may be followed by junk
+pub(crate) fn scan_listing_with_junk(line: &[u8]) -> Result<MessageInfo> {
+    let mut fields = line
+        .split(|c| c.is_ascii_whitespace())
+        .filter(|field| !field.is_empty());
+    let id = fields.next().unwrap_or_default();
+    let size = fields.next().unwrap_or_default();
+    let digits = size.iter().take_while(|c| c.is_ascii_digit()).count();
+    parser::scan_listing(&[id, b" ", &size[..digits]].concat())
+}
+
+impl Client {
+    /// The server implementation, recognized from the greeting, or from the last `CAPA` reply if the greeting
+    /// didn't tell
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::{Client, ServerType};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// if client.server_type() == ServerType::Unknown {
+    ///     client.capa()?;
+    /// }
+    /// println!("{:?}", client.server_type());
+    /// #    Ok(())
+    /// # }
+    /// ```
+    pub fn server_type(&self) -> ServerType {
+        self.server_type
+    }
+
+    pub(crate) fn quirks(&self) -> Quirks {
+        self.server_type.quirks()
+    }
+
+    pub(crate) fn fingerprint_capabilities(&mut self, capabilities: &Capabilities) {
+        if self.server_type != ServerType::Unknown {
+            return;
+        }
+        if let Some(implementation) = capabilities.arguments("IMPLEMENTATION") {
+            self.server_type = ServerType::fingerprint(&implementation.join(" "));
+        }
+    }
+
+    /// Unique IDs for servers without `UIDL`: the MD5 of the message headers, which include the `Message-ID`
+    pub(crate) fn uidl_from_headers(&mut self, msg: Option<u32>) -> Result<Vec<UidlEntry>> {
+        let mut entries = vec![];
+        for MessageInfo { id, .. } in self.list_entries(msg)? {
+            let headers = self.top(id, 0)?.headers;
+            entries.push(UidlEntry { id, uid: md5_hex(&headers) });
+        }
+        Ok(entries)
+    }
+
+    /// Unique IDs for servers without `UIDL` nor `TOP`: the MD5 of the whole messages
+    pub(crate) fn uidl_from_content(&mut self) -> Result<Vec<UidlEntry>> {
+        let mut entries = vec![];
+        for MessageInfo { id, .. } in self.list_entries(None)? {
+            let message = self.retr_bytes(id)?;
+            entries.push(UidlEntry { id, uid: md5_hex(&message) });
+        }
+        Ok(entries)
+    }
+}
+
+fn md5_hex(data: &[u8]) -> String {
+    Md5::digest(data).iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,241 @@
+//! Session transcripts, for offline tests and bug reports
+//!
+//! A transcript has a line per protocol line: the milliseconds since the recording started, the direction, and
+//! the line itself. `C` lines were sent by the client, `S` lines by the server; both without their CRLF. A
+//! server line that didn't end in CRLF is marked `s` and kept whole. Bytes outside printable ASCII, and the
+//! backslash, are written as `\xHH`.
+//!
+//! ```text
+//! 0 S +OK POP3 server ready
+//! 12 C USER sweet_username
+//! 25 S +OK
+//! 25 C PASS ***
+//! ```
+//!
+//! Passwords, `APOP` digests and SASL responses are replaced by `***` before they are written.
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::Instant;
+
+use crate::stream::Stream;
+use crate::{response, secret, Client, Error, Result};
+
+/// Writes everything going through the stream to a transcript
+///
+/// Recording is best effort: failing to write the transcript doesn't fail the session.
+pub(crate) struct Recorder {
+    inner: Stream,
+    transcript: BufWriter<File>,
+    start: Instant,
+    incoming: Vec<u8>,
+    outgoing: Vec<u8>,
+    /// The server asked for a SASL response, which is as secret as a password
+    continuation: bool,
+}
+
+impl Recorder {
+    pub fn new(inner: Stream, transcript: File, greeting: &str) -> Self {
+        let mut recorder = Self {
+            inner,
+            transcript: BufWriter::new(transcript),
+            start: Instant::now(),
+            incoming: vec![],
+            outgoing: vec![],
+            continuation: false,
+        };
+        // The greeting has been read before recording could start
+        let greeting = if greeting.is_empty() { "+OK".to_string() } else { format!("+OK {}", greeting) };
+        recorder.log('S', greeting.as_bytes());
+        recorder
+    }
+
+    pub fn get_mut(&mut self) -> &mut Stream {
+        &mut self.inner
+    }
+
+    fn log(&mut self, direction: char, line: &[u8]) {
+        let elapsed = self.start.elapsed().as_millis();
+        writeln!(self.transcript, "{} {} {}", elapsed, direction, escape(line))
+            .and_then(|_| self.transcript.flush())
+            .ok();
+    }
+}
+
+impl Read for Recorder {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.incoming.extend_from_slice(&buf[..len]);
+        while let Some(end) = self.incoming.iter().position(|c| *c == b'\n') {
+            let line: Vec<u8> = self.incoming.drain(..=end).collect();
+            match line.strip_suffix(b"\r\n") {
+                Some(line) => {
+                    self.continuation = response::is_continuation(line);
+                    self.log('S', line)
+                }
+                None => self.log('s', &line),
+            }
+        }
+        if len == 0 && !self.incoming.is_empty() {
+            let rest = std::mem::take(&mut self.incoming);
+            self.log('s', &rest);
+        }
+        Ok(len)
+    }
+}
+
+impl Write for Recorder {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let len = self.inner.write(buf)?;
+        self.outgoing.extend_from_slice(&buf[..len]);
+        while let Some(end) = self.outgoing.windows(2).position(|w| w == b"\r\n") {
+            let mut line: Vec<u8> = self.outgoing.drain(..end + 2).take(end).collect();
+            let redacted = secret::redact(&line, self.continuation);
+            self.continuation = false;
+            self.log('C', &redacted);
+            crate::secret::wipe(&mut line);
+        }
+        Ok(len)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Serves the server side of a transcript, checking that the client sends the same commands
+struct Replay {
+    events: VecDeque<(char, Vec<u8>)>,
+    output: VecDeque<u8>,
+    input: Vec<u8>,
+    continuation: bool,
+}
+
+impl Read for Replay {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.output.is_empty() {
+            while let Some((direction, _)) = self.events.front() {
+                if *direction == 'C' {
+                    break;
+                }
+                let (direction, line) = self.events.pop_front().unwrap_or_default();
+                self.continuation = response::is_continuation(&line);
+                self.output.extend(line);
+                if direction == 'S' {
+                    self.output.extend(b"\r\n");
+                }
+            }
+        }
+        let len = buf.len().min(self.output.len());
+        for (to, from) in buf.iter_mut().zip(self.output.drain(..len)) {
+            *to = from;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for Replay {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.input.extend_from_slice(buf);
+        while let Some(end) = self.input.windows(2).position(|w| w == b"\r\n") {
+            let line: Vec<u8> = self.input.drain(..end + 2).take(end).collect();
+            let sent = secret::redact(&line, self.continuation);
+            self.continuation = false;
+            match self.events.pop_front() {
+                Some(('C', expected)) if expected == sent => (),
+                expected => {
+                    return Err(io::Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "transcript expected {:?}, got {:?}",
+                            expected.map(|(_, line)| escape(&line)),
+                            escape(&sent)
+                        ),
+                    ))
+                }
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Client {
+    /// Start a session that replays a transcript written with [`Builder::record_to()`]
+    ///
+    /// The client has to send the same commands as in the transcript, apart from the redacted parts; anything
+    /// else fails with an [`Error::Io`] of kind `InvalidData`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Client::replay_from("session.log")?;
+    /// client.login("sweet_username", "any password")?;
+    /// let (messages, octets) = client.stat()?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Builder::record_to()`]: struct.Builder.html#method.record_to
+    /// [`Error::Io`]: enum.Error.html#variant.Io
+    pub fn replay_from<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path).map_err(Error::Io)?;
+        let mut events = VecDeque::new();
+        for line in BufReader::new(file).lines() {
+            let line = line.map_err(Error::Io)?;
+            let mut fields = line.splitn(3, ' ');
+            let direction = match (fields.next(), fields.next()) {
+                (Some(_), Some("C")) => 'C',
+                (Some(_), Some("S")) => 'S',
+                (Some(_), Some("s")) => 's',
+                _ => return Err(Error::InvalidArgument(format!("not a transcript line: {:?}", line))),
+            };
+            let data = unescape(fields.next().unwrap_or(""))
+                .ok_or_else(|| Error::InvalidArgument(format!("bad escape in transcript line: {:?}", line)))?;
+            events.push_back((direction, data));
+        }
+
+        let mut client = Client::from_stream(Replay {
+            events,
+            output: VecDeque::new(),
+            input: vec![],
+            continuation: false,
+        })?;
+        // Nothing goes over the network when replaying, whatever protected the recorded session
+        client.plaintext_auth = true;
+        Ok(client)
+    }
+}
+
+fn escape(line: &[u8]) -> String {
+    line.iter()
+        .map(|c| match c {
+            b' '..=b'~' if *c != b'\\' => (*c as char).to_string(),
+            _ => format!("\\x{:02x}", c),
+        })
+        .collect()
+}
+
+fn unescape(text: &str) -> Option<Vec<u8>> {
+    let bytes = text.as_bytes();
+    let mut data = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' {
+            let hex = std::str::from_utf8(bytes.get(i + 2..i + 4)?).ok()?;
+            data.push(u8::from_str_radix(hex, 16).ok()?);
+            i += 4;
+        } else {
+            data.push(bytes[i]);
+            i += 1;
+        }
+    }
+    Some(data)
+}
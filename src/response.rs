@@ -0,0 +1,344 @@
+use std::io::{self, BufRead};
+use std::ops::Range;
+
+use bytes::{Bytes, BytesMut};
+
+use crate::parser::{self, trim_line_end};
+use crate::progress::Tracker;
+use crate::{Compliance, Error, Quirks, Result};
+
+/// Status indicator of a reply
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// `+OK`
+    Ok,
+    /// `-ERR`
+    Err,
+}
+
+/// A reply exactly as the server sent it, along with its parsed parts
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: Status,
+    /// The text following the status indicator on the first line, without the line ending
+    pub info: String,
+    /// Lines of a multiline reply, dot-unstuffed and without line endings or the terminating `.`
+    pub lines: Vec<String>,
+    /// Every byte received for this reply, including the status line and the terminating `.`
+    pub raw: Bytes,
+    /// Where the payload lies in `raw`, as found while reading it
+    payload: Range<usize>,
+}
+
+impl Response {
+    pub fn is_ok(&self) -> bool {
+        self.status == Status::Ok
+    }
+
+    /// The bytes after the status line, as sent: without dot-unstuffing, but without the terminating `.` line
+    pub fn payload(&self) -> &[u8] {
+        &self.raw[self.payload.clone()]
+    }
+}
+
+impl From<Frame> for Response {
+    fn from(frame: Frame) -> Self {
+        let lines = parser::unstuff(&frame.raw[frame.payload.clone()])
+            .into_iter()
+            .map(|line| String::from_utf8_lossy(line).to_string())
+            .collect();
+
+        Self {
+            status: frame.status,
+            info: frame.info(),
+            lines,
+            raw: frame.raw,
+            payload: frame.payload,
+        }
+    }
+}
+
+/// A reply as read off the wire, not parsed beyond its status indicator
+pub(crate) struct Frame {
+    pub status: Status,
+    pub raw: Bytes,
+    /// Between the status line and the terminating `.` line, as they were read; empty for single line replies
+    payload: Range<usize>,
+}
+
+impl Frame {
+    /// A reply with `payload` after the status line; without one, the reply is the status line alone
+    pub fn new(raw: Bytes, payload: Option<Range<usize>>) -> Self {
+        let (status, _) = parser::status(&raw);
+        let payload = payload.unwrap_or(raw.len()..raw.len());
+        Self { status, raw, payload }
+    }
+
+    /// Read a reply into `buffer` and split it off; the payload is only read for successful multiline replies
+    ///
+    /// The buffer keeps its allocation between replies, so reading many small replies doesn't allocate for each of them.
+    pub fn read<R: BufRead>(
+        reader: &mut R,
+        buffer: &mut BytesMut,
+        multiline: bool,
+        compliance: Compliance,
+        quirks: Quirks,
+        limits: &Limits,
+        mut progress: Option<&mut Tracker>,
+    ) -> Result<Self> {
+        buffer.clear();
+
+        limits.status_line(compliance).read_line(reader, buffer)?;
+        let payload_start = buffer.len();
+        check_line(compliance, quirks, buffer)?;
+        compliance.check_status(buffer)?;
+
+        let (status, _) = parser::status(buffer);
+        let mut payload = None;
+
+        if multiline && status == Status::Ok {
+            loop {
+                let start = limits.read_line(reader, buffer)?;
+                let line = &buffer[start..];
+                check_line(compliance, quirks, line)?;
+                if trim_line_end(line) == b"." {
+                    if let Some(tracker) = progress.as_mut() {
+                        tracker.finish(start - payload_start);
+                    }
+                    payload = Some(payload_start..start);
+                    break;
+                }
+                if let Some(tracker) = progress.as_mut() {
+                    tracker.update(buffer.len() - payload_start);
+                }
+            }
+        }
+
+        Ok(Self::new(buffer.split().freeze(), payload))
+    }
+
+    /// The status line, with its line ending
+    pub fn status_line(&self) -> &[u8] {
+        &self.raw[..self.payload.start]
+    }
+
+    /// The lines between the status line and the terminating `.`, as sent: dot-stuffed
+    pub fn payload(&self) -> Bytes {
+        self.raw.slice(self.payload.clone())
+    }
+
+    /// The text following the status indicator
+    pub fn info(&self) -> String {
+        let (_, text) = parser::status(self.status_line());
+        String::from_utf8_lossy(text).to_string()
+    }
+
+    /// Turn `-ERR` replies into [`Error::Server`]
+    ///
+    /// [`Error::Server`]: enum.Error.html#variant.Server
+    pub fn into_result(self) -> Result<Self> {
+        match self.status {
+            Status::Ok  => Ok(self),
+            Status::Err => Err(Error::Server(self.info())),
+        }
+    }
+
+    /// The status text with its line ending followed by the payload; the shape the typed commands parse
+    pub fn into_bytes(self) -> Bytes {
+        let (_, len) = parser::indicator(&self.raw);
+        let start = if self.raw.get(len) == Some(&b' ') { len + 1 } else { len };
+        self.raw.slice(start..self.payload.end)
+    }
+}
+
+/// A line of an `AUTH` exchange: a challenge from the server, or the reply ending the exchange
+pub(crate) enum SaslReply {
+    /// The base64 text after `+ `
+    Challenge(Bytes),
+    Done(Frame),
+}
+
+impl SaslReply {
+    pub fn read<R: BufRead>(
+        reader: &mut R,
+        buffer: &mut BytesMut,
+        compliance: Compliance,
+        quirks: Quirks,
+        limits: &Limits,
+    ) -> Result<Self> {
+        buffer.clear();
+        limits.read_line(reader, buffer)?;
+        check_line(compliance, quirks, buffer)?;
+        if is_continuation(buffer) {
+            let text = trim_line_end(&buffer[1..]).trim_ascii();
+            return Ok(SaslReply::Challenge(Bytes::copy_from_slice(text)));
+        }
+        compliance.check_status(buffer)?;
+
+        Ok(SaslReply::Done(Frame::new(buffer.split().freeze(), None)))
+    }
+}
+
+/// Whether a line is a SASL challenge, `+ ` and the challenge or a bare `+`, rather than a status line
+pub(crate) fn is_continuation(line: &[u8]) -> bool {
+    line.starts_with(b"+ ") || trim_line_end(line) == b"+"
+}
+
+/// Reads the payload of a multiline reply line by line, so only one line has to be held at a time
+#[derive(Default)]
+pub(crate) struct PayloadLines {
+    received: usize,
+}
+
+impl PayloadLines {
+    /// Replace the content of `buffer` with the next line; `false` when it's the terminating `.` line
+    pub fn next<R: BufRead>(
+        &mut self,
+        reader: &mut R,
+        buffer: &mut BytesMut,
+        compliance: Compliance,
+        quirks: Quirks,
+        limits: &Limits,
+    ) -> Result<bool> {
+        buffer.clear();
+        // The size limit covers the whole reply, so each line may only use what the previous ones left
+        let remaining = Limits {
+            max_response_size: limits.max_response_size.map(|max| max.saturating_sub(self.received)),
+            max_line_length: limits.max_line_length,
+        };
+        remaining.read_line(reader, buffer).map_err(|e| match e {
+            Error::ResponseTooLarge(_) => Error::ResponseTooLarge(limits.max_response_size.unwrap_or(0)),
+            e => e,
+        })?;
+        self.received += buffer.len();
+        check_line(compliance, quirks, buffer)?;
+        Ok(trim_line_end(buffer) != b".")
+    }
+
+    /// Octets read so far, including the terminating line once it's been read
+    pub fn received(&self) -> usize {
+        self.received
+    }
+}
+
+/// A payload line without its dot-stuffing
+pub(crate) fn unstuffed(line: &[u8]) -> &[u8] {
+    if line.starts_with(b"..") { &line[1..] } else { line }
+}
+
+/// Hand each line of the payload of a multiline reply to `write`, dot-unstuffed, and return the number of octets
+/// it wrote
+///
+/// Only a single line is held in `buffer` at a time. When writing fails, the rest of the reply is still read
+/// so the session stays usable, and then the write error is returned.
+pub(crate) fn copy_payload<R: BufRead, F: FnMut(&[u8]) -> io::Result<usize>>(
+    reader: &mut R,
+    buffer: &mut BytesMut,
+    compliance: Compliance,
+    quirks: Quirks,
+    limits: &Limits,
+    mut progress: Option<&mut Tracker>,
+    mut write: F,
+) -> Result<u64> {
+    let mut lines = PayloadLines::default();
+    let mut written = 0;
+    let mut failure = None;
+
+    while lines.next(reader, buffer, compliance, quirks, limits)? {
+        if let Some(tracker) = progress.as_mut() {
+            tracker.update(lines.received());
+        }
+        if failure.is_none() {
+            match write(unstuffed(buffer)) {
+                Ok(octets) => written += octets as u64,
+                Err(e) => failure = Some(e),
+            }
+        }
+    }
+    if let Some(tracker) = progress.as_mut() {
+        tracker.finish(lines.received() - buffer.len());
+    }
+
+    match failure {
+        Some(e) => Err(Error::Io(e)),
+        None => Ok(written),
+    }
+}
+
+fn check_line(compliance: Compliance, quirks: Quirks, line: &[u8]) -> Result<()> {
+    if quirks.bare_line_endings && line.ends_with(b"\n") {
+        return Ok(());
+    }
+    compliance.check_line(line)
+}
+
+/// The longest line accepted unless `Builder::max_line_length()` says otherwise
+const DEFAULT_MAX_LINE_LENGTH: usize = 1024 * 1024;
+
+/// The longest status line [RFC 2449] allows, including the CRLF
+///
+/// [RFC 2449]: https://tools.ietf.org/html/rfc2449#section-4
+const MAX_STATUS_LINE_LENGTH: usize = 512;
+
+/// Upper bounds on what a single reply may make the client allocate
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Limits {
+    pub max_response_size: Option<usize>,
+    pub max_line_length: Option<usize>,
+}
+
+impl Limits {
+    /// The limits for the first line of a reply, which strict mode holds to 512 octets
+    fn status_line(&self, compliance: Compliance) -> Self {
+        let max_line = self.max_line_length.unwrap_or(DEFAULT_MAX_LINE_LENGTH);
+        Limits {
+            max_response_size: self.max_response_size,
+            max_line_length: Some(match compliance {
+                Compliance::Strict  => max_line.min(MAX_STATUS_LINE_LENGTH),
+                Compliance::Lenient => max_line,
+            }),
+        }
+    }
+
+    /// Append a line to `buffer` straight from the reader's buffer, and return where it starts
+    ///
+    /// At most the room left under both limits is taken from the reader, so a line that never ends can't make
+    /// `buffer` grow past them.
+    fn read_line<R: BufRead>(&self, reader: &mut R, buffer: &mut BytesMut) -> Result<usize> {
+        let max_line = self.max_line_length.unwrap_or(DEFAULT_MAX_LINE_LENGTH);
+        let max_response = self.max_response_size.unwrap_or(usize::MAX);
+        let start = buffer.len();
+
+        loop {
+            let available = reader.fill_buf().map_err(Error::Io)?;
+            if available.is_empty() {
+                return if buffer.len() == start {
+                    Err(Error::ConnectionAborted)
+                } else {
+                    Ok(start)
+                };
+            }
+
+            let line_len = buffer.len() - start;
+            let room = (max_line - line_len).min(max_response - buffer.len());
+            if room == 0 {
+                return Err(if line_len >= max_line {
+                    Error::LineTooLong(max_line)
+                } else {
+                    Error::ResponseTooLarge(max_response)
+                });
+            }
+
+            let (len, done) = match available.iter().position(|c| *c == b'\n') {
+                Some(i) if i < room => (i + 1, true),
+                _ => (available.len().min(room), false),
+            };
+            buffer.extend_from_slice(&available[..len]);
+            reader.consume(len);
+
+            if done {
+                return Ok(start);
+            }
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::time::Duration;
+use std::{mem, thread};
+
+use crate::{stage, telemetry, Builder, Client, Error, Origin, Result};
+
+/// Commands which can be sent again in a new session without changing anything
+const IDEMPOTENT_COMMANDS: &[&str] = &["NOOP", "STAT", "LIST", "UIDL", "TOP", "RETR"];
+
+/// How many times, and how patiently, to retry after a transient failure
+///
+/// The delay before the n-th retry is `initial_delay * 2^(n-1)`, capped at `max_delay`, and then
+/// randomly shortened by up to a half so that many clients failing at once don't retry in lockstep.
+///
+/// # Example
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use pop3_client::{Builder, RetryPolicy};
+/// # fn main() -> pop3_client::Result<()> {
+/// let client = Builder::default()
+///     .retries(RetryPolicy {
+///         max_attempts:  5,
+///         initial_delay: Duration::from_millis(200),
+///         max_delay:     Duration::from_secs(10),
+///     })
+///     .connect("my.host.com", 110)?;
+/// #    Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    /// Attempts in total, including the first one
+    pub max_attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            initial_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Retry up to `max_attempts` attempts in total, with the default delays
+    pub fn new(max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..Self::default()
+        }
+    }
+
+    /// Never retry
+    pub fn none() -> Self {
+        Self::new(1)
+    }
+
+    /// Run `f` until it succeeds, fails with an error `retry` rejects, or the attempts run out
+    pub(crate) fn run<T, P, F>(&self, retry: P, mut f: F) -> Result<T>
+    where
+        P: Fn(&Error) -> bool,
+        F: FnMut() -> Result<T>,
+    {
+        let mut attempt = 1;
+        loop {
+            match f() {
+                Err(e) if attempt < self.max_attempts && retry(&e) => {
+                    thread::sleep(self.delay(attempt));
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    fn delay(&self, attempt: u32) -> Duration {
+        let base = self
+            .initial_delay
+            .checked_mul(1 << (attempt - 1).min(16))
+            .map_or(self.max_delay, |delay| delay.min(self.max_delay));
+
+        base / 2 + base.mul_f64(jitter() / 2.0)
+    }
+}
+
+/// A random number in `[0, 1)` from the OS, so that clients failing in the same instant spread out their retries
+fn jitter() -> f64 {
+    // Too rare to fail the retry over: the middle of the range then
+    let x = getrandom::u64().unwrap_or(1 << 63);
+    (x >> 11) as f64 / (1u64 << 53) as f64
+}
+
+impl Builder {
+    /// Send `NOOP`, `STAT`, `LIST`, `UIDL`, `TOP` and `RETR` once more in a new session when they find the
+    /// connection dead
+    ///
+    /// Servers and firewalls drop idle connections without notice, which long-lived clients only learn from the
+    /// next command. The new session is opened as by [`open()`], so only clients opened that way reconnect, and
+    /// only when no message is marked as deleted, as the marks would be lost. Only the connection changes: the
+    /// settings of the client, its [`cancellation_token()`], UTF-8 mode and language carry over. Message numbers
+    /// are those of the new session, which only differ if another session deleted messages meanwhile. Off by
+    /// default.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let mut client = Builder::default()
+    ///     .auto_retry_idempotent(true)
+    ///     .credentials("sweet_username", "very_secret_password")
+    ///     .open("my.host.com", 995)?;
+    /// // An hour later, the server has long closed the connection
+    /// let (messages, _) = client.stat()?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`open()`]: struct.Builder.html#method.open
+    /// [`cancellation_token()`]: struct.Client.html#method.cancellation_token
+    pub fn auto_retry_idempotent(&mut self, enabled: bool) -> &mut Self {
+        self.auto_retry_idempotent = enabled;
+        self
+    }
+}
+
+impl Client {
+    /// Open a new session in place of this one, found dead by `command`, when the command can be sent again
+    pub(crate) fn reopen_for(&mut self, command: &str, error: &Error) -> bool {
+        let dead = matches!(error, Error::Io(_) | Error::ConnectionAborted) && error.is_transient();
+        if !self.auto_retry_idempotent
+            || !dead
+            || !self.is_authorized()
+            || self.undo_deletes.pending()
+            || !IDEMPOTENT_COMMANDS.contains(&stage::keyword(command).as_str())
+        {
+            return false;
+        }
+        let Origin { mut builder, host, port, address } = match &self.origin {
+            Some(origin) if origin.builder.has_credentials() => Origin::clone(origin),
+            _ => return false,
+        };
+        telemetry::reconnected(&host, &self.labels.account);
+        builder.utf8 = self.utf8;
+        match builder.open_to(&host, port, address) {
+            Ok(mut client) => {
+                self.take_session(&mut client);
+                if let Some(tag) = self.language.take() {
+                    // Left unset if the new session refuses it
+                    self.lang(Some(&tag)).ok();
+                }
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Go on with the connection and session state of `new`, keeping the settings and bookkeeping of this client
+    ///
+    /// The cancellation token handed out stays valid, and `new` is left with the dead connection.
+    fn take_session(&mut self, new: &mut Client) {
+        mem::swap(&mut self.conn, &mut new.conn);
+        self.conn.watched().take_over(new.conn.watched());
+        self.labels.take_over(&new.labels);
+        mem::swap(&mut self.stage, &mut new.stage);
+        mem::swap(&mut self.greeting, &mut new.greeting);
+        mem::swap(&mut self.server_type, &mut new.server_type);
+        mem::swap(&mut self.tls, &mut new.tls);
+        mem::swap(&mut self.capabilities, &mut new.capabilities);
+        mem::swap(&mut self.cache, &mut new.cache);
+        mem::swap(&mut self.uid_map, &mut new.uid_map);
+        mem::swap(&mut self.in_flight, &mut new.in_flight);
+        mem::swap(&mut self.peer_addr, &mut new.peer_addr);
+        mem::swap(&mut self.local_addr, &mut new.local_addr);
+        mem::swap(&mut self.connected_at, &mut new.connected_at);
+    }
+}
@@ -0,0 +1,197 @@
+//! `AUTH SCRAM-SHA-1` and `AUTH SCRAM-SHA-256` of [RFC 5802] and [RFC 7677], with their `-PLUS` variants
+//!
+//! Over TLS, the `-PLUS` variant binds the exchange to the certificate of the server (`tls-server-end-point`
+//! of [RFC 5929]), so a man in the middle holding another certificate can't relay it.
+//!
+//! [RFC 5802]: https://tools.ietf.org/html/rfc5802
+//! [RFC 7677]: https://tools.ietf.org/html/rfc7677
+//! [RFC 5929]: https://tools.ietf.org/html/rfc5929#section-4
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use hmac::digest::core_api::BlockSizeUser;
+use hmac::digest::Digest;
+use hmac::{Mac, SimpleHmac};
+use sha1::Sha1;
+use sha2::Sha256;
+
+use crate::auth::random_bytes;
+use crate::secret::Sensitive;
+use crate::{Authenticator, Client, Error, Result};
+
+/// The most PBKDF2 iterations a server may ask for, far above what servers use, so it can't keep the CPU busy
+const MAX_ITERATIONS: u32 = 1_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Hash {
+    Sha1,
+    Sha256,
+}
+
+/// `AUTH SCRAM-SHA-*`: proves the password with a salted hash, and checks the server knows it too
+///
+/// A server whose proof doesn't hold fails the login with [`Error::Sasl`].
+///
+/// [`Error::Sasl`]: enum.Error.html#variant.Sasl
+#[derive(Clone)]
+pub struct Scram {
+    username: String,
+    password: String,
+    hash: Hash,
+    channel_binding: bool,
+    cnonce: Option<String>,
+}
+
+impl Scram {
+    /// `SCRAM-SHA-1`, for servers without the SHA-256 variant
+    pub fn sha1(username: &str, password: &str) -> Self {
+        Self::new(username, password, Hash::Sha1)
+    }
+
+    /// `SCRAM-SHA-256`
+    pub fn sha256(username: &str, password: &str) -> Self {
+        Self::new(username, password, Hash::Sha256)
+    }
+
+    fn new(username: &str, password: &str, hash: Hash) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+            hash,
+            channel_binding: true,
+            cnonce: None,
+        }
+    }
+
+    /// Use the `-PLUS` variant over TLS, the default; turn it off for servers that don't offer it
+    pub fn channel_binding(mut self, enabled: bool) -> Self {
+        self.channel_binding = enabled;
+        self
+    }
+
+    /// Use a fixed client nonce instead of a random one, so responses can be compared with known ones
+    #[cfg(feature = "test-util")]
+    pub fn cnonce(mut self, cnonce: &str) -> Self {
+        self.cnonce = Some(cnonce.to_string());
+        self
+    }
+
+    fn mechanism(&self, plus: bool) -> &'static str {
+        match (self.hash, plus) {
+            (Hash::Sha1, false)   => "SCRAM-SHA-1",
+            (Hash::Sha1, true)    => "SCRAM-SHA-1-PLUS",
+            (Hash::Sha256, false) => "SCRAM-SHA-256",
+            (Hash::Sha256, true)  => "SCRAM-SHA-256-PLUS",
+        }
+    }
+
+    /// The client-final-message and the server signature to expect, from the server-first-message
+    fn client_final(&self, client_first_bare: &str, server_first: &str, gs2: &[u8]) -> Result<(Sensitive, Vec<u8>)> {
+        match self.hash {
+            Hash::Sha1   => final_message::<Sha1>(&self.password, client_first_bare, server_first, gs2),
+            Hash::Sha256 => final_message::<Sha256>(&self.password, client_first_bare, server_first, gs2),
+        }
+    }
+}
+
+impl Authenticator for Scram {
+    fn authenticate(&self, client: &mut Client) -> Result<()> {
+        client.labels.account = self.username.clone();
+        let binding = if self.channel_binding { end_point(client) } else { None };
+
+        // The GS2 header, and with it the channel binding data the server checks
+        let mut gs2 = match binding {
+            Some(_) => b"p=tls-server-end-point,,".to_vec(),
+            None => b"n,,".to_vec(),
+        };
+        let cnonce = match &self.cnonce {
+            Some(cnonce) => cnonce.clone(),
+            None => BASE64.encode(random_bytes::<18>()?),
+        };
+        let client_first_bare = format!("n={},r={}", saslname(&self.username), cnonce);
+        let client_first = [&gs2[..], client_first_bare.as_bytes()].concat();
+        gs2.extend(binding.iter().flatten());
+
+        let mut expected = None;
+        client.auth(self.mechanism(!gs2.starts_with(b"n")), Some(&client_first), |challenge| {
+            let challenge = String::from_utf8_lossy(challenge);
+            match expected.take() {
+                None => {
+                    let (message, signature) = self.client_final(&client_first_bare, &challenge, &gs2)?;
+                    expected = Some(signature);
+                    Ok(message.as_bytes().to_vec())
+                }
+                Some(signature) => {
+                    if let Some(e) = attribute(&challenge, 'e') {
+                        return Err(Error::Sasl(format!("SCRAM: {}", e)));
+                    }
+                    let verified = attribute(&challenge, 'v').and_then(|v| BASE64.decode(v).ok());
+                    if verified != Some(signature) {
+                        return Err(Error::Sasl("SCRAM: the server doesn't know the password".to_string()));
+                    }
+                    Ok(Vec::new())
+                }
+            }
+        })
+    }
+}
+
+#[cfg(feature = "with-rustls")]
+fn end_point(client: &mut Client) -> Option<Vec<u8>> {
+    client.tls_server_end_point()
+}
+
+#[cfg(not(feature = "with-rustls"))]
+fn end_point(_: &mut Client) -> Option<Vec<u8>> {
+    None
+}
+
+fn final_message<H>(password: &str, client_first_bare: &str, server_first: &str, gs2: &[u8]) -> Result<(Sensitive, Vec<u8>)>
+where
+    H: Digest + BlockSizeUser + Clone + Sync,
+{
+    let invalid = || Error::InvalidReply(format!("malformed SCRAM challenge: {:?}", server_first));
+    let nonce = attribute(server_first, 'r').ok_or_else(invalid)?;
+    let salt = attribute(server_first, 's').and_then(|s| BASE64.decode(s).ok()).ok_or_else(invalid)?;
+    let iterations = attribute(server_first, 'i').and_then(|i| i.parse::<u32>().ok()).ok_or_else(invalid)?;
+    let cnonce = client_first_bare.rsplit("r=").next().unwrap_or_default();
+    if !nonce.starts_with(cnonce) || iterations == 0 {
+        return Err(invalid());
+    }
+    if iterations > MAX_ITERATIONS {
+        return Err(Error::Sasl(format!("SCRAM: {} iterations asked, more than the {} allowed", iterations, MAX_ITERATIONS)));
+    }
+
+    let mut salted = vec![0; <H as Digest>::output_size()];
+    pbkdf2::pbkdf2::<SimpleHmac<H>>(password.as_bytes(), &salt, iterations, &mut salted)
+        .map_err(|e| Error::Sasl(format!("SCRAM: {}", e)))?;
+    let client_key = hmac::<H>(&salted, b"Client Key");
+    let server_key = hmac::<H>(&salted, b"Server Key");
+    let stored_key = H::digest(&client_key);
+
+    let without_proof = format!("c={},r={}", BASE64.encode(gs2), nonce);
+    let auth_message = format!("{},{},{}", client_first_bare, server_first, without_proof);
+    let signature = hmac::<H>(&stored_key, auth_message.as_bytes());
+    let proof: Vec<u8> = client_key.iter().zip(&signature).map(|(k, s)| k ^ s).collect();
+
+    let message = Sensitive::new(format!("{},p={}", without_proof, BASE64.encode(proof)));
+    Ok((message, hmac::<H>(&server_key, auth_message.as_bytes())))
+}
+
+fn hmac<H: Digest + BlockSizeUser + Clone>(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <SimpleHmac<H>>::new_from_slice(key).expect("HMAC takes keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// The value of the `name=` attribute of a SCRAM message
+fn attribute(message: &str, name: char) -> Option<&str> {
+    message
+        .split(',')
+        .find_map(|field| field.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// The username with `=` and `,` escaped, as they separate the attributes
+fn saslname(username: &str) -> String {
+    username.replace('=', "=3D").replace(',', "=2C")
+}
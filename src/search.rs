@@ -0,0 +1,352 @@
+//! Search by headers, which POP3 has no command for
+//!
+//! [`Client::search_headers()`] fetches the header section of every message with `TOP n 0` and keeps
+//! the numbers of the messages matching a filter: either a [`Query`] or any closure over the [`Headers`].
+//! Only the headers travel, so it's much lighter than downloading the mailbox, but still a round trip per
+//! message.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use pop3_client::Client;
+//! use pop3_client::search::Query;
+//! use std::time::{Duration, SystemTime};
+//! # fn main() -> pop3_client::Result<()> {
+//! # let mut client = Client::connect("my.host.com", 110)?;
+//! # client.login("sweet_username", "very_secret_password")?;
+//!
+//! let week_ago = SystemTime::now() - Duration::from_secs(7 * 24 * 3600);
+//! let invoices = client.search_headers(Query::default().subject("invoice").since(week_ago))?;
+//!
+//! let from_boss = client.search_headers(|headers: &pop3_client::search::Headers| {
+//!     headers.get("From").is_some_and(|from| from.contains("boss@"))
+//! })?;
+//! #    Ok(())
+//! # }
+//! ```
+//!
+//! [`Client::select()`] works the same, with the size of the message at hand as well.
+//!
+//! [`Client::search_headers()`]: ../struct.Client.html#method.search_headers
+//! [`Client::select()`]: ../struct.Client.html#method.select
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{encoded_word, Client, MessageInfo, Result};
+
+/// The header fields of a message, unfolded
+///
+/// [`get()`] takes values as they are; [`decoded()`], [`subject()`] and [`from()`] decode their encoded words
+/// such as `=?UTF-8?B?...?=`. Charsets other than UTF-8, US-ASCII and ISO-8859-1 need the `with-encoding`
+/// feature.
+///
+/// [`get()`]: #method.get
+/// [`decoded()`]: #method.decoded
+/// [`subject()`]: #method.subject
+/// [`from()`]: #method.from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// Parse a header section, stopping at the first empty line
+    pub fn parse(section: &[u8]) -> Self {
+        let mut fields: Vec<(String, String)> = vec![];
+        for line in section.split(|c| *c == b'\n') {
+            let line = String::from_utf8_lossy(line);
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with([' ', '\t']) {
+                if let Some((_, value)) = fields.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+            } else if let Some((name, value)) = line.split_once(':') {
+                fields.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+        Self { fields }
+    }
+
+    /// The value of the first field called `name`; case insensitive
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The values of all the fields called `name`, e.g. `"Received"`; case insensitive
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of the first field called `name`, with its encoded words decoded ([RFC 2047])
+    ///
+    /// [RFC 2047]: https://tools.ietf.org/html/rfc2047
+    pub fn decoded(&self, name: &str) -> Option<String> {
+        self.get(name).map(encoded_word::decode)
+    }
+
+    /// The `Subject` field, decoded
+    pub fn subject(&self) -> Option<String> {
+        self.decoded("Subject")
+    }
+
+    /// The `From` field, decoded
+    pub fn from(&self) -> Option<String> {
+        self.decoded("From")
+    }
+
+    /// The `Date` field, if there is one in the format of [RFC 5322]
+    ///
+    /// [RFC 5322]: https://tools.ietf.org/html/rfc5322#section-3.3
+    pub fn date(&self) -> Option<SystemTime> {
+        self.get("Date").and_then(parse_date)
+    }
+}
+
+/// Something to search with: a [`Query`], or a closure taking `&Headers` and returning whether it matches
+///
+/// [`Query`]: struct.Query.html
+pub trait Filter {
+    fn matches(&mut self, headers: &Headers) -> bool;
+}
+
+impl<F: FnMut(&Headers) -> bool> Filter for F {
+    fn matches(&mut self, headers: &Headers) -> bool {
+        self(headers)
+    }
+}
+
+/// Filters on the usual headers, all of which have to match
+///
+/// Texts match case insensitively anywhere in the decoded field. A message without a parsable `Date` never
+/// matches a date filter.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Query {
+    subject: Option<String>,
+    from: Option<String>,
+    since: Option<SystemTime>,
+    before: Option<SystemTime>,
+}
+
+impl Query {
+    /// The `Subject` contains `text`
+    pub fn subject(mut self, text: &str) -> Self {
+        self.subject = Some(text.to_lowercase());
+        self
+    }
+
+    /// The `From` contains `text`, e.g. a domain
+    pub fn from(mut self, text: &str) -> Self {
+        self.from = Some(text.to_lowercase());
+        self
+    }
+
+    /// Sent at or after `time`
+    pub fn since(mut self, time: SystemTime) -> Self {
+        self.since = Some(time);
+        self
+    }
+
+    /// Sent before `time`
+    pub fn before(mut self, time: SystemTime) -> Self {
+        self.before = Some(time);
+        self
+    }
+}
+
+impl Filter for Query {
+    fn matches(&mut self, headers: &Headers) -> bool {
+        let contains = |name: &str, text: &Option<String>| match text {
+            Some(text) => headers.decoded(name).is_some_and(|value| value.to_lowercase().contains(text)),
+            None       => true,
+        };
+        if !contains("Subject", &self.subject) || !contains("From", &self.from) {
+            return false;
+        }
+        if self.since.is_none() && self.before.is_none() {
+            return true;
+        }
+        match headers.date() {
+            Some(date) => self.since.is_none_or(|since| date >= since) && self.before.is_none_or(|before| date < before),
+            None       => false,
+        }
+    }
+}
+
+impl Client {
+    /// The header fields of a message, fetched with `TOP n 0`
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// # client.login("sweet_username", "very_secret_password")?;
+    /// let headers = client.top_headers(1)?;
+    /// println!("{}: {}", headers.from().unwrap_or_default(), headers.subject().unwrap_or_default());
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`top()`].
+    ///
+    /// [`top()`]: struct.Client.html#method.top
+    pub fn top_headers(&mut self, msg: u32) -> Result<Headers> {
+        Ok(Headers::parse(&self.top(msg, 0)?.headers))
+    }
+
+    /// The numbers of the messages whose headers match `filter`, in mailbox order
+    ///
+    /// See the [`search`] module.
+    ///
+    /// # Errors
+    /// Same as [`list()`] and [`top()`]; a server without `TOP` can't be searched.
+    ///
+    /// [`search`]: search/index.html
+    /// [`list()`]: struct.Client.html#method.list
+    /// [`top()`]: struct.Client.html#method.top
+    pub fn search_headers<F: Filter>(&mut self, mut filter: F) -> Result<Vec<u32>> {
+        let mut found = vec![];
+        for MessageInfo { id, .. } in self.list_entries(None)? {
+            let headers = self.top_headers(id)?;
+            if filter.matches(&headers) {
+                found.push(id);
+            }
+        }
+        Ok(found)
+    }
+
+    /// The numbers of the messages for which `predicate` holds, given their size and headers
+    ///
+    /// Like [`search_headers()`], this costs a `LIST` and a `TOP n 0` per message.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// use std::time::Duration;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// # client.login("sweet_username", "very_secret_password")?;
+    ///
+    /// let ninety_days = Duration::from_secs(90 * 24 * 3600);
+    /// for id in client.select(|meta| meta.age().is_some_and(|age| age > ninety_days))? {
+    ///     client.dele(id)?;
+    /// }
+    /// client.quit()?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`search_headers()`].
+    ///
+    /// [`search_headers()`]: struct.Client.html#method.search_headers
+    pub fn select<F: FnMut(&MessageMeta) -> bool>(&mut self, mut predicate: F) -> Result<Vec<u32>> {
+        let mut selected = vec![];
+        for MessageInfo { id, size } in self.list_entries(None)? {
+            let headers = self.top_headers(id)?;
+            let meta = MessageMeta {
+                id,
+                size,
+                date: headers.date(),
+                headers,
+            };
+            if predicate(&meta) {
+                selected.push(id);
+            }
+        }
+        Ok(selected)
+    }
+}
+
+/// What [`Client::select()`] knows about a message
+///
+/// [`Client::select()`]: ../struct.Client.html#method.select
+#[derive(Debug, Clone)]
+pub struct MessageMeta {
+    /// Message number within the session
+    pub id: u32,
+    /// Size in octets, from `LIST`
+    pub size: u64,
+    /// The `Date` header, if there is a parsable one
+    pub date: Option<SystemTime>,
+    pub headers: Headers,
+}
+
+impl MessageMeta {
+    /// How long ago the message was sent, according to its `Date`; zero for dates in the future
+    pub fn age(&self) -> Option<Duration> {
+        self.date
+            .map(|date| SystemTime::now().duration_since(date).unwrap_or_default())
+    }
+}
+
+/// `[day-of-week ","] day month year hour ":" minute [":" second] zone`, with comments left out
+fn parse_date(text: &str) -> Option<SystemTime> {
+    let text = text.split('(').next()?;
+    let text = text.split_once(',').map_or(text, |(_, rest)| rest);
+    let mut fields = text.split_whitespace();
+
+    let day: i64 = fields.next()?.parse().ok()?;
+    let month = fields.next()?.to_ascii_lowercase();
+    let month = ["jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec"]
+        .iter()
+        .position(|name| month.starts_with(name))? as i64
+        + 1;
+    let year: i64 = match fields.next()?.parse().ok()? {
+        year @ 0..=49  => year + 2000,
+        year @ 50..=999 => year + 1900,
+        year           => year,
+    };
+
+    let mut time = fields.next()?.split(':').map(|field| field.parse::<i64>().ok());
+    let hour = time.next()??;
+    let minute = time.next()??;
+    let second = time.next().unwrap_or(Some(0))?;
+
+    let offset = match fields.next().unwrap_or("+0000") {
+        zone if zone.len() == 5 && zone.starts_with(['+', '-']) => {
+            let value: i64 = zone[1..].parse().ok()?;
+            let minutes = value / 100 * 60 + value % 100;
+            if zone.starts_with('-') { -minutes } else { minutes }
+        }
+        zone => match zone.to_ascii_uppercase().as_str() {
+            "EDT"         => -4 * 60,
+            "EST" | "CDT" => -5 * 60,
+            "CST" | "MDT" => -6 * 60,
+            "MST" | "PDT" => -7 * 60,
+            "PST"         => -8 * 60,
+            _             => 0,
+        },
+    };
+
+    if !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return None;
+    }
+    let seconds = days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset * 60;
+    if seconds >= 0 {
+        UNIX_EPOCH.checked_add(Duration::from_secs(seconds as u64))
+    } else {
+        UNIX_EPOCH.checked_sub(Duration::from_secs(seconds.unsigned_abs()))
+    }
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar, after Howard Hinnant's algorithm
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
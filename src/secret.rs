@@ -0,0 +1,213 @@
+//! Handling of passwords and other credentials
+//!
+//! With the `secrecy` feature enabled, [`SecretString`] is accepted wherever a password is, every buffer which held
+//! a credential on its way to the server is zeroized as soon as it has been sent, and [`Credentials`] are zeroized
+//! on drop.
+//!
+//! With the `keyring` feature enabled, passwords can be kept in the keyring of the OS instead of configuration
+//! files, see [`Credentials::from_keyring()`].
+//!
+//! [`SecretString`]: https://docs.rs/secrecy/0.10/secrecy/type.SecretString.html
+//! [`Credentials`]: struct.Credentials.html
+//! [`Credentials::from_keyring()`]: struct.Credentials.html#method.from_keyring
+
+use std::ops::Deref;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+/// What secrets are replaced with in transcripts and audit trails
+const REDACTED: &[u8] = b"***";
+
+/// Username and password for plaintext `USER`/`PASS` authorization
+///
+/// The password is zeroized on drop when the `secrecy` feature is enabled.
+#[derive(Clone, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+impl Credentials {
+    pub fn new(username: &str, password: &str) -> Self {
+        Self {
+            username: username.to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    /// The password of `user` stored under `service` in the keyring of the OS
+    ///
+    /// That's the Keychain on macOS, the Credential Manager on Windows, and the kernel keyutils on Linux.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::{Builder, Credentials};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let client = Builder::default()
+    ///     .credentials_provider(|account| Credentials::from_keyring(account.host, "sweet_username"))
+    ///     .open("my.host.com", 995)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Fails with [`Error::Store`] when there's no such entry or the keyring can't be reached.
+    ///
+    /// [`Error::Store`]: ../enum.Error.html#variant.Store
+    #[cfg(feature = "keyring")]
+    pub fn from_keyring(service: &str, user: &str) -> crate::Result<Self> {
+        let password = keyring_entry(service, user)?
+            .get_password()
+            .map_err(|e| crate::Error::Store(format!("keyring: {}", e)))?;
+        Ok(Self::new(user, &password))
+    }
+
+    /// Store the password under `service` in the keyring of the OS, replacing the one stored before
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Credentials;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// Credentials::new("sweet_username", "very_secret_password").save_to_keyring("my.host.com")?;
+    /// let credentials = Credentials::from_keyring("my.host.com", "sweet_username")?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Fails with [`Error::Store`] when the keyring can't be reached.
+    ///
+    /// [`Error::Store`]: ../enum.Error.html#variant.Store
+    #[cfg(feature = "keyring")]
+    pub fn save_to_keyring(&self, service: &str) -> crate::Result<()> {
+        keyring_entry(service, &self.username)?
+            .set_password(&self.password)
+            .map_err(|e| crate::Error::Store(format!("keyring: {}", e)))
+    }
+}
+
+impl Drop for Credentials {
+    fn drop(&mut self) {
+        #[cfg(feature = "secrecy")]
+        {
+            use secrecy::zeroize::Zeroize;
+            self.password.zeroize();
+        }
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_entry(service: &str, user: &str) -> crate::Result<keyring::Entry> {
+    keyring::Entry::new(service, user).map_err(|e| crate::Error::Store(format!("keyring: {}", e)))
+}
+
+/// The account a [`Builder::credentials_provider()`] is asked about
+///
+/// [`Builder::credentials_provider()`]: ../struct.Builder.html#method.credentials_provider
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountRef<'a> {
+    pub host: &'a str,
+    pub port: u16,
+    /// 1 at first, and one more after each refused login
+    pub attempt: u32,
+}
+
+/// Asks for the credentials of an account when they are needed, see [`Builder::credentials_provider()`]
+///
+/// [`Builder::credentials_provider()`]: ../struct.Builder.html#method.credentials_provider
+pub(crate) type Provider = std::sync::Arc<dyn Fn(&AccountRef) -> crate::Result<Credentials> + Send + Sync>;
+
+/// How many times a [`Provider`] is asked before a refused login is final
+pub(crate) const CREDENTIAL_ATTEMPTS: u32 = 3;
+
+/// Something a password can be read from
+pub trait Password {
+    fn expose_password(&self) -> &str;
+}
+
+impl Password for str {
+    fn expose_password(&self) -> &str {
+        self
+    }
+}
+
+impl Password for String {
+    fn expose_password(&self) -> &str {
+        self
+    }
+}
+
+#[cfg(feature = "secrecy")]
+impl Password for secrecy::SecretString {
+    fn expose_password(&self) -> &str {
+        use secrecy::ExposeSecret;
+        self.expose_secret()
+    }
+}
+
+/// Empty a buffer which may have held a credential, overwriting it first when the `secrecy` feature is enabled
+pub(crate) fn wipe(buf: &mut Vec<u8>) {
+    #[cfg(feature = "secrecy")]
+    {
+        use secrecy::zeroize::Zeroize;
+        buf.zeroize();
+    }
+    buf.clear();
+}
+
+/// Hide the secret part of a command, or all of a line answering a SASL challenge
+pub(crate) fn redact(line: &[u8], continuation: bool) -> Vec<u8> {
+    if continuation {
+        return REDACTED.to_vec();
+    }
+    let upper = line.to_ascii_uppercase();
+    // Words to keep before the secret: `PASS ***`, `APOP name ***`, `AUTH mechanism ***`
+    let keep = if upper.starts_with(b"PASS ") {
+        1
+    } else if upper.starts_with(b"APOP ") || upper.starts_with(b"AUTH ") {
+        2
+    } else {
+        return line.to_vec();
+    };
+    let mut words = line.splitn(keep + 1, |c| *c == b' ');
+    let mut redacted = vec![];
+    for word in words.by_ref().take(keep) {
+        redacted.extend_from_slice(word);
+        redacted.push(b' ');
+    }
+    if words.next().is_some() {
+        redacted.extend_from_slice(REDACTED);
+    } else {
+        redacted.pop();
+    }
+    redacted
+}
+
+/// A command line carrying a credential, wiped on drop
+pub(crate) struct Sensitive(String);
+
+impl Sensitive {
+    pub fn new(line: String) -> Self {
+        Self(line)
+    }
+}
+
+impl Deref for Sensitive {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Drop for Sensitive {
+    fn drop(&mut self) {
+        #[cfg(feature = "secrecy")]
+        {
+            use secrecy::zeroize::Zeroize;
+            self.0.zeroize();
+        }
+    }
+}
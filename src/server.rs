@@ -0,0 +1,467 @@
+//! A minimal POP3 server, for tests, gateways to other stores and honeypots
+//!
+//! The mail comes from a [`MaildropProvider`], which checks the credentials and hands out a [`Maildrop`] for
+//! the session. [`Server`] speaks the protocol of [RFC 1939] over it: `USER`, `PASS`, `STAT`, `LIST`, `UIDL`,
+//! `RETR`, `TOP`, `DELE`, `RSET`, `NOOP`, `CAPA` and `QUIT`. Commands are taken apart by the same
+//! [`Command`] the client sends. There is no TLS, so the credentials travel in plain text: keep the server
+//! on localhost, or behind a TLS terminating proxy.
+//!
+//! Deletions only reach the maildrop with a `QUIT`; a session which ends otherwise leaves the maildrop as it
+//! found it. A user has one session at a time: while it lasts, `PASS` of another fails with `-ERR [IN-USE]`, the
+//! exclusive lock of RFC 1939.
+//!
+//! [`MaildirBackend`] serves maildirs on disk, e.g. to run the client against the crate's own server in
+//! tests, or as a gateway giving POP3 access to local mail.
+//!
+//! # Example
+//!
+//! ```no_run
+//! use pop3_client::server::{Entry, Maildrop, MaildropProvider, Server};
+//!
+//! struct Static(Vec<Vec<u8>>);
+//!
+//! impl Maildrop for Static {
+//!     fn list(&mut self) -> pop3_client::Result<Vec<Entry>> {
+//!         Ok(self.0.iter().enumerate().map(|(i, m)| Entry { uid: i.to_string(), size: m.len() }).collect())
+//!     }
+//!     fn retrieve(&mut self, index: usize) -> pop3_client::Result<Vec<u8>> {
+//!         Ok(self.0[index].clone())
+//!     }
+//!     fn commit(&mut self, _deleted: &[usize]) -> pop3_client::Result<()> {
+//!         Ok(())
+//!     }
+//! }
+//!
+//! struct Provider;
+//!
+//! impl MaildropProvider for Provider {
+//!     fn open(&self, user: &str, password: &str) -> pop3_client::Result<Option<Box<dyn Maildrop>>> {
+//!         if (user, password) != ("alice", "secret") {
+//!             return Ok(None);
+//!         }
+//!         Ok(Some(Box::new(Static(vec![b"Subject: hi\r\n\r\nHello\r\n".to_vec()]))))
+//!     }
+//! }
+//!
+//! let listener = std::net::TcpListener::bind("127.0.0.1:1110")?;
+//! Server::new(Provider).run(listener)?;
+//! # Ok::<(), std::io::Error>(())
+//! ```
+//!
+//! [`MaildropProvider`]: trait.MaildropProvider.html
+//! [`Maildrop`]: trait.Maildrop.html
+//! [`MaildirBackend`]: struct.MaildirBackend.html
+//! [`Server`]: struct.Server.html
+//! [`Command`]: ../proto/enum.Command.html
+//! [RFC 1939]: https://tools.ietf.org/html/rfc1939
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use sha2::{Digest, Sha256};
+
+use crate::proto::Command;
+use crate::store::maildir::Maildir;
+use crate::Error;
+
+/// Longest command line read; RFC 2449 allows 255 octets, a little slack spares clients slightly over
+const MAX_LINE: usize = 1024;
+
+/// A message of a maildrop, as listed by `LIST` and `UIDL`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// The unique ID; printable ASCII without spaces, at most 70 characters
+    pub uid: String,
+    /// Size in octets, with CRLF line endings
+    pub size: usize,
+}
+
+/// Where the mail of a server comes from
+pub trait MaildropProvider: Send + Sync {
+    /// The maildrop of `user`, or `None` when the credentials are wrong
+    ///
+    /// Errors are reported to the client as temporary failures, so it tries again later.
+    fn open(&self, user: &str, password: &str) -> crate::Result<Option<Box<dyn Maildrop>>>;
+}
+
+/// The mailbox of a session; messages are told by their index in [`list()`]
+///
+/// [`list()`]: #tymethod.list
+pub trait Maildrop: Send {
+    /// The messages, listed once when the session starts; their numbers are their places here, from 1
+    fn list(&mut self) -> crate::Result<Vec<Entry>>;
+
+    /// The content of a message, lines ending with CRLF
+    fn retrieve(&mut self, index: usize) -> crate::Result<Vec<u8>>;
+
+    /// Remove the messages deleted during the session, on `QUIT`
+    fn commit(&mut self, deleted: &[usize]) -> crate::Result<()>;
+}
+
+/// A POP3 server over a [`MaildropProvider`], see the [module] documentation
+///
+/// [`MaildropProvider`]: trait.MaildropProvider.html
+/// [module]: index.html
+pub struct Server<P> {
+    provider: P,
+    greeting: String,
+    idle_timeout: Option<Duration>,
+    in_use: Locks,
+}
+
+impl<P: MaildropProvider + 'static> Server<P> {
+    pub fn new(provider: P) -> Self {
+        Self {
+            provider,
+            greeting: "POP3 server ready".to_string(),
+            idle_timeout: Some(Duration::from_secs(600)),
+            in_use: Locks::default(),
+        }
+    }
+
+    /// The text after `+OK` in the greeting
+    pub fn greeting(mut self, text: &str) -> Self {
+        self.greeting = text.to_string();
+        self
+    }
+
+    /// Drop connections idle for this long; ten minutes by default, the least RFC 1939 allows
+    pub fn idle_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.idle_timeout = timeout;
+        self
+    }
+
+    /// Accept connections until the listener fails, serving each on a thread of its own
+    pub fn run(self, listener: TcpListener) -> io::Result<()> {
+        let server = Arc::new(self);
+        for stream in listener.incoming() {
+            let stream = stream?;
+            stream.set_read_timeout(server.idle_timeout)?;
+            let server = server.clone();
+            thread::spawn(move || server.serve(stream));
+        }
+        Ok(())
+    }
+
+    /// Serve a single connection, until `QUIT` or until the client goes away
+    pub fn serve<S: Read + Write>(&self, stream: S) -> io::Result<()> {
+        let mut stream = BufReader::new(stream);
+        let mut session = Session::Authorization { user: None };
+        reply(stream.get_mut(), &format!("+OK {}", self.greeting))?;
+
+        let mut line = vec![];
+        loop {
+            line.clear();
+            let read = stream.by_ref().take(MAX_LINE as u64).read_until(b'\n', &mut line)?;
+            if read == 0 {
+                return Ok(());
+            }
+            if !line.ends_with(b"\n") {
+                // Drop the rest of an overlong line, so it isn't taken for commands
+                stream.skip_until(b'\n')?;
+                reply(stream.get_mut(), "-ERR line too long")?;
+                continue;
+            }
+            let text = String::from_utf8_lossy(&line);
+            let text = text.trim_end_matches(['\r', '\n']);
+            let out = stream.get_mut();
+            let quit = match Command::parse(text) {
+                Ok(command) => self.handle(&mut session, command, out)?,
+                Err(e) => reply(out, &format!("-ERR {}", e)).map(|_| false)?,
+            };
+            if quit {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Answer a command; `true` when the session is over
+    fn handle<W: Write>(&self, session: &mut Session, command: Command, out: &mut W) -> io::Result<bool> {
+        match (&mut *session, command) {
+            (_, Command::Noop) => reply(out, "+OK")?,
+            (_, Command::Capa) => multiline(out, "+OK", [&b"USER"[..], b"TOP", b"UIDL", b"PIPELINING"])?,
+            (Session::Authorization { .. }, Command::Quit) => {
+                reply(out, "+OK bye")?;
+                return Ok(true);
+            }
+            (Session::Authorization { user }, Command::User(name)) => {
+                *user = Some(name.to_string());
+                reply(out, "+OK")?;
+            }
+            (Session::Authorization { user: None }, Command::Pass(_)) => reply(out, "-ERR USER first")?,
+            (Session::Authorization { user: Some(user) }, Command::Pass(password)) => {
+                let opened = self.provider.open(user, password).and_then(|maildrop| match maildrop {
+                    Some(mut maildrop) => maildrop.list().map(|entries| Some((maildrop, entries))),
+                    None => Ok(None),
+                });
+                match opened {
+                    Ok(Some((maildrop, entries))) => match self.in_use.acquire(user) {
+                        Some(lock) => {
+                            reply(out, &format!("+OK {} messages", entries.len()))?;
+                            let deleted = vec![false; entries.len()];
+                            *session = Session::Transaction { maildrop, entries, deleted, _lock: lock };
+                        }
+                        None => {
+                            *session = Session::Authorization { user: None };
+                            reply(out, "-ERR [IN-USE] maildrop already locked")?;
+                        }
+                    },
+                    Ok(None) => {
+                        *session = Session::Authorization { user: None };
+                        reply(out, "-ERR [AUTH] invalid credentials")?;
+                    }
+                    Err(e) => reply(out, &format!("-ERR [SYS/TEMP] {}", e))?,
+                }
+            }
+            (Session::Transaction { maildrop, deleted, .. }, Command::Quit) => {
+                let deleted: Vec<usize> = (0..deleted.len()).filter(|i| deleted[*i]).collect();
+                let committed = maildrop.commit(&deleted);
+                // Unlocked before answering, so that the client may start over right away
+                *session = Session::Authorization { user: None };
+                match committed {
+                    Ok(_) => reply(out, "+OK bye")?,
+                    Err(e) => reply(out, &format!("-ERR [SYS/TEMP] some deleted messages not removed: {}", e))?,
+                }
+                return Ok(true);
+            }
+            (Session::Transaction { entries, deleted, .. }, Command::Stat) => {
+                let (count, size) = live(entries, deleted).fold((0, 0), |(count, size), (_, entry)| (count + 1, size + entry.size));
+                reply(out, &format!("+OK {} {}", count, size))?;
+            }
+            (Session::Transaction { entries, deleted, .. }, Command::List(msg)) => {
+                listing(out, entries, deleted, msg, |entry| entry.size.to_string())?;
+            }
+            (Session::Transaction { entries, deleted, .. }, Command::Uidl(msg)) => {
+                listing(out, entries, deleted, msg, |entry| entry.uid.clone())?;
+            }
+            (Session::Transaction { maildrop, entries, deleted, .. }, Command::Retr(msg)) => match index(entries, deleted, msg) {
+                Some(i) => match maildrop.retrieve(i) {
+                    Ok(content) => multiline(out, &format!("+OK {} octets", entries[i].size), lines(&content))?,
+                    Err(e) => reply(out, &format!("-ERR {}", e))?,
+                },
+                None => reply(out, "-ERR no such message")?,
+            },
+            (Session::Transaction { maildrop, entries, deleted, .. }, Command::Top(msg, n)) => match index(entries, deleted, msg) {
+                Some(i) => match maildrop.retrieve(i) {
+                    Ok(content) => {
+                        let mut all = lines(&content);
+                        let mut top: Vec<&[u8]> = all.by_ref().take_while(|line| !line.is_empty()).collect();
+                        // The empty line ending the headers, when there is one
+                        if top.len() < lines(&content).count() {
+                            top.push(b"");
+                        }
+                        top.extend(all.take(n as usize));
+                        multiline(out, "+OK", top)?;
+                    }
+                    Err(e) => reply(out, &format!("-ERR {}", e))?,
+                },
+                None => reply(out, "-ERR no such message")?,
+            },
+            (Session::Transaction { entries, deleted, .. }, Command::Dele(msg)) => match index(entries, deleted, msg) {
+                Some(i) => {
+                    deleted[i] = true;
+                    reply(out, &format!("+OK message {} deleted", msg))?;
+                }
+                None => reply(out, "-ERR no such message")?,
+            },
+            (Session::Transaction { deleted, .. }, Command::Rset) => {
+                deleted.iter_mut().for_each(|deleted| *deleted = false);
+                reply(out, "+OK")?;
+            }
+            _ => reply(out, "-ERR unknown command or wrong stage")?,
+        }
+        Ok(false)
+    }
+}
+
+/// Maildirs on disk, one for each user
+///
+/// Every message in `new/` and `cur/` is listed, oldest first as far as the file names tell. The unique ID of
+/// a message is its file name without the `:2,` flags, or a SHA-256 of it when that isn't a valid unique ID.
+/// Messages deleted in a session are removed from the disk on `QUIT`; those gone by then are skipped.
+///
+/// # Example
+///
+/// ```no_run
+/// use pop3_client::server::{MaildirBackend, Server};
+///
+/// let backend = MaildirBackend::new().user("alice", "secret", "/home/alice/Maildir");
+/// let listener = std::net::TcpListener::bind("127.0.0.1:1110")?;
+/// Server::new(backend).run(listener)?;
+/// # Ok::<(), std::io::Error>(())
+/// ```
+#[derive(Default)]
+pub struct MaildirBackend {
+    users: HashMap<String, (String, PathBuf)>,
+}
+
+impl MaildirBackend {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Let `name` in with `password`, serving the maildir at `path`; it's created when missing
+    pub fn user<P: AsRef<Path>>(mut self, name: &str, password: &str, path: P) -> Self {
+        let path = path.as_ref().to_path_buf();
+        self.users.insert(name.to_string(), (password.to_string(), path));
+        self
+    }
+}
+
+impl MaildropProvider for MaildirBackend {
+    fn open(&self, user: &str, password: &str) -> crate::Result<Option<Box<dyn Maildrop>>> {
+        match self.users.get(user) {
+            Some((expected, path)) if expected == password => {
+                let maildir = Maildir::create(path).map_err(Error::Io)?;
+                Ok(Some(Box::new(MaildirDrop { maildir, files: vec![] })))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+struct MaildirDrop {
+    maildir: Maildir,
+    /// The messages as listed
+    files: Vec<PathBuf>,
+}
+
+impl Maildrop for MaildirDrop {
+    fn list(&mut self) -> crate::Result<Vec<Entry>> {
+        self.files = self.maildir.messages().map_err(Error::Io)?;
+        self.files
+            .iter()
+            .map(|path| {
+                let content = fs::read(path).map_err(Error::Io)?;
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                Ok(Entry {
+                    uid: maildir_uid(&name),
+                    size: lines(&content).map(|line| line.len() + 2).sum(),
+                })
+            })
+            .collect()
+    }
+
+    fn retrieve(&mut self, index: usize) -> crate::Result<Vec<u8>> {
+        fs::read(&self.files[index]).map_err(Error::Io)
+    }
+
+    fn commit(&mut self, deleted: &[usize]) -> crate::Result<()> {
+        for &index in deleted {
+            match fs::remove_file(&self.files[index]) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(Error::Io(e)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The unique part of a maildir file name, hashed unless it's printable ASCII of at most 70 characters
+fn maildir_uid(name: &str) -> String {
+    let unique = name.split(':').next().unwrap_or(name);
+    if !unique.is_empty() && unique.len() <= 70 && unique.bytes().all(|c| c.is_ascii_graphic()) {
+        return unique.to_string();
+    }
+    Sha256::digest(unique.as_bytes()).iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+enum Session {
+    Authorization { user: Option<String> },
+    Transaction { maildrop: Box<dyn Maildrop>, entries: Vec<Entry>, deleted: Vec<bool>, _lock: Lock },
+}
+
+/// The users with a session in the transaction stage
+#[derive(Clone, Default)]
+struct Locks(Arc<Mutex<HashSet<String>>>);
+
+impl Locks {
+    /// Lock the maildrop of `user`, unless another session holds it
+    fn acquire(&self, user: &str) -> Option<Lock> {
+        let mut held = self.0.lock().unwrap_or_else(PoisonError::into_inner);
+        held.insert(user.to_string()).then(|| Lock {
+            locks: self.clone(),
+            user: user.to_string(),
+        })
+    }
+}
+
+/// The lock on the maildrop of a session, released when the session ends, however it ends
+struct Lock {
+    locks: Locks,
+    user: String,
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        self.locks.0.lock().unwrap_or_else(PoisonError::into_inner).remove(&self.user);
+    }
+}
+
+/// The messages not deleted, with their index
+fn live<'a>(entries: &'a [Entry], deleted: &'a [bool]) -> impl Iterator<Item = (usize, &'a Entry)> {
+    entries.iter().enumerate().filter(move |(i, _)| !deleted[*i])
+}
+
+/// The index of message number `msg`, if there is such a message and it isn't deleted
+fn index(entries: &[Entry], deleted: &[bool], msg: u32) -> Option<usize> {
+    let i = (msg as usize).checked_sub(1)?;
+    Some(i).filter(|i| *i < entries.len() && !deleted[*i])
+}
+
+fn listing<W: Write, F: Fn(&Entry) -> String>(
+    out: &mut W,
+    entries: &[Entry],
+    deleted: &[bool],
+    msg: Option<u32>,
+    value: F,
+) -> io::Result<()> {
+    match msg {
+        None => {
+            let lines: Vec<Vec<u8>> = live(entries, deleted)
+                .map(|(i, entry)| format!("{} {}", i + 1, value(entry)).into_bytes())
+                .collect();
+            multiline(out, "+OK", lines.iter().map(Vec::as_slice))
+        }
+        Some(msg) => match index(entries, deleted, msg) {
+            Some(i) => reply(out, &format!("+OK {} {}", msg, value(&entries[i]))),
+            None => reply(out, "-ERR no such message"),
+        },
+    }
+}
+
+/// The lines of a message, without their line endings
+fn lines(content: &[u8]) -> impl Iterator<Item = &[u8]> {
+    let content = content.strip_suffix(b"\n").unwrap_or(content);
+    content
+        .split(|c| *c == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(move |_| !content.is_empty())
+}
+
+fn reply<W: Write>(out: &mut W, line: &str) -> io::Result<()> {
+    out.write_all(line.as_bytes())?;
+    out.write_all(b"\r\n")?;
+    out.flush()
+}
+
+/// A status line, then the lines dot-stuffed, then the terminating `.`
+fn multiline<'a, W: Write, I: IntoIterator<Item = &'a [u8]>>(out: &mut W, status: &str, lines: I) -> io::Result<()> {
+    let mut payload = format!("{}\r\n", status).into_bytes();
+    for line in lines {
+        if line.starts_with(b".") {
+            payload.push(b'.');
+        }
+        payload.extend_from_slice(line);
+        payload.extend_from_slice(b"\r\n");
+    }
+    payload.extend_from_slice(b".\r\n");
+    out.write_all(&payload)?;
+    out.flush()
+}
@@ -0,0 +1,86 @@
+use crate::{Client, Error, Result};
+
+/// Commands only allowed once logged in, see [RFC 1939], section 5
+///
+/// [RFC 1939]: https://tools.ietf.org/html/rfc1939#section-5
+const TRANSACTION_COMMANDS: &[&str] = &["STAT", "LIST", "RETR", "DELE", "NOOP", "RSET", "TOP", "UIDL", "LAST"];
+
+/// Commands which end the Authorization stage when the server accepts them
+const LOGIN_COMMANDS: &[&str] = &["PASS", "APOP", "AUTH"];
+
+/// The stage of a POP3 session, see [RFC 1939], section 3
+///
+/// The Update stage is left out, as it only lasts for `QUIT`, which consumes the client.
+///
+/// [RFC 1939]: https://tools.ietf.org/html/rfc1939#section-3
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Stage {
+    /// Not logged in yet
+    #[default]
+    Authorization,
+    /// Logged in; the maildrop can be read and changed
+    Transaction,
+}
+
+pub(crate) fn keyword(command: &str) -> String {
+    command.split(' ').next().unwrap_or_default().to_ascii_uppercase()
+}
+
+impl Client {
+    /// The stage the session is in
+    ///
+    /// A failed login leaves the session in [`Stage::Authorization`], so it can be tried again. Logins through
+    /// [`command()`] are followed as well, as long as they take a single `PASS`, `APOP` or `AUTH` line.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::{Client, Stage};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// if client.login("sweet_username", "wrong_password").is_err() {
+    ///     assert_eq!(client.stage(), Stage::Authorization);
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`Stage::Authorization`]: enum.Stage.html#variant.Authorization
+    /// [`command()`]: struct.Client.html#method.command
+    pub fn stage(&self) -> Stage {
+        self.stage
+    }
+
+    /// Whether the session has been logged in, i.e. is in [`Stage::Transaction`]
+    ///
+    /// [`Stage::Transaction`]: enum.Stage.html#variant.Transaction
+    pub fn is_authorized(&self) -> bool {
+        self.stage == Stage::Transaction
+    }
+
+    /// Follow the stage through the reply to `command`
+    pub(crate) fn track_stage<T>(&mut self, command: &str, result: Result<T>) -> Result<T> {
+        let (stage, result) = advance(self.stage, command, result);
+        // Servers may announce more once logged in, see RFC 2449, section 5
+        if stage != self.stage {
+            self.capabilities = None;
+        }
+        self.stage = stage;
+        result
+    }
+}
+
+/// The stage after the reply to `command`, and the reply with refusals due to the stage told apart
+pub(crate) fn advance<T>(stage: Stage, command: &str, result: Result<T>) -> (Stage, Result<T>) {
+    if stage == Stage::Transaction {
+        return (stage, result);
+    }
+    match result {
+        Ok(reply) if LOGIN_COMMANDS.contains(&keyword(command).as_str()) => (Stage::Transaction, Ok(reply)),
+        // The server refuses these before a login whatever their arguments, so the reason is known
+        Err(Error::Server(text)) if TRANSACTION_COMMANDS.contains(&keyword(command).as_str()) => {
+            (stage, Err(Error::WrongStage(text)))
+        }
+        result => (stage, result),
+    }
+}
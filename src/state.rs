@@ -0,0 +1,164 @@
+//! Persistence of already downloaded unique IDs
+//!
+//! A [`UidStore`] remembers, per account, which messages have been downloaded before, so that
+//! "fetch only new messages" keeps working across restarts of the process. [`SyncOptions`] tell how messages
+//! get their unique IDs on servers without `UIDL`.
+//!
+//! [`UidStore`]: trait.UidStore.html
+//! [`SyncOptions`]: struct.SyncOptions.html
+
+use std::collections::HashSet;
+#[cfg(feature = "serde")]
+use std::collections::BTreeMap;
+#[cfg(feature = "serde")]
+use std::fs;
+#[cfg(feature = "serde")]
+use std::io;
+#[cfg(feature = "serde")]
+use std::path::{Path, PathBuf};
+
+use crate::{Client, Error, Result, UidlEntry};
+
+/// Storage of the seen unique IDs, keyed by an account identifier
+pub trait UidStore {
+    /// The unique IDs seen for the account; empty if the account is not known yet
+    fn load(&mut self, account: &str) -> Result<HashSet<String>>;
+
+    /// Replace the unique IDs seen for the account
+    fn save(&mut self, account: &str, uids: &HashSet<String>) -> Result<()>;
+}
+
+/// New unique IDs the sync layer gathers before saving them, and again at the end of a session
+///
+/// A [`UidStore`] saves whole sets, so saving after each download would rewrite the store once per message.
+///
+/// [`UidStore`]: trait.UidStore.html
+pub(crate) const SAVE_BATCH: usize = 100;
+
+/// How the sync layer, the [`Fetcher`] and [`Client::migrate()`], tells messages apart across sessions
+///
+/// [`Fetcher`]: ../fetcher/struct.Fetcher.html
+/// [`Client::migrate()`]: ../struct.Client.html#method.migrate
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SyncOptions {
+    /// Where unique IDs come from when the server refuses `UIDL`
+    pub uid_fallback: UidFallback,
+}
+
+/// Unique IDs for servers refusing `UIDL`, see [`Client::sync_entries()`]
+///
+/// Both fallbacks hash with MD5; two messages hashing the same are taken for one, which with the headers takes
+/// identical `Received` and `Message-ID` lines.
+///
+/// [`Client::sync_entries()`]: ../struct.Client.html#method.sync_entries
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum UidFallback {
+    /// Fail as `UIDL` did
+    None,
+    /// Hash the headers from `TOP n 0`, or the whole message when `TOP` is refused too
+    #[default]
+    Headers,
+    /// Hash the whole message, downloading every message to list them
+    Content,
+}
+
+impl Client {
+    /// The unique-id listing of the whole mailbox, made up as `options` tell when the server refuses `UIDL`
+    ///
+    /// Only an `-ERR` without a response code counts as a refusal, besides [`Error::NotSupportedByServer`];
+    /// `[SYS/TEMP]` and the like are errors still.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// # use pop3_client::Client;
+    /// use pop3_client::state::{SyncOptions, UidFallback};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// # let mut client = Client::connect("my.host.com", 110)?;
+    /// let options = SyncOptions { uid_fallback: UidFallback::Content };
+    /// for entry in client.sync_entries(&options)? {
+    ///     println!("{}: {}", entry.id, entry.uid);
+    /// }
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Same as [`uidl_entries()`], or with a fallback, as `LIST` and then `TOP` or `RETR`.
+    ///
+    /// [`Error::NotSupportedByServer`]: enum.Error.html#variant.NotSupportedByServer
+    /// [`uidl_entries()`]: struct.Client.html#method.uidl_entries
+    pub fn sync_entries(&mut self, options: &SyncOptions) -> Result<Vec<UidlEntry>> {
+        let refused = |result: &Result<Vec<UidlEntry>>| match result {
+            Err(e @ Error::Server(_)) => e.code().is_none(),
+            Err(Error::NotSupportedByServer(_)) => true,
+            _ => false,
+        };
+        let result = self.uidl_entries(None);
+        match options.uid_fallback {
+            UidFallback::Headers if refused(&result) => {
+                let result = self.uidl_from_headers(None);
+                if refused(&result) {
+                    return self.uidl_from_content();
+                }
+                result
+            }
+            UidFallback::Content if refused(&result) => self.uidl_from_content(),
+            _ => result,
+        }
+    }
+}
+
+/// A [`UidStore`] keeping all the accounts in a single JSON file
+///
+/// The file holds an object mapping account identifiers to arrays of unique IDs, and is replaced
+/// atomically on every save. Needs the `serde` feature.
+///
+/// [`UidStore`]: trait.UidStore.html
+#[cfg(feature = "serde")]
+pub struct JsonFileStore {
+    path: PathBuf,
+}
+
+#[cfg(feature = "serde")]
+impl JsonFileStore {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    fn read(&self) -> Result<BTreeMap<String, Vec<String>>> {
+        match fs::read(&self.path) {
+            Ok(data) => serde_json::from_slice(&data).map_err(|e| Error::Store(e.to_string())),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+            Err(e) => Err(Error::Io(e)),
+        }
+    }
+
+    fn write(&self, accounts: &BTreeMap<String, Vec<String>>) -> Result<()> {
+        let data = serde_json::to_vec_pretty(accounts).map_err(|e| Error::Store(e.to_string()))?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, data)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(Error::Io)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl UidStore for JsonFileStore {
+    fn load(&mut self, account: &str) -> Result<HashSet<String>> {
+        Ok(self
+            .read()?
+            .remove(account)
+            .map(|uids| uids.into_iter().collect())
+            .unwrap_or_default())
+    }
+
+    fn save(&mut self, account: &str, uids: &HashSet<String>) -> Result<()> {
+        let mut accounts = self.read()?;
+        let mut uids: Vec<String> = uids.iter().cloned().collect();
+        uids.sort();
+        accounts.insert(account.to_string(), uids);
+        self.write(&accounts)
+    }
+}
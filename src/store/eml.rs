@@ -0,0 +1,186 @@
+//! Dump a mailbox to a directory of standalone `.eml` files
+//!
+//! Every message is written as it was received, dot-unstuffed, to a file of its own, named after its
+//! unique ID, its number or its subject and date, see [`Naming`]. Names are reduced to characters safe on
+//! any file system. A `manifest.json` next to the messages lists, for each file, the message number, the
+//! unique ID, the size and the SHA-256 of the content, and whether fewer octets arrived than `LIST` announced.
+//! Needs the `serde` feature.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use pop3_client::Client;
+//! use pop3_client::store::eml::Naming;
+//! # fn main() -> pop3_client::Result<()> {
+//! # let mut client = Client::connect("my.host.com", 110)?;
+//! # client.login("sweet_username", "very_secret_password")?;
+//! let dumped = client.dump_eml("/home/me/backup", Naming::SubjectDate)?;
+//! println!("{} messages written", dumped.len());
+//! #    Ok(())
+//! # }
+//! ```
+//!
+//! [`Naming`]: enum.Naming.html
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use serde::{Serialize, Serializer};
+
+use crate::search::Headers;
+use crate::{Algo, Client, Error, Result, UidlEntry};
+
+/// The manifest written next to the messages
+pub const MANIFEST: &str = "manifest.json";
+
+/// Longest part of a file name taken from a unique ID or a subject, well below the 255 octets most file
+/// systems allow
+const MAX_STEM_LEN: usize = 80;
+
+/// How [`Client::dump_eml()`] names the files
+///
+/// [`Client::dump_eml()`]: ../../struct.Client.html#method.dump_eml
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Naming {
+    /// The unique ID of `UIDL`, e.g. `000001a2b3c4.eml`
+    Uid,
+    /// The message number, zero padded so the files sort in mailbox order, e.g. `0042.eml`
+    Seq,
+    /// The `Date` in UTC and the `Subject`, e.g. `20240305-101500_Quarterly_report.eml`
+    SubjectDate,
+}
+
+/// A message written by [`Client::dump_eml()`], as listed in the manifest
+///
+/// [`Client::dump_eml()`]: ../../struct.Client.html#method.dump_eml
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Dumped {
+    pub id: u32,
+    pub uid: String,
+    /// Listed as `file` in the manifest, without the directory
+    #[serde(rename = "file", serialize_with = "file_name")]
+    pub path: PathBuf,
+    /// Octets written
+    pub size: u64,
+    /// SHA-256 of those octets, in lowercase hex
+    pub sha256: String,
+    /// Fewer octets arrived than `LIST` announced
+    pub truncated: bool,
+}
+
+impl Client {
+    /// Write every message of the mailbox to a `.eml` file in `dir`, with a manifest, see the [`eml`] module
+    ///
+    /// The directory is created when missing. Files of earlier dumps with the same names are replaced;
+    /// names clashing within a dump get a `-2`, `-3`... suffix. The messages are left on the server.
+    ///
+    /// # Errors
+    /// Besides the errors of `UIDL`, `LIST` and `RETR`, the dump stops at the first file that can't be
+    /// written. The manifest is written in any case, listing the messages dumped until then.
+    ///
+    /// [`eml`]: store/eml/index.html
+    pub fn dump_eml<P: AsRef<Path>>(&mut self, dir: P, naming: Naming) -> Result<Vec<Dumped>> {
+        let dir = dir.as_ref();
+        fs::create_dir_all(dir).map_err(Error::Io)?;
+
+        let entries = self.uidl_entries(None)?;
+        let width = entries.iter().map(|entry| entry.id).max().unwrap_or(0).to_string().len().max(4);
+        let mut names = HashSet::new();
+        let mut dumped = vec![];
+
+        let result = entries.into_iter().try_for_each(|UidlEntry { id, uid }| {
+            let (message, integrity) = self.retr_with_digest(id, Algo::Sha256)?;
+            let stem = match naming {
+                Naming::Uid         => sanitize(&uid).unwrap_or_else(|| "message".to_string()),
+                Naming::Seq         => format!("{:0width$}", id, width = width),
+                Naming::SubjectDate => subject_date(&Headers::parse(&message)),
+            };
+            let path = dir.join(unique_name(&mut names, &stem));
+            write_atomically(&path, &message)?;
+            dumped.push(Dumped {
+                id,
+                uid,
+                path,
+                size: integrity.octets,
+                sha256: integrity.hex(),
+                truncated: integrity.truncated,
+            });
+            Ok(())
+        });
+
+        let manifest = serde_json::to_vec_pretty(&dumped).map_err(|e| Error::Store(e.to_string()))?;
+        write_atomically(&dir.join(MANIFEST), &manifest)?;
+        result.map(|_| dumped)
+    }
+}
+
+/// `YYYYMMDD-HHMMSS_Subject`, with `undated` and `no-subject` standing in for missing fields
+fn subject_date(headers: &Headers) -> String {
+    let date = headers
+        .date()
+        .and_then(|date| date.duration_since(UNIX_EPOCH).ok())
+        .map(|since| {
+            let secs = since.as_secs();
+            let (year, month, day) = super::civil_from_days((secs / 86400) as i64);
+            let time = secs % 86400;
+            format!("{:04}{:02}{:02}-{:02}{:02}{:02}", year, month, day, time / 3600, time % 3600 / 60, time % 60)
+        })
+        .unwrap_or_else(|| "undated".to_string());
+    let subject = headers
+        .subject()
+        .and_then(|subject| sanitize(&subject))
+        .unwrap_or_else(|| "no-subject".to_string());
+    format!("{}_{}", date, subject)
+}
+
+/// Keep ASCII letters, digits and `-_.@+=`, and turn runs of anything else into a single `_`
+///
+/// Leading dots are dropped so the file isn't hidden, and names reserved on Windows get a leading `_`.
+/// `None` when nothing is left.
+fn sanitize(text: &str) -> Option<String> {
+    let mut name = String::new();
+    for c in text.chars() {
+        if c.is_ascii_alphanumeric() || "-_.@+=".contains(c) {
+            name.push(c);
+        } else if !name.ends_with('_') {
+            name.push('_');
+        }
+    }
+    let name: String = name.trim_start_matches(['.', '_']).chars().take(MAX_STEM_LEN).collect();
+    let name = name.trim_end_matches(['.', '_']);
+    let device = name.split('.').next().unwrap_or("").to_ascii_uppercase();
+    Some(match device.as_str() {
+        "" => return None,
+        "CON" | "PRN" | "AUX" | "NUL" => format!("_{}", name),
+        d if (d.starts_with("COM") || d.starts_with("LPT")) && d.len() == 4 => format!("_{}", name),
+        _ => name.to_string(),
+    })
+}
+
+/// `stem.eml`, or `stem-2.eml` and so on when taken earlier in the dump
+fn unique_name(names: &mut HashSet<String>, stem: &str) -> String {
+    let mut name = format!("{}.eml", stem);
+    let mut n = 1;
+    while !names.insert(name.to_ascii_lowercase()) {
+        n += 1;
+        name = format!("{}-{}.eml", stem, n);
+    }
+    name
+}
+
+fn write_atomically(path: &Path, content: &[u8]) -> Result<()> {
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| {
+            fs::remove_file(&tmp).ok();
+            Error::Io(e)
+        })
+}
+
+/// Only the file name goes into the manifest, which lies in the same directory
+fn file_name<S: Serializer>(path: &Path, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.file_name().map(|name| name.to_string_lossy()).unwrap_or_default())
+}
@@ -0,0 +1,181 @@
+//! Export to a [maildir]
+//!
+//! Every message is first written to `tmp/`, synced, and only then renamed into `new/`, so readers of
+//! the maildir never see a partially written message.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use pop3_client::Client;
+//! use pop3_client::store::maildir::ExportOptions;
+//! # fn main() -> pop3_client::Result<()> {
+//! # let mut client = Client::connect("my.host.com", 110)?;
+//! # client.login("sweet_username", "very_secret_password")?;
+//!
+//! let exported = client.export_to_maildir("/home/me/Maildir", &ExportOptions::default())?;
+//! println!("{} new messages", exported.len());
+//! #    Ok(())
+//! # }
+//! ```
+//!
+//! [maildir]: https://cr.yp.to/proto/maildir.html
+
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Client, Dedup, Error, Result, UidlEntry};
+
+static DELIVERIES: AtomicUsize = AtomicUsize::new(0);
+
+/// A maildir on the local file system
+pub struct Maildir {
+    root: PathBuf,
+}
+
+impl Maildir {
+    /// Open the maildir at `path`, creating `tmp/`, `new/` and `cur/` when missing
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let root = path.as_ref().to_path_buf();
+        for dir in &["tmp", "new", "cur"] {
+            fs::create_dir_all(root.join(dir))?;
+        }
+        Ok(Self { root })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.root
+    }
+
+    /// Write the message into `tmp/` and move it into `new/`, returning its final path
+    pub fn deliver(&self, message: &[u8]) -> io::Result<PathBuf> {
+        let name = unique_name();
+        let tmp = self.root.join("tmp").join(&name);
+        let new = self.root.join("new").join(&name);
+
+        let result = File::create(&tmp)
+            .and_then(|mut file| {
+                file.write_all(message)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&tmp, &new));
+
+        if result.is_err() {
+            fs::remove_file(&tmp).ok();
+        }
+        result.map(|_| new)
+    }
+
+    /// Call `f` with the content of every message in `new/` and `cur/`, one at a time
+    pub fn for_each_message(&self, f: &mut dyn FnMut(&[u8])) -> io::Result<()> {
+        for path in self.messages()? {
+            f(&fs::read(path)?);
+        }
+        Ok(())
+    }
+
+    /// The paths of the messages in `new/` and `cur/`, oldest delivery first as far as their names tell
+    pub fn messages(&self) -> io::Result<Vec<PathBuf>> {
+        let mut paths = vec![];
+        for dir in &["new", "cur"] {
+            for entry in fs::read_dir(self.root.join(dir))? {
+                let path = entry?.path();
+                if path.is_file() {
+                    paths.push(path);
+                }
+            }
+        }
+        paths.sort_by(|a, b| a.file_name().cmp(&b.file_name()));
+        Ok(paths)
+    }
+}
+
+/// What [`Client::export_to_maildir()`] should download
+///
+/// [`Client::export_to_maildir()`]: ../../struct.Client.html#method.export_to_maildir
+#[derive(Default, Clone)]
+pub struct ExportOptions {
+    /// Unique IDs of messages which were exported before and have to be skipped
+    pub skip_uids: HashSet<String>,
+    /// Also skip messages already in the maildir, or met earlier in the same export, as told by the [`Dedup`]
+    ///
+    /// [`Dedup`]: ../../enum.Dedup.html
+    pub skip_duplicates: Option<Dedup>,
+}
+
+impl Client {
+    /// Download the messages of the mailbox into the maildir at `path`
+    ///
+    /// Returns the unique IDs of the exported messages, along with the paths they were written to.
+    /// The messages are left on the server.
+    ///
+    /// # Errors
+    /// Besides the errors of the `UIDL` and `RETR` commands, the export stops on the first failure to
+    /// write into the maildir.
+    pub fn export_to_maildir<P: AsRef<Path>>(
+        &mut self,
+        path: P,
+        options: &ExportOptions,
+    ) -> Result<Vec<(String, PathBuf)>> {
+        let maildir = Maildir::create(path).map_err(Error::Io)?;
+        let mut exported = vec![];
+        let mut seen = HashSet::new();
+        if let Some(dedup) = options.skip_duplicates {
+            maildir.for_each_message(&mut |message| seen.extend(dedup.keys(message))).map_err(Error::Io)?;
+        }
+
+        for UidlEntry { id: msg, uid } in self.uidl_entries(None)? {
+            if options.skip_uids.contains(&uid) {
+                continue;
+            }
+            let bytes = match options.skip_duplicates {
+                Some(dedup) if dedup.known_id(self, msg, &seen)? => continue,
+                Some(dedup) => {
+                    let bytes = self.retr_bytes(msg)?;
+                    let keys = dedup.keys(&bytes);
+                    if keys.iter().any(|key| seen.contains(key)) {
+                        continue;
+                    }
+                    seen.extend(keys);
+                    bytes
+                }
+                None => self.retr_bytes(msg)?,
+            };
+            let path = maildir.deliver(&bytes).map_err(Error::Io)?;
+            exported.push((uid, path));
+        }
+
+        Ok(exported)
+    }
+}
+
+/// `<seconds>.M<microseconds>P<pid>Q<counter>.<hostname>`, as recommended by the maildir spec
+fn unique_name() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    format!(
+        "{}.M{}P{}Q{}.{}",
+        now.as_secs(),
+        now.subsec_micros(),
+        std::process::id(),
+        DELIVERIES.fetch_add(1, Ordering::Relaxed),
+        hostname()
+    )
+}
+
+fn hostname() -> String {
+    let name = fs::read_to_string("/etc/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "localhost".to_string());
+
+    name.replace('/', "\\057").replace(':', "\\072")
+}
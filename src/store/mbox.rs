@@ -0,0 +1,131 @@
+//! Export to an mbox file
+//!
+//! Messages are appended in the `mboxrd` flavour: each one starts with a `From ` separator line,
+//! and body lines matching `>*From ` get one more `>` prepended, so they can be told apart from
+//! separators and unquoted again by readers. Line endings are converted to LF.
+//!
+//! # Example
+//!
+//! ```no_run
+//! # use pop3_client::Client;
+//! # fn main() -> pop3_client::Result<()> {
+//! # let mut client = Client::connect("my.host.com", 110)?;
+//! # client.login("sweet_username", "very_secret_password")?;
+//! let exported = client.export_to_mbox("/var/mail/me")?;
+//! #    Ok(())
+//! # }
+//! ```
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::{Client, Error, Result, UidlEntry};
+
+/// Appends messages to an mbox
+pub struct Writer<W: Write> {
+    inner: W,
+}
+
+impl Writer<File> {
+    /// Open the mbox at `path` for appending, creating it when missing
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .map(Self::new)
+    }
+}
+
+impl<W: Write> Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+
+    /// Append the message, preceded by a `From ` line carrying its sender and the current time
+    pub fn write_message(&mut self, message: &[u8]) -> io::Result<()> {
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+
+        writeln!(self.inner, "From {} {}", sender(message), asctime(secs))?;
+
+        let message = message.strip_suffix(b"\n").unwrap_or(message);
+        for line in message.split(|c| *c == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let unquoted = &line[line.iter().take_while(|c| **c == b'>').count()..];
+            if unquoted.starts_with(b"From ") {
+                self.inner.write_all(b">")?;
+            }
+            self.inner.write_all(line)?;
+            self.inner.write_all(b"\n")?;
+        }
+
+        self.inner.write_all(b"\n")?;
+        self.inner.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl Client {
+    /// Append all the messages of the mailbox to the mbox at `path`
+    ///
+    /// Returns the unique IDs of the exported messages. The messages are left on the server.
+    ///
+    /// # Errors
+    /// Besides the errors of the `UIDL` and `RETR` commands, the export stops on the first failure to
+    /// write into the mbox.
+    pub fn export_to_mbox<P: AsRef<Path>>(&mut self, path: P) -> Result<Vec<String>> {
+        let mut writer = Writer::open(path).map_err(Error::Io)?;
+        let mut exported = vec![];
+
+        for UidlEntry { id: msg, uid } in self.uidl_entries(None)? {
+            let bytes = self.retr_bytes(msg)?;
+            writer.write_message(&bytes).map_err(Error::Io)?;
+            exported.push(uid);
+        }
+
+        Ok(exported)
+    }
+}
+
+/// The address from the `Return-Path` header, or `MAILER-DAEMON` if there is none
+fn sender(message: &[u8]) -> String {
+    message
+        .split(|c| *c == b'\n')
+        .take_while(|line| !line.is_empty() && *line != b"\r")
+        .find(|line| line.len() > 12 && line[..12].eq_ignore_ascii_case(b"Return-Path:"))
+        .and_then(|line| std::str::from_utf8(&line[12..]).ok())
+        .map(|path| path.trim().trim_start_matches('<').trim_end_matches('>').to_string())
+        .filter(|path| !path.is_empty() && !path.contains(char::is_whitespace))
+        .unwrap_or_else(|| "MAILER-DAEMON".to_string())
+}
+
+/// Format UNIX time the way `asctime()` does, e.g. `Thu Jan  1 00:00:00 1970`
+fn asctime(secs: u64) -> String {
+    const DAYS:   [&str; 7]  = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = ["Jan", "Feb", "Mar", "Apr", "May", "Jun",
+                                "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+
+    let days = secs / 86400;
+    let time = secs % 86400;
+
+    let (year, month, day) = super::civil_from_days(days as i64);
+
+    format!(
+        "{} {} {:>2} {:02}:{:02}:{:02} {}",
+        DAYS[(days % 7) as usize],
+        MONTHS[(month - 1) as usize],
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60,
+        year
+    )
+}
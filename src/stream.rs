@@ -0,0 +1,456 @@
+use std::io::{self, ErrorKind, Read, Result, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ProxyHeader;
+
+#[cfg(feature = "with-rustls")]
+use rustls::{ClientSession, StreamOwned};
+
+/// Any stream a session can run over
+pub(crate) trait Transport: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Transport for T {}
+
+/// The connection a [`Client`] talks through
+///
+/// [`Client`]: ../struct.Client.html
+pub(crate) enum Stream {
+    Plain(TcpStream),
+    #[cfg(feature = "with-rustls")]
+    Tls(Box<StreamOwned<ClientSession, TcpStream>>),
+    /// Provided by the user with `Client::from_stream()`
+    Other(Box<dyn Transport>),
+    /// Written to a transcript, see `Builder::record_to()`
+    #[cfg(feature = "record")]
+    Recorded(Box<crate::record::Recorder>),
+    /// Shut down after an unrecoverable error
+    Closed,
+}
+
+impl Stream {
+    /// Close the connection; later reads see the end of the stream, and writes fail
+    pub fn shutdown(&mut self) {
+        match self {
+            Stream::Plain(s) => s.shutdown(Shutdown::Both).ok(),
+            #[cfg(feature = "with-rustls")]
+            Stream::Tls(s) => s.sock.shutdown(Shutdown::Both).ok(),
+            #[cfg(feature = "record")]
+            Stream::Recorded(s) => {
+                s.get_mut().shutdown();
+                None
+            }
+            Stream::Other(_) | Stream::Closed => None,
+        };
+        *self = Stream::Closed;
+    }
+}
+
+impl Stream {
+    pub(crate) fn set_read_timeout(&mut self, timeout: Option<Duration>) -> Result<()> {
+        match self {
+            Stream::Plain(s) => s.set_read_timeout(timeout),
+            #[cfg(feature = "with-rustls")]
+            Stream::Tls(s) => s.sock.set_read_timeout(timeout),
+            #[cfg(feature = "record")]
+            Stream::Recorded(s) => s.get_mut().set_read_timeout(timeout),
+            Stream::Other(_) | Stream::Closed => Ok(()),
+        }
+    }
+}
+
+impl Stream {
+    /// The addresses of the server and of this end, when the connection is a socket the client opened itself
+    pub(crate) fn addresses(&mut self) -> (Option<SocketAddr>, Option<SocketAddr>) {
+        let socket = match self {
+            Stream::Plain(s) => s,
+            #[cfg(feature = "with-rustls")]
+            Stream::Tls(s) => &s.sock,
+            #[cfg(feature = "record")]
+            Stream::Recorded(s) => return s.get_mut().addresses(),
+            Stream::Other(_) | Stream::Closed => return (None, None),
+        };
+        (socket.peer_addr().ok(), socket.local_addr().ok())
+    }
+}
+
+/// TCP options for the sockets the client opens itself, see `Builder::tcp_nodelay()` and its siblings
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct SocketOptions {
+    pub nodelay: Option<bool>,
+    pub keepalive: Option<Duration>,
+    pub send_buffer: Option<usize>,
+    pub recv_buffer: Option<usize>,
+    /// Sent first thing, see `Builder::send_proxy_protocol()`
+    pub proxy: Option<ProxyHeader>,
+    /// For connecting, and then again for the greeting, see `Builder::connect_timeout()`
+    pub connect_timeout: Option<Duration>,
+}
+
+impl SocketOptions {
+    /// Connect to the first address of `host` that answers in time, or to `address` when given, then apply the
+    /// options
+    pub fn connect(&self, host: &str, port: u16, address: Option<SocketAddr>) -> Result<TcpStream> {
+        let addresses = match address {
+            Some(address) => vec![address],
+            None => (host, port).to_socket_addrs()?.collect(),
+        };
+        let socket = match self.connect_timeout {
+            None => TcpStream::connect(&addresses[..])?,
+            Some(timeout) => {
+                let deadline = Instant::now() + timeout;
+                let mut last = io::Error::new(ErrorKind::InvalidInput, "no address to connect to");
+                let mut connected = None;
+                for address in addresses {
+                    let left = deadline.saturating_duration_since(Instant::now());
+                    if left.is_zero() {
+                        last = io::Error::new(ErrorKind::TimedOut, "connection timed out");
+                        break;
+                    }
+                    match TcpStream::connect_timeout(&address, left) {
+                        Ok(socket) => {
+                            connected = Some(socket);
+                            break;
+                        }
+                        Err(e) => last = e,
+                    }
+                }
+                connected.ok_or(last)?
+            }
+        };
+        self.apply(&socket)?;
+        Ok(socket)
+    }
+
+    /// Set the options which were given, leaving the system defaults for the others, and send the PROXY header
+    pub fn apply(&self, socket: &TcpStream) -> Result<()> {
+
+        if let Some(nodelay) = self.nodelay {
+            socket.set_nodelay(nodelay)?;
+        }
+        let sock = socket2::SockRef::from(socket);
+        if let Some(idle) = self.keepalive {
+            sock.set_tcp_keepalive(&socket2::TcpKeepalive::new().with_time(idle))?;
+        }
+        if let Some(octets) = self.send_buffer {
+            sock.set_send_buffer_size(octets)?;
+        }
+        if let Some(octets) = self.recv_buffer {
+            sock.set_recv_buffer_size(octets)?;
+        }
+        if let Some(header) = &self.proxy {
+            (&mut &*socket).write_all(&header.encode())?;
+        }
+        Ok(())
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        match self {
+            Stream::Plain(s) => s.read(buf),
+            #[cfg(feature = "with-rustls")]
+            Stream::Tls(s) => s.read(buf),
+            #[cfg(feature = "record")]
+            Stream::Recorded(s) => s.read(buf),
+            Stream::Other(s) => s.read(buf),
+            Stream::Closed => Ok(0),
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match self {
+            Stream::Plain(s) => s.write(buf),
+            #[cfg(feature = "with-rustls")]
+            Stream::Tls(s) => s.write(buf),
+            #[cfg(feature = "record")]
+            Stream::Recorded(s) => s.write(buf),
+            Stream::Other(s) => s.write(buf),
+            Stream::Closed => Err(ErrorKind::NotConnected.into()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self {
+            Stream::Plain(s) => s.flush(),
+            #[cfg(feature = "with-rustls")]
+            Stream::Tls(s) => s.flush(),
+            #[cfg(feature = "record")]
+            Stream::Recorded(s) => s.flush(),
+            Stream::Other(s) => s.flush(),
+            Stream::Closed => Ok(()),
+        }
+    }
+}
+
+/// Collects written commands until flushed, so each command (or batch of pipelined commands) leaves in a single write
+///
+/// Reading flushes first, so a reply is never waited for while its command is still in the buffer.
+/// Writes can be spaced out by a pause, for servers that block clients sending commands in quick succession.
+pub(crate) struct WriteBuffered<S: Read + Write> {
+    inner: S,
+    pending: Vec<u8>,
+    pause: Option<Duration>,
+    last_write: Option<Instant>,
+}
+
+impl<S: Read + Write> WriteBuffered<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            pending: Vec::new(),
+            pause: None,
+            last_write: None,
+        }
+    }
+
+    pub fn set_pause(&mut self, pause: Option<Duration>) {
+        self.pause = pause;
+    }
+
+    /// Wait until the pause has passed since the last write
+    pub fn pace(&self) {
+        if let (Some(pause), Some(last_write)) = (self.pause, self.last_write) {
+            if let Some(left) = pause.checked_sub(last_write.elapsed()) {
+                thread::sleep(left);
+            }
+        }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+}
+
+impl<S: Read + Write> Read for WriteBuffered<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if !self.pending.is_empty() {
+            self.flush()?;
+        }
+        self.inner.read(buf)
+    }
+}
+
+impl<S: Read + Write> Write for WriteBuffered<S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.pending.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        if !self.pending.is_empty() {
+            self.pace();
+            self.last_write = Some(Instant::now());
+        }
+        let result = self.inner.write_all(&self.pending);
+        crate::secret::wipe(&mut self.pending);
+        result.and_then(|_| self.inner.flush())
+    }
+}
+
+/// Limits how fast replies are read, with a token bucket holding at most one second worth of octets
+pub(crate) struct Throttled<S: Read + Write> {
+    inner: S,
+    bucket: Option<Bucket>,
+}
+
+struct Bucket {
+    rate: u64,
+    /// Octets that may be read right away; negative while paying off a read that went over
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl<S: Read + Write> Throttled<S> {
+    pub fn new(inner: S) -> Self {
+        Self { inner, bucket: None }
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.inner
+    }
+
+    /// Read at most `rate` octets per second, or as fast as possible with `None`
+    pub fn set_rate(&mut self, rate: Option<u64>) {
+        self.bucket = rate.filter(|rate| *rate > 0).map(|rate| Bucket {
+            rate,
+            tokens: rate as f64,
+            refilled: Instant::now(),
+        });
+    }
+}
+
+impl Bucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.rate as f64).min(self.rate as f64);
+        self.refilled = now;
+    }
+
+    /// Wait until there is something to read for
+    fn acquire(&mut self) {
+        self.refill();
+        if self.tokens < 1.0 {
+            thread::sleep(Duration::from_secs_f64((1.0 - self.tokens) / self.rate as f64));
+            self.refill();
+        }
+    }
+}
+
+impl<S: Read + Write> Read for Throttled<S> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        let bucket = match &mut self.bucket {
+            Some(bucket) => bucket,
+            None => return self.inner.read(buf),
+        };
+        bucket.acquire();
+        let len = buf.len().min(bucket.tokens.max(1.0) as usize);
+        let read = self.inner.read(&mut buf[..len])?;
+        bucket.tokens -= read as f64;
+        Ok(read)
+    }
+}
+
+impl<S: Read + Write> Write for Throttled<S> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// A handle to abort the command a [`Client`] is running, from another thread
+///
+/// Get one with [`Client::cancellation_token()`]. Once cancelled, the client fails the command with
+/// [`Error::Cancelled`] and closes the connection; a new session is needed after that.
+///
+/// [`Client`]: struct.Client.html
+/// [`Client::cancellation_token()`]: struct.Client.html#method.cancellation_token
+/// [`Error::Cancelled`]: enum.Error.html#variant.Cancelled
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+/// How often a blocked read wakes up to look at the cancellation token
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Gives up reading once the command deadline has passed or the token has been cancelled
+///
+/// Blocked reads are only interrupted on sockets; other streams are checked between reads.
+/// Also counts the octets going each way, for the metrics.
+pub(crate) struct Watched {
+    inner: Stream,
+    pub deadline: Option<Instant>,
+    token: Option<CancellationToken>,
+    timeout_set: bool,
+    pub sent: u64,
+    pub received: u64,
+}
+
+impl Watched {
+    pub fn new(inner: Stream) -> Self {
+        Self {
+            inner,
+            deadline: None,
+            token: None,
+            timeout_set: false,
+            sent: 0,
+            received: 0,
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut Stream {
+        &mut self.inner
+    }
+
+    pub fn token(&mut self) -> CancellationToken {
+        self.token.get_or_insert_with(CancellationToken::default).clone()
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.token.as_ref().is_some_and(CancellationToken::is_cancelled)
+    }
+
+    /// Go on from `old`, which this connection replaces: the token handed out stays valid and the counts add up
+    pub fn take_over(&mut self, old: &mut Watched) {
+        self.token = old.token.take();
+        self.sent += old.sent;
+        self.received += old.received;
+    }
+}
+
+impl Read for Watched {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize> {
+        if self.deadline.is_none() && self.token.is_none() {
+            if self.timeout_set {
+                self.inner.set_read_timeout(None)?;
+                self.timeout_set = false;
+            }
+            let read = self.inner.read(buf)?;
+            self.received += read as u64;
+            return Ok(read);
+        }
+
+        loop {
+            if self.is_cancelled() {
+                return Err(io::Error::other("cancelled"));
+            }
+            let mut step = self.token.as_ref().map(|_| POLL_INTERVAL);
+            if let Some(deadline) = self.deadline {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(io::Error::new(ErrorKind::TimedOut, "command timed out"));
+                }
+                step = Some(step.map_or(deadline - now, |step| step.min(deadline - now)));
+            }
+
+            self.inner.set_read_timeout(step)?;
+            self.timeout_set = true;
+            match self.inner.read(buf) {
+                Err(e) if e.kind() == ErrorKind::WouldBlock || e.kind() == ErrorKind::TimedOut => continue,
+                Err(e) => return Err(e),
+                Ok(read) => {
+                    self.received += read as u64;
+                    return Ok(read);
+                }
+            }
+        }
+    }
+}
+
+impl Write for Watched {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.sent += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.inner.flush()
+    }
+}
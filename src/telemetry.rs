@@ -0,0 +1,268 @@
+//! Metrics and traces for monitoring fetch daemons
+//!
+//! # Metrics
+//!
+//! Counters and histograms go through the [`metrics`] facade. Nothing is recorded unless the crate is built
+//! with the `metrics` feature and a recorder is installed. Every metric carries a `host` label, the host
+//! connected to, and an `account` label, the user name once a login has been tried; both are empty when unknown.
+//!
+//! | Name | Kind | More labels |
+//! |------|------|-------------|
+//! | [`COMMANDS`] | counter | `command`: the keyword, e.g. `RETR` |
+//! | [`LATENCY`] | histogram, in seconds | `command` |
+//! | [`ERRORS`] | counter | `kind`, e.g. `io` or `server` |
+//! | [`SENT`], [`RECEIVED`] | counter, in octets | |
+//! | [`RECONNECTS`] | counter | |
+//!
+//! # Traces
+//!
+//! With the `tracing` feature, sessions opened by a [`Builder`] get a `pop3.session` span, a child of the span
+//! current when connecting, with `host` and `account` fields. Within it, `pop3.connect` covers the connection,
+//! the greeting and `STLS`, and `pop3.auth` the login. Every command gets a `pop3.command` span, a child of the
+//! current span in sessions not opened by a [`Builder`], with the `host`, the `command` keyword, the `msg`
+//! number if any, and, once the reply is in, the `bytes` received, the `duration_ms` and the `status`: `ok`, or
+//! the kind of error as in [`ERRORS`].
+//!
+//! [`metrics`]: https://docs.rs/metrics
+//! [`Builder`]: ../struct.Builder.html
+
+use std::time::{Duration, Instant};
+
+use crate::{Client, Error, Result};
+
+/// Commands sent
+pub const COMMANDS: &str = "pop3_commands_total";
+/// Time from sending a command until its reply has been read
+pub const LATENCY: &str = "pop3_command_duration_seconds";
+/// Commands failed, by kind of [`Error`](../enum.Error.html)
+pub const ERRORS: &str = "pop3_errors_total";
+/// Octets written to the connection
+pub const SENT: &str = "pop3_sent_octets_total";
+/// Octets read from the connection
+pub const RECEIVED: &str = "pop3_received_octets_total";
+/// Connections opened again, after a failed attempt or to replace one found dead
+pub const RECONNECTS: &str = "pop3_reconnects_total";
+
+/// Where a session is connected to, to label its metrics and traces
+#[derive(Debug, Clone, Default)]
+#[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+pub(crate) struct Labels {
+    pub host: String,
+    pub account: String,
+    /// Octets counted by the stream that were already reported, sent and received
+    reported: (u64, u64),
+    pub session: Span,
+    /// The login in progress, which the commands belong to
+    auth: Option<Span>,
+}
+
+impl Labels {
+    /// Also count as reported what `new` reported of the connection taken over by `Watched::take_over()`
+    pub fn take_over(&mut self, new: &Labels) {
+        self.reported.0 += new.reported.0;
+        self.reported.1 += new.reported.1;
+    }
+}
+
+/// A `tracing` span, or nothing without the `tracing` feature
+#[derive(Debug, Clone)]
+#[cfg_attr(not(feature = "tracing"), derive(Default))]
+pub(crate) struct Span(#[cfg(feature = "tracing")] tracing::Span);
+
+#[cfg(feature = "tracing")]
+impl Default for Span {
+    fn default() -> Self {
+        Self(tracing::Span::none())
+    }
+}
+
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+impl Span {
+    /// The span of a whole session, a child of the current one
+    pub fn session(host: &str) -> Self {
+        Self(
+            #[cfg(feature = "tracing")]
+            tracing::info_span!("pop3.session", host, account = tracing::field::Empty),
+        )
+    }
+
+    pub fn connect(&self, port: u16) -> Self {
+        Self(
+            #[cfg(feature = "tracing")]
+            tracing::info_span!(parent: &self.parent(), "pop3.connect", port),
+        )
+    }
+
+    fn auth(&self, mechanism: &str, account: &str) -> Self {
+        #[cfg(feature = "tracing")]
+        self.0.record("account", account);
+        Self(
+            #[cfg(feature = "tracing")]
+            tracing::info_span!(parent: &self.parent(), "pop3.auth", mechanism, account, status = tracing::field::Empty),
+        )
+    }
+
+    fn command(&self, host: &str, command: &str) -> Self {
+        Self(
+            #[cfg(feature = "tracing")]
+            tracing::info_span!(
+                parent: &self.parent(),
+                "pop3.command",
+                host,
+                command = crate::stage::keyword(command).as_str(),
+                msg = message_number(command),
+                bytes = tracing::field::Empty,
+                duration_ms = tracing::field::Empty,
+                status = tracing::field::Empty,
+            ),
+        )
+    }
+
+    /// The parent for the spans within this one; the current span when the session has none
+    #[cfg(feature = "tracing")]
+    fn parent(&self) -> tracing::Span {
+        if self.0.is_none() {
+            tracing::Span::current()
+        } else {
+            self.0.clone()
+        }
+    }
+
+    pub fn in_scope<T, F: FnOnce() -> T>(&self, f: F) -> T {
+        #[cfg(feature = "tracing")]
+        let _entered = self.0.enter();
+        f()
+    }
+
+    fn finish<T>(&self, bytes: u64, duration: Duration, result: &Result<T>) {
+        #[cfg(feature = "tracing")]
+        {
+            self.0.record("bytes", bytes);
+            self.0.record("duration_ms", duration.as_millis() as u64);
+            self.0.record("status", result.as_ref().map_or_else(kind, |_| "ok"));
+        }
+    }
+}
+
+/// The message number of the commands taking one; other arguments, a numeric password included, are left out
+#[cfg(feature = "tracing")]
+fn message_number(command: &str) -> Option<u32> {
+    let mut words = command.split(' ');
+    match words.next()?.to_ascii_uppercase().as_str() {
+        "RETR" | "DELE" | "LIST" | "TOP" | "UIDL" => words.next()?.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+impl Client {
+    /// Count a command as sent
+    pub(crate) fn count_command(&self, command: &str) {
+        #[cfg(feature = "metrics")]
+        metrics::counter!(
+            COMMANDS,
+            "host" => self.labels.host.clone(),
+            "account" => self.labels.account.clone(),
+            "command" => crate::stage::keyword(command)
+        )
+        .increment(1);
+    }
+
+    /// Run `f`, which sends `command` and reads the reply, in a span, and record how long it took and how it failed
+    pub(crate) fn observed<T, F: FnOnce(&mut Self) -> Result<T>>(&mut self, command: &str, f: F) -> Result<T> {
+        self.in_flight.start(command)?;
+        let span = self.labels.auth.as_ref().unwrap_or(&self.labels.session).command(&self.labels.host, command);
+        let start = Instant::now();
+        let (sent, received) = (self.watched().sent, self.watched().received);
+        let result = span.in_scope(|| f(self));
+        self.in_flight.finish();
+        let (sent, received) = (self.watched().sent - sent, self.watched().received - received);
+        span.finish(received, start.elapsed(), &result);
+        if matches!(result, Ok(_) | Err(Error::Server(_))) {
+            self.latencies.record(command, start.elapsed());
+        }
+        self.audit.record(command, start.elapsed(), sent, received, &result);
+
+        #[cfg(feature = "metrics")]
+        {
+            let host = self.labels.host.clone();
+            let account = self.labels.account.clone();
+            metrics::histogram!(
+                LATENCY,
+                "host" => host.clone(),
+                "account" => account.clone(),
+                "command" => crate::stage::keyword(command)
+            )
+            .record(start.elapsed().as_secs_f64());
+            if let Err(e) = &result {
+                metrics::counter!(ERRORS, "host" => host.clone(), "account" => account.clone(), "kind" => kind(e))
+                    .increment(1);
+            }
+
+            let watched = self.watched();
+            let (sent, received) = (watched.sent, watched.received);
+            let (reported_sent, reported_received) = self.labels.reported;
+            metrics::counter!(SENT, "host" => host.clone(), "account" => account.clone())
+                .increment(sent.saturating_sub(reported_sent));
+            metrics::counter!(RECEIVED, "host" => host, "account" => account)
+                .increment(received.saturating_sub(reported_received));
+            self.labels.reported = (sent, received);
+        }
+        result
+    }
+
+    /// Run the login `f` as `account` in a `pop3.auth` span
+    pub(crate) fn authenticating<T, F: FnOnce(&mut Self) -> Result<T>>(
+        &mut self,
+        mechanism: &str,
+        account: &str,
+        f: F,
+    ) -> Result<T> {
+        self.labels.account = account.to_string();
+        let span = self.labels.session.auth(mechanism, account);
+        self.labels.auth = Some(span.clone());
+        let result = span.in_scope(|| f(self));
+        self.labels.auth = None;
+        #[cfg(feature = "tracing")]
+        span.0.record("status", result.as_ref().map_or_else(kind, |_| "ok"));
+        result
+    }
+}
+
+/// Count a connection opened again
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+pub(crate) fn reconnected(host: &str, account: &str) {
+    #[cfg(feature = "metrics")]
+    metrics::counter!(RECONNECTS, "host" => host.to_string(), "account" => account.to_string()).increment(1);
+}
+
+/// The `kind` label of an error: the variant name in snake case
+#[cfg_attr(not(any(feature = "metrics", feature = "tracing")), allow(dead_code))]
+fn kind(e: &Error) -> &'static str {
+    match e {
+        Error::Io(_)                  => "io",
+        Error::ConnectionAborted      => "connection_aborted",
+        Error::Server(_)              => "server",
+        Error::Auth(_)                => "auth",
+        Error::MailboxLocked { .. }   => "mailbox_locked",
+        Error::LoginDelay { .. }      => "login_delay",
+        Error::InvalidReply(_)        => "invalid_reply",
+        Error::WrongStage(_)          => "wrong_stage",
+        Error::InvalidArgument(_)     => "invalid_argument",
+        Error::InvalidMessageNumber(_) => "invalid_message_number",
+        Error::Encoding(_)            => "encoding",
+        Error::Tls(_)                 => "tls",
+        Error::Store(_)               => "store",
+        Error::ResponseTooLarge(_)    => "response_too_large",
+        Error::LineTooLong(_)         => "line_too_long",
+        Error::Cancelled              => "cancelled",
+        Error::SizeMismatch(..)       => "size_mismatch",
+        Error::UnexpectedGreeting(_)  => "unexpected_greeting",
+        Error::Sasl(_)                => "sasl",
+        Error::PlaintextAuth(_)       => "plaintext_auth",
+        Error::Config(_)              => "config",
+        Error::Parse { .. }           => "parse",
+        Error::NotSupportedByServer(_) => "not_supported_by_server",
+        Error::CommandInFlight(_)     => "command_in_flight",
+    }
+}
@@ -0,0 +1,400 @@
+//! An in-memory POP3 server for testing code built on the client
+//!
+//! [`FakeServer`] is a transport: hand it to [`Builder::from_stream()`] and it answers the commands the
+//! client writes, without any socket involved. Logging in with a password takes
+//! [`Builder::allow_plaintext_auth()`], as the stream has no TLS.
+//!
+//! # Example
+//!
+//! ```
+//! use pop3_client::Builder;
+//! use pop3_client::test_util::{FakeServer, Message};
+//! # fn main() -> pop3_client::Result<()> {
+//!
+//! let server = FakeServer::new(vec![Message::new("uid-1", b"Subject: hi\r\n\r\nHello\r\n")]);
+//! let mut client = Builder::default().allow_plaintext_auth(true).from_stream(server)?;
+//! client.login("user", "password")?;
+//! assert_eq!(client.stat()?, (1, 22));
+//! #    Ok(())
+//! # }
+//! ```
+//!
+//! [`Faulty`] wraps a transport, such as a `FakeServer`, to make it slow, split its traffic in small pieces or
+//! reset the connection at a set point, so retry and timeout logic can be tested deterministically.
+//!
+//! [`Builder::from_stream()`]: ../struct.Builder.html#method.from_stream
+//! [`Builder::allow_plaintext_auth()`]: ../struct.Builder.html#method.allow_plaintext_auth
+//! [`Faulty`]: struct.Faulty.html
+
+use std::io::{self, Read, Write};
+use std::thread;
+use std::time::Duration;
+
+/// A message held by a [`FakeServer`]
+///
+/// [`FakeServer`]: struct.FakeServer.html
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    pub uid: String,
+    /// The message as lines terminated by CRLF, before byte-stuffing
+    pub content: Vec<u8>,
+}
+
+impl Message {
+    pub fn new(uid: &str, content: &[u8]) -> Self {
+        Self {
+            uid: uid.to_string(),
+            content: content.to_vec(),
+        }
+    }
+
+    fn lines(&self) -> impl Iterator<Item = &[u8]> {
+        self.content
+            .split_inclusive(|c| *c == b'\n')
+            .map(|line| line.strip_suffix(b"\r\n").unwrap_or(line))
+    }
+}
+
+/// A maildrop served over an in-memory stream, following [RFC 1939]
+///
+/// Understands `USER`, `PASS`, `APOP` (accepting any digest), `STAT`, `LIST`, `UIDL`, `RETR`, `TOP`,
+/// `DELE`, `RSET`, `NOOP`, `CAPA` and `QUIT`. Deleted messages are only removed by `QUIT`, as on a real
+/// server; [`messages()`] tells what would remain.
+///
+/// [RFC 1939]: https://tools.ietf.org/html/rfc1939
+/// [`messages()`]: #method.messages
+pub struct FakeServer {
+    messages: Vec<Message>,
+    deleted: Vec<bool>,
+    username: Option<String>,
+    password: Option<String>,
+    user: Option<String>,
+    authorized: bool,
+    closed: bool,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    read_pos: usize,
+}
+
+impl FakeServer {
+    /// A server accepting any credentials
+    pub fn new(messages: Vec<Message>) -> Self {
+        let deleted = vec![false; messages.len()];
+        Self {
+            messages,
+            deleted,
+            username: None,
+            password: None,
+            user: None,
+            authorized: false,
+            closed: false,
+            input: vec![],
+            output: b"+OK fake POP3 server ready <1.1@fake.invalid>\r\n".to_vec(),
+            read_pos: 0,
+        }
+    }
+
+    /// Only accept these credentials for `USER`/`PASS`
+    pub fn with_credentials(mut self, username: &str, password: &str) -> Self {
+        self.username = Some(username.to_string());
+        self.password = Some(password.to_string());
+        self
+    }
+
+    /// The messages as they are now, without the ones removed by `QUIT`
+    pub fn messages(&self) -> &[Message] {
+        &self.messages
+    }
+
+    fn reply(&mut self, line: &str) {
+        self.output.extend_from_slice(line.as_bytes());
+        self.output.extend_from_slice(b"\r\n");
+    }
+
+    fn reply_lines<'a, I: IntoIterator<Item = &'a [u8]>>(&mut self, status: &str, lines: I) {
+        self.reply(status);
+        for line in lines {
+            if line.starts_with(b".") {
+                self.output.push(b'.');
+            }
+            self.output.extend_from_slice(line);
+            self.output.extend_from_slice(b"\r\n");
+        }
+        self.output.extend_from_slice(b".\r\n");
+    }
+
+    /// Index of a message that exists and isn't deleted
+    fn message(&self, arg: Option<&str>) -> Option<usize> {
+        let index = arg?.parse::<usize>().ok()?.checked_sub(1)?;
+        Some(index).filter(|i| *i < self.messages.len() && !self.deleted[*i])
+    }
+
+    fn listing<F: Fn(&Message) -> String>(&mut self, arg: Option<&str>, entry: F) {
+        match arg {
+            None => {
+                let lines: Vec<Vec<u8>> = (0..self.messages.len())
+                    .filter(|i| !self.deleted[*i])
+                    .map(|i| format!("{} {}", i + 1, entry(&self.messages[i])).into_bytes())
+                    .collect();
+                self.reply_lines("+OK", lines.iter().map(Vec::as_slice));
+            }
+            Some(_) => match self.message(arg) {
+                Some(i) => {
+                    let line = format!("+OK {} {}", i + 1, entry(&self.messages[i]));
+                    self.reply(&line)
+                }
+                None => self.reply("-ERR no such message"),
+            },
+        }
+    }
+
+    fn handle(&mut self, line: &[u8]) {
+        let line = String::from_utf8_lossy(line);
+        let mut args = line.split(' ');
+        let command = args.next().unwrap_or("").to_ascii_uppercase();
+        let arg = args.next();
+
+        match (self.authorized, command.as_str()) {
+            (_, "NOOP") => self.reply("+OK"),
+            (_, "CAPA") => self.reply_lines("+OK", vec![&b"TOP"[..], b"UIDL", b"USER", b"PIPELINING"]),
+            (_, "QUIT") => {
+                if self.authorized {
+                    let mut deleted = self.deleted.iter();
+                    self.messages.retain(|_| !deleted.next().copied().unwrap_or(false));
+                    self.deleted = vec![false; self.messages.len()];
+                }
+                self.closed = true;
+                self.reply("+OK bye");
+            }
+            (false, "USER") => {
+                self.user = arg.map(str::to_string);
+                self.reply("+OK");
+            }
+            (false, "PASS") => {
+                let password = line.split_once(' ').map(|(_, password)| password);
+                let valid = self.username.is_none()
+                    || (self.user.as_deref() == self.username.as_deref() && password == self.password.as_deref());
+                if self.user.is_some() && valid {
+                    self.authorized = true;
+                    self.reply("+OK maildrop locked and ready");
+                } else {
+                    self.reply("-ERR [AUTH] invalid credentials");
+                }
+            }
+            (false, "APOP") => {
+                self.authorized = true;
+                self.reply("+OK maildrop locked and ready");
+            }
+            (true, "STAT") => {
+                let (count, size) = (0..self.messages.len())
+                    .filter(|i| !self.deleted[*i])
+                    .fold((0, 0), |(count, size), i| (count + 1, size + self.messages[i].content.len()));
+                self.reply(&format!("+OK {} {}", count, size));
+            }
+            (true, "LIST") => self.listing(arg, |m| m.content.len().to_string()),
+            (true, "UIDL") => self.listing(arg, |m| m.uid.clone()),
+            (true, "RETR") => match self.message(arg) {
+                Some(i) => {
+                    let message = self.messages[i].clone();
+                    let status = format!("+OK {} octets", message.content.len());
+                    self.reply_lines(&status, message.lines());
+                }
+                None => self.reply("-ERR no such message"),
+            },
+            (true, "TOP") => match (self.message(arg), args.next().and_then(|n| n.parse::<usize>().ok())) {
+                (Some(i), Some(n)) => {
+                    let message = self.messages[i].clone();
+                    let mut lines = message.lines();
+                    let mut top: Vec<&[u8]> = lines.by_ref().take_while(|line| !line.is_empty()).collect();
+                    if top.len() < message.lines().count() {
+                        top.push(b"");
+                    }
+                    top.extend(lines.take(n));
+                    self.reply_lines("+OK", top);
+                }
+                _ => self.reply("-ERR no such message"),
+            },
+            (true, "DELE") => match self.message(arg) {
+                Some(i) => {
+                    self.deleted[i] = true;
+                    self.reply("+OK message deleted");
+                }
+                None => self.reply("-ERR no such message"),
+            },
+            (true, "RSET") => {
+                self.deleted = vec![false; self.messages.len()];
+                self.reply("+OK");
+            }
+            _ => self.reply("-ERR unknown command or wrong stage"),
+        }
+    }
+}
+
+impl Read for FakeServer {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let pending = &self.output[self.read_pos..];
+        let len = pending.len().min(buf.len());
+        buf[..len].copy_from_slice(&pending[..len]);
+        self.read_pos += len;
+        if self.read_pos == self.output.len() {
+            self.output.clear();
+            self.read_pos = 0;
+        }
+        Ok(len)
+    }
+}
+
+impl Write for FakeServer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.closed {
+            return Err(io::ErrorKind::NotConnected.into());
+        }
+        self.input.extend_from_slice(buf);
+        while let Some(end) = self.input.windows(2).position(|w| w == b"\r\n") {
+            let line: Vec<u8> = self.input.drain(..end + 2).take(end).collect();
+            self.handle(&line);
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A transport misbehaving the way networks do, for [`Builder::from_stream()`]
+///
+/// Every fault is off until set, and they all happen at the same points on every run.
+///
+/// # Example
+///
+/// ```
+/// use pop3_client::test_util::{Faulty, FakeServer, Message};
+/// use pop3_client::Builder;
+/// # fn main() -> pop3_client::Result<()> {
+///
+/// let server = FakeServer::new(vec![Message::new("uid-1", b"Subject: hi\r\n\r\nHello\r\n")]);
+/// let transport = Faulty::new(server).read_chunk(3).write_chunk(2).reset_after_commands(3);
+/// let mut client = Builder::default().allow_plaintext_auth(true).from_stream(transport)?;
+/// client.login("user", "password")?;
+/// assert!(client.retr(1).unwrap_err().is_transient());
+/// #    Ok(())
+/// # }
+/// ```
+///
+/// [`Builder::from_stream()`]: ../struct.Builder.html#method.from_stream
+pub struct Faulty<S: Read + Write> {
+    inner: S,
+    latency: Duration,
+    read_chunk: Option<usize>,
+    write_chunk: Option<usize>,
+    reset_after_read: Option<u64>,
+    reset_after_commands: Option<usize>,
+    read: u64,
+    commands: usize,
+    reset: bool,
+}
+
+impl<S: Read + Write> Faulty<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            latency: Duration::ZERO,
+            read_chunk: None,
+            write_chunk: None,
+            reset_after_read: None,
+            reset_after_commands: None,
+            read: 0,
+            commands: 0,
+            reset: false,
+        }
+    }
+
+    /// Wait this long before every read, e.g. to run into a command timeout
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Hand out at most `octets` per read, as TCP segments split in the middle of lines do
+    pub fn read_chunk(mut self, octets: usize) -> Self {
+        self.read_chunk = Some(octets.max(1));
+        self
+    }
+
+    /// Take at most `octets` per write, leaving the caller to write the rest
+    pub fn write_chunk(mut self, octets: usize) -> Self {
+        self.write_chunk = Some(octets.max(1));
+        self
+    }
+
+    /// Reset the connection once `octets` have been read, greeting included
+    pub fn reset_after_read(mut self, octets: u64) -> Self {
+        self.reset_after_read = Some(octets);
+        self
+    }
+
+    /// Reset the connection once `n` commands have been sent, so the reply to the last one never arrives
+    pub fn reset_after_commands(mut self, n: usize) -> Self {
+        self.reset_after_commands = Some(n);
+        self
+    }
+
+    /// Whether the connection has been reset
+    pub fn is_reset(&self) -> bool {
+        self.reset
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+
+    fn check(&self) -> io::Result<()> {
+        if self.reset {
+            return Err(io::ErrorKind::ConnectionReset.into());
+        }
+        Ok(())
+    }
+}
+
+impl<S: Read + Write> Read for Faulty<S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.check()?;
+        if !self.latency.is_zero() {
+            thread::sleep(self.latency);
+        }
+        let mut len = self.read_chunk.map_or(buf.len(), |chunk| chunk.min(buf.len()));
+        if let Some(limit) = self.reset_after_read {
+            if self.read >= limit {
+                self.reset = true;
+                return self.check().map(|_| 0);
+            }
+            len = len.min((limit - self.read) as usize);
+        }
+        let read = self.inner.read(&mut buf[..len])?;
+        self.read += read as u64;
+        Ok(read)
+    }
+}
+
+impl<S: Read + Write> Write for Faulty<S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.check()?;
+        let len = self.write_chunk.map_or(buf.len(), |chunk| chunk.min(buf.len()));
+        let written = self.inner.write(&buf[..len])?;
+        self.commands += buf[..written].iter().filter(|c| **c == b'\n').count();
+        if self.reset_after_commands.is_some_and(|n| self.commands >= n) {
+            self.reset = true;
+        }
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.check()?;
+        self.inner.flush()
+    }
+}
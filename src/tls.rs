@@ -0,0 +1,271 @@
+use std::sync::{Arc, OnceLock};
+
+use rustls::internal::pemfile;
+use rustls::{Certificate, ClientConfig, NoClientSessionStorage, ProtocolVersion, RootCertStore, Session};
+use sha2::{Digest, Sha256, Sha384, Sha512};
+
+use crate::stream::Stream;
+use crate::{Builder, Client, Error, Result};
+
+/// Whether [`Builder::connect()`] upgrades the connection with `STLS`
+///
+/// [`Builder::connect()`]: struct.Builder.html#method.connect
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TlsPolicy {
+    /// Upgrade, and fail if the server refuses `STLS`
+    #[default]
+    Required,
+    /// Upgrade if the server agrees to, and stay on plain text otherwise; see [`Client::is_tls()`]
+    ///
+    /// Only a refused `STLS` falls back: a failing handshake is still an error, as it may be an attack.
+    ///
+    /// [`Client::is_tls()`]: struct.Client.html#method.is_tls
+    Opportunistic,
+    /// Stay on plain text
+    Never,
+}
+
+/// A TLS protocol version, see [`Builder::min_tls_version()`]
+///
+/// Only the versions rustls speaks; older ones are never negotiated.
+///
+/// [`Builder::min_tls_version()`]: struct.Builder.html#method.min_tls_version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TlsVersion {
+    Tls1_2,
+    Tls1_3,
+}
+
+impl TlsVersion {
+    const ALL: [TlsVersion; 2] = [TlsVersion::Tls1_2, TlsVersion::Tls1_3];
+
+    fn protocol(self) -> ProtocolVersion {
+        match self {
+            TlsVersion::Tls1_2 => ProtocolVersion::TLSv1_2,
+            TlsVersion::Tls1_3 => ProtocolVersion::TLSv1_3,
+        }
+    }
+}
+
+/// The config of `Builder::default()`, trusting the Mozilla roots
+///
+/// It is built once and shared, along with its session cache, so connections from any builder can resume
+/// the TLS sessions of earlier ones.
+pub(crate) fn default_config() -> Arc<ClientConfig> {
+    static CONFIG: OnceLock<Arc<ClientConfig>> = OnceLock::new();
+    CONFIG
+        .get_or_init(|| {
+            let mut config = ClientConfig::new();
+            config
+                .root_store
+                .add_server_trust_anchors(&webpki_roots::TLS_SERVER_ROOTS);
+            Arc::new(config)
+        })
+        .clone()
+}
+
+/// A copy of `config` neither resuming sessions nor remembering them, see `Builder::tls_resumption()`
+pub(crate) fn without_resumption(config: &ClientConfig) -> Arc<ClientConfig> {
+    let mut config = config.clone();
+    config.session_persistence = Arc::new(NoClientSessionStorage {});
+    config.enable_tickets = false;
+    Arc::new(config)
+}
+
+impl Builder {
+    /// Trust the CA certificate `cert`, DER or PEM encoded, besides the roots trusted already
+    ///
+    /// A PEM file may hold several certificates; all of them are trusted. Call [`no_default_roots()`] first to
+    /// trust an internal CA only.
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::Builder;
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let ca = std::fs::read("/etc/pki/internal-ca.pem").map_err(pop3_client::Error::Io)?;
+    /// let client = Builder::default()
+    ///     .no_default_roots()
+    ///     .add_root_certificate(&ca)?
+    ///     .connect("mail.internal", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    /// # Errors
+    /// Fails with [`Error::Tls`] when `cert` can't be parsed, leaving the trusted roots as they were.
+    ///
+    /// [`no_default_roots()`]: struct.Builder.html#method.no_default_roots
+    /// [`Error::Tls`]: enum.Error.html#variant.Tls
+    pub fn add_root_certificate(&mut self, cert: &[u8]) -> Result<&mut Self> {
+        let certs = if cert.starts_with(b"-----BEGIN") {
+            pemfile::certs(&mut &cert[..])
+                .ok()
+                .filter(|certs| !certs.is_empty())
+                .ok_or_else(|| Error::Tls("no certificate in the PEM data".to_string()))?
+        } else {
+            vec![Certificate(cert.to_vec())]
+        };
+        let mut roots = self.config.root_store.clone();
+        for cert in &certs {
+            roots.add(cert).map_err(|e| Error::Tls(format!("invalid root certificate: {}", e)))?;
+        }
+        Arc::make_mut(&mut self.config).root_store = roots;
+        Ok(self)
+    }
+
+    /// Trust the certificates of the OS store as well, e.g. those an administrator installed
+    ///
+    /// # Errors
+    /// Fails with [`Error::Tls`] when the store can't be read at all; certificates of the store which can't be
+    /// parsed are skipped.
+    ///
+    /// [`Error::Tls`]: enum.Error.html#variant.Tls
+    #[cfg(feature = "native-roots")]
+    pub fn with_native_roots(&mut self) -> Result<&mut Self> {
+        let native = match rustls_native_certs::load_native_certs() {
+            Ok(store) | Err((Some(store), _)) => store,
+            Err((None, e)) => return Err(Error::Tls(format!("native roots: {}", e))),
+        };
+        Arc::make_mut(&mut self.config).root_store.roots.extend(native.roots);
+        Ok(self)
+    }
+
+    /// Stop trusting the roots trusted so far, the Mozilla ones of `Builder::default()` among them
+    ///
+    /// Add the roots to trust afterwards, with [`add_root_certificate()`] or [`with_native_roots()`]: with none,
+    /// every TLS connection fails.
+    ///
+    /// [`add_root_certificate()`]: struct.Builder.html#method.add_root_certificate
+    /// [`with_native_roots()`]: struct.Builder.html#method.with_native_roots
+    pub fn no_default_roots(&mut self) -> &mut Self {
+        Arc::make_mut(&mut self.config).root_store = RootCertStore::empty();
+        self
+    }
+
+    /// Refuse TLS versions older than `version`; TLS 1.2 by default
+    ///
+    /// Like the other TLS settings, this changes the current config: call it after [`rustls_config()`]. A
+    /// minimum above the [`max_tls_version()`] leaves no version, and connecting fails with [`Error::Tls`].
+    ///
+    /// # Example
+    /// ```no_run
+    /// # use pop3_client::{Builder, TlsVersion};
+    /// # fn main() -> pop3_client::Result<()> {
+    /// let client = Builder::default().min_tls_version(TlsVersion::Tls1_3).connect("my.host.com", 110)?;
+    /// #    Ok(())
+    /// # }
+    /// ```
+    ///
+    /// [`rustls_config()`]: struct.Builder.html#method.rustls_config
+    /// [`max_tls_version()`]: struct.Builder.html#method.max_tls_version
+    /// [`Error::Tls`]: enum.Error.html#variant.Tls
+    pub fn min_tls_version(&mut self, version: TlsVersion) -> &mut Self {
+        self.tls_versions.0 = version;
+        self.apply_tls_versions()
+    }
+
+    /// Refuse TLS versions newer than `version`, e.g. for middleboxes choking on TLS 1.3; TLS 1.3 by default
+    ///
+    /// See [`min_tls_version()`].
+    ///
+    /// [`min_tls_version()`]: struct.Builder.html#method.min_tls_version
+    pub fn max_tls_version(&mut self, version: TlsVersion) -> &mut Self {
+        self.tls_versions.1 = version;
+        self.apply_tls_versions()
+    }
+
+    /// Offer these ALPN protocols in the handshake, e.g. `"pop3"`; none by default
+    ///
+    /// Servers knowing ALPN refuse the connection when none of them matches, which keeps a man in the middle
+    /// from redirecting it to another protocol served with the same certificate.
+    pub fn alpn_protocols(&mut self, protocols: &[&str]) -> &mut Self {
+        let protocols: Vec<Vec<u8>> = protocols.iter().map(|protocol| protocol.as_bytes().to_vec()).collect();
+        Arc::make_mut(&mut self.config).set_protocols(&protocols);
+        self
+    }
+
+    fn apply_tls_versions(&mut self) -> &mut Self {
+        let (min, max) = self.tls_versions;
+        Arc::make_mut(&mut self.config).versions = TlsVersion::ALL
+            .iter()
+            .filter(|version| (min..=max).contains(*version))
+            // Newest first, the order rustls prefers them in
+            .rev()
+            .map(|version| version.protocol())
+            .collect();
+        self
+    }
+}
+
+impl Stream {
+    /// The end-entity certificate of the server, DER encoded
+    fn peer_certificate(&mut self) -> Option<Vec<u8>> {
+        match self {
+            Stream::Tls(s) => s.sess.get_peer_certificates()?.first().map(|cert| cert.0.clone()),
+            #[cfg(feature = "record")]
+            Stream::Recorded(s) => s.get_mut().peer_certificate(),
+            _ => None,
+        }
+    }
+}
+
+impl Client {
+    /// The TLS version negotiated, `None` without TLS
+    ///
+    /// The handshake completes with the first command after `STLS`; until then, this is `None` too.
+    pub fn tls_version(&mut self) -> Option<TlsVersion> {
+        let version = match self.stream() {
+            Stream::Tls(s) => s.sess.get_protocol_version()?,
+            _ => return None,
+        };
+        TlsVersion::ALL.iter().copied().find(|known| known.protocol() == version)
+    }
+
+    /// Channel binding data of the `tls-server-end-point` type of [RFC 5929], `None` without TLS
+    ///
+    /// [RFC 5929]: https://tools.ietf.org/html/rfc5929#section-4
+    #[cfg_attr(not(feature = "scram"), allow(dead_code))]
+    pub(crate) fn tls_server_end_point(&mut self) -> Option<Vec<u8>> {
+        let cert = self.stream().peer_certificate()?;
+        // The hash of the certificate signature, but SHA-256 instead of MD5 and SHA-1
+        Some(match signature_algorithm(&cert) {
+            Some(oid) if SHA384_SIGNATURES.contains(&oid) => Sha384::digest(&cert).to_vec(),
+            Some(oid) if SHA512_SIGNATURES.contains(&oid) => Sha512::digest(&cert).to_vec(),
+            _ => Sha256::digest(&cert).to_vec(),
+        })
+    }
+}
+
+/// `sha384WithRSAEncryption` and `ecdsa-with-SHA384`
+const SHA384_SIGNATURES: [&[u8]; 2] = [
+    &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0c],
+    &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x03],
+];
+
+/// `sha512WithRSAEncryption` and `ecdsa-with-SHA512`
+const SHA512_SIGNATURES: [&[u8]; 2] = [
+    &[0x2a, 0x86, 0x48, 0x86, 0xf7, 0x0d, 0x01, 0x01, 0x0d],
+    &[0x2a, 0x86, 0x48, 0xce, 0x3d, 0x04, 0x03, 0x04],
+];
+
+/// The OID of the `signatureAlgorithm` of a DER certificate: the second element of the outer sequence
+fn signature_algorithm(cert: &[u8]) -> Option<&[u8]> {
+    let (_, certificate) = der_element(cert)?;
+    let (len, _) = der_element(certificate)?;
+    let (_, algorithm) = der_element(&certificate[len..])?;
+    let (_, oid) = der_element(algorithm)?;
+    Some(oid)
+}
+
+/// The full length of the DER element at the start of `data`, and its content
+fn der_element(data: &[u8]) -> Option<(usize, &[u8])> {
+    let first = *data.get(1)?;
+    let (header, len) = if first < 0x80 {
+        (2, first as usize)
+    } else {
+        let octets = (first & 0x7f) as usize;
+        let len = data.get(2..2 + octets)?.iter().fold(0usize, |len, b| len << 8 | *b as usize);
+        (2 + octets, len)
+    };
+    let content = data.get(header..header + len)?;
+    Some((header + len, content))
+}
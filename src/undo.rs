@@ -0,0 +1,53 @@
+use crate::{Client, Error, Result};
+
+/// Whether deletions are pending and a failure since calls for `RSET`, see `Builder::undo_deletes_on_error()`
+#[derive(Default)]
+pub(crate) struct UndoDeletes {
+    pub enabled: bool,
+    /// `DELE` was sent since the last `RSET`
+    deletions: bool,
+    /// Something failed since the first of those deletions
+    failed: bool,
+    /// `QUIT` was sent, so dropping the client has nothing left to undo
+    pub quitting: bool,
+}
+
+impl UndoDeletes {
+    /// Follow the deletions through the keyword of each command sent
+    pub fn sent(&mut self, keyword: &str) {
+        match keyword {
+            "DELE" => self.deletions = true,
+            "RSET" => {
+                self.deletions = false;
+                self.failed = false;
+            }
+            _ => (),
+        }
+    }
+
+    /// Take note of a failure, unless it is an `-ERR` reply, which the caller got to handle
+    pub fn saw<T>(&mut self, result: &Result<T>) {
+        if self.deletions && matches!(result, Err(e) if !matches!(e, Error::Server(_))) {
+            self.failed = true;
+        }
+    }
+
+    /// Whether messages are marked as deleted, which a new session would forget
+    pub fn pending(&self) -> bool {
+        self.deletions
+    }
+
+    /// Whether `RSET` has to go before `QUIT`
+    pub fn due(&self) -> bool {
+        self.enabled && self.deletions && self.failed
+    }
+}
+
+impl Drop for Client {
+    /// Send `RSET` without waiting for the reply, when messages are marked as deleted and the session was not quit
+    fn drop(&mut self) {
+        if self.undo_deletes.enabled && self.undo_deletes.deletions && !self.undo_deletes.quitting {
+            self.queue("RSET").and_then(|_| self.flush()).ok();
+        }
+    }
+}
@@ -1,205 +1,3642 @@
 #[cfg(test)]
 mod tests {
-    use pop3_client::{Client, Result};
+    use pop3_client::{Client, Error, Result};
 
-    #[cfg(not(feature = "with-rustls"))]
-    fn connect() -> Result<Client> {
-        Client::connect("pop3.mailtrap.io", 1100)
+    /// A real server to run the network tests against, given by the environment
+    ///
+    /// The network tests are ignored unless asked for with `--ignored` or `--include-ignored`, which requires:
+    ///
+    /// - `POP3_TEST_SERVER`: `host:port` of the POP3 server, e.g. `localhost:3110` for the GreenMail container of
+    ///   `tests/docker-compose.yml`
+    /// - `POP3_TEST_USERNAME` and `POP3_TEST_PASSWORD`: an account on it, `pop3test` for both by default
+    /// - `POP3_TEST_TLS`: `1` for TLS on connect, with the `with-rustls` feature
+    /// - `POP3_TEST_SMTP`: `host:port` of an SMTP server delivering to the account, to seed it with a few
+    ///   messages first
+    struct LiveServer {
+        host: String,
+        port: u16,
+        tls: bool,
+        username: String,
+        password: String,
     }
 
-    #[cfg(feature = "with-rustls")]
-    fn connect() -> Result<Client> {
-        pop3_client::Builder::default().connect("pop3.mailtrap.io", 1100)
+    fn live() -> LiveServer {
+        let var = |name: &str, default: &str| std::env::var(name).unwrap_or_else(|_| default.to_string());
+        let server = std::env::var("POP3_TEST_SERVER").expect("POP3_TEST_SERVER is set for the ignored tests");
+        let (host, port) = server.rsplit_once(':').expect("POP3_TEST_SERVER is host:port");
+        let server = LiveServer {
+            host: host.to_string(),
+            port: port.parse().expect("POP3_TEST_SERVER is host:port"),
+            tls: var("POP3_TEST_TLS", "0") == "1",
+            username: var("POP3_TEST_USERNAME", "pop3test"),
+            password: var("POP3_TEST_PASSWORD", "pop3test"),
+        };
+        if let Ok(smtp) = std::env::var("POP3_TEST_SMTP") {
+            static SEEDED: std::sync::Once = std::sync::Once::new();
+            SEEDED.call_once(|| seed(&smtp, &server.username));
+        }
+        server
+    }
+
+    impl LiveServer {
+        fn builder(&self) -> pop3_client::Builder {
+            let mut builder = pop3_client::Builder::default();
+            builder.allow_plaintext_auth(true);
+            #[cfg(feature = "with-rustls")]
+            builder.tls(self.tls);
+            builder
+        }
+
+        fn connect(&self) -> Client {
+            self.connect_with(&mut self.builder())
+        }
+
+        fn connect_with(&self, builder: &mut pop3_client::Builder) -> Client {
+            builder.connect(&self.host, self.port).unwrap()
+        }
+
+        fn login(&self, client: &mut Client) {
+            client.login(&self.username, &self.password).unwrap()
+        }
+
+        fn account(&self) -> pop3_client::fetcher::AccountConfig {
+            pop3_client::fetcher::AccountConfig {
+                host:                 self.host.clone(),
+                port:                 self.port,
+                tls:                  self.tls,
+                allow_plaintext_auth: true,
+                credentials:          pop3_client::fetcher::Credentials::new(&self.username, &self.password),
+                ..Default::default()
+            }
+        }
+    }
+
+    /// Deliver a few messages to `username` over SMTP
+    fn seed(smtp: &str, username: &str) {
+        use std::io::{BufRead, Write};
+
+        let socket = std::net::TcpStream::connect(smtp).expect("POP3_TEST_SMTP accepts connections");
+        let mut replies = std::io::BufReader::new(socket.try_clone().unwrap());
+        let mut socket = socket;
+        let mut expect = |code: &str| loop {
+            let mut line = String::new();
+            replies.read_line(&mut line).unwrap();
+            assert!(line.starts_with(code), "SMTP: expected {}, got {:?}", code, line);
+            if line.as_bytes().get(3) != Some(&b'-') {
+                break;
+            }
+        };
+        expect("220");
+        socket.write_all(b"HELO localhost\r\n").unwrap();
+        expect("250");
+        for i in 1..=3 {
+            let message = format!(
+                "From: seed@localhost\r\nTo: {0}@localhost\r\nSubject: Seed {1}\r\n\r\nMessage {1}\r\n.leading dot\r\n.\r\n",
+                username, i
+            );
+            socket.write_all(b"MAIL FROM:<seed@localhost>\r\n").unwrap();
+            expect("250");
+            socket.write_all(format!("RCPT TO:<{}@localhost>\r\n", username).as_bytes()).unwrap();
+            expect("250");
+            socket.write_all(b"DATA\r\n").unwrap();
+            expect("354");
+            socket.write_all(message.replace("\r\n.leading", "\r\n..leading").as_bytes()).unwrap();
+            expect("250");
+        }
+        socket.write_all(b"QUIT\r\n").unwrap();
+    }
+
+    /// Replays canned server replies, and records the commands sent
+    struct Scripted {
+        replies: std::io::Cursor<Vec<u8>>,
+        sent: std::sync::Arc<std::sync::Mutex<Vec<u8>>>,
+    }
+
+    impl std::io::Read for Scripted {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.replies.read(buf)
+        }
+    }
+
+    impl std::io::Write for Scripted {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.sent.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn scripted(replies: &str) -> (Client, std::sync::Arc<std::sync::Mutex<Vec<u8>>>) {
+        scripted_with_greeting("+OK ready", replies)
+    }
+
+    fn scripted_with_greeting(greeting: &str, replies: &str) -> (Client, std::sync::Arc<std::sync::Mutex<Vec<u8>>>) {
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let stream = Scripted {
+            replies: std::io::Cursor::new(format!("{}\r\n{}", greeting, replies).into_bytes()),
+            sent: sent.clone(),
+        };
+        (plain().from_stream(stream).unwrap(), sent)
+    }
+
+    /// A builder for in-memory servers, which may see the password
+    fn plain() -> pop3_client::Builder {
+        let mut builder = pop3_client::Builder::default();
+        builder.allow_plaintext_auth(true);
+        builder
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn connects() {
-        assert!(connect().is_ok());
+        let server = live();
+        server.connect();
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn login_success() {
-        let mut client = connect().unwrap();
-        let result = client.login("e913202b66b623", "1ddf1a9bd7fc45");
+        let server = live();
+        let mut client = server.connect();
+        let result = client.login(&server.username, &server.password);
         eprintln!("login_success: {:?}", result);
         assert!(result.is_ok())
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn login_wrong_login() {
-        let mut client = connect().unwrap();
-        let result = client.login("e913202b66b62", "1ddf1a9bd7fc45");
+        let server = live();
+        let mut client = server.connect();
+        let result = client.login(&format!("{}-nobody", server.username), &server.password);
         eprintln!("wrong_login: {:?}", result);
         assert!(result.is_err());
-        assert_ne!(result.unwrap_err(), "Connection aborted".to_owned())
+        assert!(!matches!(result.unwrap_err(), Error::ConnectionAborted))
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn login_wrong_password() {
-        let mut client = connect().unwrap();
-        let result = client.login("e913202b66b623", "1ddf1a9bd7fc4");
+        let server = live();
+        let mut client = server.connect();
+        let result = client.login(&server.username, &format!("{}-wrong", server.password));
         eprintln!("wrong_password: {:?}", result);
         assert!(result.is_err());
-        assert_ne!(result.unwrap_err(), "Connection aborted".to_owned())
+        assert!(!matches!(result.unwrap_err(), Error::ConnectionAborted))
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn login_wrong_stage() {
-        let mut client = connect().unwrap();
-        client.login("e913202b66b623", "1ddf1a9bd7fc45").ok();
-        let result = client.login("e913202b66b623", "1ddf1a9bd7fc45");
+        let server = live();
+        let mut client = server.connect();
+        server.login(&mut client);
+        let result = client.login(&server.username, &server.password);
         eprintln!("login_wrong_stage: {:?}", result);
         assert!(result.is_err());
-        assert_ne!(result.unwrap_err(), "Connection aborted".to_owned())
+        assert!(!matches!(result.unwrap_err(), Error::ConnectionAborted))
     }
 
     // This test will fail if the server implementation does not comply to specification
     #[test]
-    #[ignore]
+    #[ignore = "needs POP3_TEST_SERVER locking maildrops, which GreenMail doesn't"]
     fn login_already_locked() {
-        connect()
-            .unwrap()
-            .login("e913202b66b623", "1ddf1a9bd7fc45")
-            .ok();
-        let mut client = connect().unwrap();
-        let result = client.login("e913202b66b623", "1ddf1a9bd7fc45");
+        let server = live();
+        let mut first = server.connect();
+        server.login(&mut first);
+        let mut client = server.connect();
+        let result = client.login(&server.username, &server.password);
         eprintln!("login_already_locked: {:?}", result);
         assert!(result.is_err())
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn quit() {
-        connect().unwrap().quit().unwrap()
+        let server = live();
+        server.connect().quit().unwrap()
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn stat_success() {
-        let mut client = connect().unwrap();
-        client.login("e913202b66b623", "1ddf1a9bd7fc45").ok();
+        let server = live();
+        let mut client = server.connect();
+        server.login(&mut client);
         let result = client.stat();
         eprintln!("stat_success: {:?}", result);
         assert!(result.is_ok())
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn stat_wrong_stage() {
-        let mut client = connect().unwrap();
+        let server = live();
+        let mut client = server.connect();
         let result = client.stat();
         eprintln!("stat_wrong_stage: {:?}", result);
         assert!(result.is_err());
-        assert_ne!(result.unwrap_err(), "Connection aborted".to_owned())
+        assert!(!matches!(result.unwrap_err(), Error::ConnectionAborted))
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
+    fn cached_stat_success() {
+        let server = live();
+        let mut client = server.connect_with(server.builder().cache(true));
+        server.login(&mut client);
+        let first = client.cached_stat();
+        let second = client.cached_stat();
+        eprintln!("cached_stat_success: {:?}", second);
+        assert!(first.is_ok());
+        assert_eq!(first.unwrap(), second.unwrap())
+    }
+
+    #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn list_all() {
-        let mut client = connect().unwrap();
-        client.login("e913202b66b623", "1ddf1a9bd7fc45").ok();
+        let server = live();
+        let mut client = server.connect();
+        server.login(&mut client);
         let result = client.list(None);
         eprintln!("list_all: {:?}", result);
         assert!(result.is_ok())
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn list_wrong_stage()
     {
-        let mut client = connect().unwrap();
+        let server = live();
+        let mut client = server.connect();
         let result = client.list(None);
         eprintln!("list_wrong_stage: {:?}", result);
         assert!(result.is_err());
-        assert_ne!(result.unwrap_err(), "Connection aborted".to_owned())
+        assert!(!matches!(result.unwrap_err(), Error::ConnectionAborted))
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn retr_not_found()
     {
-        let mut client = connect().unwrap();
-        client.login("e913202b66b623", "1ddf1a9bd7fc45").ok();
+        let server = live();
+        let mut client = server.connect();
+        server.login(&mut client);
         let result = client.retr(8);
         eprintln!("retr_not_found: {:?}", result);
         assert!(result.is_err());
-        assert_ne!(result.unwrap_err(), "Connection aborted".to_owned())
+        assert!(!matches!(result.unwrap_err(), Error::ConnectionAborted))
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn retr_wrong_stage()
     {
-        let mut client = connect().unwrap();
+        let server = live();
+        let mut client = server.connect();
         let result = client.retr(10);
         eprintln!("retr_wrong_stage: {:?}", result);
         assert!(result.is_err());
-        assert_ne!(result.unwrap_err(), "Connection aborted".to_owned())
+        assert!(!matches!(result.unwrap_err(), Error::ConnectionAborted))
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn dele_not_found()
     {
-        let mut client = connect().unwrap();
-        client.login("e913202b66b623", "1ddf1a9bd7fc45").ok();
+        let server = live();
+        let mut client = server.connect();
+        server.login(&mut client);
         let result = client.dele(8);
         eprintln!("dele_not_found: {:?}", result);
         assert!(result.is_err());
-        assert_ne!(result.unwrap_err(), "Connection aborted".to_owned())
+        assert!(!matches!(result.unwrap_err(), Error::ConnectionAborted))
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn dele_wrong_stage()
     {
-        let mut client = connect().unwrap();
+        let server = live();
+        let mut client = server.connect();
         let result = client.dele(10);
         eprintln!("dele_wrong_stage: {:?}", result);
         assert!(result.is_err());
-        assert_ne!(result.unwrap_err(), "Connection aborted".to_owned())
+        assert!(!matches!(result.unwrap_err(), Error::ConnectionAborted))
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn noop_success()
     {
-        let mut client = connect().unwrap();
-        client.login("e913202b66b623", "1ddf1a9bd7fc45").ok();
+        let server = live();
+        let mut client = server.connect();
+        server.login(&mut client);
         let result = client.noop();
         eprintln!("noop_success: {:?}", result);
         assert!(result.is_ok())
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn rset_all() {
-        let mut client = connect().unwrap();
-        client.login("e913202b66b623", "1ddf1a9bd7fc45").ok();
+        let server = live();
+        let mut client = server.connect();
+        server.login(&mut client);
         let result = client.rset();
         eprintln!("rset_success: {:?}", result);
         assert!(result.is_ok())
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn rset_wrong_stage()
     {
-        let mut client = connect().unwrap();
+        let server = live();
+        let mut client = server.connect();
         let result = client.rset();
         eprintln!("rset_wrong_stage: {:?}", result);
         assert!(result.is_err());
-        assert_ne!(result.unwrap_err(), "Connection aborted".to_owned())
+        assert!(!matches!(result.unwrap_err(), Error::ConnectionAborted))
     }
 
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn top_not_found()
     {
-        let mut client = connect().unwrap();
-        client.login("e913202b66b623", "1ddf1a9bd7fc45").ok();
+        let server = live();
+        let mut client = server.connect();
+        server.login(&mut client);
         let result = client.top(8, 3);
         eprintln!("top_not_found: {:?}", result);
         assert!(result.is_err());
-        assert_ne!(result.unwrap_err(), "Connection aborted".to_owned())
+        assert!(!matches!(result.unwrap_err(), Error::ConnectionAborted))
     }
 
     #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
     fn top_wrong_stage()
     {
-        let mut client = connect().unwrap();
+        let server = live();
+        let mut client = server.connect();
         let result = client.top(10, 4);
         eprintln!("top_wrong_stage: {:?}", result);
         assert!(result.is_err());
-        assert_ne!(result.unwrap_err(), "Connection aborted".to_owned())
+        assert!(!matches!(result.unwrap_err(), Error::ConnectionAborted))
+    }
+
+    #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
+    fn fetcher_delivers() {
+        use pop3_client::fetcher::Fetcher;
+
+        let server = live();
+        let mut fetcher = Fetcher::default();
+        fetcher.account(server.account());
+        let deliveries: Vec<_> = fetcher.spawn().into_iter().collect();
+        eprintln!("fetcher_delivers: {:?}", deliveries);
+        assert!(deliveries.iter().all(|(id, result)| *id == 0 && result.is_ok()))
+    }
+
+    #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
+    fn pool_reuses_connection() {
+        use pop3_client::pool::ClientPool;
+
+        let server = live();
+        let pool = ClientPool::new(server.account(), 1);
+        assert!(pool.get().unwrap().stat().is_ok());
+        assert_eq!(pool.idle(), 1);
+        let result = pool.get().unwrap().stat();
+        eprintln!("pool_reuses_connection: {:?}", result);
+        assert!(result.is_ok());
+        assert_eq!(pool.idle(), 1)
+    }
+
+    #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
+    fn export_to_maildir_success() {
+        use pop3_client::store::maildir::ExportOptions;
+
+        let server = live();
+        let path = std::env::temp_dir().join("pop3-client-maildir-test");
+        let mut client = server.connect();
+        server.login(&mut client);
+        let result = client.export_to_maildir(&path, &ExportOptions::default());
+        eprintln!("export_to_maildir_success: {:?}", result);
+        assert!(result.is_ok());
+        assert!(result.unwrap().iter().all(|(_, file)| file.starts_with(path.join("new"))))
+    }
+
+    #[test]
+    fn mbox_writer_quotes_from_lines() {
+        let mut writer = pop3_client::store::mbox::Writer::new(vec![]);
+        writer
+            .write_message(b"Return-Path: <me@my.host.com>\r\n\r\nFrom here\r\n>From there\r\n")
+            .unwrap();
+        let mbox = String::from_utf8(writer.into_inner()).unwrap();
+        eprintln!("mbox_writer_quotes_from_lines: {:?}", mbox);
+        assert!(mbox.starts_with("From me@my.host.com "));
+        assert!(mbox.ends_with("\n\n>From here\n>>From there\n\n"))
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn json_file_store_round_trip() {
+        use pop3_client::state::{JsonFileStore, UidStore};
+        use std::collections::HashSet;
+
+        let path = std::env::temp_dir().join("pop3-client-uid-store-test.json");
+        std::fs::remove_file(&path).ok();
+        let uids: HashSet<String> = vec!["a\"b".to_string(), "c\\d".to_string()].into_iter().collect();
+
+        let mut store = JsonFileStore::new(&path);
+        assert!(store.load("me@my.host.com:110").unwrap().is_empty());
+        store.save("me@my.host.com:110", &uids).unwrap();
+        store.save("other@my.host.com:110", &HashSet::new()).unwrap();
+
+        let result = JsonFileStore::new(&path).load("me@my.host.com:110");
+        eprintln!("json_file_store_round_trip: {:?}", result);
+        assert_eq!(result.unwrap(), uids)
+    }
+
+    #[test]
+    fn transient_errors() {
+        assert!(Error::Server("[IN-USE] Do you have another POP session running?".to_string()).is_transient());
+        assert!(Error::Server("[SYS/TEMP] try again later".to_string()).is_transient());
+        assert!(Error::ConnectionAborted.is_transient());
+        assert!(!Error::Server("[AUTH] invalid password".to_string()).is_transient());
+        assert!(!Error::Server("no such message".to_string()).is_transient())
+    }
+
+    #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
+    fn lang_list() {
+        let server = live();
+        let mut client = server.connect();
+        let result = client.lang(None);
+        eprintln!("lang_list: {:?}", result);
+        assert!(result.is_ok() || matches!(result.unwrap_err(), Error::Server(_)))
+    }
+
+    #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
+    fn login_non_ascii_without_utf8() {
+        let server = live();
+        let mut client = server.connect_with(server.builder().compliance(pop3_client::Compliance::Strict));
+        let result = client.login("пользователь", &server.password);
+        eprintln!("login_non_ascii_without_utf8: {:?}", result);
+        assert!(matches!(result.unwrap_err(), Error::Encoding(_)));
+        assert!(!client.is_utf8())
+    }
+
+    #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
+    fn list_all_strict() {
+        let server = live();
+        let mut client = server.connect_with(server.builder().compliance(pop3_client::Compliance::Strict));
+        server.login(&mut client);
+        let result = client.list(None);
+        eprintln!("list_all_strict: {:?}", result);
+        assert!(result.is_ok())
+    }
+
+    #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
+    fn command_raw_response() {
+        let server = live();
+        let mut client = server.connect();
+        let result = client.command("STAT", false);
+        eprintln!("command_raw_response: {:?}", result);
+        let response = result.unwrap();
+        assert_eq!(response.status, pop3_client::Status::Err);
+        assert!(response.raw.starts_with(b"-ERR"))
+    }
+
+    #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
+    fn login_command_injection() {
+        let server = live();
+        let mut client = server.connect();
+        let result = client.login(&format!("{}\r\nDELE 1", server.username), &server.password);
+        eprintln!("login_command_injection: {:?}", result);
+        assert!(matches!(result.unwrap_err(), Error::InvalidArgument(_)))
+    }
+
+    #[test]
+    #[ignore = "needs POP3_TEST_SERVER"]
+    fn line_too_long_closes_connection() {
+        let server = live();
+        let mut client = server.connect_with(server.builder().max_line_length(4));
+        let result = client.noop();
+        eprintln!("line_too_long_closes_connection: {:?}", result);
+        assert!(matches!(result.unwrap_err(), Error::LineTooLong(4)));
+        assert!(client.noop().is_err())
+    }
+
+    #[test]
+    fn command_multiline_scripted() {
+        let (mut client, sent) = scripted("+OK 2 lines\r\nfirst\r\n..second\r\n.\r\n");
+        let response = client.command("TOP 1 0", true).unwrap();
+        assert_eq!(response.info, "2 lines");
+        assert_eq!(response.lines, vec!["first".to_string(), ".second".to_string()]);
+        assert_eq!(response.payload(), b"first\r\n..second\r\n");
+        assert_eq!(sent.lock().unwrap().as_slice(), b"TOP 1 0\r\n");
+    }
+
+    #[test]
+    fn pipelined_commands_scripted() {
+        let (mut client, sent) = scripted("+OK deleted\r\n-ERR no such message\r\n");
+        client.queue_command("DELE 1").unwrap();
+        client.queue_command("DELE 2").unwrap();
+        assert!(sent.lock().unwrap().is_empty());
+        client.flush().unwrap();
+        assert_eq!(sent.lock().unwrap().as_slice(), b"DELE 1\r\nDELE 2\r\n");
+        assert!(client.next_response(false).unwrap().is_ok());
+        assert!(!client.next_response(false).unwrap().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "secrecy")]
+    fn login_secret_scripted() {
+        let (mut client, sent) = scripted("+OK\r\n+OK logged in\r\n");
+        let password = secrecy::SecretString::from("very_secret_password");
+        client.login("sweet_username", &password).unwrap();
+        assert_eq!(
+            sent.lock().unwrap().as_slice(),
+            b"USER sweet_username\r\nPASS very_secret_password\r\n"
+        );
+    }
+
+    #[test]
+    fn login_non_ascii_scripted() {
+        let (mut client, sent) = scripted("+OK\r\n+OK logged in\r\n");
+        client.login("пользователь", "пароль").unwrap();
+        assert_eq!(sent.lock().unwrap().as_slice(), "USER пользователь\r\nPASS пароль\r\n".as_bytes());
+
+        let (mut client, sent) = strict_scripted("+OK ready", "");
+        assert!(matches!(client.login("пользователь", "пароль"), Err(Error::Encoding(_))));
+        assert!(sent.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn apop_with_password_scripted() {
+        let (mut client, sent) = scripted_with_greeting(
+            "+OK POP3 server ready <1896.697170952@dbc.mtview.ca.us>",
+            "+OK maildrop has 1 message (369 octets)\r\n",
+        );
+        assert_eq!(client.greeting(), "POP3 server ready <1896.697170952@dbc.mtview.ca.us>");
+        assert!(client.apop_with_password("mrose", "tanstaaf").is_ok());
+        assert_eq!(sent.lock().unwrap().as_slice(), b"APOP mrose c4c9334bac560ecc979e58001b3e22fb\r\n");
+    }
+
+    #[test]
+    fn apop_without_timestamp_scripted() {
+        let (mut client, _) = scripted("");
+        assert!(matches!(client.apop_with_password("mrose", "tanstaaf"), Err(Error::InvalidReply(_))));
+    }
+
+    #[test]
+    fn open_without_credentials() {
+        let result = pop3_client::Builder::default().open("localhost", 110);
+        assert!(matches!(result, Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn typed_listings_scripted() {
+        use pop3_client::{MessageInfo, UidlEntry};
+
+        let (mut client, _) = scripted(concat!(
+            "+OK 2 messages\r\n1 120\r\n2 200\r\n.\r\n",
+            "+OK\r\n1 whqtswO00WBw418f9t5JxYwZ\r\n2 QhdPYR:00WBw1Ph7x7\r\n.\r\n",
+            "+OK CAPA follows\r\nTOP\r\nsasl PLAIN SCRAM-SHA-1\r\nUIDL\r\n.\r\n",
+        ));
+        assert_eq!(
+            client.list_entries(None).unwrap(),
+            vec![MessageInfo { id: 1, size: 120 }, MessageInfo { id: 2, size: 200 }]
+        );
+        assert_eq!(client.uidl_entries(None).unwrap()[1], UidlEntry { id: 2, uid: "QhdPYR:00WBw1Ph7x7".to_string() });
+        let capabilities = client.capa().unwrap();
+        assert!(capabilities.contains("top") && !capabilities.contains("STLS"));
+        assert_eq!(capabilities.arguments("SASL").unwrap(), ["PLAIN", "SCRAM-SHA-1"]);
+    }
+
+    #[test]
+    fn error_codes() {
+        use pop3_client::ErrorCode;
+
+        assert_eq!(Error::Server("[IN-USE] locked".to_string()).code(), Some(ErrorCode::InUse));
+        assert_eq!(Error::Server(" [auth] wrong".to_string()).code(), Some(ErrorCode::Auth));
+        assert_eq!(Error::Server("[XYZ/ABC] hm".to_string()).code(), Some(ErrorCode::Other("XYZ/ABC".to_string())));
+        assert_eq!(Error::Server("no such message".to_string()).code(), None);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn metadata_serde_round_trip() {
+        use pop3_client::{Capabilities, ErrorCode, MailboxStat, UidlEntry};
+
+        let stat = MailboxStat { count: 2, size: 320 };
+        assert_eq!(serde_json::to_string(&stat).unwrap(), r#"{"count":2,"size":320}"#);
+        let entry = UidlEntry { id: 1, uid: "abc".to_string() };
+        let json = serde_json::to_string(&entry).unwrap();
+        assert_eq!(serde_json::from_str::<UidlEntry>(&json).unwrap(), entry);
+        let code: ErrorCode = serde_json::from_str(r#""InUse""#).unwrap();
+        assert_eq!(code, ErrorCode::InUse);
+
+        let (mut client, _) = scripted("+OK\r\nUIDL\r\nSASL PLAIN\r\n.\r\n");
+        let capabilities = client.capa().unwrap();
+        let json = serde_json::to_string(&capabilities).unwrap();
+        assert_eq!(json, r#"{"SASL":["PLAIN"],"UIDL":[]}"#);
+        assert_eq!(serde_json::from_str::<Capabilities>(&json).unwrap(), capabilities);
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn cli_stat_against_local_server() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut writer = socket.try_clone().unwrap();
+            writer.write_all(b"+OK ready\r\n").unwrap();
+            for line in BufReader::new(socket).lines() {
+                let line = line.unwrap();
+                let reply = if line == "STAT" { "+OK 2 320\r\n" } else { "+OK\r\n" };
+                writer.write_all(reply.as_bytes()).unwrap();
+                if line == "QUIT" {
+                    break;
+                }
+            }
+        });
+
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_pop3-cli"))
+            .args(["--host", "127.0.0.1", "--port", &port.to_string(), "--no-tls", "--plaintext"])
+            .args(["--user", "me", "--password", "secret", "stat"])
+            .output()
+            .unwrap();
+        server.join().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"2 320\n");
+    }
+
+    #[cfg(feature = "cli")]
+    #[test]
+    fn cli_retr_keeps_the_first_header_line() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut writer = socket.try_clone().unwrap();
+            writer.write_all(b"+OK ready\r\n").unwrap();
+            for line in BufReader::new(socket).lines() {
+                let line = line.unwrap();
+                let reply = if line == "RETR 1" {
+                    "+OK 44 octets\r\nFrom: me@example.com\r\nSubject: hi\r\n\r\nHello\r\n.\r\n"
+                } else {
+                    "+OK\r\n"
+                };
+                writer.write_all(reply.as_bytes()).unwrap();
+                if line == "QUIT" {
+                    break;
+                }
+            }
+        });
+
+        let output = std::process::Command::new(env!("CARGO_BIN_EXE_pop3-cli"))
+            .args(["--host", "127.0.0.1", "--port", &port.to_string(), "--no-tls", "--plaintext"])
+            .args(["--user", "me", "--password", "secret", "retr", "1"])
+            .output()
+            .unwrap();
+        server.join().unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"From: me@example.com\r\nSubject: hi\r\n\r\nHello\r\n");
+    }
+
+    #[test]
+    fn parser_on_slices() {
+        use pop3_client::parser;
+        use pop3_client::{MessageInfo, Status};
+
+        assert_eq!(parser::status(b"+OK  2 200\r\n"), (Status::Ok, &b"2 200"[..]));
+        assert_eq!(parser::status(b"-ERR [IN-USE] locked\r\n"), (Status::Err, &b"[IN-USE] locked"[..]));
+        assert_eq!(parser::status(b"garbage"), (Status::Err, &b"garbage"[..]));
+        assert_eq!(parser::status(b""), (Status::Err, &b""[..]));
+        assert_eq!(parser::status(b"+ok 1 octets\r\n"), (Status::Ok, &b"1 octets"[..]));
+        assert_eq!(parser::status(b"+OK\r\n"), (Status::Ok, &b""[..]));
+        assert_eq!(parser::status(b"-err\r\n"), (Status::Err, &b""[..]));
+        assert_eq!(parser::status(b"+O\r\n"), (Status::Err, &b"+O"[..]));
+        assert_eq!(parser::status(b"-E"), (Status::Err, &b"-E"[..]));
+
+        assert_eq!(parser::scan_listing(b"3 120 extra\r\n").unwrap(), MessageInfo { id: 3, size: 120 });
+        assert!(matches!(parser::scan_listing(b"3\r\n"), Err(Error::InvalidReply(_))));
+        assert!(matches!(parser::scan_listing(b"3 \xff\r\n"), Err(Error::Parse { field: "size", .. })));
+        assert_eq!(parser::uidl_listing(b"1 \xffuid\r\n").unwrap().uid, "\u{fffd}uid");
+
+        let lines = parser::unstuff(b"first\r\n..second\r\n.\r\nafter\r\n");
+        assert_eq!(lines, vec![&b"first"[..], &b".second"[..]]);
+        assert!(parser::capabilities(b"top\r\n\r\nSASL PLAIN\r\n.\r\nUIDL\r\n").contains("TOP"));
+        assert!(!parser::capabilities(b"top\r\n.\r\nUIDL\r\n").contains("UIDL"));
+    }
+
+    mod properties {
+        use pop3_client::parser;
+        use pop3_client::test_util::{FakeServer, Message};
+        use pop3_client::{Client, MessageInfo, UidlEntry};
+        use proptest::prelude::*;
+
+        use super::plain;
+
+        /// Header lines and body lines of a message
+        type Lines = (Vec<Vec<u8>>, Vec<Vec<u8>>);
+
+        /// A line without line breaks: leading dots, 8-bit bytes and stray spaces are all fair game
+        fn line() -> impl Strategy<Value = Vec<u8>> {
+            prop_oneof![
+                proptest::collection::vec(any::<u8>().prop_filter("no line breaks", |c| *c != b'\r' && *c != b'\n'), 0..40),
+                "\\.{1,3}[ -~]{0,10}".prop_map(String::into_bytes),
+            ]
+        }
+
+        fn message() -> impl Strategy<Value = Lines> {
+            (
+                proptest::collection::vec("[A-Z][a-z]{0,8}: [ -~]{0,20}".prop_map(String::into_bytes), 1..5),
+                proptest::collection::vec(line(), 0..12),
+            )
+        }
+
+        fn crlf(lines: &[Vec<u8>]) -> Vec<u8> {
+            lines.iter().flat_map(|line| line.iter().chain(b"\r\n")).copied().collect()
+        }
+
+        fn mailbox() -> impl Strategy<Value = Vec<Lines>> {
+            proptest::collection::vec(message(), 0..8)
+        }
+
+        fn serve(mailbox: &[Lines]) -> (Client, Vec<Message>) {
+            let messages: Vec<Message> = mailbox
+                .iter()
+                .enumerate()
+                .map(|(i, (headers, body))| {
+                    let mut lines = headers.clone();
+                    lines.push(vec![]);
+                    lines.extend(body.iter().cloned());
+                    Message::new(&format!("uid-{}", i), &crlf(&lines))
+                })
+                .collect();
+            let mut client = plain().from_stream(FakeServer::new(messages.clone())).unwrap();
+            client.login("user", "password").unwrap();
+            (client, messages)
+        }
+
+        proptest! {
+            #[test]
+            fn listings_match_the_mailbox(mailbox in mailbox()) {
+                let (mut client, messages) = serve(&mailbox);
+
+                let expected: Vec<MessageInfo> = messages
+                    .iter()
+                    .enumerate()
+                    .map(|(i, m)| MessageInfo { id: i as u32 + 1, size: m.content.len() as u64 })
+                    .collect();
+                prop_assert_eq!(client.list_entries(None).unwrap(), expected.clone());
+                for info in &expected {
+                    prop_assert_eq!(client.list_entries(Some(info.id)).unwrap(), vec![*info]);
+                }
+
+                let uids: Vec<UidlEntry> = messages
+                    .iter()
+                    .enumerate()
+                    .map(|(i, m)| UidlEntry { id: i as u32 + 1, uid: m.uid.clone() })
+                    .collect();
+                prop_assert_eq!(client.uidl_entries(None).unwrap(), uids);
+
+                let size = expected.iter().map(|m| m.size).sum::<u64>();
+                prop_assert_eq!(client.stat().unwrap(), (expected.len() as u32, size));
+            }
+
+            #[test]
+            fn retr_and_top_preserve_bytes(mailbox in mailbox(), n in 0usize..15) {
+                let (mut client, _) = serve(&mailbox);
+
+                for (i, (headers, body)) in mailbox.iter().enumerate() {
+                    let id = i + 1;
+                    let retr = client.command(&format!("RETR {}", id), true).unwrap();
+                    let mut expected = headers.clone();
+                    expected.push(vec![]);
+                    expected.extend(body.iter().cloned());
+                    prop_assert_eq!(parser::unstuff(retr.payload()), expected.iter().map(Vec::as_slice).collect::<Vec<_>>());
+
+                    let mut written = vec![];
+                    let octets = client.retr_to_writer(id as u32, &mut written).unwrap();
+                    prop_assert_eq!(octets, written.len() as u64);
+                    prop_assert_eq!(written, crlf(&expected));
+
+                    let top = client.top(id as u32, n as u32).unwrap();
+                    prop_assert_eq!(top.headers, crlf(headers));
+                    prop_assert_eq!(top.body_lines, body.iter().take(n).cloned().collect::<Vec<_>>());
+                }
+            }
+
+            #[test]
+            fn dele_hides_until_rset(mailbox in mailbox(), pick in any::<prop::sample::Index>()) {
+                prop_assume!(!mailbox.is_empty());
+                let (mut client, _) = serve(&mailbox);
+                let id = pick.index(mailbox.len()) as u32 + 1;
+
+                client.dele(id).unwrap();
+                let listed = client.list_entries(None).unwrap();
+                prop_assert_eq!(listed.len(), mailbox.len() - 1);
+                prop_assert!(listed.iter().all(|m| m.id != id));
+                prop_assert!(client.list_entries(Some(id)).is_err());
+
+                client.rset().unwrap();
+                prop_assert_eq!(client.list_entries(None).unwrap().len(), mailbox.len());
+            }
+        }
+    }
+
+    #[test]
+    fn top_keeps_bytes_scripted() {
+        let replies = b"+OK ready\r\n+OK\r\nSubject: \xe9t\xe9\r\n..From: x\r\n\r\n..dot\r\n\xff\r\n.\r\n";
+        let stream = Scripted {
+            replies: std::io::Cursor::new(replies.to_vec()),
+            sent: Default::default(),
+        };
+        let mut client = Client::from_stream(stream).unwrap();
+        let top = client.top(1, 2).unwrap();
+        assert_eq!(top.headers, b"Subject: \xe9t\xe9\r\n.From: x\r\n");
+        assert_eq!(top.body_lines, vec![b".dot".to_vec(), b"\xff".to_vec()]);
+        assert_eq!(top.to_text(), "Subject: \u{fffd}t\u{fffd}\r\n.From: x\r\n\r\n.dot\r\n\u{fffd}\r\n");
+    }
+
+    #[test]
+    fn retr_to_failing_writer_scripted() {
+        struct Full;
+
+        impl std::io::Write for Full {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::WriteZero.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let (mut client, _) = scripted("+OK\r\nSubject: x\r\n\r\n..body\r\n.\r\n+OK\r\n");
+        assert!(matches!(client.retr_to_writer(1, &mut Full), Err(Error::Io(_))));
+        assert!(client.noop().is_ok());
+    }
+
+    #[test]
+    fn progress_reports_downloads() {
+        use pop3_client::test_util::{FakeServer, Message};
+        use pop3_client::Progress;
+        use std::sync::{Arc, Mutex};
+
+        let mut content = b"Subject: big\r\n\r\n".to_vec();
+        for _ in 0..1000 {
+            content.extend_from_slice(&[b'x'; 98]);
+            content.extend_from_slice(b"\r\n");
+        }
+        let size = content.len() as u64;
+
+        let reports = Arc::new(Mutex::new(vec![]));
+        let sink = reports.clone();
+        let server = FakeServer::new(vec![Message::new("big", &content), Message::new("small", b"\r\n")]);
+        let mut client = plain()
+            .progress(move |p| sink.lock().unwrap().push(p))
+            .from_stream(server)
+            .unwrap();
+        client.login("user", "password").unwrap();
+
+        let mut written = vec![];
+        client.retr_to_writer(1, &mut written).unwrap();
+        client.retr(2).unwrap();
+
+        let reports = reports.lock().unwrap();
+        let done = |msg, received| Progress { msg, received, expected: Some(received) };
+        assert_eq!(reports.len(), 3);
+        assert!(reports[0].msg == 1 && reports[0].received >= 64 * 1024 && reports[0].expected == Some(size));
+        assert_eq!(reports[1..], [done(1, size), done(2, 2)]);
+    }
+
+    #[test]
+    fn throttle_slows_down_reads() {
+        use pop3_client::test_util::{FakeServer, Message};
+        use std::time::{Duration, Instant};
+
+        let content = b"0123456789abcdefghijklmnopqrstuvwxyz0123456789abcdefghijklmnopqrstuvw\r\n".repeat(500);
+        let server = FakeServer::new(vec![Message::new("uid", &content)]);
+        let mut client = plain().throttle(20_000).from_stream(server).unwrap();
+        client.login("user", "password").unwrap();
+
+        let start = Instant::now();
+        let octets = client.retr_to_writer(1, &mut std::io::sink()).unwrap();
+        assert_eq!(octets, content.len() as u64);
+        assert!(start.elapsed() >= Duration::from_millis(600));
+    }
+
+    /// A server that greets and then never answers
+    fn silent_server() -> (std::net::TcpListener, u16) {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        (listener, port)
+    }
+
+    #[cfg(not(feature = "with-rustls"))]
+    fn connect_plain(port: u16) -> Client {
+        pop3_client::Builder::default().allow_plaintext_auth(true).connect("127.0.0.1", port).unwrap()
+    }
+
+    #[cfg(feature = "with-rustls")]
+    fn connect_plain(port: u16) -> Client {
+        pop3_client::Builder::default().tls(false).allow_plaintext_auth(true).connect("127.0.0.1", port).unwrap()
+    }
+
+    fn accept_silently(listener: std::net::TcpListener) -> std::thread::JoinHandle<()> {
+        use std::io::{Read, Write};
+
+        std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            socket.write_all(b"+OK ready\r\n").unwrap();
+            let mut buf = [0; 64];
+            while socket.read(&mut buf).is_ok_and(|n| n > 0) {}
+        })
+    }
+
+    #[test]
+    fn command_timeout_closes_connection() {
+        use std::time::{Duration, Instant};
+
+        let (listener, port) = silent_server();
+        let server = accept_silently(listener);
+        let mut client = connect_plain(port);
+        client.set_command_timeout(Duration::from_millis(200));
+
+        let start = Instant::now();
+        let result = client.noop();
+        assert!(matches!(&result, Err(Error::Io(e)) if e.kind() == std::io::ErrorKind::TimedOut), "{:?}", result);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert!(client.noop().is_err());
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn cancellation_aborts_blocked_read() {
+        use std::time::Duration;
+
+        let (listener, port) = silent_server();
+        let server = accept_silently(listener);
+        let mut client = connect_plain(port);
+
+        let token = client.cancellation_token();
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            token.cancel();
+        });
+        assert!(matches!(client.retr(1), Err(Error::Cancelled)));
+        assert!(matches!(client.noop(), Err(Error::Io(_))));
+        canceller.join().unwrap();
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "record")]
+    fn record_and_replay() {
+        use pop3_client::test_util::{FakeServer, Message};
+
+        let path = std::env::temp_dir().join("pop3-client-record-test.log");
+        let server = FakeServer::new(vec![Message::new("uid-1", b"Subject: hi\r\n\r\n.dot\r\n")])
+            .with_credentials("user", "hunter2");
+
+        let mut client = plain().record_to(&path).from_stream(server).unwrap();
+        client.login("user", "hunter2").unwrap();
+        let stat = client.stat().unwrap();
+        let message = client.retr(1).unwrap();
+        client.quit().unwrap();
+
+        let transcript = std::fs::read_to_string(&path).unwrap();
+        assert!(!transcript.contains("hunter2"));
+        assert!(transcript.lines().any(|line| line.ends_with(" C PASS ***")));
+
+        let mut replay = Client::replay_from(&path).unwrap();
+        replay.login("user", "another password").unwrap();
+        assert_eq!(replay.stat().unwrap(), stat);
+        assert_eq!(replay.retr(1).unwrap(), message);
+        assert!(matches!(replay.dele(1), Err(Error::Io(_))));
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    #[cfg(feature = "record")]
+    fn record_redacts_responses_to_bare_challenges() {
+        use std::collections::VecDeque;
+        use std::io::{Read, Write};
+
+        /// Answers each line sent with the next reply, so that every reply goes through the recorder
+        struct LockStep {
+            replies: VecDeque<&'static [u8]>,
+            ready: VecDeque<u8>,
+        }
+
+        impl Read for LockStep {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                self.ready.read(buf)
+            }
+        }
+
+        impl Write for LockStep {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                for _ in buf.iter().filter(|c| **c == b'\n') {
+                    self.ready.extend(self.replies.pop_front().unwrap_or_default());
+                }
+                Ok(buf.len())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let path = std::env::temp_dir().join("pop3-client-record-bare-challenge.log");
+        let stream = LockStep {
+            replies: VecDeque::from([&b"+\r\n"[..], b"+OK\r\n"]),
+            ready: b"+OK ready\r\n".iter().copied().collect(),
+        };
+        let mut client = plain().record_to(&path).from_stream(stream).unwrap();
+        // Too long for the command line, so it's sent after the empty challenge
+        let token = vec![b'x'; 400];
+        client.auth("XOAUTH2", Some(&token), |_| Ok(vec![])).unwrap();
+
+        let transcript = std::fs::read_to_string(&path).unwrap();
+        assert!(!transcript.contains("eHh4"));
+        assert!(transcript.lines().any(|line| line.ends_with(" S +")));
+        assert!(transcript.lines().any(|line| line.ends_with(" C ***")));
+
+        let mut replay = Client::replay_from(&path).unwrap();
+        replay.auth("XOAUTH2", Some(&[b'y'; 400]), |_| Ok(vec![])).unwrap();
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn tiny_read_buffer() {
+        use pop3_client::test_util::{FakeServer, Message};
+
+        let content = b"Subject: buffers\r\n\r\n..a stuffed line\r\nand another\r\n";
+        let messages = vec![Message::new("uid-1", content)];
+        let mut reference = plain().from_stream(FakeServer::new(messages.clone())).unwrap();
+        reference.login("user", "password").unwrap();
+
+        let server = FakeServer::new(messages);
+        let mut client = plain().read_buffer_size(1).from_stream(server).unwrap();
+        client.login("user", "password").unwrap();
+        assert_eq!(client.stat().unwrap(), (1, content.len() as u64));
+        assert_eq!(client.retr(1).unwrap(), reference.retr(1).unwrap());
+        let mut written = vec![];
+        client.retr_to_writer(1, &mut written).unwrap();
+        assert_eq!(written, content);
+    }
+
+    #[test]
+    fn server_type_from_greeting_and_capa() {
+        let (client, _) = scripted_with_greeting("+OK Dovecot ready.", "");
+        assert_eq!(client.server_type(), pop3_client::ServerType::Dovecot);
+
+        let (mut client, _) = scripted("+OK\r\nTOP\r\nIMPLEMENTATION Zimbra 9.0\r\n.\r\n");
+        assert_eq!(client.server_type(), pop3_client::ServerType::Unknown);
+        client.capa().unwrap();
+        assert_eq!(client.server_type(), pop3_client::ServerType::Zimbra);
+    }
+
+    fn strict_scripted(greeting: &str, replies: &str) -> (Client, std::sync::Arc<std::sync::Mutex<Vec<u8>>>) {
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let stream = Scripted {
+            replies: std::io::Cursor::new(format!("{}\r\n{}", greeting, replies).into_bytes()),
+            sent: sent.clone(),
+        };
+        let client = pop3_client::Builder::default()
+            .compliance(pop3_client::Compliance::Strict)
+            .from_stream(stream)
+            .unwrap();
+        (client, sent)
+    }
+
+    #[test]
+    fn exchange_listing_junk_scripted() {
+        let (mut client, _) = strict_scripted(
+            "+OK Microsoft Exchange Server POP3 service ready",
+            "+OK 2 messages\r\n1 1204bytes\r\n2 88bytes\r\n.\r\n",
+        );
+        let sizes: Vec<u64> = client.list_entries(None).unwrap().iter().map(|m| m.size).collect();
+        assert_eq!(sizes, vec![1204, 88]);
+    }
+
+    #[test]
+    fn qpopper_without_uidl_scripted() {
+        let (mut client, sent) = strict_scripted(
+            "+OK Qpopper (version 4.0.5) at mail.example.com starting.",
+            "+OK\r\n1 20\r\n.\r\n+OK\r\nMessage-ID: <1@x>\n\n.\r\n",
+        );
+        let entries = client.uidl_entries(None).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].uid.len(), 32);
+        assert_eq!(String::from_utf8_lossy(&sent.lock().unwrap()), "LIST\r\nTOP 1 0\r\n");
+    }
+
+    /// A server that refuses `STLS` and answers `+OK` to anything else
+    #[cfg(feature = "with-rustls")]
+    fn serve_without_stls(listener: std::net::TcpListener, connections: usize) -> std::thread::JoinHandle<()> {
+        use std::io::{BufRead, Write};
+
+        std::thread::spawn(move || {
+            for _ in 0..connections {
+                let (mut socket, _) = listener.accept().unwrap();
+                socket.write_all(b"+OK ready\r\n").unwrap();
+                let mut commands = std::io::BufReader::new(socket.try_clone().unwrap());
+                let mut line = String::new();
+                while commands.read_line(&mut line).is_ok_and(|n| n > 0) {
+                    let reply: &[u8] = if line.starts_with("STLS") { b"-ERR not supported\r\n" } else { b"+OK 0 0\r\n" };
+                    socket.write_all(reply).unwrap();
+                    line.clear();
+                }
+            }
+        })
     }
 
+    #[test]
+    #[cfg(feature = "with-rustls")]
+    fn opportunistic_tls_falls_back() {
+        use pop3_client::{Builder, TlsPolicy};
+
+        let (listener, port) = silent_server();
+        let server = serve_without_stls(listener, 2);
+
+        let refused = Builder::default().tls_policy(TlsPolicy::Required).connect("127.0.0.1", port);
+        assert!(matches!(refused, Err(Error::Server(_))));
+
+        let mut client = Builder::default()
+            .tls_policy(TlsPolicy::Opportunistic)
+            .connect("127.0.0.1", port)
+            .unwrap();
+        assert!(!client.is_tls());
+        assert_eq!(client.stat().unwrap(), (0, 0));
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn message_handle_fetches_lazily() {
+        let content = "Subject: lazy\r\nFrom: me@my.host.com\r\n\r\n..body\r\n";
+        let replies = format!(
+            "+OK 1 {size}\r\n+OK 1 uid-1\r\n+OK\r\n{stuffed}.\r\n+OK\r\n",
+            size = content.len() - 1,
+            stuffed = content,
+        );
+        let (mut client, sent) = scripted(&replies);
+
+        assert!(matches!(client.message(0), Err(Error::InvalidArgument(_))));
+        let mut message = client.message(1).unwrap();
+        assert_eq!(message.size().unwrap(), content.len() as u64 - 1);
+        assert_eq!(message.size().unwrap(), content.len() as u64 - 1);
+        assert_eq!(message.uid().unwrap(), "uid-1");
+        assert_eq!(message.content().unwrap(), content.replacen("..", ".", 1).as_bytes());
+        assert_eq!(message.headers().unwrap(), b"Subject: lazy\r\nFrom: me@my.host.com\r\n");
+        message.delete().unwrap();
+        assert_eq!(String::from_utf8_lossy(&sent.lock().unwrap()), "LIST 1\r\nUIDL 1\r\nRETR 1\r\nDELE 1\r\n");
+    }
+
+    #[test]
+    fn search_headers_with_query_and_closure() {
+        use pop3_client::search::{Headers, Query};
+        use pop3_client::test_util::{FakeServer, Message};
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let messages = vec![
+            Message::new("a", b"Subject: Invoice 42\r\nFrom: Bank <billing@bank.example>\r\nDate: Thu, 1 Jan 2004 00:00:00 +0000\r\n\r\nPay\r\n"),
+            Message::new("b", b"Subject: lunch?\r\nFrom: friend@example.com\r\nDate: 2 Jan 2004 01:00 +0100\r\n\r\nSure\r\n"),
+            Message::new("c", b"Subject: =?UTF-8?Q?invoice?=\r\n reminder\r\nFrom: billing@bank.example\r\n\r\nAgain\r\n"),
+        ];
+        let mut client = plain().from_stream(FakeServer::new(messages)).unwrap();
+        client.login("user", "password").unwrap();
+
+        let new_year = UNIX_EPOCH + Duration::from_secs(1_072_915_200);
+        assert_eq!(client.search_headers(Query::default().subject("INVOICE")).unwrap(), vec![1, 3]);
+        assert_eq!(client.search_headers(Query::default().since(new_year)).unwrap(), vec![1, 2]);
+        assert_eq!(
+            client.search_headers(Query::default().from("bank.example").before(new_year + Duration::from_secs(1))).unwrap(),
+            vec![1]
+        );
+        let folded = client
+            .search_headers(|headers: &Headers| headers.get("subject").is_some_and(|s| s.ends_with("?= reminder")))
+            .unwrap();
+        assert_eq!(folded, vec![3]);
+
+        let headers = Headers::parse(b"Date: Fri, 2 Jan 2004 01:00:00 +0100 (CET)\r\n");
+        assert_eq!(headers.date(), Some(new_year + Duration::from_secs(86400)));
+    }
+
+    #[test]
+    fn select_by_size_and_age() {
+        use pop3_client::test_util::{FakeServer, Message};
+        use std::time::Duration;
+
+        let messages = vec![
+            Message::new("old", b"Date: Thu, 1 Jan 2004 00:00:00 +0000\r\n\r\nOld\r\n"),
+            Message::new("big", format!("Date: Thu, 1 Jan 2099 00:00:00 GMT\r\n\r\n{}\r\n", "x".repeat(1000)).as_bytes()),
+            Message::new("undated", b"Subject: no date\r\n\r\nHm\r\n"),
+        ];
+        let mut client = plain().from_stream(FakeServer::new(messages)).unwrap();
+        client.login("user", "password").unwrap();
+
+        let ninety_days = Duration::from_secs(90 * 24 * 3600);
+        assert_eq!(client.select(|meta| meta.age().is_some_and(|age| age > ninety_days)).unwrap(), vec![1]);
+        assert_eq!(client.select(|meta| meta.size > 500).unwrap(), vec![2]);
+        assert_eq!(client.select(|meta| meta.date.is_none()).unwrap(), vec![3]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn migrate_resumes_after_failure() {
+        use pop3_client::migrate::{MessageSink, MigrateOptions};
+        use pop3_client::state::JsonFileStore;
+        use pop3_client::test_util::{FakeServer, Message};
+
+        /// Keeps the messages, failing once after `fail_after` of them
+        struct Collect {
+            messages: Vec<(String, Vec<u8>)>,
+            fail_after: Option<usize>,
+        }
+
+        impl MessageSink for Collect {
+            fn store(&mut self, uid: &str, message: &[u8]) -> Result<()> {
+                if self.fail_after == Some(self.messages.len()) {
+                    self.fail_after = None;
+                    return Err(Error::Store("disk full".to_string()));
+                }
+                self.messages.push((uid.to_string(), message.to_vec()));
+                Ok(())
+            }
+        }
+
+        let path = std::env::temp_dir().join("pop3-client-migrate-test.json");
+        std::fs::remove_file(&path).ok();
+        let messages = vec![
+            Message::new("a", b"Subject: one\r\n\r\n.leading dot\r\n"),
+            Message::new("b", b"Subject: two\r\n\r\nTwo\r\n"),
+            Message::new("c", b"Subject: three\r\n\r\nThree\r\n"),
+        ];
+        let mut sink = Collect { messages: vec![], fail_after: Some(1) };
+
+        let mut store = JsonFileStore::new(&path);
+        let mut client = plain().from_stream(FakeServer::new(messages.clone())).unwrap();
+        client.login("user", "password").unwrap();
+        let mut options = MigrateOptions::default();
+        options.resume(&mut store, "user@fake");
+        assert!(matches!(client.migrate(&mut sink, &mut options), Err(Error::Store(_))));
+
+        let mut store = JsonFileStore::new(&path);
+        let mut client = plain().from_stream(FakeServer::new(messages.clone())).unwrap();
+        client.login("user", "password").unwrap();
+        let mut options = MigrateOptions::default();
+        options.resume(&mut store, "user@fake");
+        let report = client.migrate(&mut sink, &mut options).unwrap();
+
+        assert_eq!(report.skipped, 1);
+        assert_eq!(report.migrated.iter().map(|m| m.uid.as_str()).collect::<Vec<_>>(), vec!["b", "c"]);
+        assert_eq!(report.octets, (messages[1].content.len() + messages[2].content.len()) as u64);
+        assert!(report.migrated.iter().all(|m| m.md5.len() == 32));
+        let copied: Vec<_> = sink.messages.into_iter().map(|(_, message)| message).collect();
+        assert_eq!(copied, messages.iter().map(|m| m.content.clone()).collect::<Vec<_>>());
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn migrate_saves_uids_in_batches() {
+        use pop3_client::migrate::MigrateOptions;
+        use pop3_client::state::UidStore;
+        use pop3_client::test_util::{FakeServer, Message};
+        use std::collections::HashSet;
+
+        #[derive(Default)]
+        struct Counting {
+            saves: usize,
+            uids: HashSet<String>,
+        }
+
+        impl UidStore for Counting {
+            fn load(&mut self, _account: &str) -> Result<HashSet<String>> {
+                Ok(self.uids.clone())
+            }
+
+            fn save(&mut self, _account: &str, uids: &HashSet<String>) -> Result<()> {
+                self.saves += 1;
+                self.uids = uids.clone();
+                Ok(())
+            }
+        }
+
+        let messages: Vec<_> = (0..150).map(|i| Message::new(&format!("uid{}", i), b"Subject: hi\r\n\r\nHi\r\n")).collect();
+        let mut store = Counting::default();
+        let mut client = plain().from_stream(FakeServer::new(messages)).unwrap();
+        client.login("user", "password").unwrap();
+        let mut options = MigrateOptions::default();
+        options.resume(&mut store, "user@fake");
+        let report = client.migrate(&mut pop3_client::store::mbox::Writer::new(vec![]), &mut options).unwrap();
+
+        assert_eq!(report.migrated.len(), 150);
+        assert_eq!(store.saves, 2);
+        assert_eq!(store.uids.len(), 150);
+    }
+
+    #[test]
+    fn retr_with_digest_checks_size() {
+        use pop3_client::test_util::{FakeServer, Message};
+        use pop3_client::Algo;
+
+        let content = b"Subject: sum\r\n\r\n.dot\r\n";
+        let mut client = plain().from_stream(FakeServer::new(vec![Message::new("a", content)])).unwrap();
+        client.login("user", "password").unwrap();
+
+        let (message, integrity) = client.retr_with_digest(1, Algo::Sha256).unwrap();
+        assert_eq!(&message[..], &content[..]);
+        assert_eq!(integrity.hex(), "7dfc04c6cb835cae39d7e0d8cde05e6138141de37754180b29a9f105b51f0c4a");
+        assert_eq!((integrity.octets, integrity.listed_size, integrity.truncated), (22, 22, false));
+        let (_, integrity) = client.retr_with_digest(1, Algo::Md5).unwrap();
+        assert_eq!(integrity.hex(), "7c6c26a5824058f0b1ec246379745ed4");
+
+        let (mut client, _) = scripted("+OK 1 100\r\n+OK\r\nSubject: cut\r\n.\r\n");
+        let (_, integrity) = client.retr_with_digest(1, Algo::Sha256).unwrap();
+        assert_eq!((integrity.octets, integrity.listed_size, integrity.truncated), (14, 100, true));
+    }
+
+    #[test]
+    fn verify_sizes_flags_truncation() {
+        let replies = "+OK 1 100\r\n+OK\r\nshort\r\n.\r\n+OK\r\n+OK 2 7\r\n+OK\r\nfine!\r\n.\r\n";
+        let stream = Scripted {
+            replies: std::io::Cursor::new(format!("+OK ready\r\n{}", replies).into_bytes()),
+            sent: Default::default(),
+        };
+        let mut client = pop3_client::Builder::default().verify_sizes(10).from_stream(stream).unwrap();
+
+        assert!(matches!(client.retr(1), Err(Error::SizeMismatch(100, 7))));
+        assert!(client.noop().is_ok());
+        let mut message = vec![];
+        client.retr_to_writer(2, &mut message).unwrap();
+        assert_eq!(message, b"fine!\r\n");
+    }
+
+    #[test]
+    fn stage_follows_login() {
+        use pop3_client::test_util::FakeServer;
+        use pop3_client::Stage;
+
+        let server = FakeServer::new(vec![]).with_credentials("user", "secret");
+        let mut client = plain().from_stream(server).unwrap();
+        assert_eq!(client.stage(), Stage::Authorization);
+        assert_eq!(client.stage(), pop3_client::Stage::Authorization);
+
+        let refused = client.login("user", "wrong");
+        assert!(matches!(refused, Err(Error::Auth(pop3_client::AuthError { step: pop3_client::AuthStep::Pass, .. }))));
+        assert_eq!(client.stage(), Stage::Authorization);
+        assert!(!client.is_authorized());
+
+        client.login("user", "secret").unwrap();
+        assert_eq!(client.stage(), Stage::Transaction);
+        assert!(matches!(client.login("user", "secret"), Err(Error::WrongStage(_))));
+        assert_eq!(client.stat().unwrap(), (0, 0));
+
+        let server = FakeServer::new(vec![]).with_credentials("user", "secret");
+        let mut client = plain().from_stream(server).unwrap();
+        assert!(client.command("USER user", false).unwrap().is_ok());
+        assert!(!client.command("PASS wrong", false).unwrap().is_ok());
+        assert!(!client.is_authorized());
+        client.command("USER user", false).unwrap();
+        client.command("PASS secret", false).unwrap();
+        assert!(client.is_authorized());
+    }
+
+    #[test]
+    fn login_refusal_is_structured_and_retryable() {
+        use pop3_client::{AuthStep, ErrorCode};
+
+        let (mut client, sent) = scripted("-ERR [AUTH] no such user\r\n+OK\r\n-ERR [IN-USE] locked\r\n+OK\r\n+OK\r\n");
+        match client.login("nobody", "x") {
+            Err(Error::Auth(e)) => {
+                assert_eq!(e.step, AuthStep::User);
+                assert_eq!(e.code(), Some(ErrorCode::Auth));
+            }
+            other => panic!("{:?}", other),
+        }
+        let locked = client.login("user", "secret").unwrap_err();
+        assert!(matches!(&locked, Error::MailboxLocked { step: AuthStep::Pass, reason, .. } if reason.contains("locked")));
+        assert!(locked.is_transient());
+        client.login("user", "secret").unwrap();
+        assert!(client.is_authorized());
+        assert_eq!(
+            String::from_utf8_lossy(&sent.lock().unwrap()),
+            "USER nobody\r\nUSER user\r\nPASS secret\r\nUSER user\r\nPASS secret\r\n"
+        );
+    }
+
+    #[test]
+    fn proto_session_without_io() {
+        use pop3_client::proto::{Command, Event, Session};
+        use pop3_client::{Compliance, Stage};
+
+        let mut session = Session::new();
+        session.compliance(Compliance::Strict);
+        assert!(session.poll().is_none());
+        session.receive(b"+OK hi\r\n");
+        assert!(matches!(session.poll(), Some(Ok(Event::Greeting(r))) if r.info == "hi"));
+
+        // Pipelined, with replies arriving byte by byte
+        let mut sent = vec![];
+        for command in [Command::Stat, Command::User("me"), Command::Pass("secret"), Command::Retr(1)] {
+            sent.extend(session.send(&command).unwrap());
+        }
+        assert_eq!(sent, b"STAT\r\nUSER me\r\nPASS secret\r\nRETR 1\r\n");
+        assert!(session.send(&Command::User("me\r\nDELE 1")).is_err());
+        assert_eq!(session.pending(), 4);
+
+        let mut events = vec![];
+        for byte in b"-ERR log in first\r\n+OK\r\n+OK\r\n+OK\r\n..dot\r\n.\r\n" {
+            session.receive(&[*byte]);
+            events.extend(std::iter::from_fn(|| session.poll()));
+        }
+        assert_eq!(events.len(), 4);
+        assert!(matches!(&events[0], Err(Error::WrongStage(_))));
+        assert!(matches!(&events[2], Ok(Event::Reply { command, .. }) if command == "PASS"));
+        match &events[3] {
+            Ok(Event::Reply { command, response }) => {
+                assert_eq!(command, "RETR");
+                assert_eq!(response.lines, vec![".dot"]);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(session.stage(), Stage::Transaction);
+        assert_eq!(session.pending(), 0);
+
+        session.send(&Command::Noop).unwrap();
+        session.receive(b"+OK\n");
+        assert!(matches!(session.poll(), Some(Err(Error::InvalidReply(_)))));
+    }
+
+    #[test]
+    fn listing_iterators_read_lazily() {
+        let (mut client, _) = scripted(
+            "+OK 3 messages\r\n1 120\r\n2 200\r\n3 5000\r\n.\r\n\
+              +OK\r\n1 abc\r\n2 def\r\n3 ghi\r\n.\r\n\
+              +OK 3 5320\r\n",
+        );
+        let sizes: Vec<u64> = client.list_iter().unwrap().map(|info| info.unwrap().size).collect();
+        assert_eq!(sizes, vec![120, 200, 5000]);
+
+        let first = client.uidl_iter().unwrap().next().unwrap().unwrap();
+        assert_eq!(first.uid, "abc");
+        // dropping the iterator early drained the rest of the listing
+        assert_eq!(client.stat().unwrap(), (3, 5320));
+    }
+
+    #[test]
+    fn banner_is_parsed_and_checked() {
+        let (client, _) = scripted_with_greeting("+OK mail.example.com POP3 ready <1896.697170952@dbc.mtview.ca.us>", "");
+        let banner = client.banner();
+        assert_eq!(banner.host.as_deref(), Some("mail.example.com"));
+        assert_eq!(banner.timestamp.as_deref(), Some("<1896.697170952@dbc.mtview.ca.us>"));
+
+        let (client, _) = scripted_with_greeting("+OK POP3 server ready <1896.697170952@dbc.mtview.ca.us>", "");
+        assert_eq!(client.banner().host.as_deref(), Some("dbc.mtview.ca.us"));
+
+        let stream = Scripted {
+            replies: std::io::Cursor::new(b"+OK SMTP relay at your service\r\n".to_vec()),
+            sent: Default::default(),
+        };
+        let refused = pop3_client::Builder::default()
+            .expect_banner(|greeting| greeting.text.contains("POP3"))
+            .from_stream(stream);
+        match refused {
+            Err(Error::UnexpectedGreeting(text)) => assert_eq!(text, "SMTP relay at your service"),
+            other => panic!("expected an unexpected greeting, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics_count_commands_and_octets() {
+        use metrics_util::debugging::{DebugValue, DebuggingRecorder};
+        use pop3_client::telemetry;
+
+        let recorder = DebuggingRecorder::new();
+        let snapshotter = recorder.snapshotter();
+        metrics::with_local_recorder(&recorder, || {
+            let (mut client, _) = scripted("+OK\r\n+OK\r\n+OK 2 320\r\n-ERR no such message\r\n");
+            client.login("sweet_username", "pass").unwrap();
+            client.stat().unwrap();
+            client.dele(7).unwrap_err();
+        });
+
+        let snapshot = snapshotter.snapshot().into_vec();
+        let value = |name: &str, label: Option<(&str, &str)>| {
+            snapshot.iter().find_map(|(key, _, _, value)| {
+                let key = key.key();
+                let labelled = label.is_none_or(|(k, v)| key.labels().any(|l| l.key() == k && l.value() == v));
+                let account = key.labels().any(|l| l.key() == "account" && l.value() == "sweet_username");
+                (key.name() == name && labelled && account).then_some(value)
+            })
+        };
+        assert!(matches!(value(telemetry::COMMANDS, Some(("command", "STAT"))), Some(DebugValue::Counter(1))));
+        assert!(matches!(value(telemetry::ERRORS, Some(("kind", "server"))), Some(DebugValue::Counter(1))));
+        assert!(matches!(value(telemetry::SENT, None), Some(DebugValue::Counter(n)) if *n > 0));
+        assert!(matches!(value(telemetry::LATENCY, Some(("command", "DELE"))), Some(DebugValue::Histogram(h)) if h.len() == 1));
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn tracing_spans_per_session_and_command() {
+        use std::collections::HashMap;
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+
+        #[derive(Debug, Default)]
+        struct Recorded {
+            name: &'static str,
+            parent: Option<u64>,
+            fields: HashMap<String, String>,
+        }
+
+        struct Fields<'a>(&'a mut HashMap<String, String>);
+
+        impl Visit for Fields<'_> {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                self.0.insert(field.name().to_string(), format!("{:?}", value).trim_matches('"').to_string());
+            }
+        }
+
+        #[derive(Clone, Default)]
+        struct Collector {
+            spans: Arc<Mutex<Vec<Recorded>>>,
+            stack: Arc<Mutex<Vec<u64>>>,
+        }
+
+        impl tracing::Subscriber for Collector {
+            fn enabled(&self, _: &tracing::Metadata<'_>) -> bool {
+                true
+            }
+
+            fn new_span(&self, attrs: &Attributes<'_>) -> Id {
+                let parent = if attrs.is_contextual() {
+                    self.stack.lock().unwrap().last().copied()
+                } else {
+                    attrs.parent().map(Id::into_u64)
+                };
+                let mut span = Recorded { name: attrs.metadata().name(), parent, ..Recorded::default() };
+                attrs.record(&mut Fields(&mut span.fields));
+                let mut spans = self.spans.lock().unwrap();
+                spans.push(span);
+                Id::from_u64(spans.len() as u64)
+            }
+
+            fn record(&self, id: &Id, values: &Record<'_>) {
+                let mut spans = self.spans.lock().unwrap();
+                values.record(&mut Fields(&mut spans[id.into_u64() as usize - 1].fields));
+            }
+
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+            fn event(&self, _: &tracing::Event<'_>) {}
+
+            fn enter(&self, id: &Id) {
+                self.stack.lock().unwrap().push(id.into_u64());
+            }
+
+            fn exit(&self, _: &Id) {
+                self.stack.lock().unwrap().pop();
+            }
+        }
+
+        let collector = Collector::default();
+        tracing::subscriber::with_default(collector.clone(), || {
+            let server = pop3_client::test_util::FakeServer::new(vec![pop3_client::test_util::Message::new("uid-1", b"hi\r\n")])
+                .with_credentials("sweet_username", "1234");
+            let mut client = plain().from_stream(server).unwrap();
+            client.login("sweet_username", "1234").unwrap();
+            client.retr(1).unwrap();
+            client.dele(2).unwrap_err();
+        });
+
+        let spans = collector.spans.lock().unwrap();
+        let find = |name: &str, command: Option<&str>| {
+            let index = spans
+                .iter()
+                .position(|s| s.name == name && command.is_none_or(|c| s.fields.get("command").map(String::as_str) == Some(c)))
+                .unwrap_or_else(|| panic!("no {} span for {:?}", name, command));
+            (index as u64 + 1, &spans[index])
+        };
+        let (session, recorded) = find("pop3.session", None);
+        assert_eq!(recorded.fields["account"], "sweet_username");
+        let (auth, recorded) = find("pop3.auth", None);
+        assert_eq!((recorded.parent, recorded.fields["status"].as_str()), (Some(session), "ok"));
+        let (_, pass) = find("pop3.command", Some("PASS"));
+        assert_eq!(pass.parent, Some(auth));
+        // A numeric password isn't a message number
+        assert!(!pass.fields.contains_key("msg"));
+
+        let (_, retr) = find("pop3.command", Some("RETR"));
+        assert_eq!(retr.parent, Some(session));
+        assert_eq!((retr.fields["msg"].as_str(), retr.fields["status"].as_str()), ("1", "ok"));
+        assert!(retr.fields["bytes"].parse::<u64>().unwrap() > 4);
+        assert_eq!(find("pop3.command", Some("DELE")).1.fields["status"], "server");
+    }
+
+    #[test]
+    fn sasl_authenticators_scripted() {
+        use pop3_client::{AuthStep, Authenticator, SaslPlain, XOAuth2};
+
+        let (mut client, sent) = scripted("+OK welcome\r\n");
+        SaslPlain::new("sweet_username", "tanstaaf").authenticate(&mut client).unwrap();
+        assert!(client.is_authorized());
+        assert_eq!(&*sent.lock().unwrap(), b"AUTH PLAIN AHN3ZWV0X3VzZXJuYW1lAHRhbnN0YWFm\r\n");
+
+        // The failure details come in a challenge, answered with an empty line
+        let (mut client, sent) = scripted("+ eyJzdGF0dXMiOiI0MDEifQ==\r\n-ERR [AUTH] invalid token\r\n");
+        match XOAuth2::new("sweet_username", "expired").authenticate(&mut client) {
+            Err(Error::Auth(e)) => assert_eq!(e.step, AuthStep::Auth),
+            other => panic!("expected a refused login, got {:?}", other),
+        }
+        assert!(sent.lock().unwrap().ends_with(b"\r\n\r\n"));
+
+        struct Echo;
+
+        impl Authenticator for Echo {
+            fn authenticate(&self, client: &mut Client) -> Result<()> {
+                client.auth("X-ECHO", None, |challenge| Ok(challenge.to_ascii_uppercase()))
+            }
+        }
+
+        let (mut client, sent) = scripted("+ aGVsbG8=\r\n+OK\r\n");
+        Echo.authenticate(&mut client).unwrap();
+        assert_eq!(&*sent.lock().unwrap(), b"AUTH X-ECHO\r\nSEVMTE8=\r\n");
+    }
+
+    #[cfg(feature = "gssapi")]
+    #[test]
+    fn gssapi_negotiates_no_security_layer() {
+        use pop3_client::gssapi::{GssResult, Gssapi, SecurityContext};
+        use pop3_client::Authenticator;
+
+        /// Two rounds, then wraps by prefixing `W`
+        struct Fake(u8);
+
+        impl SecurityContext for Fake {
+            fn step(&mut self, token: Option<&[u8]>) -> GssResult<Option<Vec<u8>>> {
+                self.0 += 1;
+                match (self.0, token) {
+                    (1, None) => Ok(Some(b"ap-req".to_vec())),
+                    (2, Some(b"ap-rep")) => Ok(None),
+                    _ => Err("unexpected token".into()),
+                }
+            }
+
+            fn is_complete(&self) -> bool {
+                self.0 >= 2
+            }
+
+            fn wrap(&mut self, message: &[u8]) -> GssResult<Vec<u8>> {
+                Ok([b"W", message].concat())
+            }
+
+            fn unwrap(&mut self, token: &[u8]) -> GssResult<Vec<u8>> {
+                token.strip_prefix(b"W").map(<[u8]>::to_vec).ok_or_else(|| "bad wrap".into())
+            }
+        }
+
+        // "ap-rep", then a wrapped offer of no security layer or integrity, up to 64 KiB
+        let (mut client, sent) = scripted("+ YXAtcmVw\r\n+ VwMBAAA=\r\n+OK\r\n");
+        Gssapi::new(|| Ok(Fake(0))).authzid("shared").authenticate(&mut client).unwrap();
+        assert!(client.is_authorized());
+        assert_eq!(&*sent.lock().unwrap(), b"AUTH GSSAPI YXAtcmVx\r\n\r\nVwEAAABzaGFyZWQ=\r\n");
+
+        // Only integrity offered: cancelled, and told apart from a refusal
+        let (mut client, sent) = scripted("+ YXAtcmVw\r\n+ VwIBAAA=\r\n-ERR cancelled\r\n");
+        match Gssapi::new(|| Ok(Fake(0))).authenticate(&mut client) {
+            Err(Error::Sasl(e)) => assert!(e.contains("security layer")),
+            other => panic!("expected a SASL error, got {:?}", other),
+        }
+        assert!(sent.lock().unwrap().ends_with(b"\r\n*\r\n"));
+        assert!(!client.is_authorized());
+    }
+
+    #[cfg(feature = "ntlm")]
+    #[test]
+    fn ntlm_v2_exchange_scripted() {
+        use pop3_client::{Authenticator, Ntlm};
+
+        // The challenge of MS-NLMP 4.2.4, with a zero timestamp added to its target information
+        let (mut client, sent) = scripted(
+            "+ \r\n\
+             + TlRMTVNTUAACAAAADAAMADAAAAAHgogAASNFZ4mrze8AAAAAAAAAADAAMAA8AAAARABvAG0AYQBpAG4AAgAMAEQAbwBtAGEAaQBuAAEADABTAGUAcgB2AGUAcgAHAAgAAAAAAAAAAAAAAAAA\r\n\
+             +OK\r\n",
+        );
+        Ntlm::new("User", "Password")
+            .client_challenge([0xaa; 8])
+            .authenticate(&mut client)
+            .unwrap();
+        assert!(client.is_authorized());
+
+        let sent = String::from_utf8(sent.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = sent.split("\r\n").collect();
+        assert_eq!(lines[..2], ["AUTH NTLM", "TlRMTVNTUAABAAAAB4IIAAAAAAAAAAAAAAAAAAAAAAA="]);
+        assert_eq!(
+            lines[2],
+            "TlRMTVNTUAADAAAAGAAYAEAAAABgAGAAWAAAAAwADAC4AAAACAAIAMQAAAAAAAAAzAAAAAAAAADMAAAAB4IIAAAAAAAAAAAAAAAAAAAAAAAAAAAA\
+             AAAAAPtOxY1mKh68O1Uklx13IH4BAQAAAAAAAAAAAAAAAAAAqqqqqqqqqqoAAAAAAgAMAEQAbwBtAGEAaQBuAAEADABTAGUAcgB2AGUAcgAHAAgA\
+             AAAAAAAAAAAAAAAAAAAAAEQAbwBtAGEAaQBuAFUAcwBlAHIA"
+        );
+    }
+
+    #[test]
+    fn cram_and_digest_md5_scripted() {
+        use pop3_client::{Authenticator, CramMd5, DigestMd5};
+
+        // RFC 2195, section 2
+        let (mut client, sent) = scripted("+ PDE4OTYuNjk3MTcwOTUyQHBvc3RvZmZpY2UucmVzdG9uLm1jaS5uZXQ+\r\n+OK\r\n");
+        CramMd5::new("tim", "tanstaaftanstaaf").authenticate(&mut client).unwrap();
+        assert_eq!(
+            &*sent.lock().unwrap(),
+            b"AUTH CRAM-MD5\r\ndGltIGI5MTNhNjAyYzdlZGE3YTQ5NWI0ZTZlNzMzNGQzODkw\r\n"
+        );
+
+        // RFC 2831, section 4, for the pop service
+        let challenge = "+ cmVhbG09ImVsd29vZC5pbm5vc29mdC5jb20iLG5vbmNlPSJPQTZNRzl0RVFHbTJoaCIscW9wPSJhdXRoIixhbGdvcml0aG09bWQ1LXNlc3MsY2hhcnNldD11dGYtOA==\r\n";
+        let digest = DigestMd5::new("chris", "secret").host("elwood.innosoft.com").cnonce("OA6MHXh6VqTrRk");
+        let (mut client, sent) = scripted(&format!("{}+ cnNwYXV0aD0wYjk3MTQ2MmNlZjVlOGY5MzBkYjlhMzNiMDJmYzlhMA==\r\n+OK\r\n", challenge));
+        digest.authenticate(&mut client).unwrap();
+        assert!(client.is_authorized());
+        assert_eq!(
+            String::from_utf8(sent.lock().unwrap().clone()).unwrap(),
+            "AUTH DIGEST-MD5\r\n\
+             Y2hhcnNldD11dGYtOCx1c2VybmFtZT0iY2hyaXMiLHJlYWxtPSJlbHdvb2QuaW5ub3NvZnQuY29tIixub25jZT0iT0E2TUc5dEVRR20yaGgiLG5j\
+             PTAwMDAwMDAxLGNub25jZT0iT0E2TUhYaDZWcVRyUmsiLGRpZ2VzdC11cmk9InBvcC9lbHdvb2QuaW5ub3NvZnQuY29tIixyZXNwb25zZT1iMGQ1\
+             NmQyZjA1NGMyNGI2MjA3MjMyMjEwNjQ2OGRiOSxxb3A9YXV0aA==\r\n\r\n"
+        );
+
+        // The proof of the imap service doesn't hold for pop
+        let (mut client, sent) = scripted(&format!("{}+ cnNwYXV0aD1lYTQwZjYwMzM1YzQyN2I1NTI3Yjg0ZGJhYmNkZmZmZA==\r\n-ERR\r\n", challenge));
+        assert!(matches!(digest.authenticate(&mut client), Err(Error::Sasl(_))));
+        assert!(sent.lock().unwrap().ends_with(b"\r\n*\r\n"));
+    }
+
+    #[test]
+    #[cfg(feature = "scram")]
+    fn scram_sha1_and_sha256_scripted() {
+        use pop3_client::{Authenticator, Scram};
+
+        // RFC 5802, section 5
+        let server_first = "+ cj1meWtvK2QybGJiRmdPTlJ2OXFreGRhd0wzcmZjTkhZSlkxWlZ2V1ZzN2oscz1RU1hDUitRNnNlazhiZjkyLGk9NDA5Ng==\r\n";
+        let scram = Scram::sha1("user", "pencil").cnonce("fyko+d2lbbFgONRv9qkxdawL");
+        let (mut client, sent) = scripted(&format!("{}+ dj1ybUY5cHFWOFM3c3VBb1pXamE0ZEpSa0ZzS1E9\r\n+OK\r\n", server_first));
+        scram.authenticate(&mut client).unwrap();
+        assert!(client.is_authorized());
+        assert_eq!(
+            String::from_utf8(sent.lock().unwrap().clone()).unwrap(),
+            "AUTH SCRAM-SHA-1 biwsbj11c2VyLHI9ZnlrbytkMmxiYkZnT05Sdjlxa3hkYXdM\r\n\
+             Yz1iaXdzLHI9ZnlrbytkMmxiYkZnT05Sdjlxa3hkYXdMM3JmY05IWUpZMVpWdldWczdqLHA9djBYOHYzQnoyVDBDSkdiSlF5RjBYK0hJNFRzPQ==\r\n\
+             \r\n"
+        );
+
+        // A server signature that doesn't hold cancels the exchange
+        let (mut client, sent) = scripted(&format!("{}+ dj1ybUY5cHFWOFM3c3VBb1pXamE0ZEpSAAAAAAA=\r\n-ERR\r\n", server_first));
+        assert!(matches!(scram.authenticate(&mut client), Err(Error::Sasl(_))));
+        assert!(sent.lock().unwrap().ends_with(b"\r\n*\r\n"));
+
+        // RFC 7677, section 3
+        let (mut client, sent) = scripted(
+            "+ cj1yT3ByTkdmd0ViZVJXZ2JORWtxTyVodllEcFdVYTJSYVRDQWZ1eEZJbGopaE5sRiRrMCxzPVcyMlphSjBTTlk3c29Fc1VFamI2Z1E9PSxpPTQwOTY=\r\n\
+             + dj02cnJpVFJCaTIzV3BSUi93dHVwK21NaFVaVW4vZEI1bkxUSlJzamw5NUc0PQ==\r\n+OK\r\n",
+        );
+        Scram::sha256("user", "pencil").cnonce("rOprNGfwEbeRWgbNEkqO").authenticate(&mut client).unwrap();
+        assert_eq!(
+            String::from_utf8(sent.lock().unwrap().clone()).unwrap(),
+            "AUTH SCRAM-SHA-256 biwsbj11c2VyLHI9ck9wck5HZndFYmVSV2diTkVrcU8=\r\n\
+             Yz1iaXdzLHI9ck9wck5HZndFYmVSV2diTkVrcU8laHZZRHBXVWEyUmFUQ0FmdXhGSWxqKWhObEYkazAscD1kSHpiWmFwV0lrNGpVaE4rVXRlOXl0\
+             YWc5empmTUhnc3FtbWl6N0FuZFZRPQ==\r\n\r\n"
+        );
+
+        // A server asking for billions of iterations is refused before any is run
+        let (mut client, sent) = scripted(
+            "+ cj1meWtvK2QybGJiRmdPTlJ2OXFreGRhd0wzcmZjTkhZSlkxWlZ2V1ZzN2oscz1RU1hDUitRNnNlazhiZjkyLGk9NDI5NDk2NzI5NQ==\r\n\
+             -ERR\r\n",
+        );
+        assert!(matches!(scram.authenticate(&mut client), Err(Error::Sasl(e)) if e.contains("4294967295")));
+        assert!(sent.lock().unwrap().ends_with(b"\r\n*\r\n"));
+
+        // Without a fixed one, every exchange gets a nonce of its own
+        let first_line = || {
+            let (mut client, sent) = scripted("-ERR\r\n");
+            Scram::sha256("user", "pencil").authenticate(&mut client).unwrap_err();
+            let sent = sent.lock().unwrap().clone();
+            String::from_utf8(sent).unwrap()
+        };
+        assert_ne!(first_line(), first_line());
+    }
+
+    #[test]
+    fn auto_auth_picks_the_strongest_mechanism() {
+        use pop3_client::{Builder, Credentials};
+
+        let credentials = Credentials::new("tim", "tanstaaftanstaaf");
+        let capa = "+OK\r\nUSER\r\nSASL PLAIN CRAM-MD5\r\n.\r\n";
+
+        // CRAM-MD5 beats PLAIN, which needs TLS anyway
+        let (mut client, sent) = scripted(&format!(
+            "{}+ PDE4OTYuNjk3MTcwOTUyQHBvc3RvZmZpY2UucmVzdG9uLm1jaS5uZXQ+\r\n+OK\r\n",
+            capa
+        ));
+        client.auto_auth(&credentials).unwrap();
+        assert!(client.is_authorized());
+        assert_eq!(
+            &*sent.lock().unwrap(),
+            b"CAPA\r\nAUTH CRAM-MD5\r\ndGltIGI5MTNhNjAyYzdlZGE3YTQ5NWI0ZTZlNzMzNGQzODkw\r\n"
+        );
+
+        // Plain text and no CAPA leave USER/PASS
+        let (mut client, sent) = scripted("+OK\r\nUSER\r\nSASL PLAIN\r\n.\r\n+OK\r\n+OK\r\n");
+        client.auto_auth(&credentials).unwrap();
+        assert_eq!(&*sent.lock().unwrap(), b"CAPA\r\nUSER tim\r\nPASS tanstaaftanstaaf\r\n");
+        let (mut client, sent) = scripted("-ERR unknown command\r\n+OK\r\n+OK\r\n");
+        client.auto_auth(&credentials).unwrap();
+        assert_eq!(&*sent.lock().unwrap(), b"CAPA\r\nUSER tim\r\nPASS tanstaaftanstaaf\r\n");
+
+        // The list of the builder wins, and may leave nothing in common
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let stream = Scripted {
+            replies: std::io::Cursor::new(format!("+OK ready\r\n{}+OK\r\n", capa).into_bytes()),
+            sent: sent.clone(),
+        };
+        let mut client = Builder::default().mechanisms(&["plain", "user"]).allow_plaintext_auth(true).from_stream(stream).unwrap();
+        client.auto_auth(&credentials).unwrap();
+        assert_eq!(&*sent.lock().unwrap(), b"CAPA\r\nAUTH PLAIN AHRpbQB0YW5zdGFhZnRhbnN0YWFm\r\n");
+
+        let stream = Scripted {
+            replies: std::io::Cursor::new(b"+OK ready\r\n+OK\r\nSASL PLAIN\r\n.\r\n".to_vec()),
+            sent: sent.clone(),
+        };
+        let mut client = Builder::default().mechanisms(&["CRAM-MD5"]).from_stream(stream).unwrap();
+        assert!(matches!(client.auto_auth(&credentials), Err(Error::Sasl(_))));
+        let mut client = Builder::default().mechanisms(&["KERBEROS_V4"]).from_stream(Scripted {
+            replies: std::io::Cursor::new(b"+OK ready\r\n+OK\r\n.\r\n".to_vec()),
+            sent,
+        }).unwrap();
+        assert!(matches!(client.auto_auth(&credentials), Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn plaintext_auth_needs_tls_or_consent() {
+        use pop3_client::{Authenticator, CramMd5, SaslPlain};
+
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let stream = Scripted {
+            replies: std::io::Cursor::new(b"+OK ready\r\n+ PDE4OTYuNjk3MTcwOTUyQHBvc3RvZmZpY2UucmVzdG9uLm1jaS5uZXQ+\r\n+OK\r\n".to_vec()),
+            sent: sent.clone(),
+        };
+        let mut client = Client::from_stream(stream).unwrap();
+        assert!(!client.is_tls());
+        match client.login("tim", "tanstaaftanstaaf") {
+            Err(Error::PlaintextAuth(command)) => assert_eq!(command, "USER"),
+            other => panic!("expected a policy error, got {:?}", other),
+        }
+        let refused = SaslPlain::new("tim", "tanstaaftanstaaf").authenticate(&mut client);
+        assert!(matches!(refused, Err(Error::PlaintextAuth(_))));
+        assert!(sent.lock().unwrap().is_empty());
+
+        // Mechanisms which don't reveal the password go ahead
+        CramMd5::new("tim", "tanstaaftanstaaf").authenticate(&mut client).unwrap();
+        assert!(client.is_authorized());
+    }
+
+    #[test]
+    fn lock_and_login_delay_carry_the_capa_delay() {
+        use pop3_client::{AuthStep, ErrorCode};
+        use std::time::Duration;
+
+        let (mut client, _) = scripted("-ERR [LOGIN-DELAY] wait a bit\r\n");
+        let early = client.login("user", "secret").unwrap_err();
+        assert!(matches!(&early, Error::LoginDelay { step: AuthStep::User, retry_after: None, .. }));
+        assert_eq!(early.code(), Some(ErrorCode::LoginDelay));
+        assert!(!early.is_transient());
+
+        let (mut client, _) = scripted(
+            "+OK\r\nLOGIN-DELAY 900\r\nUSER\r\n.\r\n\
+             +OK\r\n-ERR [IN-USE] do you have another POP session running?\r\n\
+             -ERR [LOGIN-DELAY] wait a bit\r\n",
+        );
+        assert_eq!(client.capa().unwrap().login_delay(), Some(Duration::from_secs(900)));
+        let locked = client.login("user", "secret").unwrap_err();
+        assert!(matches!(locked, Error::MailboxLocked { step: AuthStep::Pass, .. }));
+        assert_eq!(locked.retry_after(), Some(Duration::from_secs(900)));
+        assert!(locked.is_transient());
+        let early = client.apop("user", "c4c9334bac560ecc979e58001b3e22fb").unwrap_err();
+        assert!(matches!(early, Error::LoginDelay { step: AuthStep::Apop, .. }));
+        assert_eq!(early.retry_after(), Some(Duration::from_secs(900)));
+    }
+
+    #[test]
+    fn health_check_and_latency_stats() {
+        let (mut client, sent) = scripted("-ERR no CAPA here\r\n+OK\r\n+OK\r\n+OK 1 octets\r\nx\r\n.\r\n+OK\r\n");
+        assert_eq!(client.latency().samples, 0);
+
+        // Before login the check takes CAPA, and an -ERR still proves the server is there
+        let health = client.health_check();
+        assert!(health.alive);
+        assert_eq!(health.latency.samples, 1);
+        assert_eq!(health.latency.last, health.latency.max);
+
+        client.login("user", "secret").unwrap();
+        client.retr(1).unwrap();
+        let health = client.health_check();
+        assert!(health.alive);
+        assert_eq!(health.latency.samples, 4);
+        assert!(health.latency.min <= health.latency.mean && health.latency.mean <= health.latency.max);
+        assert_eq!(
+            String::from_utf8_lossy(&sent.lock().unwrap()),
+            "CAPA\r\nUSER user\r\nPASS secret\r\nRETR 1\r\nNOOP\r\n"
+        );
+
+        // The script is over, as if the server had hung up
+        let health = client.health_check();
+        assert!(!health.alive);
+        assert_eq!(health.latency.samples, 4);
+    }
+
+    #[test]
+    fn line_endings_are_normalized_on_request() {
+        use pop3_client::test_util::{FakeServer, Message};
+        use pop3_client::Normalize;
+
+        let content = b"Subject: endings\r\n\r\n.dotted\r\nlast\r\n";
+        let open = |normalize: Normalize| {
+            let server = FakeServer::new(vec![Message::new("uid-1", content)]);
+            let mut client = plain().line_ending(normalize).from_stream(server).unwrap();
+            client.login("user", "password").unwrap();
+            client
+        };
+
+        let mut client = open(Normalize::Lf);
+        let lf = b"Subject: endings\n\n.dotted\nlast\n";
+        assert_eq!(&client.retr_bytes(1).unwrap()[..], &lf[..]);
+        let mut written = vec![];
+        assert_eq!(client.retr_to_writer(1, &mut written).unwrap(), lf.len() as u64);
+        assert_eq!(written, lf);
+        assert!(!client.retr(1).unwrap().contains('\r'));
+
+        let crlf = b"Subject: endings\r\n\r\n.dotted\r\nlast\r\n";
+        for normalize in [Normalize::CrLf, Normalize::Preserve] {
+            let mut client = open(normalize);
+            assert_eq!(&client.retr_bytes(1).unwrap()[..], &crlf[..]);
+            let mut written = vec![];
+            client.retr_to_writer(1, &mut written).unwrap();
+            assert_eq!(written, crlf);
+        }
+    }
+
+    #[test]
+    fn oversized_lines_are_bounded() {
+        let long = format!("+OK {}", "x".repeat(600));
+
+        // RFC 2449 holds status lines to 512 octets, which only strict mode enforces
+        let (mut client, _) = strict_scripted("+OK ready", &format!("{}\r\n", long));
+        assert!(matches!(client.noop().unwrap_err(), Error::LineTooLong(512)));
+        assert!(client.noop().is_err());
+        let (mut client, _) = scripted(&format!("{}\r\n", long));
+        client.noop().unwrap();
+
+        // A line that never ends is cut off at the configured cap
+        let endless = format!("+OK ready\r\n+OK {}", "x".repeat(10_000));
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let stream = Scripted { replies: std::io::Cursor::new(endless.into_bytes()), sent };
+        let mut client = plain().max_line_length(1024).from_stream(stream).unwrap();
+        assert!(matches!(client.noop().unwrap_err(), Error::LineTooLong(1024)));
+    }
+
+    #[test]
+    fn single_message_listings_are_optional() {
+        use pop3_client::{MessageInfo, UidlEntry};
+
+        let (mut client, _) = scripted(concat!(
+            "+OK\r\n+OK\r\n",
+            "+OK 1 120\r\n-ERR no such message\r\n-ERR [SYS/TEMP] try again later\r\n",
+            "+OK 1 uid-1\r\n-ERR no such message\r\n",
+        ));
+        client.login("user", "password").unwrap();
+        assert_eq!(client.list_one(1).unwrap(), Some(MessageInfo { id: 1, size: 120 }));
+        assert_eq!(client.list_one(9).unwrap(), None);
+        assert!(matches!(client.list_one(2), Err(Error::Server(_))));
+        assert_eq!(client.uidl_one(1).unwrap(), Some(UidlEntry { id: 1, uid: "uid-1".to_string() }));
+        assert_eq!(client.uidl_one(9).unwrap(), None);
+
+        // Before the login the server refuses whatever the message
+        let (mut client, _) = scripted("-ERR not yet\r\n");
+        assert!(matches!(client.list_one(1), Err(Error::WrongStage(_))));
+    }
+
+    #[test]
+    fn batch_deletes_report_each_message() {
+        // One by one, without PIPELINING
+        let (mut client, sent) = scripted("+OK\r\n+OK\r\n+OK\r\n-ERR no such message\r\n+OK\r\n");
+        client.login("user", "password").unwrap();
+        let report = client.dele_many(&[1, 9, 2]).unwrap();
+        assert_eq!(report.deleted, vec![1, 2]);
+        assert_eq!(report.failed, vec![(9, "no such message".to_string())]);
+        assert!(!report.is_complete());
+        assert!(sent.lock().unwrap().ends_with(b"DELE 1\r\nDELE 9\r\nDELE 2\r\n"));
+
+        // Pipelined once CAPA announced it, and undone on a refusal
+        let (mut client, sent) = scripted(concat!(
+            "+OK\r\n+OK\r\n+OK\r\nPIPELINING\r\n.\r\n",
+            "+OK\r\n-ERR already deleted\r\n+OK\r\n",
+        ));
+        client.login("user", "password").unwrap();
+        client.capa().unwrap();
+        let report = client.dele_all_or_nothing(&[1, 2]).unwrap();
+        assert_eq!(report.deleted, vec![1]);
+        assert!(report.reset);
+        assert!(sent.lock().unwrap().ends_with(b"CAPA\r\nDELE 1\r\nDELE 2\r\nRSET\r\n"));
+
+        let (mut client, _) = scripted("");
+        assert!(matches!(client.dele_many(&[1]), Err(Error::WrongStage(_))));
+    }
+
+    #[test]
+    fn expire_capability_is_a_retention_policy() {
+        use pop3_client::RetentionPolicy;
+
+        let (mut client, _) = scripted(concat!(
+            "+OK\r\nEXPIRE 30 USER\r\n.\r\n",
+            "+OK\r\nEXPIRE NEVER\r\n.\r\n",
+            "+OK\r\nUIDL\r\n.\r\n",
+        ));
+        assert_eq!(client.retention_policy(), None);
+        client.capa().unwrap();
+        assert_eq!(client.retention_policy(), Some(RetentionPolicy { days: Some(30), user_specific: true, never: false }));
+        let capabilities = client.capa().unwrap();
+        assert_eq!(capabilities.retention(), Some(RetentionPolicy { days: None, user_specific: false, never: true }));
+        // A later CAPA without EXPIRE doesn't forget the policy
+        client.capa().unwrap();
+        assert!(client.retention_policy().unwrap().never);
+    }
+
+    #[test]
+    fn account_config_from_env_and_toml() {
+        use pop3_client::fetcher::AccountConfig;
+        use pop3_client::AuthMechanism;
+        use std::time::Duration;
+
+        std::env::set_var("POP3_ENV_TEST_HOST", "pop.example.com");
+        std::env::set_var("POP3_ENV_TEST_USERNAME", "me");
+        std::env::set_var("POP3_ENV_TEST_PASSWORD", "secret");
+        std::env::set_var("POP3_ENV_TEST_PORT", "995");
+        std::env::set_var("POP3_ENV_TEST_AUTH", "apop");
+        std::env::set_var("POP3_ENV_TEST_TIMEOUT", "30");
+        let config = AccountConfig::from_env("POP3_ENV_TEST").unwrap();
+        assert_eq!((config.host.as_str(), config.port), ("pop.example.com", 995));
+        assert_eq!(config.credentials.password, "secret");
+        assert_eq!(config.auth, AuthMechanism::Apop);
+        assert_eq!(config.timeout, Some(Duration::from_secs(30)));
+        assert_eq!(config.retention_days, None);
+
+        std::env::set_var("POP3_ENV_TEST_PORT", "pop3s");
+        assert!(matches!(AccountConfig::from_env("POP3_ENV_TEST"), Err(Error::Config(_))));
+        assert!(matches!(AccountConfig::from_env("POP3_ENV_MISSING"), Err(Error::Config(_))));
+
+        #[cfg(feature = "config")]
+        {
+            let config = AccountConfig::from_toml(concat!(
+                "host = \"pop.example.com\"\n",
+                "allow_plaintext_auth = true\n",
+                "auth = \"user-pass\"\n",
+                "retention_days = 14\n",
+                "[credentials]\n",
+                "username = \"me\"\n",
+                "password = \"secret\"\n",
+            )).unwrap();
+            assert_eq!(config.port, 110);
+            assert!(config.allow_plaintext_auth);
+            assert_eq!(config.retention_days, Some(14));
+            assert!(matches!(AccountConfig::from_toml("host = \"pop.example.com\""), Err(Error::Config(_))));
+            assert!(matches!(AccountConfig::from_toml("auth = \"telnet\""), Err(Error::Config(_))));
+        }
+    }
+
+    #[test]
+    fn credentials_provider_is_asked_again_after_a_refusal() {
+        use pop3_client::Credentials;
+        use std::io::{BufRead, Write};
+
+        let (listener, port) = silent_server();
+        let server = std::thread::spawn(move || {
+            let (mut socket, _) = listener.accept().unwrap();
+            socket.write_all(b"+OK ready\r\n").unwrap();
+            let mut commands = std::io::BufReader::new(socket.try_clone().unwrap());
+            let mut line = String::new();
+            while commands.read_line(&mut line).is_ok_and(|n| n > 0) {
+                let reply: &[u8] = if line.starts_with("PASS wrong") { b"-ERR [AUTH] invalid password\r\n" } else { b"+OK\r\n" };
+                socket.write_all(reply).unwrap();
+                line.clear();
+            }
+        });
+
+        let attempts = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let asked = attempts.clone();
+        let mut builder = plain();
+        #[cfg(feature = "with-rustls")]
+        builder.tls(false);
+        let client = builder
+            .credentials_provider(move |account| {
+                asked.lock().unwrap().push((account.host.to_string(), account.attempt));
+                let password = if account.attempt == 1 { "wrong" } else { "right" };
+                Ok(Credentials::new("me", password))
+            })
+            .open("127.0.0.1", port)
+            .unwrap();
+        assert!(client.is_authorized());
+        assert_eq!(*attempts.lock().unwrap(), vec![("127.0.0.1".to_string(), 1), ("127.0.0.1".to_string(), 2)]);
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    #[cfg(feature = "keyring")]
+    fn keyring_lookup_failures_are_store_errors() {
+        use pop3_client::Credentials;
+
+        // The mock keyring forgets every entry, so a lookup never finds anything
+        keyring::set_default_credential_builder(keyring::mock::default_credential_builder());
+        Credentials::new("me", "secret").save_to_keyring("pop3-client-test").unwrap();
+        assert!(matches!(Credentials::from_keyring("pop3-client-test", "me"), Err(Error::Store(_))));
+    }
+
+    #[test]
+    fn pipelined_batches_collect_every_failure() {
+        use pop3_client::proto::Command;
+
+        let (mut client, sent) = scripted(concat!(
+            "+OK\r\n+OK\r\n+OK\r\nPIPELINING\r\n.\r\n",
+            "+OK 2 320\r\n-ERR no such message\r\n+OK\r\n1 uid-1\r\n.\r\n",
+            "-ERR [AUTH] nope\r\n",
+        ));
+        client.login("user", "password").unwrap();
+        client.capa().unwrap();
+        let errors = client
+            .pipeline(&[Command::Stat, Command::Dele(9), Command::Uidl(None), Command::Pass("secret"), Command::Noop])
+            .unwrap_err();
+        assert!(sent.lock().unwrap().ends_with(b"STAT\r\nDELE 9\r\nUIDL\r\nPASS secret\r\nNOOP\r\n"));
+        assert_eq!(errors.total(), 5);
+        assert_eq!(
+            errors.to_string(),
+            "3 of 5 commands failed: DELE 9 (no such message); PASS ([AUTH] nope); NOOP (Connection aborted)"
+        );
+        let failures: Vec<_> = errors.into_iter().map(|failure| (failure.index, failure.error)).collect();
+        assert!(matches!(failures[..], [(1, Error::Server(_)), (3, Error::Server(_)), (4, Error::ConnectionAborted)]));
+
+        // One by one without PIPELINING
+        let (mut client, _) = scripted("+OK 2 320\r\n+OK\r\n");
+        let responses = client.pipeline(&[Command::Stat, Command::Noop]).unwrap();
+        assert_eq!(responses[0].info, "2 320");
+    }
+
+    #[test]
+    fn batches_queue_commands_until_executed() {
+        use pop3_client::{MailboxStat, MessageInfo, Outcome};
+
+        let (mut client, sent) = scripted(concat!(
+            "+OK\r\n+OK\r\n+OK\r\nPIPELINING\r\n.\r\n",
+            "+OK\r\n+OK 2 320\r\n+OK 2 200\r\n+OK\r\nSubject: hi\r\n\r\n..dot\r\n.\r\n",
+        ));
+        client.login("user", "password").unwrap();
+        client.capa().unwrap();
+        let batch = client.batch().dele(1).stat().list(Some(2)).retr(2);
+        let outcomes = batch.execute().unwrap();
+        assert!(sent.lock().unwrap().ends_with(b"DELE 1\r\nSTAT\r\nLIST 2\r\nRETR 2\r\n"));
+        assert_eq!(outcomes, vec![
+            Outcome::Dele,
+            Outcome::Stat(MailboxStat { count: 2, size: 320 }),
+            Outcome::List(vec![MessageInfo { id: 2, size: 200 }]),
+            Outcome::Retr(b"Subject: hi\r\n\r\n.dot\r\n"[..].into()),
+        ]);
+
+        let (mut client, _) = scripted("+OK\r\n-ERR no such message\r\n+OK garbage\r\n");
+        let errors = client.batch().noop().dele(9).stat().execute().unwrap_err();
+        let failures: Vec<_> = errors.failures().iter().map(|failure| (failure.index, failure.command.as_str())).collect();
+        assert_eq!(failures, [(1, "DELE 9"), (2, "STAT")]);
+    }
+
+    #[test]
+    fn encoded_words_of_headers_are_decoded() {
+        use pop3_client::search::{Filter, Headers, Query};
+
+        let headers = Headers::parse(concat!(
+            "Subject: =?UTF-8?B?SGVsbG8sIOS4lueVjA==?=\r\n",
+            " =?utf-8*en?Q?_and_caf=C3=A9?= (=?x-unknown?Q?kept?=)\r\n",
+            "From: =?ISO-8859-1?Q?Andr=E9?= <andre@example.com>\r\n",
+            "X-Plain: a =?broken\r\n",
+            "\r\n",
+        ).as_bytes());
+        assert_eq!(headers.subject().unwrap(), "Hello, 世界 and café (=?x-unknown?Q?kept?=)");
+        assert_eq!(headers.from().unwrap(), "André <andre@example.com>");
+        assert_eq!(headers.decoded("X-Plain").unwrap(), "a =?broken");
+        assert!(Query::default().subject("世界").from("andré").matches(&headers));
+
+        let (mut client, sent) = scripted("+OK\r\n+OK\r\n+OK\r\nSubject: =?UTF-8?Q?=E2=82=AC?=\r\n\r\n.\r\n");
+        client.login("user", "password").unwrap();
+        assert_eq!(client.top_headers(4).unwrap().subject().unwrap(), "€");
+        assert!(sent.lock().unwrap().ends_with(b"TOP 4 0\r\n"));
+    }
+
+    #[test]
+    fn attachments_are_listed_from_the_mime_structure() {
+        use pop3_client::AttachmentInfo;
+
+        let message = concat!(
+            "Subject: report\r\n",
+            "Content-Type: multipart/mixed; boundary=\"outer; b\"\r\n",
+            "\r\n",
+            "preamble\r\n",
+            "--outer; b\r\n",
+            "Content-Type: multipart/alternative; boundary=inner\r\n",
+            "\r\n",
+            "--inner\r\n",
+            "Content-Type: text/plain\r\n",
+            "\r\n",
+            "Hello\r\n",
+            "--inner--\r\n",
+            "--outer; b\r\n",
+            "Content-Type: application/pdf; name=ignored.pdf\r\n",
+            "Content-Disposition: attachment;\r\n",
+            " filename*=UTF-8''r%C3%A9sum%C3%A9.pdf\r\n",
+            "Content-Transfer-Encoding: base64\r\n",
+            "\r\n",
+            "AAAAAAAA\r\n",
+            "AAAA\r\n",
+            "--outer; b\r\n",
+            "Content-Type: image/PNG; name=\"=?UTF-8?Q?logo=5F1.png?=\"\r\n",
+            "\r\n",
+            "png\r\n",
+            "--outer; b--\r\n",
+            "epilogue\r\n",
+        );
+        let (mut client, sent) = scripted(&format!("+OK\r\n+OK\r\n+OK\r\n{}.\r\n", message));
+        client.login("user", "password").unwrap();
+        assert_eq!(client.attachments(2).unwrap(), vec![
+            AttachmentInfo { filename: Some("résumé.pdf".to_string()), mime_type: "application/pdf".to_string(), approx_size: 9 },
+            AttachmentInfo { filename: Some("logo_1.png".to_string()), mime_type: "image/png".to_string(), approx_size: 5 },
+        ]);
+        assert!(sent.lock().unwrap().ends_with(b"TOP 2 256\r\n"));
+
+        // A plain message needs its headers only
+        let (mut client, _) = scripted("+OK\r\n+OK\r\n+OK\r\nSubject: hi\r\n\r\nHello\r\n.\r\n");
+        client.login("user", "password").unwrap();
+        assert!(client.attachments(1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn parallel_fetches_share_the_messages_across_sessions() {
+        use std::io::{BufRead, Write};
+
+        let (listener, port) = silent_server();
+        std::thread::spawn(move || {
+            for socket in listener.incoming().take(2) {
+                let mut socket = socket.unwrap();
+                std::thread::spawn(move || {
+                    socket.write_all(b"+OK ready\r\n").unwrap();
+                    let mut commands = std::io::BufReader::new(socket.try_clone().unwrap());
+                    let mut line = String::new();
+                    while commands.read_line(&mut line).is_ok_and(|n| n > 0) {
+                        let reply = match line.trim_end().strip_prefix("RETR ") {
+                            Some("9") => "-ERR no such message\r\n".to_string(),
+                            Some(msg) => format!("+OK\r\nmessage {}\r\n.\r\n", msg),
+                            None      => "+OK\r\n".to_string(),
+                        };
+                        socket.write_all(reply.as_bytes()).unwrap();
+                        line.clear();
+                    }
+                });
+            }
+        });
+
+        let opened = std::sync::atomic::AtomicUsize::new(0);
+        let open = || {
+            if opened.fetch_add(1, std::sync::atomic::Ordering::SeqCst) > 0 {
+                return Err(Error::Server("[IN-USE] maildrop locked".to_string()));
+            }
+            let mut client = connect_plain(port);
+            client.login("me", "secret")?;
+            Ok(client)
+        };
+        let mut client = connect_plain(port);
+        client.login("me", "secret").unwrap();
+        let ids = [3, 1, 9, 4, 2, 7];
+        let results = client.parallel_fetch(&ids, 2, open);
+        assert_eq!(opened.load(std::sync::atomic::Ordering::SeqCst), 2);
+        for (id, result) in ids.iter().zip(results) {
+            match result {
+                Ok(message) => assert_eq!(message, format!("message {}\r\n", id).as_bytes()),
+                Err(e)      => assert!(*id == 9 && matches!(e, Error::Server(_))),
+            }
+        }
+        assert!(client.noop().is_ok());
+    }
+
+    #[test]
+    fn fetch_streams_wait_for_their_consumer() {
+        let (mut client, sent) = scripted(concat!(
+            "+OK\r\n+OK\r\n",
+            "+OK\r\none\r\n.\r\n-ERR no such message\r\n+OK\r\nthree\r\n.\r\n",
+            "+OK bye\r\n",
+        ));
+        client.login("user", "password").unwrap();
+        let retrs = || sent.lock().unwrap().windows(4).filter(|window| window == b"RETR").count();
+
+        let mut messages = client.fetch_stream(vec![1, 2, 3], 1);
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert_eq!(retrs(), 1);
+        assert_eq!(messages.next().unwrap().unwrap(), (1, b"one\r\n".to_vec()));
+        assert!(matches!(messages.next(), Some(Err(Error::Server(_)))));
+        #[cfg(feature = "stream")]
+        {
+            use futures_core::Stream;
+            use std::task::{Context, Poll, Waker};
+
+            let mut cx = Context::from_waker(Waker::noop());
+            let polled = loop {
+                match std::pin::Pin::new(&mut messages).poll_next(&mut cx) {
+                    Poll::Ready(item) => break item,
+                    Poll::Pending     => std::thread::yield_now(),
+                }
+            };
+            assert_eq!(polled.unwrap().unwrap(), (3, b"three\r\n".to_vec()));
+        }
+        #[cfg(not(feature = "stream"))]
+        assert_eq!(messages.next().unwrap().unwrap(), (3, b"three\r\n".to_vec()));
+        assert!(messages.next().is_none());
+        messages.finish().unwrap().quit().unwrap();
+        assert_eq!(retrs(), 3);
+    }
+
+    #[test]
+    fn fetch_streams_end_when_their_worker_panics() {
+        use pop3_client::test_util::{FakeServer, Message};
+
+        let server = FakeServer::new(vec![Message::new("uid-1", b"one\r\n"), Message::new("uid-2", b"two\r\n")]);
+        let mut client = plain().progress(|_| panic!("progress callback")).from_stream(server).unwrap();
+        client.login("user", "password").unwrap();
+
+        let mut messages = client.fetch_stream(vec![1, 2], 1);
+        assert!(matches!(messages.next(), Some(Err(Error::ConnectionAborted))));
+        assert!(messages.next().is_none());
+        assert!(matches!(messages.finish(), Err(Error::ConnectionAborted)));
+    }
+
+    #[test]
+    fn bare_and_lower_case_status_lines_are_understood() {
+        let (mut client, _) = scripted("+ok\r\n+ok\r\n+ok 2 320\r\n+OK\r\n-ERR\r\n-e\r\n");
+        client.login("user", "password").unwrap();
+        assert_eq!(client.stat().unwrap(), (2, 320));
+        assert!(client.noop().is_ok());
+        assert!(matches!(client.dele(1), Err(Error::Server(ref text)) if text.is_empty()));
+        assert!(matches!(client.dele(2), Err(Error::Server(ref text)) if text == "-e"));
+
+        let (mut client, _) = strict_scripted("+OK ready", "+ok\r\n");
+        assert!(matches!(client.noop(), Err(Error::InvalidReply(_))));
+    }
+
+    #[test]
+    fn status_lines_of_real_servers() {
+        use pop3_client::{Compliance, Status};
+
+        // (status line, status, text, accepted in strict mode)
+        let lines: &[(&str, Status, &str, bool)] = &[
+            ("+OK Dovecot ready.", Status::Ok, "Dovecot ready.", true),
+            ("+OK <1896.697170952@dbc.mtview.ca.us>", Status::Ok, "<1896.697170952@dbc.mtview.ca.us>", true),
+            ("+OK", Status::Ok, "", true),
+            ("-ERR Unknown command: XTND", Status::Err, "Unknown command: XTND", true),
+            ("+Ok The Microsoft Exchange POP3 service is ready.", Status::Ok, "The Microsoft Exchange POP3 service is ready.", false),
+            ("+ok 2 messages (320 octets)", Status::Ok, "2 messages (320 octets)", false),
+            ("-err invalid command", Status::Err, "invalid command", false),
+            (" +OK maildrop has 2 messages", Status::Ok, "maildrop has 2 messages", false),
+            ("\t-ERR authentication failed", Status::Err, "authentication failed", false),
+            ("+OK\tQPOP (version 3.1.2) at mail.example.com starting.", Status::Ok, "QPOP (version 3.1.2) at mail.example.com starting.", false),
+        ];
+        for (line, status, text, strict) in lines {
+            let reply = format!("{}\r\n", line);
+            assert_eq!(pop3_client::parser::status(reply.as_bytes()), (*status, text.as_bytes()), "{}", line);
+
+            // The greeting is read before the compliance mode applies, so the line is the reply to a NOOP
+            for compliance in [Compliance::Lenient, Compliance::Strict] {
+                let mut builder = plain();
+                builder.compliance(compliance);
+                let mut client = builder
+                    .from_stream(Scripted {
+                        replies: std::io::Cursor::new(format!("+OK ready\r\n+OK\r\n+OK\r\n{}", reply).into_bytes()),
+                        sent: Default::default(),
+                    })
+                    .unwrap();
+                client.login("user", "password").unwrap();
+                let accepted = compliance == Compliance::Lenient || *strict;
+                match client.noop() {
+                    Ok(())                   => assert!(accepted && *status == Status::Ok, "{}", line),
+                    Err(Error::Server(info)) => assert!(accepted && *status == Status::Err && info == *text, "{}", line),
+                    Err(e)                   => assert!(!accepted && matches!(e, Error::InvalidReply(_)), "{}: {}", line, e),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn connections_report_their_addresses_and_traffic() {
+        let (listener, port) = silent_server();
+        let server = accept_silently(listener);
+        let before = std::time::Instant::now();
+        let mut client = connect_plain(port);
+        assert_eq!(client.peer_addr().map(|addr| addr.port()), Some(port));
+        let local = client.local_addr().unwrap();
+        assert!(local.ip().is_loopback());
+        assert_ne!(local.port(), port);
+        assert!(!client.is_tls());
+        assert!(client.connected_at() >= before && client.connected_at() <= std::time::Instant::now());
+        assert_eq!((client.bytes_sent(), client.bytes_received()), (0, "+OK ready\r\n".len() as u64));
+        client.set_command_timeout(std::time::Duration::from_millis(100));
+        assert!(client.noop().is_err());
+        assert_eq!(client.bytes_sent(), "NOOP\r\n".len() as u64);
+        // Still known after the timeout closed the connection
+        assert_eq!(client.peer_addr().map(|addr| addr.port()), Some(port));
+        drop(client);
+        server.join().unwrap();
+
+        let (mut client, _) = scripted("+OK\r\n+OK 2 320\r\n");
+        assert_eq!((client.peer_addr(), client.local_addr()), (None, None));
+        client.noop().unwrap();
+        client.stat().unwrap();
+        assert_eq!(client.bytes_sent(), "NOOP\r\nSTAT\r\n".len() as u64);
+        assert_eq!(client.bytes_received(), "+OK ready\r\n+OK\r\n+OK 2 320\r\n".len() as u64);
+    }
+
+    #[test]
+    fn socket_options_apply_to_the_connection() {
+        let (listener, port) = silent_server();
+        let server = accept_silently(listener);
+        let mut builder = pop3_client::Builder::default();
+        #[cfg(feature = "with-rustls")]
+        builder.tls(false);
+        let client = builder
+            .tcp_nodelay(true)
+            .tcp_keepalive(std::time::Duration::from_secs(30))
+            .send_buffer_size(64 * 1024)
+            .recv_buffer_size(256 * 1024)
+            .connect("127.0.0.1", port)
+            .unwrap();
+        assert_eq!(client.peer_addr().map(|addr| addr.port()), Some(port));
+        drop(client);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn message_numbers_are_checked_before_sending() {
+        let (mut client, sent) = scripted("-ERR no such message\r\n");
+        assert!(matches!(client.retr(0), Err(Error::InvalidMessageNumber(0))));
+        assert!(matches!(client.top(0, 1), Err(Error::InvalidMessageNumber(0))));
+        assert!(client.retr_bytes(7).is_err());
+        assert_eq!(&sent.lock().unwrap()[..], b"RETR 7\r\n");
+
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let stream = Scripted {
+            replies: std::io::Cursor::new(b"+OK ready\r\n+OK 2 320\r\n+OK\r\n+OK 1 200\r\n".to_vec()),
+            sent: sent.clone(),
+        };
+        let mut client = plain().cache(true).from_stream(stream).unwrap();
+        assert_eq!(client.mailbox_stat().unwrap().ids(), 1..=2);
+        assert!(matches!(client.dele(3), Err(Error::InvalidMessageNumber(3))));
+        assert_eq!(client.list_one(3).unwrap(), None);
+        client.dele(1).unwrap();
+        // Numbers don't shift after a deletion, so the first count still holds
+        assert_eq!(client.stat().unwrap(), (1, 200));
+        assert!(matches!(client.uidl(Some(3)), Err(Error::InvalidMessageNumber(3))));
+        assert_eq!(&sent.lock().unwrap()[..], b"STAT\r\nDELE 1\r\nSTAT\r\n");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn dump_eml_writes_files_and_manifest() {
+        use pop3_client::store::eml::{Naming, MANIFEST};
+        use pop3_client::test_util::{FakeServer, Message};
+
+        let messages = vec![
+            Message::new("a/b", b"Date: Tue, 5 Mar 2024 11:15:00 +0100\r\nSubject: Re: =?UTF-8?Q?caf=C3=A9?= plans?\r\n\r\nx\r\n"),
+            Message::new("..", b"Subject: Re: caf\xc3\xa9 plans?\r\n\r\n.dot\r\n"),
+            Message::new("con", b"\r\nno headers\r\n"),
+        ];
+        let dir = std::env::temp_dir().join("pop3-client-eml-test");
+        std::fs::remove_dir_all(&dir).ok();
+        let names = |naming| {
+            let mut client = plain().from_stream(FakeServer::new(messages.clone())).unwrap();
+            client.login("user", "password").unwrap();
+            let dumped = client.dump_eml(&dir, naming).unwrap();
+            dumped.iter().map(|m| m.path.file_name().unwrap().to_string_lossy().into_owned()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(names(Naming::Seq), vec!["0001.eml", "0002.eml", "0003.eml"]);
+        assert_eq!(std::fs::read(dir.join("0002.eml")).unwrap(), messages[1].content);
+        assert_eq!(names(Naming::Uid), vec!["a_b.eml", "message.eml", "_con.eml"]);
+        assert_eq!(
+            names(Naming::SubjectDate),
+            vec!["20240305-101500_Re_caf_plans.eml", "undated_Re_caf_plans.eml", "undated_no-subject.eml"]
+        );
+
+        let manifest: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(dir.join(MANIFEST)).unwrap()).unwrap();
+        assert_eq!(manifest[1]["uid"], "..");
+        assert_eq!(manifest[1]["file"], "undated_Re_caf_plans.eml");
+        assert_eq!(manifest[1]["size"], messages[1].content.len());
+        assert_eq!(manifest[1]["sha256"].as_str().unwrap().len(), 64);
+        assert_eq!(manifest[1]["truncated"], false);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn duplicates_under_new_uids_are_skipped() {
+        use pop3_client::migrate::MigrateOptions;
+        use pop3_client::state::JsonFileStore;
+        use pop3_client::store::maildir::{ExportOptions, Maildir};
+        use pop3_client::test_util::{FakeServer, Message};
+        use pop3_client::Dedup;
+
+        let first = b"Message-ID: <1@host>\r\n\r\nfirst\r\n";
+        let messages = vec![
+            Message::new("a", first),
+            Message::new("b", b"Message-ID: <1@host>\r\n\r\nfirst, edited\r\n"),
+            Message::new("c", b"Subject: no id\r\n\r\nsame\r\n"),
+            Message::new("d", b"Subject: no id\r\n\r\nsame\r\n"),
+        ];
+        let connect = || {
+            let mut client = plain().from_stream(FakeServer::new(messages.clone())).unwrap();
+            client.login("user", "password").unwrap();
+            client
+        };
+
+        let dir = std::env::temp_dir().join("pop3-client-dedup-test");
+        std::fs::remove_dir_all(&dir).ok();
+        let path = dir.join("maildir");
+        Maildir::create(&path).unwrap().deliver(first).unwrap();
+        let mut options = ExportOptions { skip_duplicates: Some(Dedup::MessageId), ..ExportOptions::default() };
+        let exported = connect().export_to_maildir(&path, &options).unwrap();
+        assert_eq!(exported.iter().map(|(uid, _)| uid.as_str()).collect::<Vec<_>>(), vec!["c", "d"]);
+        options.skip_duplicates = Some(Dedup::ContentHash);
+        let exported = connect().export_to_maildir(dir.join("other"), &options).unwrap();
+        assert_eq!(exported.iter().map(|(uid, _)| uid.as_str()).collect::<Vec<_>>(), vec!["a", "b", "c"]);
+
+        let mut store = JsonFileStore::new(dir.join("uids.json"));
+        let mut sink = Maildir::create(dir.join("migrated")).unwrap();
+        let mut options = MigrateOptions::default();
+        options.resume(&mut store, "user@fake").skip_duplicates(Dedup::Any);
+        let report = connect().migrate(&mut sink, &mut options).unwrap();
+        assert_eq!(report.migrated.iter().map(|m| m.uid.as_str()).collect::<Vec<_>>(), vec!["a", "c"]);
+        assert_eq!((report.skipped, report.duplicates), (0, 2));
+
+        // The same messages come back under new unique IDs
+        let renamed: Vec<_> = messages.iter().map(|m| Message::new(&format!("new-{}", m.uid), &m.content)).collect();
+        let mut client = plain().from_stream(FakeServer::new(renamed)).unwrap();
+        client.login("user", "password").unwrap();
+        let mut store = JsonFileStore::new(dir.join("uids.json"));
+        let mut options = MigrateOptions::default();
+        options.resume(&mut store, "user@fake").skip_duplicates(Dedup::ContentHash);
+        let report = client.migrate(&mut sink, &mut options).unwrap();
+        assert_eq!(report.migrated.iter().map(|m| m.uid.as_str()).collect::<Vec<_>>(), vec!["new-b"]);
+        assert_eq!(report.duplicates, 3);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn inter_command_delay_spaces_out_commands() {
+        use std::time::{Duration, Instant};
+
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let stream = Scripted {
+            replies: std::io::Cursor::new(b"+OK ready\r\n+OK\r\n+OK\r\n+OK\r\n+OK\r\n+OK\r\n".to_vec()),
+            sent: sent.clone(),
+        };
+        let mut client = plain().inter_command_delay(Duration::from_millis(50)).from_stream(stream).unwrap();
+        let start = Instant::now();
+        for _ in 0..3 {
+            client.noop().unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // Pipelined commands leave in one write, after a single pause
+        let start = Instant::now();
+        client.queue_command("NOOP").unwrap();
+        client.queue_command("NOOP").unwrap();
+        client.flush().unwrap();
+        assert!(client.next_response(false).unwrap().is_ok());
+        assert!(client.next_response(false).unwrap().is_ok());
+        assert!(start.elapsed() < Duration::from_millis(100));
+        assert_eq!(sent.lock().unwrap().len(), "NOOP\r\n".len() * 5);
+    }
+
+    #[test]
+    fn failed_jobs_undo_their_deletions() {
+        struct Full;
+
+        impl std::io::Write for Full {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                Err(std::io::ErrorKind::WriteZero.into())
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let undoing = |replies: &str| {
+            let sent = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+            let stream = Scripted {
+                replies: std::io::Cursor::new(format!("+OK ready\r\n{}", replies).into_bytes()),
+                sent: sent.clone(),
+            };
+            (plain().undo_deletes_on_error(true).from_stream(stream).unwrap(), sent)
+        };
+
+        let (mut client, sent) = undoing("+OK\r\n+OK\r\nx\r\n.\r\n+OK\r\n+OK\r\n");
+        client.dele(1).unwrap();
+        assert!(matches!(client.retr_to_writer(2, &mut Full), Err(Error::Io(_))));
+        client.quit().unwrap();
+        assert_eq!(&sent.lock().unwrap()[..], b"DELE 1\r\nRETR 2\r\nRSET\r\nQUIT\r\n");
+
+        // An -ERR is the caller's to handle, and a failure before any deletion doesn't count
+        let (mut client, sent) = undoing("+OK\r\nx\r\n.\r\n+OK\r\n-ERR no such message\r\n+OK\r\n");
+        assert!(client.retr_to_writer(1, &mut Full).is_err());
+        client.dele(1).unwrap();
+        assert!(client.dele(7).is_err());
+        client.quit().unwrap();
+        assert_eq!(&sent.lock().unwrap()[..], b"RETR 1\r\nDELE 1\r\nDELE 7\r\nQUIT\r\n");
+
+        let (mut client, sent) = undoing("+OK\r\n");
+        client.dele(1).unwrap();
+        drop(client);
+        assert_eq!(&sent.lock().unwrap()[..], b"DELE 1\r\nRSET\r\n");
+
+        let (mut client, sent) = scripted("+OK\r\n");
+        client.dele(1).unwrap();
+        drop(client);
+        assert_eq!(&sent.lock().unwrap()[..], b"DELE 1\r\n");
+    }
+
+    #[test]
+    fn faulty_transports_split_delay_and_reset() {
+        use pop3_client::test_util::{Faulty, FakeServer, Message};
+        use std::io::ErrorKind;
+        use std::time::Duration;
+
+        let messages = || vec![Message::new("uid-1", b"Subject: hi\r\n\r\n..dotted\r\n"), Message::new("uid-2", b"\r\n")];
+
+        let transport = Faulty::new(FakeServer::new(messages())).read_chunk(1).write_chunk(1);
+        let mut client = plain().from_stream(transport).unwrap();
+        client.login("user", "password").unwrap();
+        assert_eq!(client.retr(1).unwrap(), "Subject: hi\r\n\r\n...dotted\r\n");
+        assert_eq!(client.stat().unwrap().0, 2);
+
+        let transport = Faulty::new(FakeServer::new(messages())).reset_after_commands(3);
+        let mut client = plain().from_stream(transport).unwrap();
+        client.login("user", "password").unwrap();
+        match client.retr(1) {
+            Err(error @ Error::Io(_)) => {
+                assert!(error.is_transient());
+                assert!(matches!(error, Error::Io(ref e) if e.kind() == ErrorKind::ConnectionReset));
+            }
+            other => panic!("expected a reset, got {:?}", other),
+        }
+
+        let transport = Faulty::new(FakeServer::new(messages())).read_chunk(4).latency(Duration::from_millis(20));
+        let mut client = plain().from_stream(transport).unwrap();
+        client.login("user", "password").unwrap();
+        client.set_command_timeout(Duration::from_millis(50));
+        assert!(matches!(client.retr(1), Err(Error::Io(ref e)) if e.kind() == ErrorKind::TimedOut));
+    }
+
+    #[test]
+    fn reconnect_opens_a_fresh_session() {
+        use std::io::{BufRead, BufReader, Write};
+
+        let (listener, port) = silent_server();
+        let server = std::thread::spawn(move || {
+            (0..2)
+                .map(|_| {
+                    let (socket, _) = listener.accept().unwrap();
+                    let mut writer = socket.try_clone().unwrap();
+                    writer.write_all(b"+OK ready\r\n").unwrap();
+                    let mut commands = vec![];
+                    for line in BufReader::new(socket).lines() {
+                        let line = line.unwrap();
+                        writer.write_all(b"+OK\r\n").unwrap();
+                        commands.push(line.clone());
+                        if line == "QUIT" {
+                            break;
+                        }
+                    }
+                    commands
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let mut client = connect_plain(port);
+        client.login("alice", "secret").unwrap();
+        client.reconnect().unwrap();
+        assert_eq!(client.stage(), pop3_client::Stage::Authorization);
+        client.login("bob", "secret").unwrap();
+        client.quit().unwrap();
+
+        let sessions = server.join().unwrap();
+        assert_eq!(sessions[0], ["USER alice", "PASS secret", "QUIT"]);
+        assert_eq!(sessions[1], ["USER bob", "PASS secret", "QUIT"]);
+
+        let (mut client, _) = scripted("");
+        assert!(matches!(client.reconnect(), Err(Error::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn proxy_protocol_header_precedes_the_exchange() {
+        use pop3_client::ProxyHeader;
+        use std::io::{BufRead, BufReader, Write};
+
+        let (listener, port) = silent_server();
+        let server = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(socket.try_clone().unwrap());
+            let mut header = String::new();
+            reader.read_line(&mut header).unwrap();
+            (&socket).write_all(b"+OK ready\r\n+OK bye\r\n").unwrap();
+            let mut quit = String::new();
+            reader.read_line(&mut quit).unwrap();
+            (header, quit)
+        });
+
+        let header = ProxyHeader::V1 {
+            source: "[2001:db8::1]:56324".parse().unwrap(),
+            destination: "198.51.100.1:110".parse().unwrap(),
+        };
+        let mut builder = plain();
+        #[cfg(feature = "with-rustls")]
+        builder.tls(false);
+        builder.send_proxy_protocol(header).connect("127.0.0.1", port).unwrap().quit().unwrap();
+        let (header, quit) = server.join().unwrap();
+        assert_eq!(header, "PROXY TCP6 2001:db8::1 ::ffff:198.51.100.1 56324 110\r\n");
+        assert_eq!(quit, "QUIT\r\n");
+
+        let v2 = ProxyHeader::V2 {
+            source: "192.0.2.1:56324".parse().unwrap(),
+            destination: "198.51.100.1:110".parse().unwrap(),
+        };
+        let mut expected = b"\r\n\r\n\0\r\nQUIT\n\x21\x11\x00\x0c".to_vec();
+        expected.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 1, 0xdc, 0x04, 0, 110]);
+        assert_eq!(v2.encode(), expected);
+        assert_eq!(ProxyHeader::V2Local.encode()[12..], [0x20, 0x00, 0x00, 0x00]);
+    }
+
+    #[test]
+    fn grab_banner_reads_the_greeting_and_leaves() {
+        use std::io::{BufRead, BufReader, Write};
+        use std::time::{Duration, Instant};
+
+        let (listener, port) = silent_server();
+        let server = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            (&socket).write_all(b"+OK mail.example.com POP3 ready <1.2@mail.example.com>\r\n+OK bye\r\n").unwrap();
+            let mut quit = String::new();
+            BufReader::new(socket).read_line(&mut quit).unwrap();
+            quit
+        });
+        let greeting = Client::grab_banner("127.0.0.1", port, Duration::from_secs(5)).unwrap();
+        assert_eq!(greeting.host.as_deref(), Some("mail.example.com"));
+        assert_eq!(server.join().unwrap(), "QUIT\r\n");
+
+        let (_listener, port) = silent_server();
+        let start = Instant::now();
+        let result = Client::grab_banner("127.0.0.1", port, Duration::from_millis(200));
+        assert!(matches!(result, Err(Error::Io(ref e)) if e.kind() == std::io::ErrorKind::TimedOut));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn probe_times_each_step_and_compares_capabilities() {
+        use pop3_client::fetcher::{AccountConfig, Credentials};
+        use pop3_client::monitor::{self, ProbeConfig, Step};
+        use std::io::{BufRead, BufReader, Write};
+
+        let (listener, port) = silent_server();
+        let server = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let (socket, _) = listener.accept().unwrap();
+                let mut writer = socket.try_clone().unwrap();
+                writer.write_all(b"+OK mail.example.com ready\r\n").unwrap();
+                for line in BufReader::new(socket).lines() {
+                    let line = line.unwrap();
+                    let reply: &[u8] = match line.as_str() {
+                        "CAPA" => b"+OK\r\nTOP\r\nUSER\r\n.\r\n",
+                        "STAT" => b"+OK 2 300\r\n",
+                        "PASS wrong" => b"-ERR [AUTH] invalid\r\n",
+                        _ => b"+OK\r\n",
+                    };
+                    writer.write_all(reply).unwrap();
+                    if line == "QUIT" {
+                        break;
+                    }
+                }
+            }
+        });
+
+        let mut config = ProbeConfig {
+            account: AccountConfig {
+                host: "127.0.0.1".to_string(),
+                port,
+                tls: false,
+                allow_plaintext_auth: true,
+                credentials: Credentials::new("monitoring", "secret"),
+                ..AccountConfig::default()
+            },
+            expected_capabilities: vec!["UIDL".to_string(), "top".to_string()],
+            ..ProbeConfig::default()
+        };
+        let report = monitor::probe(&config);
+        assert!(report.error.is_none(), "{:?}", report.error);
+        assert!(!report.is_ok());
+        assert_eq!(report.greeting.unwrap().host.as_deref(), Some("mail.example.com"));
+        assert!(report.connect.is_some() && report.capa.is_some() && report.auth.is_some() && report.quit.is_some());
+        assert_eq!(report.mailbox, Some((2, 300)));
+        assert_eq!(report.missing, ["UIDL"]);
+        assert_eq!(report.unexpected, ["USER"]);
+
+        config.account.credentials = Credentials::new("monitoring", "wrong");
+        let report = monitor::probe(&config);
+        assert!(matches!(report.error, Some((Step::Auth, Error::Auth(_)))));
+        assert!(report.auth.is_none() && report.stat.is_none());
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn connect_addr_skips_name_lookup() {
+        use std::io::{BufRead, BufReader, Write};
+
+        let (listener, port) = silent_server();
+        let server = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let (socket, _) = listener.accept().unwrap();
+                (&socket).write_all(b"+OK ready\r\n+OK bye\r\n").unwrap();
+                let mut quit = String::new();
+                BufReader::new(socket).read_line(&mut quit).unwrap();
+            }
+        });
+
+        let address: std::net::SocketAddr = ([127, 0, 0, 1], port).into();
+        let mut builder = plain();
+        #[cfg(feature = "with-rustls")]
+        builder.tls(false);
+        let mut client = builder.connect_addr(address, "pop3.name-that-does-not-resolve.invalid").unwrap();
+        assert_eq!(client.peer_addr(), Some(address));
+        client.reconnect().unwrap();
+        assert_eq!(client.peer_addr(), Some(address));
+        client.quit().unwrap();
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn status_line_and_payload_are_kept_apart() {
+        let (mut client, _) = scripted(
+            "+OK\r\nSubject: first\r\n\r\nbody\r\n.\r\n\
+             +OK 31 octets\r\nSubject: second\r\n\r\n..dot\r\n.\r\n\
+             +OK\r\nSubject: top\r\n\r\n.\r\n\
+             +OK\r\nReceived: x\r\n\r\nraw\r\n.\r\n",
+        );
+        assert_eq!(client.retr(1).unwrap(), "Subject: first\r\n\r\nbody\r\n");
+
+        let response = client.retr_response(2).unwrap();
+        assert_eq!(response.info, "31 octets");
+        assert_eq!(response.payload(), b"Subject: second\r\n\r\n..dot\r\n");
+        assert_eq!(response.lines, ["Subject: second", "", ".dot"]);
+
+        let top = client.top(3, 0).unwrap();
+        assert_eq!(top.headers, b"Subject: top\r\n");
+        assert_eq!(&client.retr_bytes(4).unwrap()[..], b"Received: x\r\n\r\nraw\r\n");
+    }
+
+    #[test]
+    fn connections_talk_to_the_server_without_checks() {
+        use pop3_client::test_util::{FakeServer, Message};
+        use pop3_client::{Connection, Status};
+
+        let server = || FakeServer::new(vec![Message::new("uid-1", b"Subject: hi\r\n\r\n.dot\r\n")]);
+
+        let mut connection = Connection::from_stream(server());
+        assert!(connection.read_status().unwrap().is_ok());
+        // A transaction command before logging in, which the client would refuse to send
+        connection.send_command("STAT").unwrap();
+        assert_eq!(connection.read_status().unwrap().status, Status::Err);
+        connection.send_command("USER user").unwrap();
+        connection.read_status().unwrap();
+        connection.send_command("PASS password").unwrap();
+        connection.read_status().unwrap();
+        connection.send_command("RETR 1").unwrap();
+        let response = connection.read_multiline().unwrap();
+        assert_eq!(response.payload(), b"Subject: hi\r\n\r\n..dot\r\n");
+        assert!(matches!(connection.send_command("NOOP\r\nDELE 1"), Err(Error::InvalidArgument(_))));
+
+        let mut connection = Connection::from_stream(server());
+        let greeting = connection.read_status().unwrap();
+        let mut client = plain().from_connection(connection, Some(greeting)).unwrap();
+        client.login("user", "password").unwrap();
+        assert_eq!(client.stat().unwrap().0, 1);
+
+        let client = Client::from_connection(Connection::from_stream(server()), None).unwrap();
+        assert!(client.banner().text.contains("ready"));
+    }
+
+    #[test]
+    fn server_serves_a_maildrop_to_the_client() {
+        use pop3_client::server::{Entry, Maildrop, MaildropProvider, Server};
+        use std::sync::{Arc, Mutex};
+
+        struct Memory {
+            messages: Vec<Vec<u8>>,
+            committed: Arc<Mutex<Vec<usize>>>,
+        }
+
+        impl Maildrop for Memory {
+            fn list(&mut self) -> pop3_client::Result<Vec<Entry>> {
+                let entries = self.messages.iter().enumerate();
+                Ok(entries.map(|(i, m)| Entry { uid: format!("uid-{}", i + 1), size: m.len() }).collect())
+            }
+
+            fn retrieve(&mut self, index: usize) -> pop3_client::Result<Vec<u8>> {
+                Ok(self.messages[index].clone())
+            }
+
+            fn commit(&mut self, deleted: &[usize]) -> pop3_client::Result<()> {
+                self.committed.lock().unwrap().extend_from_slice(deleted);
+                Ok(())
+            }
+        }
+
+        struct Provider(Arc<Mutex<Vec<usize>>>);
+
+        impl MaildropProvider for Provider {
+            fn open(&self, user: &str, password: &str) -> pop3_client::Result<Option<Box<dyn Maildrop>>> {
+                if (user, password) != ("alice", "secret") {
+                    return Ok(None);
+                }
+                let messages = vec![
+                    b"Subject: one\r\n\r\n.leading dot\r\nsecond line\r\n".to_vec(),
+                    b"Subject: two\r\n\r\nbody\r\n".to_vec(),
+                ];
+                Ok(Some(Box::new(Memory { messages, committed: self.0.clone() })))
+            }
+        }
+
+        let committed = Arc::new(Mutex::new(vec![]));
+        let server = Arc::new(Server::new(Provider(committed.clone())).greeting("test.invalid ready"));
+        let (listener, port) = silent_server();
+        let serving = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let (socket, _) = listener.accept().unwrap();
+                server.serve(socket).unwrap();
+            }
+        });
+
+        let mut client = connect_plain(port);
+        assert_eq!(client.banner().host.as_deref(), Some("test.invalid"));
+        assert!(matches!(client.login("alice", "wrong"), Err(Error::Auth(_))));
+        client.login("alice", "secret").unwrap();
+        assert_eq!(client.stat().unwrap(), (2, 65));
+        assert_eq!(client.uidl_entries(None).unwrap()[1].uid, "uid-2");
+        assert_eq!(&client.retr_bytes(1).unwrap()[..], b"Subject: one\r\n\r\n.leading dot\r\nsecond line\r\n");
+        assert_eq!(client.top(1, 1).unwrap().body_lines, [b".leading dot".to_vec()]);
+        client.dele(2).unwrap();
+        assert!(matches!(client.retr(2), Err(Error::Server(_))));
+        client.quit().unwrap();
+
+        let mut client = connect_plain(port);
+        assert!(client.capa().unwrap().contains("UIDL"));
+        assert!(client.stat().is_err());
+        client.quit().unwrap();
+        serving.join().unwrap();
+        assert_eq!(*committed.lock().unwrap(), [1]);
+    }
+
+    #[test]
+    fn maildir_backend_serves_and_deletes_on_quit() {
+        use pop3_client::server::{MaildirBackend, Server};
+        use pop3_client::store::maildir::Maildir;
+        use std::sync::Arc;
+
+        let path = std::env::temp_dir().join("pop3-client-maildir-server-test");
+        std::fs::remove_dir_all(&path).ok();
+        let maildir = Maildir::create(&path).unwrap();
+        let first = maildir.deliver(b"Subject: one\n\nfirst\n").unwrap();
+        let second = maildir.deliver(b"Subject: two\r\n\r\nsecond\r\n").unwrap();
+
+        let server = Arc::new(Server::new(MaildirBackend::new().user("alice", "secret", &path)));
+        let (listener, port) = silent_server();
+        let serving = std::thread::spawn(move || {
+            for _ in 0..2 {
+                let (socket, _) = listener.accept().unwrap();
+                server.serve(socket).unwrap();
+            }
+        });
+
+        let mut client = connect_plain(port);
+        client.login("alice", "secret").unwrap();
+        // Sizes count CRLF line endings, whatever the file has
+        assert_eq!(client.stat().unwrap(), (2, 47));
+        let uids = client.uidl_entries(None).unwrap();
+        assert_eq!(uids[0].uid, first.file_name().unwrap().to_str().unwrap());
+        assert_eq!(&client.retr_bytes(1).unwrap()[..], b"Subject: one\r\n\r\nfirst\r\n");
+        client.dele(1).unwrap();
+        client.quit().unwrap();
+
+        let mut client = connect_plain(port);
+        client.login("alice", "secret").unwrap();
+        assert_eq!(client.stat().unwrap(), (1, 24));
+        client.quit().unwrap();
+        serving.join().unwrap();
+        assert!(!first.exists());
+        assert!(second.exists());
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn maildrops_are_locked_while_a_session_holds_them() {
+        use pop3_client::server::{MaildirBackend, Server};
+        use pop3_client::store::maildir::Maildir;
+        use std::net::TcpListener;
+
+        let path = std::env::temp_dir().join("pop3-client-maildir-lock-test");
+        std::fs::remove_dir_all(&path).ok();
+        Maildir::create(&path).unwrap().deliver(b"Subject: one\r\n\r\nfirst\r\n").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let backend = MaildirBackend::new().user("alice", "secret", &path);
+        std::thread::spawn(move || Server::new(backend).run(listener));
+
+        let mut first = connect_plain(port);
+        first.login("alice", "secret").unwrap();
+        first.dele(1).unwrap();
+        let mut second = connect_plain(port);
+        assert!(matches!(second.login("alice", "secret"), Err(Error::MailboxLocked { .. })));
+        // A wrong password is told as such, not as a lock
+        assert!(matches!(connect_plain(port).login("alice", "wrong"), Err(Error::Auth(_))));
+
+        first.quit().unwrap();
+        second.login("alice", "secret").unwrap();
+        assert_eq!(second.stat().unwrap(), (0, 0));
+        drop(second);
+        // The lock goes with the connection, even without a QUIT, once the server has seen it close
+        let relocked = (0..100).any(|_| {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            connect_plain(port).login("alice", "secret").is_ok()
+        });
+        assert!(relocked);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    fn cancelled_bulk_fetches_keep_what_they_have() {
+        use pop3_client::server::{Entry, Maildrop, MaildropProvider, Server};
+        use pop3_client::CancellationToken;
+        use std::sync::Arc;
+
+        // Pulls the plug while the second message is being sent
+        struct Stopping(CancellationToken);
+
+        impl Maildrop for Stopping {
+            fn list(&mut self) -> pop3_client::Result<Vec<Entry>> {
+                Ok((1..=3).map(|i| Entry { uid: i.to_string(), size: 3 }).collect())
+            }
+
+            fn retrieve(&mut self, index: usize) -> pop3_client::Result<Vec<u8>> {
+                if index == 1 {
+                    self.0.cancel();
+                }
+                Ok(format!("m{}\r\n", index + 1).into_bytes())
+            }
+
+            fn commit(&mut self, _: &[usize]) -> pop3_client::Result<()> {
+                Ok(())
+            }
+        }
+
+        struct Provider(CancellationToken);
+
+        impl MaildropProvider for Provider {
+            fn open(&self, _: &str, _: &str) -> pop3_client::Result<Option<Box<dyn Maildrop>>> {
+                Ok(Some(Box::new(Stopping(self.0.clone()))))
+            }
+        }
+
+        let cancel = CancellationToken::default();
+        let server = Arc::new(Server::new(Provider(cancel.clone())));
+        let (listener, port) = silent_server();
+        std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            server.serve(socket).unwrap();
+        });
+
+        let mut client = connect_plain(port);
+        client.login("me", "secret").unwrap();
+        let fetched = client.retr_many(&[1, 2, 3], &cancel);
+        assert_eq!(fetched.completed_up_to, 2);
+        assert!(!fetched.is_complete());
+        assert_eq!(fetched.messages[1].as_ref().unwrap(), &b"m2\r\n"[..]);
+        assert!(matches!(fetched.messages[2], Err(Error::Cancelled)));
+        // The session is still usable
+        assert!(client.noop().is_ok());
+
+        let open = || -> Result<Client> { panic!("no session is needed once cancelled") };
+        let fetched = client.parallel_fetch_cancellable(&[1, 3], 2, open, &cancel);
+        assert_eq!(fetched.completed_up_to, 0);
+        assert!(fetched.messages.iter().all(|message| matches!(message, Err(Error::Cancelled))));
+    }
+
+    #[test]
+    fn unique_ids_are_mapped_to_numbers_once_per_session() {
+        let (mut client, sent) = scripted(concat!(
+            "+OK\r\n+OK\r\n",
+            "+OK\r\n1 uid-a\r\n2 uid-b\r\n.\r\n",
+            "+OK\r\nSubject: b\r\n\r\nbody\r\n.\r\n",
+            "+OK message 1 deleted\r\n",
+            "-ERR message 1 already deleted\r\n",
+        ));
+        client.login("user", "password").unwrap();
+        assert_eq!(client.retr_by_uid("uid-b").unwrap().unwrap(), "Subject: b\r\n\r\nbody\r\n");
+        assert_eq!(client.uid_for_msg(1).unwrap().as_deref(), Some("uid-a"));
+        assert!(client.dele_by_uid("uid-a").unwrap().is_some());
+        assert_eq!(client.retr_by_uid("uid-a").unwrap(), None);
+        // Unknown IDs are answered without asking the server
+        assert_eq!(client.dele_by_uid("uid-z").unwrap(), None);
+        assert_eq!(client.msg_for_uid("uid-b").unwrap(), Some(2));
+
+        let sent = String::from_utf8(sent.lock().unwrap().clone()).unwrap();
+        assert!(sent.ends_with("UIDL\r\nRETR 2\r\nDELE 1\r\nRETR 1\r\n"));
+    }
+
+    #[test]
+    fn sync_entries_make_up_ids_without_uidl() {
+        use pop3_client::state::{SyncOptions, UidFallback};
+
+        let listing = "+OK\r\n1 30\r\n2 30\r\n.\r\n";
+        let (mut client, sent) = scripted(&[
+            "+OK\r\n+OK\r\n-ERR unknown command\r\n",
+            listing,
+            "+OK\r\nSubject: one\r\n\r\n.\r\n",
+            "+OK\r\nSubject: two\r\n\r\n.\r\n",
+            // Then TOP is refused too, so the whole messages are hashed
+            "-ERR unknown command\r\n",
+            listing,
+            "-ERR unknown command\r\n",
+            listing,
+            "+OK\r\nSubject: one\r\n\r\nbody\r\n.\r\n",
+            "+OK\r\nSubject: two\r\n\r\nbody\r\n.\r\n",
+            // Temporary failures aren't refusals
+            "-ERR [SYS/TEMP] try later\r\n",
+        ].concat());
+        client.login("user", "password").unwrap();
+        let headers = client.sync_entries(&SyncOptions::default()).unwrap();
+        assert_eq!(headers.len(), 2);
+        assert!(headers[0].uid.len() == 32 && headers[0].uid != headers[1].uid);
+        let contents = client.sync_entries(&SyncOptions::default()).unwrap();
+        assert_eq!(contents.iter().map(|entry| entry.id).collect::<Vec<_>>(), [1, 2]);
+        assert_ne!(contents[0].uid, headers[0].uid);
+        let options = SyncOptions { uid_fallback: UidFallback::Content };
+        assert!(matches!(client.sync_entries(&options), Err(Error::Server(_))));
+
+        let sent = String::from_utf8(sent.lock().unwrap().clone()).unwrap();
+        assert!(sent.ends_with("UIDL\r\nLIST\r\nTOP 1 0\r\nTOP 2 0\r\nUIDL\r\nLIST\r\nTOP 1 0\r\nLIST\r\nRETR 1\r\nRETR 2\r\nUIDL\r\n"));
+    }
+
+    #[test]
+    fn debug_output_leaves_secrets_out() {
+        let mut builder = plain();
+        builder.credentials("sweet_username", "very_secret_password");
+        let settings = format!("{:?}", builder.clone());
+        assert!(settings.contains("plaintext_auth: true") && settings.contains("<redacted>"));
+        assert!(!settings.contains("sweet_username") && !settings.contains("very_secret_password"));
+
+        let (mut client, _) = scripted("+OK\r\n+OK\r\n+OK\r\nSubject: private\r\n.\r\n");
+        client.login("sweet_username", "very_secret_password").unwrap();
+        client.retr(1).unwrap();
+        let session = format!("{:?}", client);
+        assert!(session.contains("stage: Transaction") && session.contains("bytes_sent: "));
+        for secret in ["sweet_username", "very_secret_password", "private"] {
+            assert!(!session.contains(secret));
+        }
+    }
+
+    #[test]
+    fn factories_open_independent_sessions() {
+        use pop3_client::pool::ClientPool;
+        use pop3_client::server::{MaildirBackend, Server};
+        use pop3_client::store::maildir::Maildir;
+        use pop3_client::Stage;
+
+        let path = std::env::temp_dir().join("pop3-client-factory-test");
+        std::fs::remove_dir_all(&path).ok();
+        let maildir = Maildir::create(&path).unwrap();
+        for i in 1..=3 {
+            maildir.deliver(format!("Subject: {}\r\n\r\nbody\r\n", i).as_bytes()).unwrap();
+        }
+        let (listener, port) = silent_server();
+        // The server locks the maildrop of a session, so every session logs in as a user of its own
+        let backend = (0..8).fold(MaildirBackend::new(), |backend, i| backend.user(&format!("user{}", i), "secret", &path));
+        std::thread::spawn(move || Server::new(backend).run(listener));
+
+        let mut builder = plain();
+        #[cfg(feature = "with-rustls")]
+        builder.tls(false);
+        let anonymous = builder.build_factory("127.0.0.1", port);
+        let next = std::sync::atomic::AtomicUsize::new(0);
+        let factory = builder
+            .credentials_provider(move |_| {
+                let user = format!("user{}", next.fetch_add(1, std::sync::atomic::Ordering::SeqCst));
+                Ok(pop3_client::Credentials::new(&user, "secret"))
+            })
+            .build_factory("127.0.0.1", port);
+        assert_eq!(anonymous.new_client().unwrap().stage(), Stage::Authorization);
+
+        let mut client = factory.new_client().unwrap();
+        assert_eq!(client.stage(), Stage::Transaction);
+        let messages = client.parallel_fetch(&[1, 2, 3], 2, || factory.new_client());
+        assert_eq!(&messages[2].as_ref().unwrap()[..], b"Subject: 3\r\n\r\nbody\r\n");
+
+        let pool = ClientPool::from_factory(factory, 1);
+        assert_eq!(pool.get().unwrap().stat().unwrap().0, 3);
+        assert_eq!(pool.idle(), 1);
+        std::fs::remove_dir_all(&path).ok();
+    }
+
+    #[test]
+    #[cfg(feature = "with-rustls")]
+    fn root_certificates_can_be_replaced() {
+        let mut builder = pop3_client::Builder::default();
+        assert!(!format!("{:?}", builder).contains("trusted_roots: 0"));
+        builder.no_default_roots();
+        assert!(format!("{:?}", builder).contains("trusted_roots: 0"));
+
+        assert!(matches!(builder.add_root_certificate(b"not a certificate"), Err(Error::Tls(_))));
+        assert!(matches!(builder.add_root_certificate(b"-----BEGIN NOTHING-----"), Err(Error::Tls(_))));
+        builder.add_root_certificate(include_bytes!("ca.pem")).unwrap();
+        assert!(format!("{:?}", builder).contains("trusted_roots: 1"));
+        // Other builders still trust the Mozilla roots
+        assert!(!format!("{:?}", pop3_client::Builder::default()).contains("trusted_roots: 0"));
+    }
+
+    #[test]
+    #[cfg(feature = "with-rustls")]
+    fn tls_versions_can_be_pinned() {
+        use pop3_client::TlsVersion;
+
+        let mut builder = pop3_client::Builder::default();
+        builder.min_tls_version(TlsVersion::Tls1_3).alpn_protocols(&["pop3"]);
+        assert!(format!("{:?}", builder).contains("tls_versions: (Tls1_3, Tls1_3)"));
+
+        // Nothing left to negotiate: refused before connecting
+        let (_listener, port) = silent_server();
+        builder.max_tls_version(TlsVersion::Tls1_2);
+        assert!(matches!(builder.connect("127.0.0.1", port), Err(Error::Tls(_))));
+    }
+
+    #[test]
+    fn large_and_padded_numbers_are_parsed() {
+        use pop3_client::parser;
+        use pop3_client::MessageInfo;
+
+        assert_eq!(parser::stat(b"0002   6000000000").unwrap(), (2, 6_000_000_000));
+        assert_eq!(parser::stat(b" 2 320 octets").unwrap(), (2, 320));
+        assert_eq!(parser::scan_listing(b"007  5000000000\r\n").unwrap(), MessageInfo { id: 7, size: 5_000_000_000 });
+        fn parse_error<T: std::fmt::Debug>(result: pop3_client::Result<T>) -> (&'static str, String) {
+            match result {
+                Err(Error::Parse { field, raw }) => (field, raw),
+                other => panic!("{:?}", other),
+            }
+        }
+        assert_eq!(parse_error(parser::stat(b"4294967296 0")), ("message count", "4294967296".to_string()));
+        assert_eq!(parse_error(parser::stat(b"2 -320")), ("mailbox size", "-320".to_string()));
+        assert_eq!(parse_error(parser::stat(b"+2 320")), ("message count", "+2".to_string()));
+        assert_eq!(parse_error(parser::stat(b"2 18446744073709551616")).0, "mailbox size");
+        assert!(matches!(parser::stat(b"2"), Err(Error::InvalidReply(_))));
+        assert_eq!(parse_error(parser::scan_listing(b"x1 20\r\n")), ("message number", "x1".to_string()));
+
+        let (mut client, _) = scripted("+OK\r\n+OK\r\n+OK 3  08589934592\r\n+OK 3 lots\r\n");
+        client.login("user", "password").unwrap();
+        assert_eq!(client.mailbox_stat().unwrap().size, 8_589_934_592);
+        let e = client.stat().unwrap_err();
+        assert_eq!(e.to_string(), "Invalid mailbox size in reply: \"lots\"");
+    }
+
+    #[test]
+    fn audit_trail_keeps_the_last_commands_without_secrets() {
+        use pop3_client::AuditOutcome;
+
+        let mut builder = plain();
+        builder.audit_log(3);
+        let mut client = builder
+            .from_stream(Scripted {
+                replies: std::io::Cursor::new(
+                    b"+OK ready\r\n+OK\r\n+OK logged in\r\n+OK 1 5\r\n-ERR no such message\r\n".to_vec(),
+                ),
+                sent: Default::default(),
+            })
+            .unwrap();
+        client.login("user", "tanstaaf").unwrap();
+        client.stat().unwrap();
+        assert!(client.dele(2).is_err());
+        assert!(client.noop().is_err());
+
+        let audit = client.audit();
+        let commands: Vec<&str> = audit.iter().map(|event| event.command.as_str()).collect();
+        assert_eq!(commands, ["STAT", "DELE 2", "NOOP"]);
+        assert_eq!(audit[0].outcome, AuditOutcome::Ok);
+        assert_eq!(audit[0].sent, "STAT\r\n".len() as u64);
+        assert_eq!(audit[1].outcome, AuditOutcome::Refused("no such message".to_string()));
+        assert!(matches!(audit[2].outcome, AuditOutcome::Failed(_)));
+        assert!(audit[0].at <= audit[2].at);
+
+        let mut builder = plain();
+        builder.audit_log(10);
+        let mut client = builder
+            .from_stream(Scripted {
+                replies: std::io::Cursor::new(b"+OK ready\r\n+OK\r\n+OK\r\n".to_vec()),
+                sent: Default::default(),
+            })
+            .unwrap();
+        client.login("user", "tanstaaf").unwrap();
+        let audit = format!("{:?}", client.audit());
+        assert!(audit.contains("PASS ***") && !audit.contains("tanstaaf"), "{}", audit);
+        assert!(scripted("").0.audit().is_empty());
+    }
+
+    #[test]
+    fn unannounced_commands_are_refused_without_a_round_trip() {
+        let sent = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+        let mut builder = plain();
+        builder.capability_checks(true);
+        let mut client = builder
+            .from_stream(Scripted {
+                replies: std::io::Cursor::new(
+                    b"+OK ready\r\n+OK\r\nUSER\r\n.\r\n+OK\r\n+OK\r\n+OK\r\n1 abc\r\n.\r\n+OK\r\nUIDL\r\n.\r\n".to_vec(),
+                ),
+                sent: sent.clone(),
+            })
+            .unwrap();
+        // Nothing announced yet
+        assert!(client.capabilities().is_none());
+
+        client.capa().unwrap();
+        let e = client.auth("CRAM-MD5", None, |_| Ok(vec![])).unwrap_err();
+        assert!(matches!(e, Error::NotSupportedByServer(ref capability) if capability == "SASL CRAM-MD5"), "{}", e);
+        client.login("user", "password").unwrap();
+        // What was announced before the login no longer counts
+        assert!(client.capabilities().is_none());
+        assert_eq!(client.uidl_entries(None).unwrap().len(), 1);
+
+        client.capa().unwrap();
+        assert!(matches!(client.top(1, 0), Err(Error::NotSupportedByServer(ref capability)) if capability == "TOP"));
+        assert!(matches!(client.batch().top(1, 0).execute(), Err(ref e) if matches!(e.failures()[0].error, Error::NotSupportedByServer(_))));
+        assert_eq!(
+            String::from_utf8(sent.lock().unwrap().clone()).unwrap(),
+            "CAPA\r\nUSER user\r\nPASS password\r\nUIDL\r\nCAPA\r\n"
+        );
+    }
+
+    #[test]
+    fn commands_wait_for_the_replies_in_flight() {
+        let (mut client, sent) = scripted("+OK\r\n+OK\r\n+OK\r\n+OK 1 5\r\n+OK 1 5\r\n");
+        client.login("user", "password").unwrap();
+        client.queue_command("NOOP").unwrap();
+        client.flush().unwrap();
+        assert!(matches!(client.stat(), Err(Error::CommandInFlight(ref command)) if command == "NOOP"));
+        assert!(matches!(client.batch().stat().execute(), Err(ref e) if matches!(e.failures()[0].error, Error::CommandInFlight(_))));
+        assert!(client.next_response(false).unwrap().is_ok());
+        assert_eq!(client.stat().unwrap(), (1, 5));
+        assert!(String::from_utf8(sent.lock().unwrap().clone()).unwrap().ends_with("PASS password\r\nNOOP\r\nSTAT\r\n"));
+
+        // A panicking writer leaves the rest of the message on the wire
+        struct Panicking;
+        impl std::io::Write for Panicking {
+            fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+                panic!("writer failed")
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+        let (mut client, _) = scripted("+OK\r\n+OK\r\n+OK\r\nfirst\r\nsecond\r\n.\r\n+OK\r\n");
+        client.login("user", "password").unwrap();
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| client.retr_to_writer(1, &mut Panicking)));
+        assert!(panicked.is_err());
+        let e = client.noop().unwrap_err();
+        assert!(matches!(e, Error::CommandInFlight(ref command) if command == "RETR"), "{}", e);
+        assert!(matches!(client.queue_command("NOOP"), Err(Error::CommandInFlight(_))));
+    }
+
+    #[test]
+    fn idempotent_commands_survive_a_dropped_connection() {
+        use std::io::{BufRead, BufReader, Write};
+
+        let (listener, port) = silent_server();
+        // The first session is dropped once logged in, the second one after a deletion
+        let server = std::thread::spawn(move || {
+            [2, 4]
+                .iter()
+                .map(|commands| {
+                    let (socket, _) = listener.accept().unwrap();
+                    let mut writer = socket.try_clone().unwrap();
+                    writer.write_all(b"+OK ready\r\n").unwrap();
+                    let mut lines = vec![];
+                    for line in BufReader::new(socket).lines().take(*commands) {
+                        let line = line.unwrap();
+                        let reply: &[u8] = if line == "STAT" { b"+OK 2 320\r\n" } else { b"+OK\r\n" };
+                        writer.write_all(reply).unwrap();
+                        lines.push(line);
+                    }
+                    lines
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let mut builder = pop3_client::Builder::default();
+        #[cfg(feature = "with-rustls")]
+        builder.tls(false);
+        let mut client = builder
+            .allow_plaintext_auth(true)
+            .auto_retry_idempotent(true)
+            .credentials("alice", "secret")
+            .open("127.0.0.1", port)
+            .unwrap();
+        assert_eq!(client.stat().unwrap(), (2, 320));
+        client.dele(1).unwrap();
+        // The deletion would be lost in a new session
+        assert!(matches!(client.stat(), Err(Error::ConnectionAborted) | Err(Error::Io(_))));
+
+        let sessions = server.join().unwrap();
+        assert_eq!(sessions[0], ["USER alice", "PASS secret"]);
+        assert_eq!(sessions[1], ["USER alice", "PASS secret", "STAT", "DELE 1"]);
+    }
+
+    #[test]
+    fn tokens_taken_before_a_reconnect_still_cancel() {
+        use std::io::{BufRead, BufReader, Write};
+
+        let (listener, port) = silent_server();
+        // The first session is dropped at the first command after the login, the second one at the next
+        let server = std::thread::spawn(move || {
+            [2, 3]
+                .iter()
+                .map(|commands| {
+                    let (socket, _) = listener.accept().unwrap();
+                    let mut writer = socket.try_clone().unwrap();
+                    writer.write_all(b"+OK ready\r\n").unwrap();
+                    let mut lines = BufReader::new(socket).lines();
+                    for line in lines.by_ref().take(*commands) {
+                        let reply: &[u8] = if line.unwrap() == "STAT" { b"+OK 2 320\r\n" } else { b"+OK\r\n" };
+                        writer.write_all(reply).unwrap();
+                    }
+                    lines.next().map(|line| line.unwrap())
+                })
+                .collect::<Vec<_>>()
+        });
+
+        let mut builder = pop3_client::Builder::default();
+        #[cfg(feature = "with-rustls")]
+        builder.tls(false);
+        let mut client = builder
+            .allow_plaintext_auth(true)
+            .auto_retry_idempotent(true)
+            .credentials("alice", "secret")
+            .open("127.0.0.1", port)
+            .unwrap();
+        let token = client.cancellation_token();
+        assert_eq!(client.stat().unwrap(), (2, 320));
+
+        token.cancel();
+        assert!(matches!(client.noop(), Err(Error::Cancelled)));
+        let unanswered = server.join().unwrap();
+        assert_eq!(unanswered, [Some("STAT".to_string()), Some("NOOP".to_string())]);
+    }
+
+    #[test]
+    fn async_client_runs_over_any_transport() {
+        use std::future::Future;
+        use std::io;
+        use std::sync::{Arc, Mutex};
+        use std::task::{Context, Poll, Wake, Waker};
+
+        use pop3_client::{AsyncClient, AsyncTransport, Stage};
+
+        /// Hands out the replies a few octets at a time, pending before each read
+        struct Trickle {
+            replies: Vec<u8>,
+            pending: bool,
+            sent: Arc<Mutex<Vec<u8>>>,
+        }
+
+        impl AsyncTransport for Trickle {
+            fn poll_read(&mut self, cx: &mut Context<'_>, buf: &mut [u8]) -> Poll<io::Result<usize>> {
+                self.pending = !self.pending;
+                if self.pending {
+                    cx.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+                let len = buf.len().min(self.replies.len()).min(5);
+                buf[..len].copy_from_slice(&self.replies[..len]);
+                self.replies.drain(..len);
+                Poll::Ready(Ok(len))
+            }
+
+            fn poll_write(&mut self, _: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+                self.sent.lock().unwrap().extend_from_slice(buf);
+                Poll::Ready(Ok(buf.len()))
+            }
+
+            fn poll_flush(&mut self, _: &mut Context<'_>) -> Poll<io::Result<()>> {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        struct Noop;
+
+        impl Wake for Noop {
+            fn wake(self: Arc<Self>) {}
+        }
+
+        fn block_on<F: Future>(future: F) -> F::Output {
+            let waker = Waker::from(Arc::new(Noop));
+            let mut cx = Context::from_waker(&waker);
+            let mut future = Box::pin(future);
+            loop {
+                if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                    return output;
+                }
+            }
+        }
+
+        let sent = Arc::new(Mutex::new(Vec::new()));
+        let transport = Trickle {
+            replies: b"+OK ready\r\n+OK\r\n+OK\r\n+OK 1 120\r\n+OK\r\nSubject: hi\r\n\r\n..dot\r\n.\r\n\
+                       -ERR no such message\r\n+OK bye\r\n"
+                .to_vec(),
+            pending: false,
+            sent: sent.clone(),
+        };
+
+        block_on(async {
+            let mut client = AsyncClient::new(transport).await.unwrap();
+            assert_eq!(client.greeting(), "ready");
+            client.login("alice", "secret").await.unwrap();
+            assert_eq!(client.stage(), Stage::Transaction);
+            assert_eq!(client.stat().await.unwrap(), (1, 120));
+            let message = client.retr(1).await.unwrap();
+            assert_eq!(message.lines, ["Subject: hi", "", ".dot"]);
+            assert!(matches!(client.dele(2).await, Err(Error::Server(reason)) if reason == "no such message"));
+            client.quit().await.unwrap();
+            let rest = client.into_inner();
+            assert!(rest.replies.is_empty());
+        });
+        assert_eq!(
+            String::from_utf8(sent.lock().unwrap().clone()).unwrap(),
+            "USER alice\r\nPASS secret\r\nSTAT\r\nRETR 1\r\nDELE 2\r\nQUIT\r\n"
+        );
+
+        let refused = Trickle {
+            replies: b"+OK ready\r\n+OK\r\n-ERR [AUTH] wrong password\r\n".to_vec(),
+            pending: false,
+            sent: Arc::new(Mutex::new(Vec::new())),
+        };
+        block_on(async {
+            let mut client = AsyncClient::new(refused).await.unwrap();
+            assert!(matches!(client.login("alice", "wrong").await, Err(Error::Auth(_))));
+            assert!(matches!(client.noop().await, Err(Error::ConnectionAborted)));
+        });
+    }
 }